@@ -0,0 +1,259 @@
+use crate::model::TokenUsage;
+use crate::request_id::REQUEST_ID_HEADER;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+// Target of access events, so they can be filtered or routed apart from other logs
+// (e.g. RUST_LOG=llm_edge::access=info).
+pub const TARGET: &str = "llm_edge::access";
+
+// Fields for the one structured event logged per chat completion request, filled in as the
+// handler makes progress and emitted once the response is ready, whatever path it took.
+#[derive(Debug)]
+pub struct AccessLog {
+    started: Instant,
+    request_id: String,
+    pub model: String,
+    pub stream: bool,
+    pub cache_hit: bool,
+    // Provider that served the request (for a cache hit, the one whose answer was stored),
+    // or the last one tried when all failed
+    pub provider: Option<String>,
+    // When the request started waiting for a provider slot; its queue wait ends at dispatch
+    pub enqueued_at: Instant,
+    // Time spent waiting on the provider (to the first delta when streaming)
+    pub upstream: Option<Duration>,
+    pub usage: Option<TokenUsage>,
+    // What the request cost; unknown for streams until they end
+    pub cost_usd: Option<f64>,
+    // Only filled in for the audit log (see AuditLog::prompt_of)
+    pub prompt_hash: Option<String>,
+    pub prompt: Option<String>,
+}
+
+// Attribution headers set on every successful completion (see AccessLog::attribute). On a
+// request, `x-llm-provider` pins it to that provider instead.
+pub const PROVIDER_HEADER: HeaderName = HeaderName::from_static("x-llm-provider");
+pub const CACHE_HEADER: HeaderName = HeaderName::from_static("x-llm-cache");
+pub const LATENCY_HEADER: HeaderName = HeaderName::from_static("x-llm-latency-ms");
+pub const COST_HEADER: HeaderName = HeaderName::from_static("x-llm-cost-usd");
+
+impl AccessLog {
+    pub fn new(headers: &HeaderMap) -> Self {
+        let request_id = headers.get(&REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+        let started = Instant::now();
+        Self {
+            started,
+            request_id: request_id.to_string(),
+            model: String::new(),
+            stream: false,
+            cache_hit: false,
+            provider: None,
+            enqueued_at: started,
+            upstream: None,
+            usage: None,
+            cost_usd: None,
+            prompt_hash: None,
+            prompt: None,
+        }
+    }
+
+    // Arrival of the request at the handler
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    // Took longer than `threshold_ms` so far; never without a threshold.
+    pub fn is_slow(&self, threshold_ms: Option<u64>) -> bool {
+        threshold_ms.is_some_and(|ms| self.started.elapsed() > Duration::from_millis(ms))
+    }
+
+    // Who served the response, whether from cache, the time the gateway took to produce it
+    // (to the first byte for streams) and its cost, when known.
+    pub fn attribute(&self, headers: &mut HeaderMap) {
+        if let Some(provider) = self.provider.as_deref().and_then(|p| HeaderValue::from_str(p).ok()) {
+            headers.insert(PROVIDER_HEADER, provider);
+        }
+        headers.insert(CACHE_HEADER, HeaderValue::from_static(if self.cache_hit { "hit" } else { "miss" }));
+        headers.insert(LATENCY_HEADER, HeaderValue::from(self.started.elapsed().as_millis() as u64));
+        if let Some(Ok(cost)) = self.cost_usd.map(|c| HeaderValue::from_str(&format!("{:.6}", c))) {
+            headers.insert(COST_HEADER, cost);
+        }
+    }
+
+    // Overhead is the gateway's own share of the total: everything but the upstream wait.
+    // With a slow-request threshold, requests over it are logged at warn and successes under
+    // it at debug; errors stay at info like everything without one.
+    pub fn emit(&self, status: StatusCode, slow_threshold_ms: Option<u64>) {
+        let total = self.started.elapsed();
+        let upstream_ms = self.upstream.map(|d| d.as_secs_f64() * 1000.0);
+        let overhead_ms = total.saturating_sub(self.upstream.unwrap_or_default()).as_secs_f64() * 1000.0;
+        macro_rules! event {
+            ($level:ident, $message:literal) => {
+                $level!(
+                    target: TARGET,
+                    request_id = %self.request_id,
+                    model = %self.model,
+                    stream = self.stream,
+                    cache_hit = self.cache_hit,
+                    provider = self.provider.as_deref(),
+                    upstream_ms,
+                    total_ms = total.as_secs_f64() * 1000.0,
+                    overhead_ms,
+                    status = status.as_u16(),
+                    prompt_tokens = self.usage.as_ref().map(|u| u.prompt_tokens),
+                    completion_tokens = self.usage.as_ref().map(|u| u.completion_tokens),
+                    $message
+                )
+            };
+        }
+        match slow_threshold_ms {
+            Some(ms) if total > Duration::from_millis(ms) => event!(warn, "slow request"),
+            Some(_) if status.is_success() => event!(debug, "request completed"),
+            _ => event!(info, "request completed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockUpstream};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    type Fields = HashMap<String, String>;
+
+    // Fields of every access event
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<Fields>>>);
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for Captured {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            if event.metadata().target() == TARGET {
+                let mut fields = Fields::new();
+                fields.insert("level".to_string(), event.metadata().level().to_string());
+                event.record(&mut FieldVisitor(&mut fields));
+                self.0.lock().unwrap().push(fields);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn every_terminal_path_logs_one_structured_event() {
+        let captured = Captured::default();
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let headers = |id: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(&REQUEST_ID_HEADER, id.parse().unwrap());
+            headers
+        };
+
+        let req = test_support::request("hi", serde_json::json!({}));
+        test_support::complete(&state, headers("miss"), req.clone()).await;
+        state.cache.drain_writes(Duration::from_secs(1)).await;
+        test_support::complete(&state, headers("hit"), req).await;
+        upstream.fail_with(500);
+        test_support::complete(&state, headers("error"), test_support::request("other", serde_json::json!({}))).await;
+
+        let events = captured.0.lock().unwrap().clone();
+        let ids: Vec<&str> = events.iter().map(|e| e["request_id"].as_str()).collect();
+        assert_eq!(ids, ["miss", "hit", "error"]);
+        for event in &events {
+            for field in ["model", "stream", "cache_hit", "total_ms", "overhead_ms", "status"] {
+                assert!(event.contains_key(field), "{field} missing from {event:?}");
+            }
+            assert_eq!(event["model"], "m");
+        }
+
+        let (miss, hit, error) = (&events[0], &events[1], &events[2]);
+        assert_eq!((miss["cache_hit"].as_str(), miss["status"].as_str()), ("false", "200"));
+        assert_eq!(miss["provider"], "a");
+        assert!(miss.contains_key("upstream_ms"));
+        assert_eq!(miss["prompt_tokens"], test_support::PROMPT_TOKENS.to_string());
+        assert_eq!(miss["completion_tokens"], test_support::COMPLETION_TOKENS.to_string());
+        assert_eq!((hit["cache_hit"].as_str(), hit["status"].as_str()), ("true", "200"));
+        assert_eq!(error["status"], "502");
+        assert_eq!(error["provider"], "a");
+    }
+
+    #[tokio::test]
+    async fn only_requests_over_the_slow_threshold_log_at_warn() {
+        let captured = Captured::default();
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.options.slow_request_threshold_ms = Some(200);
+        let state = Arc::new(state);
+        let headers = |id: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(&REQUEST_ID_HEADER, id.parse().unwrap());
+            headers
+        };
+
+        test_support::complete(&state, headers("fast"), test_support::request("fast", serde_json::json!({}))).await;
+        upstream.delay(Duration::from_millis(300));
+        test_support::complete(&state, headers("slow"), test_support::request("slow", serde_json::json!({}))).await;
+
+        let events = captured.0.lock().unwrap().clone();
+        let (fast, slow) = (&events[0], &events[1]);
+        assert_eq!((fast["request_id"].as_str(), fast["level"].as_str()), ("fast", "DEBUG"));
+        assert_eq!((slow["request_id"].as_str(), slow["level"].as_str()), ("slow", "WARN"));
+        assert_eq!(slow["message"], "slow request");
+        assert_eq!(slow["provider"], "a");
+        assert!(slow["total_ms"].parse::<f64>().unwrap() >= 300.0);
+        assert_eq!(events.iter().filter(|e| e["level"] == "WARN").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn successes_carry_provider_cache_latency_and_cost_headers() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(50));
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let req = || test_support::request("hi", serde_json::json!({}));
+        let header = |response: &axum::response::Response, name: &HeaderName| response.headers()[name].to_str().unwrap().to_string();
+
+        let served = test_support::complete(&state, HeaderMap::new(), req()).await;
+        assert_eq!(header(&served, &PROVIDER_HEADER), "a");
+        assert_eq!(header(&served, &CACHE_HEADER), "miss");
+        assert!(header(&served, &LATENCY_HEADER).parse::<u64>().unwrap() >= 50);
+        // 20 tokens at $1 per 1k
+        assert_eq!(header(&served, &COST_HEADER), "0.020000");
+
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        let hit = test_support::complete(&state, HeaderMap::new(), req()).await;
+        assert_eq!(header(&hit, &PROVIDER_HEADER), "a");
+        assert_eq!(header(&hit, &CACHE_HEADER), "hit");
+        assert!(header(&hit, &LATENCY_HEADER).parse::<u64>().unwrap() < 50);
+        assert_eq!(header(&hit, &COST_HEADER), "0.000000");
+
+        upstream.fail_with(500);
+        let failed = test_support::complete(&state, HeaderMap::new(), test_support::request("other", serde_json::json!({}))).await;
+        assert!(!failed.status().is_success());
+        for name in [PROVIDER_HEADER, CACHE_HEADER, LATENCY_HEADER, COST_HEADER] {
+            assert!(failed.headers().get(&name).is_none(), "{name} on an error");
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::access_log::AccessLog;
+use crate::model::LlmRequest;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// In-memory record of the last `capacity` chat completions, served by `GET /admin/requests`
+// for incident investigation; off unless configured.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub capacity: usize,
+    // Keep the prompt text itself; by default only a digest of it is kept, which still tells
+    // identical prompts apart without storing what users asked.
+    pub include_prompts: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self { capacity: 1_000, include_prompts: false }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    // Position in the order requests completed, from 0 at startup
+    pub seq: u64,
+    pub request_id: String,
+    // Unix time in ms when the request arrived
+    pub timestamp_ms: u64,
+    pub model: String,
+    pub provider: Option<String>,
+    pub cache_hit: bool,
+    pub latency_ms: u64,
+    pub status: u16,
+    // blake3 of the conversation text, truncated to 16 hex characters
+    pub prompt_hash: Option<String>,
+    // Only with `include_prompts`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+}
+
+// Ring buffer of AuditRecords. Writers take a slot with one atomic increment and lock only
+// that slot, so concurrent requests contend only when the buffer has wrapped all the way
+// around onto a slot still being written or read.
+#[derive(Debug)]
+pub struct AuditLog {
+    config: AuditConfig,
+    next: AtomicU64,
+    slots: Vec<Mutex<Option<AuditRecord>>>,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig) -> Self {
+        let slots = (0..config.capacity.max(1)).map(|_| Mutex::new(None)).collect();
+        Self { config, next: AtomicU64::new(0), slots }
+    }
+
+    // What of the prompt the record keeps, computed while the request is at hand.
+    pub fn prompt_of(&self, req: &LlmRequest) -> (Option<String>, Option<String>) {
+        let (Some(canonical), Some(text)) = (req.canonical_text(), req.transcript()) else { return (None, None) };
+        let hash = blake3::hash(canonical.as_bytes()).to_hex()[..16].to_string();
+        (Some(hash), self.config.include_prompts.then_some(text))
+    }
+
+    pub fn record(&self, log: &AccessLog, status: StatusCode) {
+        let latency = log.started().elapsed();
+        let arrived = SystemTime::now().checked_sub(latency).unwrap_or_else(SystemTime::now);
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let record = AuditRecord {
+            seq,
+            request_id: log.request_id().to_string(),
+            timestamp_ms: arrived.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+            model: log.model.clone(),
+            provider: log.provider.clone(),
+            cache_hit: log.cache_hit,
+            latency_ms: latency.as_millis() as u64,
+            status: status.as_u16(),
+            prompt_hash: log.prompt_hash.clone(),
+            prompt: log.prompt.clone(),
+        };
+        if let Ok(mut slot) = self.slots[(seq % self.slots.len() as u64) as usize].lock() {
+            *slot = Some(record);
+        }
+    }
+
+    // Up to `limit` most recent records, oldest first.
+    pub fn recent(&self, limit: usize) -> Vec<AuditRecord> {
+        let mut records: Vec<AuditRecord> =
+            self.slots.iter().filter_map(|slot| slot.lock().ok()?.clone()).collect();
+        records.sort_by_key(|r| r.seq);
+        let skip = records.len().saturating_sub(limit);
+        records.split_off(skip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_id::REQUEST_ID_HEADER;
+    use axum::http::HeaderMap;
+
+    fn completed(audit: &AuditLog, id: &str, prompt: &str) {
+        let mut headers = HeaderMap::new();
+        headers.insert(&REQUEST_ID_HEADER, id.parse().unwrap());
+        let mut log = AccessLog::new(&headers);
+        log.model = "m".to_string();
+        let req: LlmRequest = serde_json::from_value(serde_json::json!({"model": "m", "prompt": prompt})).unwrap();
+        (log.prompt_hash, log.prompt) = audit.prompt_of(&req);
+        audit.record(&log, StatusCode::OK);
+    }
+
+    fn ids(records: &[AuditRecord]) -> Vec<&str> {
+        records.iter().map(|r| r.request_id.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_the_latest_requests_in_order() {
+        let audit = AuditLog::new(AuditConfig { capacity: 3, include_prompts: false });
+        for id in ["a", "b"] {
+            completed(&audit, id, "hi");
+        }
+        assert_eq!(ids(&audit.recent(10)), ["a", "b"]);
+        for id in ["c", "d", "e"] {
+            completed(&audit, id, "hi");
+        }
+        // Oldest evicted first once the ring wraps
+        assert_eq!(ids(&audit.recent(10)), ["c", "d", "e"]);
+        assert_eq!(ids(&audit.recent(2)), ["d", "e"]);
+    }
+
+    #[test]
+    fn prompts_are_hashed_unless_included() {
+        let redacted = AuditLog::new(AuditConfig::default());
+        completed(&redacted, "a", "secret question");
+        completed(&redacted, "b", "secret question");
+        let records = redacted.recent(10);
+        assert!(records.iter().all(|r| r.prompt.is_none()));
+        assert_eq!(records[0].prompt_hash, records[1].prompt_hash);
+        assert!(!serde_json::to_string(&records).unwrap().contains("secret"));
+
+        let full = AuditLog::new(AuditConfig { include_prompts: true, ..AuditConfig::default() });
+        completed(&full, "a", "secret question");
+        assert_eq!(full.recent(1)[0].prompt.as_deref(), Some("secret question"));
+    }
+}
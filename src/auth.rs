@@ -0,0 +1,156 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use crate::error::ApiError;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+// Client authentication for the gateway's own API.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    // Accepted `Authorization: Bearer <key>` values; may use `${VAR}` like provider keys.
+    pub api_keys: Vec<String>,
+    // Keys accepted like `api_keys` that may also pin requests to a provider (see
+    // gateway::pinned_provider) and use the operator endpoints (see require_admin).
+    pub admin_keys: Vec<String>,
+    // Accept every request without a key. For local development only.
+    pub disabled: bool,
+}
+
+// Allowed keys are kept as blake3 digests, so a lookup compares fixed-size hashes rather
+// than the secrets themselves and the raw keys don't linger in memory.
+pub struct ClientAuth {
+    key_hashes: HashSet<[u8; 32]>,
+    admin_hashes: HashSet<[u8; 32]>,
+    disabled: bool,
+}
+
+impl ClientAuth {
+    pub fn new(config: &AuthConfig) -> Self {
+        let hashes = |keys: &[String]| keys.iter().map(|k| *blake3::hash(k.as_bytes()).as_bytes()).collect();
+        Self { key_hashes: hashes(&config.api_keys), admin_hashes: hashes(&config.admin_keys), disabled: config.disabled }
+    }
+
+    pub fn allows(&self, bearer: Option<&str>) -> bool {
+        self.disabled || self.is_admin(bearer) || bearer.is_some_and(|key| self.key_hashes.contains(blake3::hash(key.as_bytes()).as_bytes()))
+    }
+
+    // Every client counts as admin while auth is disabled.
+    pub fn is_admin(&self, bearer: Option<&str>) -> bool {
+        self.disabled || bearer.is_some_and(|key| self.admin_hashes.contains(blake3::hash(key.as_bytes()).as_bytes()))
+    }
+}
+
+// Client key from an `Authorization: Bearer <key>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+// Rejects requests without a valid client key before any handler (and so any cache lookup
+// or provider call) runs.
+pub async fn require_api_key(State(auth): State<Arc<ClientAuth>>, request: Request, next: Next) -> Response {
+    let bearer = bearer_token(request.headers());
+    if auth.allows(bearer) {
+        return next.run(request).await;
+    }
+    let mut resp = ApiError::new(StatusCode::UNAUTHORIZED, "invalid_request_error", "invalid_api_key", "Missing or invalid API key").into_response();
+    resp.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    resp
+}
+
+// Keeps operator endpoints (draining providers, purging the cache, reading other clients'
+// requests, metrics) to `admin_keys`. Runs inside require_api_key, so unknown keys already
+// got their 401 and only valid non-admin keys reach the 403.
+pub async fn require_admin(State(auth): State<Arc<ClientAuth>>, request: Request, next: Next) -> Response {
+    if auth.is_admin(bearer_token(request.headers())) {
+        return next.run(request).await;
+    }
+    ApiError::new(StatusCode::FORBIDDEN, "invalid_request_error", "admin_key_required", "This endpoint needs an admin key").into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+
+    fn auth(disabled: bool) -> ClientAuth {
+        ClientAuth::new(&AuthConfig { api_keys: vec!["client".into()], admin_keys: vec!["admin".into()], disabled })
+    }
+
+    #[test]
+    fn admin_keys_are_client_keys_too() {
+        let auth = auth(false);
+        assert!(auth.allows(Some("client")) && auth.allows(Some("admin")));
+        assert!(!auth.allows(Some("other")) && !auth.allows(None));
+        assert!(auth.is_admin(Some("admin")));
+        assert!(!auth.is_admin(Some("client")) && !auth.is_admin(None));
+    }
+
+    #[test]
+    fn everyone_is_admin_with_auth_disabled() {
+        assert!(auth(true).is_admin(None));
+    }
+
+    #[tokio::test]
+    async fn operator_routes_need_an_admin_key() {
+        let auth = Arc::new(auth(false));
+        let app = Router::new()
+            .route("/admin/thing", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(auth.clone(), require_admin))
+            .route_layer(middleware::from_fn_with_state(auth, require_api_key));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/admin/thing", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let status = |key: Option<&'static str>| {
+            let mut request = client.get(&url);
+            if let Some(key) = key {
+                request = request.bearer_auth(key);
+            }
+            async move { request.send().await.unwrap().status().as_u16() }
+        };
+        assert_eq!(status(None).await, 401);
+        assert_eq!(status(Some("client")).await, 403);
+        assert_eq!(status(Some("admin")).await, 200);
+    }
+
+    #[tokio::test]
+    async fn completions_need_a_client_key() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let state = Arc::new(crate::test_support::state(vec![upstream.provider("a")]));
+        let app = Router::new()
+            .route("/v1/chat/completions", axum::routing::post(crate::gateway::handle_chat_completions))
+            .route_layer(middleware::from_fn_with_state(Arc::new(auth(false)), require_api_key))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let send = |key: Option<&'static str>| {
+            let mut request = client.post(&url).json(&serde_json::json!({"model": "m", "prompt": "hi"}));
+            if let Some(key) = key {
+                request = request.bearer_auth(key);
+            }
+            async move { request.send().await.unwrap() }
+        };
+        for key in [None, Some("wrong")] {
+            let response = send(key).await;
+            assert_eq!(response.status().as_u16(), 401);
+            assert_eq!(response.headers()["www-authenticate"], "Bearer");
+        }
+        assert_eq!(upstream.calls(), 0, "rejected before reaching a provider");
+        assert_eq!(send(Some("client")).await.status().as_u16(), 200);
+        assert_eq!(upstream.calls(), 1);
+    }
+}
@@ -0,0 +1,118 @@
+use rand::Rng;
+use serde::Deserialize;
+use std::time::Duration;
+
+// Pause before each failover retry, so a transient upstream error isn't answered with an
+// immediate burst of calls. Delays grow by `multiplier` per retry up to `max_ms`, and their
+// sum per request stops at `max_total_ms`; retries past that go out without waiting.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BackoffConfig {
+    pub base_ms: u64,
+    pub max_ms: u64,
+    pub multiplier: f64,
+    pub max_total_ms: u64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self { base_ms: 50, max_ms: 1000, multiplier: 2.0, max_total_ms: 2000 }
+    }
+}
+
+impl BackoffConfig {
+    // Delay before retry number `retry` (1-based), before jitter and the total cap.
+    pub fn ceiling(&self, retry: u32) -> Duration {
+        let ms = self.base_ms as f64 * self.multiplier.powi(retry.saturating_sub(1) as i32);
+        Duration::from_millis(ms.min(self.max_ms as f64) as u64)
+    }
+}
+
+// One request's backoff state.
+#[derive(Debug)]
+pub struct Backoff {
+    config: BackoffConfig,
+    retries: u32,
+    waited: Duration,
+}
+
+impl Backoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self { config, retries: 0, waited: Duration::ZERO }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    // Delay before the next retry. `jitter` in [0, 1) picks a point in the upper half of the
+    // exponential ceiling, so retries from concurrent requests spread out while still growing.
+    // An upstream `Retry-After` replaces the computed delay, still bounded by `max_ms`.
+    pub fn next_delay(&mut self, retry_after: Option<Duration>, jitter: f64) -> Duration {
+        self.retries += 1;
+        let ceiling = self.config.ceiling(self.retries);
+        let delay = match retry_after {
+            Some(retry_after) => retry_after.min(Duration::from_millis(self.config.max_ms)),
+            None => ceiling.mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0),
+        };
+        let remaining = Duration::from_millis(self.config.max_total_ms).saturating_sub(self.waited);
+        let delay = delay.min(remaining);
+        self.waited += delay;
+        delay
+    }
+
+    pub async fn wait(&mut self, retry_after: Option<Duration>) {
+        let delay = self.next_delay(retry_after, rand::thread_rng().gen());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockUpstream};
+    use axum::http::HeaderMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn delays_grow_within_the_jittered_ceiling() {
+        let config = BackoffConfig { base_ms: 100, max_ms: 1_000, multiplier: 2.0, max_total_ms: 10_000 };
+        let mut low = Backoff::new(config);
+        let mut high = Backoff::new(config);
+        let lows: Vec<u64> = (0..5).map(|_| low.next_delay(None, 0.0).as_millis() as u64).collect();
+        let highs: Vec<u64> = (0..5).map(|_| high.next_delay(None, 0.999).as_millis() as u64).collect();
+        assert_eq!(lows, [50, 100, 200, 400, 500]);
+        assert_eq!(highs, [99, 199, 399, 799, 999]);
+    }
+
+    #[test]
+    fn retry_after_replaces_the_delay_and_the_total_is_capped() {
+        let config = BackoffConfig { base_ms: 100, max_ms: 1_000, multiplier: 2.0, max_total_ms: 1_500 };
+        let mut backoff = Backoff::new(config);
+        assert_eq!(backoff.next_delay(Some(Duration::from_millis(700)), 0.0), Duration::from_millis(700));
+        // Bounded by max_ms, then by what is left of max_total_ms
+        assert_eq!(backoff.next_delay(Some(Duration::from_secs(30)), 0.0), Duration::from_millis(800));
+        assert_eq!(backoff.next_delay(None, 0.0), Duration::ZERO);
+        assert_eq!(backoff.retries(), 3);
+    }
+
+    #[tokio::test]
+    async fn failover_retries_wait_longer_each_time() {
+        let upstream = MockUpstream::start().await;
+        upstream.fail_with(503);
+        let mut state = test_support::state(["a", "b", "c"].iter().map(|id| upstream.provider(id)).collect());
+        state.backoff = Some(BackoffConfig { base_ms: 40, max_ms: 1_000, multiplier: 3.0, max_total_ms: 2_000 });
+        state.options.max_retries = 2;
+        let state = Arc::new(state);
+
+        test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        let arrivals = upstream.arrivals();
+        assert_eq!(arrivals.len(), 3);
+        let gaps: Vec<Duration> = arrivals.windows(2).map(|w| w[1] - w[0]).collect();
+        // At least half of each ceiling: 20ms, then 60ms, more than the first could be
+        assert!(gaps[0] >= Duration::from_millis(20), "{gaps:?}");
+        assert!(gaps[1] >= Duration::from_millis(60) && gaps[1] > gaps[0], "{gaps:?}");
+    }
+}
@@ -0,0 +1,117 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// Consecutive failures that open a closed breaker.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+// How long an open breaker rejects traffic before letting a probe through.
+pub const DEFAULT_COOLDOWN_MS: u64 = 30_000;
+// How long a provider whose breaker closed again takes to get back to full traffic.
+pub const DEFAULT_RECOVERY_RAMP_MS: u64 = 30_000;
+
+const CLOSED: u8 = 0;
+const OPEN: u8 = 1;
+const HALF_OPEN: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+// Milliseconds on a process-local monotonic clock. Breaker timestamps are stored in this
+// unit so they fit in an AtomicU64.
+pub fn now_millis() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+// Three-state circuit breaker built from two atomics so `Router::select` stays lock-free.
+// Closed -> Open after enough consecutive failures (or a high recent error rate); Open -> HalfOpen once the cooldown has
+// elapsed, letting exactly one probe through; the probe's outcome closes or re-opens it.
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    state: AtomicU8,
+    // Open: when the breaker opened. HalfOpen: when the current probe was let through.
+    changed_at_ms: AtomicU64,
+    // When the breaker last closed after being open; 0 if it never was.
+    closed_at_ms: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn raw_state(&self) -> BreakerState {
+        match self.state.load(Ordering::Acquire) {
+            OPEN => BreakerState::Open,
+            HALF_OPEN => BreakerState::HalfOpen,
+            _ => BreakerState::Closed,
+        }
+    }
+
+    // Observed state. An open breaker whose cooldown has elapsed reports HalfOpen even
+    // before a probe has claimed it.
+    pub fn state_at(&self, now_ms: u64, cooldown_ms: u64) -> BreakerState {
+        match self.raw_state() {
+            BreakerState::Open if self.elapsed_since_change(now_ms) >= cooldown_ms => BreakerState::HalfOpen,
+            state => state,
+        }
+    }
+
+    // Whether a request may be sent now. In the half-open phase only one caller wins the
+    // probe slot; if that probe never reports back, the slot frees up after another cooldown.
+    pub fn try_acquire_at(&self, now_ms: u64, cooldown_ms: u64) -> bool {
+        if self.raw_state() == BreakerState::Closed {
+            return true;
+        }
+        let changed = self.changed_at_ms.load(Ordering::Acquire);
+        if now_ms.saturating_sub(changed) < cooldown_ms {
+            return false;
+        }
+        // Whoever moves the timestamp owns the probe.
+        if self
+            .changed_at_ms
+            .compare_exchange(changed, now_ms, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return false;
+        }
+        self.state.store(HALF_OPEN, Ordering::Release);
+        true
+    }
+
+    pub fn on_success_at(&self, now_ms: u64) {
+        if self.state.swap(CLOSED, Ordering::AcqRel) != CLOSED {
+            self.closed_at_ms.store(now_ms.max(1), Ordering::Release);
+        }
+    }
+
+    // When the breaker last recovered, i.e. closed after being open.
+    pub fn recovered_at_ms(&self) -> Option<u64> {
+        Some(self.closed_at_ms.load(Ordering::Acquire)).filter(|&ms| ms > 0)
+    }
+
+    // `trip` says whether the failure pushed a closed breaker over its trip condition
+    // (consecutive failures, or the windowed error rate); a failed probe always re-opens.
+    pub fn on_failure_at(&self, now_ms: u64, trip: bool) {
+        match self.raw_state() {
+            BreakerState::HalfOpen => self.open_at(now_ms),
+            BreakerState::Closed if trip => self.open_at(now_ms),
+            _ => {}
+        }
+    }
+
+    fn open_at(&self, now_ms: u64) {
+        self.changed_at_ms.store(now_ms, Ordering::Release);
+        self.state.store(OPEN, Ordering::Release);
+    }
+
+    fn elapsed_since_change(&self, now_ms: u64) -> u64 {
+        now_ms.saturating_sub(self.changed_at_ms.load(Ordering::Acquire))
+    }
+}
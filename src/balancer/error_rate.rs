@@ -0,0 +1,32 @@
+use super::window::WindowCounter;
+
+// Fewer calls than this in the window is too little signal to trust a rate from.
+pub const MIN_SAMPLES: u64 = 10;
+
+// Fraction of calls that failed over the last WINDOW_SECS. Unlike a consecutive-error count,
+// this sees a provider failing every other request.
+#[derive(Debug, Default)]
+pub struct ErrorRateTracker {
+    calls: WindowCounter,
+    failures: WindowCounter,
+}
+
+impl ErrorRateTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_at(&self, now_ms: u64, failed: bool) {
+        self.calls.record_at(now_ms);
+        if failed {
+            self.failures.record_at(now_ms);
+        }
+    }
+
+    // Failed fraction in [0, 1], or None while the window holds fewer than MIN_SAMPLES calls.
+    pub fn rate_at(&self, now_ms: u64) -> Option<f64> {
+        let calls = self.calls.count_at(now_ms);
+        // The counters are read one after the other, so clamp a racing update
+        (calls >= MIN_SAMPLES).then(|| (self.failures.count_at(now_ms) as f64 / calls as f64).min(1.0))
+    }
+}
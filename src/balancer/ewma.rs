@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+// Weight of the newest sample unless configured otherwise (ProviderConfig::ewma_alpha).
+pub const DEFAULT_ALPHA: f64 = 0.125;
+
+// Values are stored multiplied by this, so integer updates keep three decimal places and
+// small samples don't truncate toward zero.
+const VALUE_SCALE: u64 = 1000;
+// Alpha in 1/65536ths
+const ALPHA_ONE: u32 = 1 << 16;
+
+// Exponentially weighted moving average in fixed point, updated with a lock-free CAS loop:
+// new = old + alpha * (sample - old). The first sample seeds the average.
+#[derive(Debug)]
+pub struct Ewma {
+    scaled: AtomicU64,
+    alpha: AtomicU32,
+}
+
+impl Default for Ewma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ewma {
+    pub fn new() -> Self {
+        let ewma = Self { scaled: AtomicU64::new(0), alpha: AtomicU32::new(0) };
+        ewma.set_alpha(DEFAULT_ALPHA);
+        ewma
+    }
+
+    // Clamped to (0, 1]; takes effect from the next sample.
+    pub fn set_alpha(&self, alpha: f64) {
+        let fixed = (alpha.clamp(0.0, 1.0) * ALPHA_ONE as f64).round() as u32;
+        self.alpha.store(fixed.max(1), Ordering::Relaxed);
+    }
+
+    pub fn record(&self, sample: u64) {
+        let sample = sample.saturating_mul(VALUE_SCALE) as i128;
+        let alpha = self.alpha.load(Ordering::Relaxed) as i128;
+        let mut old = self.scaled.load(Ordering::Relaxed);
+        loop {
+            let new = if old == 0 {
+                sample
+            } else {
+                old as i128 + alpha * (sample - old as i128) / ALPHA_ONE as i128
+            };
+            // Never store 0 for a nonzero sample, which would read as "unseeded"
+            let new = (new.max(0) as u64).max((sample > 0) as u64);
+            match self.scaled.compare_exchange_weak(old, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(x) => old = x,
+            }
+        }
+    }
+
+    // Current average in sample units; 0 before the first sample.
+    pub fn value(&self) -> f64 {
+        self.scaled.load(Ordering::Relaxed) as f64 / VALUE_SCALE as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_constant_latency_converges_without_drifting_low() {
+        for latency in [3, 50, 1_234] {
+            let ewma = Ewma::new();
+            // Seeded far off, so it has to converge
+            ewma.record(latency * 10);
+            for _ in 0..200 {
+                ewma.record(latency);
+            }
+            let error = (ewma.value() - latency as f64).abs() / latency as f64;
+            assert!(error < 0.01, "{latency}: converged to {}", ewma.value());
+        }
+    }
+
+    #[test]
+    fn alpha_sets_the_weight_of_each_sample() {
+        let ewma = Ewma::new();
+        ewma.set_alpha(0.5);
+        ewma.record(100);
+        ewma.record(200);
+        assert_eq!(ewma.value(), 150.0);
+
+        ewma.set_alpha(1.0);
+        ewma.record(7);
+        assert_eq!(ewma.value(), 7.0);
+    }
+}
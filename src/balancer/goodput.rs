@@ -0,0 +1,49 @@
+use super::window::{WindowCounter, WINDOW_SECS};
+
+// Fewer completions than this in the window is too little signal to estimate a rate from.
+const MIN_COMPLETIONS: u64 = 5;
+
+// Successful completions per second over the last WINDOW_SECS.
+#[derive(Debug, Default)]
+pub struct GoodputTracker {
+    completions: WindowCounter,
+}
+
+impl GoodputTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_at(&self, now_ms: u64) {
+        self.completions.record_at(now_ms);
+    }
+
+    // Completions per second, or None while the window holds too few to tell.
+    pub fn rate_at(&self, now_ms: u64) -> Option<f64> {
+        let completions = self.completions.count_at(now_ms);
+        (completions >= MIN_COMPLETIONS).then(|| completions as f64 / WINDOW_SECS as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_needs_enough_completions_in_the_window() {
+        let goodput = GoodputTracker::new();
+        let start = 1_000_000;
+        for i in 0..MIN_COMPLETIONS - 1 {
+            goodput.record_at(start + i * 100);
+        }
+        assert_eq!(goodput.rate_at(start + 1_000), None);
+
+        for i in 0..=MIN_COMPLETIONS * 3 {
+            goodput.record_at(start + 1_000 + i * 100);
+        }
+        let expected = (MIN_COMPLETIONS * 4) as f64 / WINDOW_SECS as f64;
+        assert_eq!(goodput.rate_at(start + 3_000), Some(expected));
+        // Completions age out with the window
+        assert_eq!(goodput.rate_at(start + 3_000 + WINDOW_SECS * 1_000), None);
+    }
+}
@@ -1 +1,7 @@
 pub mod stats;
+pub mod breaker;
+pub mod ewma;
+pub mod error_rate;
+pub mod quota;
+pub mod goodput;
+pub mod window;
@@ -0,0 +1,160 @@
+use reqwest::header::HeaderMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Value stored in an atomic when the provider never reported that field.
+const UNKNOWN: u64 = u64::MAX;
+// Without a reset header, an observation is trusted for this long before it's ignored,
+// so a deprioritized provider gets traffic (and fresh headers) again.
+const DEFAULT_QUOTA_TTL_MS: u64 = 60_000;
+
+// One snapshot of the `x-ratelimit-*` headers on a provider response.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimitQuota {
+    pub remaining_requests: Option<u64>,
+    pub limit_requests: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+    pub limit_tokens: Option<u64>,
+    // Time until the later of the two windows resets, in milliseconds.
+    pub reset_ms: Option<u64>,
+}
+
+impl RateLimitQuota {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+        let number = |name: &str| get(name).and_then(|v| v.parse::<u64>().ok());
+        let reset = |name: &str| get(name).and_then(parse_reset_ms);
+        Self {
+            remaining_requests: number("x-ratelimit-remaining-requests"),
+            limit_requests: number("x-ratelimit-limit-requests"),
+            remaining_tokens: number("x-ratelimit-remaining-tokens"),
+            limit_tokens: number("x-ratelimit-limit-tokens"),
+            reset_ms: reset("x-ratelimit-reset-requests").max(reset("x-ratelimit-reset-tokens")),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining_requests.is_none() && self.remaining_tokens.is_none()
+    }
+}
+
+// Parses reset durations as sent by OpenAI-style APIs: "20ms", "1s", "6m0s", "1h2m3.5s",
+// or a bare number of seconds.
+pub fn parse_reset_ms(value: &str) -> Option<u64> {
+    if let Ok(secs) = value.parse::<f64>() {
+        return (secs >= 0.0).then_some((secs * 1000.0) as u64);
+    }
+    let mut total_ms = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let amount: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let factor = match &rest[..unit_len] {
+            "ms" => 1.0,
+            "s" => 1000.0,
+            "m" => 60_000.0,
+            "h" => 3_600_000.0,
+            _ => return None,
+        };
+        total_ms += amount * factor;
+        rest = &rest[unit_len..];
+    }
+    Some(total_ms as u64)
+}
+
+// Latest quota reported by a provider. Fields are updated independently, which is fine for a
+// routing hint: a torn read only mixes two consecutive observations.
+#[derive(Debug)]
+pub struct QuotaTracker {
+    remaining_requests: AtomicU64,
+    limit_requests: AtomicU64,
+    remaining_tokens: AtomicU64,
+    limit_tokens: AtomicU64,
+    // `breaker::now_millis()` timestamp after which the observation is stale
+    expires_at_ms: AtomicU64,
+}
+
+impl Default for QuotaTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QuotaTracker {
+    pub fn new() -> Self {
+        Self {
+            remaining_requests: AtomicU64::new(UNKNOWN),
+            limit_requests: AtomicU64::new(UNKNOWN),
+            remaining_tokens: AtomicU64::new(UNKNOWN),
+            limit_tokens: AtomicU64::new(UNKNOWN),
+            expires_at_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_at(&self, now_ms: u64, quota: &RateLimitQuota) {
+        if quota.is_empty() {
+            return;
+        }
+        let store = |target: &AtomicU64, value: Option<u64>| target.store(value.unwrap_or(UNKNOWN), Ordering::Relaxed);
+        store(&self.remaining_requests, quota.remaining_requests);
+        store(&self.limit_requests, quota.limit_requests);
+        store(&self.remaining_tokens, quota.remaining_tokens);
+        store(&self.limit_tokens, quota.limit_tokens);
+        let ttl = quota.reset_ms.unwrap_or(DEFAULT_QUOTA_TTL_MS);
+        self.expires_at_ms.store(now_ms.saturating_add(ttl), Ordering::Relaxed);
+    }
+
+    // Fraction of quota left in [0, 1], taking the tighter of requests and tokens. None when
+    // the provider didn't report a quota or the last report's window has reset since.
+    pub fn headroom_at(&self, now_ms: u64) -> Option<f64> {
+        if now_ms >= self.expires_at_ms.load(Ordering::Relaxed) {
+            return None;
+        }
+        let ratio = |remaining: &AtomicU64, limit: &AtomicU64| {
+            let remaining = remaining.load(Ordering::Relaxed);
+            let limit = limit.load(Ordering::Relaxed);
+            match (remaining, limit) {
+                (UNKNOWN, _) => None,
+                (0, _) => Some(0.0),
+                (_, UNKNOWN) | (_, 0) => None,
+                (r, l) => Some((r as f64 / l as f64).min(1.0)),
+            }
+        };
+        let requests = ratio(&self.remaining_requests, &self.limit_requests);
+        let tokens = ratio(&self.remaining_tokens, &self.limit_tokens);
+        match (requests, tokens) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_durations_parse_in_every_form() {
+        for (value, ms) in [("20ms", 20), ("1s", 1_000), ("6m0s", 360_000), ("1h2m3.5s", 3_723_500), ("2.5", 2_500)] {
+            assert_eq!(parse_reset_ms(value), Some(ms), "{value}");
+        }
+        assert_eq!(parse_reset_ms("soon"), None);
+    }
+
+    #[test]
+    fn headroom_is_the_tighter_quota_until_it_resets() {
+        let tracker = QuotaTracker::new();
+        assert_eq!(tracker.headroom_at(0), None);
+        let quota = RateLimitQuota {
+            remaining_requests: Some(50),
+            limit_requests: Some(100),
+            remaining_tokens: Some(1_000),
+            limit_tokens: Some(10_000),
+            reset_ms: Some(1_000),
+        };
+        tracker.record_at(0, &quota);
+        assert_eq!(tracker.headroom_at(999), Some(0.1));
+        assert_eq!(tracker.headroom_at(1_000), None);
+    }
+}
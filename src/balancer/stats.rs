@@ -1,17 +1,115 @@
-use std::sync::atomic::{AtomicU64, AtomicU32, Ordering};
-use std::sync::Arc;
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::Notify;
+use super::breaker::{self, CircuitBreaker};
+use super::ewma::{self, Ewma};
+use super::error_rate::ErrorRateTracker;
+use super::goodput::GoodputTracker;
+use super::quota::QuotaTracker;
+use crate::error::ProviderError;
+use crate::model::ProviderConfig;
 
 #[derive(Debug)]
 pub struct ProviderStats {
     pub request_count: AtomicU64,
     pub error_count: AtomicU64,
+    // Subset of `error_count` caused by the request (see ProviderError::is_provider_fault),
+    // which doesn't affect health
+    pub client_error_count: AtomicU64,
     // Latency stored as microseconds to allow atomic operations
     pub p50_latency_us: AtomicU64, 
     pub p99_latency_us: AtomicU64,
     // EWMA of latency (microseconds)
-    pub ewma_latency_us: AtomicU64,
+    pub ewma_latency_us: Ewma,
+    // EWMA of time spent between request arrival and dispatch to this provider (microseconds)
+    pub ewma_queue_wait_us: Ewma,
+    // EWMA of time from dispatch to the first streamed delta (microseconds); 0 before the
+    // first stream
+    pub ewma_ttft_us: Ewma,
     pub consec_errors: AtomicU32,
+    pub breaker: CircuitBreaker,
+    // Requests currently dispatched to this provider (short-term congestion signal)
+    pub in_flight: AtomicU64,
+    // Woken whenever an in-flight call ends, for requests queued on a full provider
+    pub capacity_freed: Notify,
+    // Rate-limit quota last reported by the provider's response headers
+    pub quota: QuotaTracker,
+    // Spend on this provider in micro-dollars, so it can be accumulated atomically
+    pub cost_micros: AtomicU64,
+    // Recent successful completions per second (throughput actually delivered)
+    pub goodput: GoodputTracker,
+    // Outcomes of recent calls (see `error_rate`)
+    pub errors: ErrorRateTracker,
+    // ProviderConfig::breaker_error_rate in thousandths; 0 when unset
+    breaker_error_rate_permille: AtomicU32,
+    // Set while the provider's active health check (if any) is failing
+    pub health_check_failing: AtomicBool,
+    // Set by an operator to take the provider out of rotation (see Router::set_enabled)
+    pub draining: AtomicBool,
+    // breaker::now_millis() of the last health check started; 0 before the first
+    pub last_health_check_ms: AtomicU64,
+    // breaker::now_millis() of the last successful call; 0 before the first
+    pub last_success_ms: AtomicU64,
+    // breaker::now_millis() until which the provider sits out after a 429; 0 when it never did
+    pub throttled_until_ms: AtomicU64,
+    // breaker::now_millis() until which outlier detection keeps the provider out; 0 when never
+    pub ejected_until_ms: AtomicU64,
+    // Latency distribution (microseconds) backing p50/p99. Only locked to record a sample
+    // and to refresh the percentile atomics, never on the routing path.
+    latency_histogram: Mutex<Histogram<u64>>,
+}
+
+// Counters of one provider as of a single instant, for reporting (see ProviderStats::snapshot).
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct StatsSnapshot {
+    pub request_count: u64,
+    pub error_count: u64,
+    pub client_error_count: u64,
+    pub in_flight: u64,
+    pub consec_errors: u32,
+    pub ewma_latency_us: u64,
+    pub ewma_queue_wait_us: u64,
+    pub ewma_ttft_us: u64,
+    pub p50_latency_us: u64,
+    pub p99_latency_us: u64,
+    pub cost_usd: f64,
+}
+
+// Error rates are capped here when scoring, so a provider failing every call still gets a
+// finite score.
+pub const MAX_SCORED_ERROR_RATE: f64 = 0.9;
+// Percentile atomics are refreshed every this many samples (and on every `percentiles()` call).
+const PERCENTILE_REFRESH_INTERVAL: u64 = 16;
+// Collect attempts before a snapshot settles for the last read under heavy write traffic.
+const SNAPSHOT_ATTEMPTS: usize = 8;
+// Histogram range: 1µs .. 5 minutes, 2 significant digits (~1% error)
+const HISTOGRAM_MAX_US: u64 = 300_000_000;
+// How long a provider sits out after a 429 without `Retry-After`, and the most it sits out
+// with one.
+const DEFAULT_THROTTLE_MS: u64 = 5_000;
+const MAX_THROTTLE_MS: u64 = 60_000;
+
+// Decrements `in_flight` when dropped, so the count stays correct on every exit path
+// including errors and cancelled requests.
+#[derive(Debug)]
+pub struct InFlightGuard {
+    stats: Arc<ProviderStats>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.stats.capacity_freed.notify_waiters();
+    }
+}
+
+impl Default for ProviderStats {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ProviderStats {
@@ -19,53 +117,384 @@ impl ProviderStats {
         Self {
             request_count: AtomicU64::new(0),
             error_count: AtomicU64::new(0),
+            client_error_count: AtomicU64::new(0),
             p50_latency_us: AtomicU64::new(0),
             p99_latency_us: AtomicU64::new(0),
-            ewma_latency_us: AtomicU64::new(0),
+            ewma_latency_us: Ewma::new(),
+            ewma_queue_wait_us: Ewma::new(),
+            ewma_ttft_us: Ewma::new(),
             consec_errors: AtomicU32::new(0),
+            breaker: CircuitBreaker::new(),
+            in_flight: AtomicU64::new(0),
+            capacity_freed: Notify::new(),
+            quota: QuotaTracker::new(),
+            cost_micros: AtomicU64::new(0),
+            goodput: GoodputTracker::new(),
+            errors: ErrorRateTracker::new(),
+            breaker_error_rate_permille: AtomicU32::new(0),
+            health_check_failing: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            last_health_check_ms: AtomicU64::new(0),
+            last_success_ms: AtomicU64::new(0),
+            throttled_until_ms: AtomicU64::new(0),
+            ejected_until_ms: AtomicU64::new(0),
+            latency_histogram: Mutex::new(
+                Histogram::new_with_bounds(1, HISTOGRAM_MAX_US, 2).expect("valid histogram bounds"),
+            ),
+        }
+    }
+
+    // (p50, p99) latency in microseconds over all recorded successes.
+    pub fn percentiles(&self) -> (u64, u64) {
+        match self.latency_histogram.lock() {
+            Ok(hist) => self.refresh_percentiles(&hist),
+            Err(_) => (
+                self.p50_latency_us.load(Ordering::Relaxed),
+                self.p99_latency_us.load(Ordering::Relaxed),
+            ),
+        }
+    }
+
+    // Applies the tuning knobs from the provider's config; called again on reload, since
+    // stats outlive config changes.
+    pub fn configure(&self, config: &ProviderConfig) {
+        let alpha = config.ewma_alpha.unwrap_or(ewma::DEFAULT_ALPHA);
+        self.ewma_latency_us.set_alpha(alpha);
+        self.ewma_queue_wait_us.set_alpha(alpha);
+        self.ewma_ttft_us.set_alpha(alpha);
+        let permille = config.breaker_error_rate.map_or(0, |rate| (rate.clamp(0.0, 1.0) * 1000.0).round() as u32);
+        self.breaker_error_rate_permille.store(permille, Ordering::Relaxed);
+    }
+
+    // Fraction of calls that failed over the last few seconds; 0 until there are enough
+    // calls to tell (see ErrorRateTracker).
+    pub fn error_rate(&self) -> f64 {
+        self.errors.rate_at(breaker::now_millis()).unwrap_or(0.0)
+    }
+
+    pub fn record_cost(&self, cost_usd: f64) {
+        let micros = (cost_usd * 1_000_000.0).round();
+        if micros > 0.0 {
+            self.cost_micros.fetch_add(micros as u64, Ordering::Relaxed);
+        }
+    }
+
+    // All counters read together. The monotonic ones (requests, errors, cost) are collected
+    // until two reads in a row agree, so calls landing mid-read don't leave them describing
+    // different moments. Gauges and EWMAs are read once alongside.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let collect = || {
+            [
+                self.request_count.load(Ordering::Acquire),
+                self.error_count.load(Ordering::Acquire),
+                self.client_error_count.load(Ordering::Acquire),
+                self.cost_micros.load(Ordering::Acquire),
+            ]
+        };
+        let mut counters = collect();
+        for _ in 1..SNAPSHOT_ATTEMPTS {
+            let again = collect();
+            if again == counters {
+                break;
+            }
+            counters = again;
+        }
+        let [request_count, error_count, client_error_count, cost_micros] = counters;
+        let (p50_latency_us, p99_latency_us) = self.percentiles();
+        StatsSnapshot {
+            request_count,
+            error_count,
+            client_error_count,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            consec_errors: self.consec_errors.load(Ordering::Relaxed),
+            ewma_latency_us: self.ewma_latency_us.value().round() as u64,
+            ewma_queue_wait_us: self.ewma_queue_wait_us.value().round() as u64,
+            ewma_ttft_us: self.ewma_ttft_us.value().round() as u64,
+            p50_latency_us,
+            p99_latency_us,
+            cost_usd: cost_micros as f64 / 1_000_000.0,
+        }
+    }
+
+    // Cumulative spend in USD since startup.
+    pub fn total_cost(&self) -> f64 {
+        self.cost_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    }
+
+    fn refresh_percentiles(&self, hist: &Histogram<u64>) -> (u64, u64) {
+        let p50 = hist.value_at_quantile(0.50);
+        let p99 = hist.value_at_quantile(0.99);
+        self.p50_latency_us.store(p50, Ordering::Relaxed);
+        self.p99_latency_us.store(p99, Ordering::Relaxed);
+        (p50, p99)
+    }
+
+    pub fn track_in_flight(self: &Arc<Self>) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { stats: self.clone() }
+    }
+
+    // Like `track_in_flight`, but refuses once `limit` requests are already in flight.
+    // The slot is released by the guard's Drop, so it can't leak on error, timeout or a
+    // cancelled (dropped) request future.
+    pub fn try_track_in_flight(self: &Arc<Self>, limit: Option<u64>) -> Option<InFlightGuard> {
+        let Some(limit) = limit else { return Some(self.track_in_flight()) };
+        let mut current = self.in_flight.load(Ordering::Relaxed);
+        loop {
+            if current >= limit {
+                return None;
+            }
+            match self.in_flight.compare_exchange_weak(current, current + 1, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => return Some(InFlightGuard { stats: self.clone() }),
+                Err(x) => current = x,
+            }
         }
     }
 
     pub fn record_success(&self, latency: Duration) {
-        self.request_count.fetch_add(1, Ordering::Relaxed);
+        let count = self.request_count.fetch_add(1, Ordering::Relaxed) + 1;
         self.consec_errors.store(0, Ordering::Relaxed);
-        
+        let now = breaker::now_millis();
+        self.breaker.on_success_at(now);
+        self.last_success_ms.store(now.max(1), Ordering::Relaxed);
+        self.goodput.record_at(now);
+        self.errors.record_at(now, false);
+
         let latency_us = latency.as_micros() as u64;
-        
-        // Update EWMA: New = Alpha * sample + (1 - Alpha) * Old
-        // Using Alpha = 0.2 approx? For simple atomic, we might need a spin loop or just relaxed approximation.
-        // Let's implement a simple relaxed update for now. 
-        // This is a simplification; for strict EWMA we need f64 or fixed point arithmetic.
-        // Here we use integer math: new_avg = (old_avg * 7 + new_val) / 8  (Alpha = 1/8 = 0.125)
-        
-        let mut old = self.ewma_latency_us.load(Ordering::Relaxed);
-        loop {
-            let new_val = if old == 0 {
-                 latency_us 
-            } else {
-                (old * 7 + latency_us) / 8
-            };
-            
-            match self.ewma_latency_us.compare_exchange_weak(old, new_val, Ordering::Relaxed, Ordering::Relaxed) {
-                Ok(_) => break,
-                Err(x) => old = x,
+        self.ewma_latency_us.record(latency_us);
+
+        if let Ok(mut hist) = self.latency_histogram.lock() {
+            hist.saturating_record(latency_us.max(1));
+            if count % PERCENTILE_REFRESH_INTERVAL == 1 {
+                self.refresh_percentiles(&hist);
             }
         }
     }
 
-    pub fn record_failure(&self) {
+    // Queue wait is tracked separately from service time so slow dispatch (gateway-side
+    // contention) can be told apart from slow providers.
+    pub fn record_queue_wait(&self, wait: Duration) {
+        self.ewma_queue_wait_us.record(wait.as_micros() as u64);
+    }
+
+    // Time to first token of a stream, tracked apart from the total latency `record_success`
+    // gets once the stream ends.
+    pub fn record_ttft(&self, ttft: Duration) {
+        self.ewma_ttft_us.record(ttft.as_micros() as u64);
+    }
+
+    pub fn record_failure(&self, error: &ProviderError) {
         self.error_count.fetch_add(1, Ordering::Relaxed);
-        self.consec_errors.fetch_add(1, Ordering::Relaxed);
+        let now = breaker::now_millis();
+        if error.is_rate_limited() {
+            // Throttled, not broken: skipped by routing for a while instead of counting
+            // toward the breaker. A half-open probe answered with 429 still closes it.
+            let pause_ms = error.retry_after().map_or(DEFAULT_THROTTLE_MS, |d| d.as_millis() as u64).min(MAX_THROTTLE_MS);
+            self.throttled_until_ms.fetch_max(now + pause_ms, Ordering::Relaxed);
+            self.breaker.on_success_at(now);
+            return;
+        }
+        if !error.is_provider_fault() {
+            // The provider answered, so it's reachable: a half-open probe ending in a 4xx
+            // still closes the breaker instead of leaving the probe claimed.
+            self.client_error_count.fetch_add(1, Ordering::Relaxed);
+            self.errors.record_at(now, false);
+            self.breaker.on_success_at(now);
+            return;
+        }
+        self.errors.record_at(now, true);
+        let consec = self.consec_errors.fetch_add(1, Ordering::Relaxed) + 1;
+        let trip = consec >= breaker::DEFAULT_FAILURE_THRESHOLD || self.error_rate_exceeded(now);
+        self.breaker.on_failure_at(now, trip);
+    }
+
+    pub fn is_throttled_at(&self, now_ms: u64) -> bool {
+        self.throttled_until_ms.load(Ordering::Relaxed) > now_ms
+    }
+
+    pub fn is_ejected_at(&self, now_ms: u64) -> bool {
+        self.ejected_until_ms.load(Ordering::Relaxed) > now_ms
+    }
+
+    fn error_rate_exceeded(&self, now_ms: u64) -> bool {
+        let permille = self.breaker_error_rate_permille.load(Ordering::Relaxed);
+        permille > 0 && self.errors.rate_at(now_ms).is_some_and(|rate| rate * 1000.0 > permille as f64)
     }
     
     pub fn score(&self) -> f64 {
         // Lower is better.
         // Score = EWMA_Latency * (1 + Error_Rate_Penalty)
         // Simplistic example.
-        let l = self.ewma_latency_us.load(Ordering::Relaxed) as f64;
+        let l = self.ewma_latency_us.value();
         let e = self.consec_errors.load(Ordering::Relaxed) as f64;
         
-        // Massive penalty for consecutive errors to trigger circuit breaking logic elsewhere
-        l * (1.0 + e * 10.0) 
+        // Massive penalty for consecutive errors to trigger circuit breaking logic elsewhere,
+        // scaled by the expected attempts per success at the recent error rate
+        l * (1.0 + e * 10.0) / (1.0 - self.error_rate().min(MAX_SCORED_ERROR_RATE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balancer::breaker::BreakerState;
+
+    fn within(estimate: u64, expected: u64, tolerance: f64) -> bool {
+        (estimate as f64 - expected as f64).abs() <= expected as f64 * tolerance
+    }
+
+    #[test]
+    fn percentiles_track_the_latency_distribution() {
+        let stats = ProviderStats::new();
+        assert_eq!(stats.percentiles(), (0, 0));
+
+        // 1ms .. 1000ms, one sample each: p50 ~500ms, p99 ~990ms
+        for ms in 1..=1_000 {
+            stats.record_success(Duration::from_millis(ms));
+        }
+        let (p50, p99) = stats.percentiles();
+        assert!(within(p50, 500_000, 0.02), "p50 {p50}");
+        assert!(within(p99, 990_000, 0.02), "p99 {p99}");
+        assert_eq!(stats.p50_latency_us.load(Ordering::Relaxed), p50);
+        assert_eq!(stats.p99_latency_us.load(Ordering::Relaxed), p99);
+    }
+
+    #[test]
+    fn a_slow_tail_moves_p99_but_not_p50() {
+        let stats = ProviderStats::new();
+        for i in 0..1_000 {
+            let ms = if i % 50 == 0 { 2_000 } else { 20 };
+            stats.record_success(Duration::from_millis(ms));
+        }
+        let (p50, p99) = stats.percentiles();
+        assert!(within(p50, 20_000, 0.02), "p50 {p50}");
+        assert!(within(p99, 2_000_000, 0.02), "p99 {p99}");
+    }
+
+    #[test]
+    fn client_errors_do_not_trip_the_breaker() {
+        let stats = ProviderStats::new();
+        let status = |status| ProviderError::Status { status, retry_after_ms: None };
+        for _ in 0..50 {
+            stats.record_failure(&status(400));
+        }
+        assert_eq!(stats.breaker.state_at(breaker::now_millis(), breaker::DEFAULT_COOLDOWN_MS), BreakerState::Closed);
+        assert_eq!(stats.client_error_count.load(Ordering::Relaxed), 50);
+        assert_eq!(stats.consec_errors.load(Ordering::Relaxed), 0);
+
+        for _ in 0..breaker::DEFAULT_FAILURE_THRESHOLD {
+            stats.record_failure(&status(503));
+        }
+        assert_eq!(stats.breaker.state_at(breaker::now_millis(), breaker::DEFAULT_COOLDOWN_MS), BreakerState::Open);
+        assert_eq!(stats.client_error_count.load(Ordering::Relaxed), 50);
+    }
+
+    #[test]
+    fn rejected_credentials_trip_the_breaker() {
+        for status in [401, 403] {
+            let stats = ProviderStats::new();
+            for _ in 0..breaker::DEFAULT_FAILURE_THRESHOLD {
+                stats.record_failure(&ProviderError::Status { status, retry_after_ms: None });
+            }
+            assert_eq!(stats.breaker.state_at(breaker::now_millis(), breaker::DEFAULT_COOLDOWN_MS), BreakerState::Open);
+            assert_eq!(stats.client_error_count.load(Ordering::Relaxed), 0);
+        }
+    }
+
+    fn alternate(stats: &ProviderStats, calls: usize) {
+        for i in 0..calls {
+            if i % 2 == 0 {
+                stats.record_success(Duration::from_millis(100));
+            } else {
+                stats.record_failure(&ProviderError::Status { status: 500, retry_after_ms: None });
+            }
+        }
+    }
+
+    #[test]
+    fn alternating_failures_show_in_the_error_rate() {
+        let stats = ProviderStats::new();
+        alternate(&stats, 20);
+        assert_eq!(stats.error_rate(), 0.5);
+        // Never two failures in a row, so the consecutive count alone would look healthy
+        assert_eq!(stats.breaker.state_at(breaker::now_millis(), breaker::DEFAULT_COOLDOWN_MS), BreakerState::Closed);
+
+        let clean = ProviderStats::new();
+        for _ in 0..20 {
+            clean.record_success(Duration::from_millis(100));
+        }
+        assert_eq!(clean.error_rate(), 0.0);
+        assert!(stats.score() > clean.score());
+    }
+
+    #[test]
+    fn a_high_error_rate_trips_the_breaker_when_configured() {
+        let stats = ProviderStats::new();
+        stats.configure(&ProviderConfig { breaker_error_rate: Some(0.3), ..ProviderConfig::default() });
+        alternate(&stats, 20);
+        assert_eq!(stats.breaker.state_at(breaker::now_millis(), breaker::DEFAULT_COOLDOWN_MS), BreakerState::Open);
+    }
+
+    #[test]
+    fn rate_limits_pause_for_the_retry_after_without_tripping() {
+        let stats = ProviderStats::new();
+        let now = breaker::now_millis();
+        for _ in 0..2 * breaker::DEFAULT_FAILURE_THRESHOLD {
+            stats.record_failure(&ProviderError::Status { status: 429, retry_after_ms: Some(2_000) });
+        }
+        assert_eq!(stats.breaker.state_at(breaker::now_millis(), breaker::DEFAULT_COOLDOWN_MS), BreakerState::Closed);
+        assert_eq!(stats.consec_errors.load(Ordering::Relaxed), 0);
+        assert!(stats.is_throttled_at(now + 1_900));
+        assert!(!stats.is_throttled_at(now + 3_000));
+
+        // Without a Retry-After the default pause applies; a huge one is capped
+        let stats = ProviderStats::new();
+        stats.record_failure(&ProviderError::Status { status: 429, retry_after_ms: None });
+        assert!(stats.is_throttled_at(now + DEFAULT_THROTTLE_MS - 1_000));
+        stats.record_failure(&ProviderError::Status { status: 429, retry_after_ms: Some(3_600_000) });
+        assert!(!stats.is_throttled_at(now + MAX_THROTTLE_MS + 1_000));
+    }
+
+    #[test]
+    fn snapshots_stay_consistent_under_concurrent_updates() {
+        let stats = Arc::new(ProviderStats::new());
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let stats = stats.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..2_000 {
+                        if i % 2 == 0 {
+                            stats.record_success(Duration::from_millis(5));
+                            stats.record_cost(0.001);
+                        } else {
+                            stats.record_failure(&ProviderError::Status { status: 400, retry_after_ms: None });
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // Counters never run backwards between snapshots, whichever moment each describes
+        let mut last = stats.snapshot();
+        while !writers.iter().all(|w| w.is_finished()) {
+            let next = stats.snapshot();
+            assert!(next.request_count >= last.request_count);
+            assert!(next.error_count >= last.error_count);
+            assert!(next.client_error_count >= last.client_error_count);
+            assert!(next.cost_usd >= last.cost_usd);
+            assert!(next.request_count + next.error_count <= 8_000);
+            last = next;
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let settled = stats.snapshot();
+        assert_eq!((settled.request_count, settled.error_count, settled.client_error_count), (4_000, 4_000, 4_000));
+        assert!((settled.cost_usd - 4.0).abs() < 1e-9);
+        assert_eq!(settled.in_flight, 0);
+        let json = serde_json::to_value(settled).unwrap();
+        assert_eq!(json["request_count"], 4_000);
+        assert_eq!(json["ewma_latency_us"], 5_000);
     }
 }
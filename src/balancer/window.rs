@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Length of the sliding window, in one-second buckets.
+pub const WINDOW_SECS: u64 = 10;
+
+#[derive(Debug, Default)]
+struct Bucket {
+    // Second (breaker::now_millis() / 1000) the count belongs to
+    second: AtomicU64,
+    count: AtomicU64,
+}
+
+// Events over the last WINDOW_SECS, lock-free. A bucket is reset by whichever writer first
+// sees it belongs to an older second; an event racing that reset can be lost, which only
+// makes the count a little low.
+#[derive(Debug, Default)]
+pub struct WindowCounter {
+    buckets: [Bucket; WINDOW_SECS as usize],
+}
+
+impl WindowCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_at(&self, now_ms: u64) {
+        let second = now_ms / 1000;
+        let bucket = &self.buckets[(second % WINDOW_SECS) as usize];
+        let seen = bucket.second.load(Ordering::Acquire);
+        if seen != second && bucket.second.compare_exchange(seen, second, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            bucket.count.store(0, Ordering::Release);
+        }
+        bucket.count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // Takes back an event recorded this second. If the bucket has moved on since, the event
+    // already left the window or was reset away, and there is nothing to undo.
+    pub fn unrecord_at(&self, now_ms: u64) {
+        let second = now_ms / 1000;
+        let bucket = &self.buckets[(second % WINDOW_SECS) as usize];
+        if bucket.second.load(Ordering::Acquire) == second {
+            let _ = bucket.count.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+        }
+    }
+
+    pub fn count_at(&self, now_ms: u64) -> u64 {
+        let second = now_ms / 1000;
+        self.buckets
+            .iter()
+            .filter(|b| second.saturating_sub(b.second.load(Ordering::Acquire)) < WINDOW_SECS)
+            .map(|b| b.count.load(Ordering::Acquire))
+            .sum()
+    }
+}
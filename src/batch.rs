@@ -0,0 +1,139 @@
+use crate::error::ApiError;
+use crate::gateway::{logged_completion, AppState};
+use crate::model::LlmRequest;
+use crate::rate_limit::{rate_limited, ClientBucket};
+use axum::{
+    extract::{rejection::JsonRejection, Extension, Json, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+// Outcome of one batch item: the completion on success, the error object otherwise.
+#[derive(Debug, Serialize)]
+pub struct BatchItem {
+    pub index: usize,
+    pub status: u16,
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+// Several chat completions in one call. Each item goes through the same pipeline as a
+// request to `/v1/chat/completions` (validation, cache, routing, failover), at most
+// `batch_concurrency` at a time, and fails on its own; results come back in request order.
+// Under client rate limiting every item counts as a request, all taken before any runs.
+pub async fn handle_batch_completions(
+    State(state): State<Arc<AppState>>,
+    bucket: Option<Extension<ClientBucket>>,
+    headers: HeaderMap,
+    payload: Result<Json<Vec<Value>>, JsonRejection>,
+) -> Response {
+    let Json(items) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => {
+            return ApiError::new(rejection.status(), "invalid_request_error", "invalid_body", rejection.body_text()).into_response();
+        }
+    };
+    let max_items = state.options.max_batch_items;
+    if items.len() > max_items {
+        let message = format!("Batch has {} items, the limit is {}", items.len(), max_items);
+        return ApiError::invalid_request("batch_too_large", message).into_response();
+    }
+    // The request itself took the first item's token
+    if let Some(Extension(bucket)) = bucket.filter(|_| items.len() > 1) {
+        if items.len() > bucket.burst() as usize {
+            let message = format!("Batch has {} items, more than the rate limit burst of {}", items.len(), bucket.burst());
+            return ApiError::invalid_request("batch_too_large", message).into_response();
+        }
+        if let Err(wait) = bucket.try_acquire(items.len() as u32 - 1) {
+            return rate_limited(wait);
+        }
+    }
+
+    let concurrency = state.options.batch_concurrency.max(1);
+    let results: Vec<BatchItem> = stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| {
+            let (state, headers) = (state.clone(), &headers);
+            async move { run_item(state, headers, index, item).await }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+    Json(results).into_response()
+}
+
+async fn run_item(state: Arc<AppState>, headers: &HeaderMap, index: usize, item: Value) -> BatchItem {
+    let response = match serde_json::from_value::<LlmRequest>(item) {
+        Ok(req) if req.is_streaming() => {
+            ApiError::invalid_request("stream_not_supported", "Batch items cannot stream").into_response()
+        }
+        Ok(req) => logged_completion(state, headers, Ok(Json(req))).await,
+        Err(e) => ApiError::invalid_request("invalid_body", e.to_string()).into_response(),
+    };
+
+    let status = response.status();
+    let cached = response.headers().get("x-cache").is_some_and(|v| v == "HIT");
+    let body = match axum::body::to_bytes(response.into_body(), usize::MAX).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        Err(_) => Value::Null,
+    };
+    let (response, error) = if status.is_success() {
+        (Some(body), None)
+    } else {
+        (None, Some(body.get("error").cloned().unwrap_or(body)))
+    };
+    BatchItem { index, status: status.as_u16(), cached, response, error }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::{limit_clients, ClientRateLimit, RateLimiter};
+    use crate::test_support::{self, MockUpstream};
+    use axum::{middleware, routing::post};
+
+    async fn serve(upstream: &MockUpstream, burst: u32) -> String {
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let limiter = Arc::new(RateLimiter::new(ClientRateLimit { requests_per_sec: 0.001, burst }));
+        let app = axum::Router::new()
+            .route("/batch", post(handle_batch_completions))
+            .route_layer(middleware::from_fn_with_state(limiter, limit_clients))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/batch", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    async fn send(url: &str, batch: &str, items: usize) -> reqwest::StatusCode {
+        let items: Vec<Value> = (0..items).map(|i| serde_json::json!({"model": "m", "prompt": format!("{batch} {i}")})).collect();
+        reqwest::Client::new().post(url).json(&items).send().await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn every_item_is_charged_to_the_rate_limit() {
+        let upstream = MockUpstream::start().await;
+        let url = serve(&upstream, 5).await;
+        assert_eq!(send(&url, "a", 3).await, reqwest::StatusCode::OK);
+        // 2 tokens left: a 3-item batch is refused as a whole, though its request spends one
+        assert_eq!(send(&url, "b", 3).await, reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(upstream.calls(), 3);
+        assert_eq!(send(&url, "c", 1).await, reqwest::StatusCode::OK);
+        assert_eq!(send(&url, "d", 1).await, reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(upstream.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn batches_larger_than_the_burst_are_rejected() {
+        let upstream = MockUpstream::start().await;
+        let url = serve(&upstream, 2).await;
+        assert_eq!(send(&url, "a", 3).await, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(upstream.calls(), 0);
+    }
+}
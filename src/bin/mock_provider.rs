@@ -1,14 +1,88 @@
 use axum::{routing::post, Router, Json, extract::State};
+use axum::response::{IntoResponse, Response, sse::{Event, Sse}};
+use std::convert::Infallible;
 use serde_json::Value;
 use std::net::SocketAddr;
 use std::time::Duration;
 use rand::Rng;
 use tokio::time::sleep;
+use futures::StreamExt;
 
 #[derive(Clone)]
 struct ServerConfig {
     latency_ms: u64,
     error_rate: f64,
+    failure: Failure,
+    prompt_tokens: PromptTokens,
+    completion_tokens: u64,
+}
+
+// What an injected failure looks like: an HTTP status, or a request that never completes
+// (a dead provider, for exercising timeouts).
+#[derive(Clone, Copy)]
+enum Failure {
+    Status(u16),
+    Hang,
+}
+
+impl Failure {
+    fn parse(arg: &str) -> Self {
+        if arg == "hang" {
+            Failure::Hang
+        } else {
+            Failure::Status(arg.parse().unwrap())
+        }
+    }
+
+    async fn respond(self) -> Response {
+        match self {
+            Failure::Hang => std::future::pending().await,
+            Failure::Status(code) => {
+                let status = axum::http::StatusCode::from_u16(code).unwrap();
+                let body = Json(serde_json::json!({"error": "simulated failure"}));
+                if status == axum::http::StatusCode::TOO_MANY_REQUESTS {
+                    (status, [(axum::http::header::RETRY_AFTER, "1")], body).into_response()
+                } else {
+                    (status, body).into_response()
+                }
+            }
+        }
+    }
+}
+
+// Reported `prompt_tokens`: a fixed count, or "echo" to size the incoming prompt instead.
+#[derive(Clone, Copy)]
+enum PromptTokens {
+    Fixed(u64),
+    Echo,
+}
+
+impl PromptTokens {
+    fn parse(arg: &str) -> Self {
+        if arg == "echo" {
+            PromptTokens::Echo
+        } else {
+            PromptTokens::Fixed(arg.parse().unwrap())
+        }
+    }
+
+    fn count(self, req: &Value) -> u64 {
+        match self {
+            PromptTokens::Fixed(n) => n,
+            // Roughly 4 characters per token, like most BPE tokenizers on English text
+            PromptTokens::Echo => {
+                let chars: usize = req
+                    .get("messages")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|m| m.get("content").and_then(Value::as_str))
+                    .map(|c| c.chars().count())
+                    .sum();
+                chars.div_ceil(4) as u64
+            }
+        }
+    }
 }
 
 #[tokio::main]
@@ -16,31 +90,44 @@ async fn main() {
     let args: Vec<String> = std::env::args().collect();
     let port = args.get(1).unwrap_or(&"3000".to_string()).parse::<u16>().unwrap();
     let latency_ms = args.get(2).unwrap_or(&"500".to_string()).parse::<u64>().unwrap();
-    let error_rate = args.get(3).unwrap_or(&"0.0".to_string()).parse::<f64>().unwrap();
+    // <error_rate>[:<failure>], e.g. "0.2:429" or "0.05:hang"; failures are 500s by default
+    let error_arg = args.get(3).map(String::as_str).unwrap_or("0.0");
+    let (error_rate, failure_arg) = error_arg.split_once(':').unwrap_or((error_arg, "500"));
+    let error_rate = error_rate.parse::<f64>().unwrap();
+    let failure = Failure::parse(failure_arg);
+    let prompt_tokens = PromptTokens::parse(args.get(4).map(String::as_str).unwrap_or("10"));
+    let completion_tokens = args.get(5).unwrap_or(&"10".to_string()).parse::<u64>().unwrap();
 
-    let config = ServerConfig { latency_ms, error_rate };
+    let config = ServerConfig { latency_ms, error_rate, failure, prompt_tokens, completion_tokens };
     
     let app = Router::new()
         .route("/chat/completions", post(handler))
         .with_state(config);
 
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    println!("Mock Provider running on localhost:{}. Latency: {}ms, Error Rate: {}", port, latency_ms, error_rate);
+    println!("Mock Provider running on localhost:{}. Latency: {}ms, Error Rate: {} ({})", port, latency_ms, error_rate, failure_arg);
     
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
-async fn handler(State(config): State<ServerConfig>, Json(_req): Json<Value>) -> (axum::http::StatusCode, Json<Value>) {
+const MOCK_CONTENT: &str = "Hello! This is a mock response from the provider.";
+
+async fn handler(State(config): State<ServerConfig>, Json(req): Json<Value>) -> Response {
     // Simulate Latency
     let jitter = rand::thread_rng().gen_range(0..=20);
     sleep(Duration::from_millis(config.latency_ms + jitter)).await;
 
     // Simulate Error
     if config.error_rate > 0.0 && rand::thread_rng().gen_bool(config.error_rate) {
-        return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "simulated failure"})));
+        return config.failure.respond().await;
+    }
+
+    if req.get("stream").and_then(Value::as_bool).unwrap_or(false) {
+        return stream_response().into_response();
     }
 
+    let prompt_tokens = config.prompt_tokens.count(&req);
     (axum::http::StatusCode::OK, Json(serde_json::json!({
         "id": "mock-response",
         "object": "chat.completion",
@@ -49,14 +136,86 @@ async fn handler(State(config): State<ServerConfig>, Json(_req): Json<Value>) ->
             "index": 0,
             "message": {
                 "role": "assistant",
-                "content": "Hello! This is a mock response from the provider."
+                "content": MOCK_CONTENT
             },
             "finish_reason": "stop"
         }],
         "usage": {
-            "prompt_tokens": 10,
-            "completion_tokens": 10,
-            "total_tokens": 20
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": config.completion_tokens,
+            "total_tokens": prompt_tokens + config.completion_tokens
+        }
+    }))).into_response()
+}
+
+// Streams the mock content word by word as OpenAI-style SSE chunks.
+fn stream_response() -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+    let mut events: Vec<Event> = MOCK_CONTENT
+        .split_inclusive(' ')
+        .map(|word| {
+            Event::default().data(serde_json::json!({
+                "id": "mock-response",
+                "object": "chat.completion.chunk",
+                "choices": [{ "index": 0, "delta": { "content": word } }]
+            }).to_string())
+        })
+        .collect();
+    events.push(Event::default().data("[DONE]"));
+
+    let stream = futures::stream::iter(events).then(|event| async move {
+        sleep(Duration::from_millis(5)).await;
+        Ok(event)
+    });
+    Sse::new(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(prompt_tokens: PromptTokens, completion_tokens: u64) -> ServerConfig {
+        ServerConfig { latency_ms: 0, error_rate: 0.0, failure: Failure::Status(500), prompt_tokens, completion_tokens }
+    }
+
+    async fn usage(config: ServerConfig, req: Value) -> Value {
+        let response = handler(State(config), Json(req)).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice::<Value>(&body).unwrap()["usage"].clone()
+    }
+
+    #[tokio::test]
+    async fn usage_reports_the_configured_token_counts() {
+        let req = serde_json::json!({"messages": [{"role": "user", "content": "12345678"}]});
+        let default = config(PromptTokens::parse("10"), 10);
+        assert_eq!(usage(default, req.clone()).await, serde_json::json!({"prompt_tokens": 10, "completion_tokens": 10, "total_tokens": 20}));
+
+        let configured = config(PromptTokens::parse("300"), 50);
+        assert_eq!(usage(configured, req.clone()).await["total_tokens"], 350);
+
+        // 8 characters of prompt, at ~4 per token
+        let echoed = usage(config(PromptTokens::parse("echo"), 5), req).await;
+        assert_eq!((echoed["prompt_tokens"].as_u64(), echoed["total_tokens"].as_u64()), (Some(2), Some(7)));
+    }
+
+    #[tokio::test]
+    async fn injected_failures_use_the_chosen_status() {
+        for (arg, status) in [("500", 500), ("429", 429), ("503", 503)] {
+            let response = Failure::parse(arg).respond().await;
+            assert_eq!(response.status().as_u16(), status);
         }
-    })))
+        // Throttling tells the client when to come back
+        let throttled = Failure::parse("429").respond().await;
+        assert_eq!(throttled.headers()[axum::http::header::RETRY_AFTER], "1");
+
+        let failing = ServerConfig { error_rate: 1.0, failure: Failure::parse("503"), ..config(PromptTokens::Fixed(10), 10) };
+        let response = handler(State(failing), Json(serde_json::json!({}))).await;
+        assert_eq!(response.status().as_u16(), 503);
+    }
+
+    #[tokio::test]
+    async fn hang_mode_never_answers() {
+        let hanging = ServerConfig { error_rate: 1.0, failure: Failure::parse("hang"), ..config(PromptTokens::Fixed(10), 10) };
+        let answer = handler(State(hanging), Json(serde_json::json!({})));
+        assert!(tokio::time::timeout(Duration::from_millis(200), answer).await.is_err());
+    }
 }
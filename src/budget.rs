@@ -0,0 +1,137 @@
+use axum::http::StatusCode;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Spend ceiling per fixed window. Once a window's spend reaches `max_spend_usd`, cache misses
+// are rejected with `status` until the next window starts; cache hits are still served.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BudgetConfig {
+    pub max_spend_usd: f64,
+    // Windows are aligned to the Unix epoch, so the hourly default resets on the hour.
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    #[serde(default = "default_status")]
+    pub status: u16,
+}
+
+fn default_window_secs() -> u64 {
+    3600
+}
+
+fn default_status() -> u16 {
+    StatusCode::PAYMENT_REQUIRED.as_u16()
+}
+
+// Spend in the current window, in micro-dollars like ProviderStats::cost_micros.
+#[derive(Debug)]
+pub struct SpendBudget {
+    config: BudgetConfig,
+    window_ms: u64,
+    max_micros: u64,
+    window_start_ms: AtomicU64,
+    spent_micros: AtomicU64,
+}
+
+impl SpendBudget {
+    pub fn new(config: BudgetConfig) -> Self {
+        Self {
+            config,
+            window_ms: config.window_secs.max(1) * 1000,
+            max_micros: (config.max_spend_usd * 1_000_000.0).round().max(0.0) as u64,
+            window_start_ms: AtomicU64::new(0),
+            spent_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.config.status).unwrap_or(StatusCode::PAYMENT_REQUIRED)
+    }
+
+    pub fn record(&self, cost_usd: f64) {
+        self.record_at(unix_millis(), cost_usd)
+    }
+
+    pub fn record_at(&self, now_ms: u64, cost_usd: f64) {
+        self.roll_at(now_ms);
+        let micros = (cost_usd * 1_000_000.0).round();
+        if micros > 0.0 {
+            self.spent_micros.fetch_add(micros as u64, Ordering::Relaxed);
+        }
+    }
+
+    // Milliseconds until the window resets while the budget is spent; None while there is
+    // budget left.
+    pub fn exhausted(&self) -> Option<u64> {
+        self.exhausted_at(unix_millis())
+    }
+
+    pub fn exhausted_at(&self, now_ms: u64) -> Option<u64> {
+        let start = self.roll_at(now_ms);
+        (self.spent_micros.load(Ordering::Relaxed) >= self.max_micros).then(|| start + self.window_ms - now_ms)
+    }
+
+    pub fn spent_usd(&self) -> f64 {
+        self.spent_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    }
+
+    // Starts a new window when `now_ms` is past the current one. The thread that moves the
+    // window resets the accumulator; spend recorded in between may be lost, never doubled.
+    fn roll_at(&self, now_ms: u64) -> u64 {
+        let start = now_ms - now_ms % self.window_ms;
+        let current = self.window_start_ms.load(Ordering::Relaxed);
+        if current < start
+            && self
+                .window_start_ms
+                .compare_exchange(current, start, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.spent_micros.store(0, Ordering::Relaxed);
+        }
+        start
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockUpstream};
+    use axum::http::HeaderMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn spend_resets_at_window_boundaries() {
+        let budget = SpendBudget::new(BudgetConfig { max_spend_usd: 1.0, window_secs: 60, status: 402 });
+        budget.record_at(60_000, 0.6);
+        assert_eq!(budget.exhausted_at(61_000), None);
+        budget.record_at(62_000, 0.4);
+        assert_eq!(budget.exhausted_at(90_000), Some(30_000));
+        // The next window starts from nothing
+        assert_eq!(budget.exhausted_at(120_000), None);
+        assert_eq!(budget.spent_usd(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn misses_are_refused_past_the_budget_while_hits_are_served() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        // Each call costs $0.02 (10 tokens each way at $1/1k): the second one crosses it
+        state.budget = Some(SpendBudget::new(BudgetConfig { max_spend_usd: 0.03, window_secs: 3600, status: 402 }));
+        let state = Arc::new(state);
+        let send = |prompt: &str| test_support::complete(&state, HeaderMap::new(), test_support::request(prompt, serde_json::json!({})));
+
+        for prompt in ["one", "two"] {
+            assert_eq!(send(prompt).await.status(), StatusCode::OK);
+        }
+        assert!(state.cache.drain_writes(std::time::Duration::from_secs(1)).await);
+        assert_eq!(send("three").await.status(), StatusCode::PAYMENT_REQUIRED);
+        let cached = send("one").await;
+        assert_eq!(cached.status(), StatusCode::OK);
+        assert_eq!(cached.headers()["x-cache"], "HIT");
+        assert_eq!(upstream.calls(), 2);
+    }
+}
@@ -0,0 +1,316 @@
+use super::CacheKey;
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+
+// Turns a prompt into a vector whose cosine similarity tracks semantic similarity.
+// Boxed future rather than async fn so the cache can hold a `dyn Embedder`; any backend
+// (a hosted API, a local model, a fixture) plugs in through `SemanticCache::with_embedder`.
+pub trait Embedder: Send + Sync {
+    fn embed<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>, EmbedError>>;
+}
+
+// Why a prompt couldn't be embedded. The cache treats every variant the same (the prompt
+// is cached exact-only), so the split is for logs.
+#[derive(Debug, Clone, Error)]
+pub enum EmbedError {
+    #[error("request failed: {0}")]
+    Request(String),
+    #[error("HTTP {0}")]
+    Status(u16),
+    #[error("invalid response: {0}")]
+    Decode(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+// Which embedder backs `CacheMode::Embedding`.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbedderConfig {
+    // In-process hashed bag-of-words model; no network, no model files.
+    Local { dims: usize },
+    // OpenAI-compatible `/v1/embeddings` endpoint.
+    Http { endpoint: String, api_key: String, model: String },
+}
+
+impl Default for EmbedderConfig {
+    fn default() -> Self {
+        Self::Local { dims: DEFAULT_LOCAL_DIMS }
+    }
+}
+
+impl EmbedderConfig {
+    pub fn build(&self) -> Arc<dyn Embedder> {
+        match self {
+            Self::Local { dims } => Arc::new(HashingEmbedder::new(*dims)),
+            Self::Http { endpoint, api_key, model } => {
+                Arc::new(HttpEmbedder::new(endpoint.clone(), api_key.clone(), model.clone()))
+            }
+        }
+    }
+}
+
+pub const DEFAULT_LOCAL_DIMS: usize = 256;
+
+// Feature-hashing embedder: lowercased words and word bigrams are hashed into `dims`
+// signed buckets and the result L2-normalized. Catches rewordings that share vocabulary
+// (reordering, extra filler words) at a fraction of a model's cost; it has no notion of
+// synonyms, for which an HTTP embedder is needed.
+pub struct HashingEmbedder {
+    dims: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dims: usize) -> Self {
+        Self { dims: dims.max(1) }
+    }
+
+    pub fn embed_sync(&self, text: &str) -> Vec<f32> {
+        let lowered = text.to_lowercase();
+        let words: Vec<&str> = lowered
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        let mut vector = vec![0.0f32; self.dims];
+        let mut add = |feature: &[&str], weight: f32| {
+            let mut h = DefaultHasher::new();
+            feature.hash(&mut h);
+            let hash = h.finish();
+            let bucket = (hash % self.dims as u64) as usize;
+            // Sign bit keeps colliding features from always adding up
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[bucket] += sign * weight;
+        };
+        for word in &words {
+            add(std::slice::from_ref(word), 1.0);
+        }
+        for pair in words.windows(2) {
+            add(pair, 0.5);
+        }
+        normalize(&mut vector);
+        vector
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn embed<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>, EmbedError>> {
+        Box::pin(async move { Ok(self.embed_sync(text)) })
+    }
+}
+
+pub struct HttpEmbedder {
+    endpoint: String,
+    api_key: String,
+    model: String,
+    client: reqwest::Client,
+}
+
+impl HttpEmbedder {
+    pub fn new(endpoint: String, api_key: String, model: String) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_default();
+        Self { endpoint, api_key, model, client }
+    }
+}
+
+impl Embedder for HttpEmbedder {
+    fn embed<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>, EmbedError>> {
+        Box::pin(async move {
+            let body = serde_json::json!({ "model": self.model, "input": text });
+            let resp = self.client.post(&self.endpoint)
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&body)
+                .send()
+                .await
+                .map_err(|e| EmbedError::Request(e.to_string()))?;
+            if !resp.status().is_success() {
+                return Err(EmbedError::Status(resp.status().as_u16()));
+            }
+            let body: serde_json::Value = resp.json().await.map_err(|e| EmbedError::Decode(e.to_string()))?;
+            let mut vector: Vec<f32> = body
+                .pointer("/data/0/embedding")
+                .and_then(|v| v.as_array())
+                .ok_or_else(|| EmbedError::Decode("no /data/0/embedding".to_string()))?
+                .iter()
+                .filter_map(|x| x.as_f64().map(|f| f as f32))
+                .collect();
+            normalize(&mut vector);
+            Ok(vector)
+        })
+    }
+}
+
+// Deterministic embedder for tests and fixtures: texts registered with `with_vector` get
+// that vector, so two prompts given the same one are a guaranteed similarity hit; any other
+// text gets a one-hot vector picked by its hash, dissimilar to everything else (bar collisions
+// within `dims`). Texts registered with `with_failure` fail to embed.
+#[derive(Debug, Clone, Default)]
+pub struct MockEmbedder {
+    dims: usize,
+    vectors: HashMap<String, Vec<f32>>,
+    failures: Vec<String>,
+}
+
+impl MockEmbedder {
+    pub fn new(dims: usize) -> Self {
+        Self { dims: dims.max(1), ..Self::default() }
+    }
+
+    pub fn with_vector(mut self, text: impl Into<String>, mut vector: Vec<f32>) -> Self {
+        normalize(&mut vector);
+        self.vectors.insert(text.into(), vector);
+        self
+    }
+
+    pub fn with_failure(mut self, text: impl Into<String>) -> Self {
+        self.failures.push(text.into());
+        self
+    }
+}
+
+impl Embedder for MockEmbedder {
+    fn embed<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>, EmbedError>> {
+        let result = if self.failures.iter().any(|f| f == text) {
+            Err(EmbedError::Other(format!("mock failure for {:?}", text)))
+        } else if let Some(vector) = self.vectors.get(text) {
+            Ok(vector.clone())
+        } else {
+            let mut h = DefaultHasher::new();
+            text.hash(&mut h);
+            let mut vector = vec![0.0f32; self.dims];
+            vector[(h.finish() % self.dims as u64) as usize] = 1.0;
+            Ok(vector)
+        };
+        Box::pin(futures::future::ready(result))
+    }
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+// Cosine similarity of two vectors. Embedders normalize their output, so this is a dot
+// product; mismatched lengths (e.g. the embedder changed) compare as dissimilar.
+pub fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| (x * y) as f64).sum()
+}
+
+// Bounded index of recently cached prompts' embeddings, mapped to their cache keys.
+// Same shape as `NgramIndex`: a linear scan over recent entries, with evicted keys
+// missing harmlessly on fetch.
+pub struct VectorIndex {
+    // (namespace, features, cache key)
+    entries: RwLock<VecDeque<(String, Vec<f32>, CacheKey)>>,
+    capacity: usize,
+}
+
+impl VectorIndex {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: RwLock::new(VecDeque::with_capacity(capacity)), capacity }
+    }
+
+    pub fn insert(&self, namespace: &str, vector: Vec<f32>, key: CacheKey) {
+        let Ok(mut entries) = self.entries.write() else { return };
+        entries.retain(|(_, _, k)| *k != key);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((namespace.to_string(), vector, key));
+    }
+
+    // Key of the nearest prompt indexed under `namespace`, if its similarity reaches `threshold`.
+    pub fn best_match(&self, namespace: &str, vector: &[f32], threshold: f64) -> Option<CacheKey> {
+        let entries = self.entries.read().ok()?;
+        entries
+            .iter()
+            .filter(|(ns, _, _)| ns == namespace)
+            .map(|(_, v, k)| (cosine(vector, v), k))
+            .filter(|(score, _)| *score >= threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, k)| *k)
+    }
+
+    pub fn remove(&self, key: &CacheKey) {
+        if let Ok(mut entries) = self.entries.write() {
+            entries.retain(|(_, _, k)| k != key);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.write() {
+            entries.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheMode, SemanticCache};
+    use crate::test_support;
+
+    fn cache(embedder: MockEmbedder) -> SemanticCache {
+        SemanticCache::new(100, 60).with_mode(CacheMode::Embedding { threshold: 0.9 }).with_embedder(Arc::new(embedder))
+    }
+
+    #[tokio::test]
+    async fn the_mock_embedder_drives_similarity_hits() {
+        let embedder = MockEmbedder::new(16)
+            .with_vector("how do I reset my password", vec![1.0, 0.1, 0.0])
+            .with_vector("password reset steps?", vec![0.9, 0.2, 0.0])
+            .with_vector("what's the weather", vec![0.0, 0.0, 1.0]);
+        let cache = cache(embedder);
+        let ask = |prompt: &str| test_support::request(prompt, serde_json::json!({}));
+        cache.put(&ask("how do I reset my password"), test_support::response("a", "go to settings")).await;
+
+        assert_eq!(cache.get(&ask("password reset steps?")).await.unwrap().content, "go to settings");
+        assert!(cache.get(&ask("what's the weather")).await.is_none());
+        assert!(cache.get(&ask("unregistered prompt")).await.is_none());
+        // Similar, but for another model
+        let other_model = test_support::request("password reset steps?", serde_json::json!({"model": "other"}));
+        assert!(cache.get(&other_model).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_embeddings_leave_exact_matching_working() {
+        let cache = cache(MockEmbedder::new(16).with_failure("unembeddable"));
+        let req = test_support::request("unembeddable", serde_json::json!({}));
+        cache.put(&req, test_support::response("a", "stored anyway")).await;
+        assert_eq!(cache.get(&req).await.unwrap().content, "stored anyway");
+    }
+
+    #[tokio::test]
+    async fn the_http_embedder_reads_and_normalizes_openai_embeddings() {
+        let app = axum::Router::new().route(
+            "/v1/embeddings",
+            axum::routing::post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+                if body["input"] == "fail" {
+                    return Err(axum::http::StatusCode::TOO_MANY_REQUESTS);
+                }
+                Ok(axum::Json(serde_json::json!({"data": [{"embedding": [3.0, 4.0]}], "model": body["model"]})))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/embeddings", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let embedder = HttpEmbedder::new(endpoint, "key".to_string(), "text-embedding-3-small".to_string());
+        assert_eq!(embedder.embed("hi").await.unwrap(), vec![0.6, 0.8]);
+        assert!(matches!(embedder.embed("fail").await, Err(EmbedError::Status(429))));
+    }
+}
@@ -0,0 +1,98 @@
+use super::{CacheEntry, CacheKey};
+use moka::Expiry;
+use rand::Rng;
+use serde::Deserialize;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+// Hit-driven TTL: new entries start on a short probation, the first re-hit promotes them to
+// the regular TTL, and every further hit extends their life by `extend_on_hit_secs`, never
+// beyond `max_ttl_secs` since insertion. One-hit wonders expire quickly; popular entries stay.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct AdaptiveTtl {
+    pub probation_secs: u64,
+    pub extend_on_hit_secs: u64,
+    pub max_ttl_secs: u64,
+}
+
+// Per-entry expiry policy for the moka cache.
+pub struct EntryExpiry {
+    pub ttl: Duration,
+    pub adaptive: Option<AdaptiveTtl>,
+    // Share of the TTL each entry's lifetime is randomly moved by, up or down (0.1 = ±10%),
+    // so entries stored together don't all expire together and hit providers at once.
+    pub jitter: f64,
+}
+
+impl EntryExpiry {
+    fn jittered(&self, ttl: Duration) -> Duration {
+        if self.jitter <= 0.0 {
+            return ttl;
+        }
+        ttl.mul_f64(1.0 + rand::thread_rng().gen_range(-self.jitter..=self.jitter))
+    }
+
+    // Refreshed entries arrive with their predecessor's hits and skip probation.
+    fn initial_ttl(&self, value: &CacheEntry) -> Duration {
+        if let Some(ttl) = value.restored_ttl {
+            // Capped in case the configured TTLs shrank since the snapshot
+            let longest = self.adaptive.map_or(self.ttl, |a| self.ttl.max(Duration::from_secs(a.max_ttl_secs)));
+            return ttl.min(longest);
+        }
+        // Hints are the provider's ceiling, so they're kept exact
+        if let Some(hint) = value.response.cache_ttl {
+            return hint.min(self.ttl);
+        }
+        match self.adaptive {
+            Some(a) if value.hit_count() == 0 => self.jittered(Duration::from_secs(a.probation_secs).min(self.ttl)),
+            _ => self.jittered(self.ttl),
+        }
+    }
+}
+
+impl Expiry<CacheKey, CacheEntry> for EntryExpiry {
+    fn expire_after_create(&self, _key: &CacheKey, value: &CacheEntry, created_at: Instant) -> Option<Duration> {
+        let ttl = self.initial_ttl(value);
+        value.set_expiry(created_at, ttl);
+        Some(ttl)
+    }
+
+    fn expire_after_read(
+        &self,
+        _key: &CacheKey,
+        value: &CacheEntry,
+        read_at: Instant,
+        duration_until_expiry: Option<Duration>,
+        _last_modified_at: Instant,
+    ) -> Option<Duration> {
+        // Reads are where hits are counted; this hook runs once per successful lookup.
+        let hits = value.hits.fetch_add(1, Ordering::Relaxed) + 1;
+        // A provider's TTL hint is a ceiling that hits don't extend
+        let Some(adaptive) = self.adaptive.filter(|_| value.response.cache_ttl.is_none()) else { return duration_until_expiry };
+
+        let remaining = duration_until_expiry.unwrap_or_default();
+        let extended = if hits == 1 {
+            remaining.max(self.jittered(self.ttl))
+        } else {
+            remaining + Duration::from_secs(adaptive.extend_on_hit_secs)
+        };
+        let age = read_at.saturating_duration_since(value.inserted_at);
+        let cap = Duration::from_secs(adaptive.max_ttl_secs).saturating_sub(age);
+        let ttl = extended.min(cap);
+        value.set_expiry(read_at, ttl);
+        Some(ttl)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &CacheKey,
+        value: &CacheEntry,
+        updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        // A re-put is a fresh entry
+        let ttl = self.initial_ttl(value);
+        value.set_expiry(updated_at, ttl);
+        Some(ttl)
+    }
+}
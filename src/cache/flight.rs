@@ -0,0 +1,124 @@
+use super::CacheKey;
+use crate::model::LlmResponse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::watch;
+
+// What a flight's leader got back, and whether it went into the cache.
+#[derive(Debug, Clone)]
+pub struct SharedAnswer {
+    pub response: LlmResponse,
+    pub cached: bool,
+}
+
+type Answer = watch::Receiver<Option<SharedAnswer>>;
+
+// Single-flight for cache misses: the first request for a key calls a provider, and
+// identical requests arriving meanwhile wait for its answer instead of making calls of
+// their own. Keyed like the cache, so only requests the cache would answer alike share.
+#[derive(Debug, Default)]
+pub struct Flights {
+    calls: Mutex<HashMap<CacheKey, Answer>>,
+}
+
+pub enum Flight {
+    Leader(FlightLeader),
+    Follower(FlightFollower),
+}
+
+impl Flights {
+    pub fn join(self: &Arc<Self>, key: CacheKey) -> Flight {
+        let mut calls = self.calls.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(answer) = calls.get(&key) {
+            return Flight::Follower(FlightFollower(answer.clone()));
+        }
+        let (tx, rx) = watch::channel(None);
+        calls.insert(key, rx);
+        Flight::Leader(FlightLeader { flights: self.clone(), key, tx })
+    }
+}
+
+// Dropping the leader without `complete` (failure, client gone) releases the followers
+// empty-handed, and they go upstream themselves.
+pub struct FlightLeader {
+    flights: Arc<Flights>,
+    key: CacheKey,
+    tx: watch::Sender<Option<SharedAnswer>>,
+}
+
+impl FlightLeader {
+    pub fn complete(self, response: &LlmResponse, cached: bool) {
+        let _ = self.tx.send(Some(SharedAnswer { response: response.clone(), cached }));
+    }
+}
+
+impl Drop for FlightLeader {
+    fn drop(&mut self) {
+        self.flights.calls.lock().unwrap_or_else(PoisonError::into_inner).remove(&self.key);
+    }
+}
+
+pub struct FlightFollower(Answer);
+
+impl FlightFollower {
+    // The leader's answer, or None once it gave up without one.
+    pub async fn answer(mut self) -> Option<SharedAnswer> {
+        if self.0.borrow().is_none() {
+            // Errs when the leader is gone; `borrow` then tells whether it answered first
+            let _ = self.0.changed().await;
+        }
+        self.0.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{self, MockUpstream, CONTENT};
+    use axum::http::{HeaderMap, StatusCode};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    async fn burst(state: &Arc<crate::gateway::AppState>, n: usize) -> Vec<StatusCode> {
+        let calls = (0..n).map(|_| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                test_support::complete(&state, HeaderMap::new(), test_support::request("same", serde_json::json!({}))).await
+            })
+        });
+        let mut statuses = Vec::new();
+        for call in calls.collect::<Vec<_>>() {
+            let resp = call.await.unwrap();
+            statuses.push(resp.status());
+            if resp.status() == StatusCode::OK {
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["content"], CONTENT);
+            }
+        }
+        statuses
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_requests_make_one_provider_call() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(200));
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+
+        let statuses = burst(&state, 10).await;
+        assert!(statuses.iter().all(|s| *s == StatusCode::OK), "{statuses:?}");
+        assert_eq!(upstream.calls(), 1);
+        assert_eq!(state.cache.stats().coalesced, 9);
+    }
+
+    #[tokio::test]
+    async fn a_failed_leader_sends_its_followers_upstream() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(200));
+        upstream.fail_with(500);
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+
+        let statuses = burst(&state, 5).await;
+        assert!(statuses.iter().all(|s| *s == StatusCode::BAD_GATEWAY), "{statuses:?}");
+        assert!(upstream.calls() > 1);
+    }
+}
@@ -0,0 +1,102 @@
+use super::CacheKey;
+use crate::balancer::breaker;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+// Rows of the sketch; each takes its index from a different quarter of the key.
+const DEPTH: usize = 4;
+const MIN_WIDTH: usize = 1 << 10;
+const MAX_WIDTH: usize = 1 << 20;
+
+// Count-min sketch of how often each cache key missed recently, for frequency-based
+// admission (see AdmissionPolicy::min_requests). Counts can only be overestimated, by
+// collisions, never under. Every `window_ms` all counters are halved, so old popularity
+// fades and a key has to keep being asked for to stay admissible.
+#[derive(Debug)]
+pub struct FrequencySketch {
+    rows: Vec<Vec<AtomicU8>>,
+    mask: usize,
+    window_ms: u64,
+    last_aged_ms: AtomicU64,
+}
+
+impl FrequencySketch {
+    // Sized after the cache: about one counter per entry it can hold, per row.
+    pub fn new(capacity: u64, window_ms: u64) -> Self {
+        let width = (capacity as usize).clamp(MIN_WIDTH, MAX_WIDTH).next_power_of_two();
+        let rows = (0..DEPTH).map(|_| (0..width).map(|_| AtomicU8::new(0)).collect()).collect();
+        Self { rows, mask: width - 1, window_ms: window_ms.max(1), last_aged_ms: AtomicU64::new(breaker::now_millis()) }
+    }
+
+    // Counts one more miss for `key`.
+    pub fn record(&self, key: &CacheKey) {
+        self.age_if_due(breaker::now_millis());
+        for (row, index) in self.rows.iter().zip(self.indexes(key)) {
+            let _ = row[index].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_add(1));
+        }
+    }
+
+    // Misses recorded for `key` in about the last window.
+    pub fn estimate(&self, key: &CacheKey) -> u8 {
+        self.rows
+            .iter()
+            .zip(self.indexes(key))
+            .map(|(row, index)| row[index].load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0)
+    }
+
+    // The key is already a uniform digest, so its 8-byte quarters serve as the row hashes.
+    fn indexes(&self, key: &CacheKey) -> [usize; DEPTH] {
+        std::array::from_fn(|row| {
+            let word = u64::from_le_bytes(key[row * 8..row * 8 + 8].try_into().unwrap_or_default());
+            word as usize & self.mask
+        })
+    }
+
+    fn age_if_due(&self, now_ms: u64) {
+        let last = self.last_aged_ms.load(Ordering::Relaxed);
+        if now_ms.saturating_sub(last) < self.window_ms {
+            return;
+        }
+        // One caller wins the window and does the halving
+        if self.last_aged_ms.compare_exchange(last, now_ms, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+            return;
+        }
+        for counter in self.rows.iter().flatten() {
+            let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| Some(n / 2));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn key(i: u8) -> CacheKey {
+        *blake3::hash(&[i]).as_bytes()
+    }
+
+    #[test]
+    fn counts_misses_and_halves_them_every_window() {
+        let sketch = FrequencySketch::new(100, 200);
+        for _ in 0..6 {
+            sketch.record(&key(1));
+        }
+        sketch.record(&key(2));
+        assert_eq!((sketch.estimate(&key(1)), sketch.estimate(&key(2)), sketch.estimate(&key(3))), (6, 1, 0));
+
+        std::thread::sleep(Duration::from_millis(250));
+        sketch.record(&key(2));
+        assert_eq!((sketch.estimate(&key(1)), sketch.estimate(&key(2))), (3, 1));
+    }
+
+    #[test]
+    fn counters_saturate_instead_of_wrapping() {
+        let sketch = FrequencySketch::new(100, 60_000);
+        for _ in 0..300 {
+            sketch.record(&key(1));
+        }
+        assert_eq!(sketch.estimate(&key(1)), u8::MAX);
+    }
+}
@@ -0,0 +1,88 @@
+use super::CacheKey;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+// Word n-gram size used for similarity. Bigrams keep word order significant without
+// making short prompts too brittle.
+const NGRAM: usize = 2;
+
+// Normalized word n-gram set: lowercased, split on anything that isn't alphanumeric.
+// Prompts with fewer than NGRAM words fall back to their single words.
+pub fn ngrams(text: &str) -> HashSet<u64> {
+    let lowered = text.to_lowercase();
+    let tokens: Vec<&str> = lowered
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    let shingle = |words: &[&str]| {
+        let mut h = DefaultHasher::new();
+        words.hash(&mut h);
+        h.finish()
+    };
+    if tokens.len() < NGRAM {
+        return tokens.iter().map(|t| shingle(std::slice::from_ref(t))).collect();
+    }
+    tokens.windows(NGRAM).map(shingle).collect()
+}
+
+pub fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let intersection = a.intersection(b).count();
+    let union = a.len() + b.len() - intersection;
+    intersection as f64 / union as f64
+}
+
+// Bounded index of recently cached prompts' n-gram sets, mapped to their cache keys.
+// Lookups are a linear scan, which is fine at the sizes this is meant for; entries that
+// have since been evicted from the cache simply miss when their key is fetched.
+pub struct NgramIndex {
+    // (namespace, features, cache key)
+    entries: RwLock<VecDeque<(String, HashSet<u64>, CacheKey)>>,
+    capacity: usize,
+}
+
+impl NgramIndex {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: RwLock::new(VecDeque::with_capacity(capacity)), capacity }
+    }
+
+    pub fn insert(&self, namespace: &str, text: &str, key: CacheKey) {
+        let grams = ngrams(text);
+        let Ok(mut entries) = self.entries.write() else { return };
+        entries.retain(|(_, _, k)| *k != key);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((namespace.to_string(), grams, key));
+    }
+
+    // Key of the most similar prompt indexed under `namespace`, if any reaches `threshold`.
+    pub fn best_match(&self, namespace: &str, text: &str, threshold: f64) -> Option<CacheKey> {
+        let grams = ngrams(text);
+        let entries = self.entries.read().ok()?;
+        entries
+            .iter()
+            .filter(|(ns, _, _)| ns == namespace)
+            .map(|(_, g, k)| (jaccard(&grams, g), k))
+            .filter(|(score, _)| *score >= threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, k)| *k)
+    }
+
+    pub fn remove(&self, key: &CacheKey) {
+        if let Ok(mut entries) = self.entries.write() {
+            entries.retain(|(_, _, k)| k != key);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.write() {
+            entries.clear();
+        }
+    }
+}
@@ -1,38 +1,946 @@
-use crate::model::LlmResponse;
+use crate::model::{LlmRequest, LlmResponse};
 use moka::future::Cache;
-use blake3::Hash;
-use std::time::Duration;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+pub mod embedding;
+pub mod expiry;
+pub mod flight;
+pub mod frequency;
+pub mod fuzzy;
+pub mod negative;
+pub mod snapshot;
+pub mod warmup;
+
+use embedding::{Embedder, HashingEmbedder, VectorIndex, DEFAULT_LOCAL_DIMS};
+use expiry::{AdaptiveTtl, EntryExpiry};
+use frequency::FrequencySketch;
+use flight::{Flight, Flights};
+use fuzzy::NgramIndex;
+use tracing::warn;
+
+// Prompts remembered by the fuzzy and embedding indexes
+const FUZZY_INDEX_CAPACITY: usize = 1024;
+
+// How lookups match prompts. `Exact` hashes the prompt; `FuzzyNgram` additionally falls back
+// to the most similar recently cached prompt, by Jaccard similarity of word n-grams, when
+// it reaches `threshold` (0.0..=1.0). `Embedding` does the same by cosine similarity of
+// prompt embeddings (see `SemanticCache::with_embedder`).
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    #[default]
+    Exact,
+    FuzzyNgram { threshold: f64 },
+    Embedding { threshold: f64 },
+}
+
+// How prompt text is normalized before it is hashed into a key, so trivially different
+// prompts can share an entry. Each mode includes the ones before it: `trim` strips leading
+// and trailing whitespace, `trim_lowercase` also lowercases, and `collapse_whitespace` also
+// turns every run of whitespace into a single space.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKeyNormalization {
+    #[default]
+    None,
+    Trim,
+    TrimLowercase,
+    CollapseWhitespace,
+}
+
+impl CacheKeyNormalization {
+    pub fn apply(self, prompt: &str) -> Cow<'_, str> {
+        match self {
+            CacheKeyNormalization::None => Cow::Borrowed(prompt),
+            CacheKeyNormalization::Trim => Cow::Borrowed(prompt.trim()),
+            CacheKeyNormalization::TrimLowercase => Cow::Owned(prompt.trim().to_lowercase()),
+            CacheKeyNormalization::CollapseWhitespace => {
+                Cow::Owned(prompt.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub response: LlmResponse,
+    // Insertion time, used to report `Age` to clients.
+    pub inserted_at: Instant,
+    // Lookups served by this entry (shared across clones handed out by moka).
+    pub hits: Arc<AtomicU32>,
+    // Serialized size of the response and of the request kept alongside it, the basis for
+    // memory accounting and the byte cap.
+    pub size_bytes: u32,
+    // Request that produced the response, so the entry can be regenerated before it expires.
+    pub request: Arc<LlmRequest>,
+    // Current lifetime in ms since `inserted_at`, kept up to date by EntryExpiry.
+    expires_after_ms: Arc<AtomicU64>,
+    // Set while a background refresh of this entry is running.
+    refreshing: Arc<AtomicBool>,
+    // Lifetime left when the entry was saved to a snapshot; replaces the initial TTL.
+    restored_ttl: Option<Duration>,
+}
+
+impl CacheEntry {
+    pub fn new(request: Arc<LlmRequest>, response: LlmResponse) -> Self {
+        let size_of = |json: serde_json::Result<Vec<u8>>| json.map_or(0, |v| v.len());
+        let size_bytes = size_of(serde_json::to_vec(&response)) + size_of(serde_json::to_vec(&*request));
+        Self {
+            response,
+            inserted_at: Instant::now(),
+            hits: Arc::new(AtomicU32::new(0)),
+            size_bytes: size_bytes.min(u32::MAX as usize) as u32,
+            request,
+            expires_after_ms: Arc::new(AtomicU64::new(u64::MAX)),
+            refreshing: Arc::new(AtomicBool::new(false)),
+            restored_ttl: None,
+        }
+    }
+
+    // Called by EntryExpiry whenever moka (re)computes the entry's expiry.
+    pub(crate) fn set_expiry(&self, at: Instant, ttl: Duration) {
+        let lifetime = at.saturating_duration_since(self.inserted_at) + ttl;
+        self.expires_after_ms.store(lifetime.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    // Total time the entry is kept, counted from insertion.
+    pub fn lifetime(&self) -> Duration {
+        Duration::from_millis(self.expires_after_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn time_to_live_at(&self, now: Instant) -> Duration {
+        self.lifetime().saturating_sub(now.saturating_duration_since(self.inserted_at))
+    }
+
+    // Gives up a refresh claim taken by `refresh_candidates_at` after a failed refresh.
+    pub fn release_refresh(&self) {
+        self.refreshing.store(false, Ordering::Release);
+    }
+
+    pub fn age(&self) -> Duration {
+        self.inserted_at.elapsed()
+    }
+
+    pub fn hit_count(&self) -> u32 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+// Decides whether a response is worth caching. A response is admitted when it was slow
+// OR expensive enough to regenerate; cheap, fast responses are cheaper to recompute than
+// to hold in memory. Zero thresholds admit everything.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct AdmissionPolicy {
+    pub min_latency_ms: u64,
+    pub min_cost_usd: f64,
+    // On top of the thresholds: a prompt is only stored once it has missed this many times
+    // within about `frequency_window_secs` (the cache TTL when 0), so one-off prompts don't
+    // evict popular ones. 0 and 1 store on the first miss.
+    pub min_requests: u8,
+    pub frequency_window_secs: u64,
+}
+
+impl AdmissionPolicy {
+    pub fn admits(&self, latency_ms: u64, cost_usd: f64) -> bool {
+        if self.min_latency_ms == 0 && self.min_cost_usd <= 0.0 {
+            return true;
+        }
+        (self.min_latency_ms > 0 && latency_ms >= self.min_latency_ms)
+            || (self.min_cost_usd > 0.0 && cost_usd >= self.min_cost_usd)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // Approximate (moka applies pending writes lazily)
+    pub entries: u64,
+    // hits / (hits + misses), 0.0 before the first lookup
+    pub hit_ratio: f64,
+    // Misses that waited on an identical in-flight call instead of making their own
+    pub coalesced: u64,
+    // Hits served while no provider was up (see Router::is_degraded)
+    pub degraded_hits: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CacheMemory {
+    pub entries: u64,
+    // Sum of serialized entry sizes (keys included); excludes moka's own bookkeeping.
+    pub bytes: u64,
+    pub max_bytes: Option<u64>,
+}
+
+// Lookup counters, shared by clones of the cache.
+#[derive(Debug, Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    degraded_hits: AtomicU64,
+}
+
+// Cache writes running off the request path, so shutdown can wait for them.
+#[derive(Debug, Default)]
+struct PendingWrites {
+    count: AtomicUsize,
+    done: Notify,
+}
+
+// Held for the duration of one background write; see `SemanticCache::begin_write`.
+pub struct WriteGuard {
+    pending: Arc<PendingWrites>,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        if self.pending.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.pending.done.notify_waiters();
+        }
+    }
+}
+
+// Raw blake3 digest of namespace and prompt (see `hash_key`). Kept inline rather than as a
+// 64-char hex String: 32 bytes per entry instead of a 24-byte String plus its heap buffer.
+pub type CacheKey = [u8; 32];
 
 #[derive(Clone)]
 pub struct SemanticCache {
-    inner: Cache<String, LlmResponse>,
+    inner: Cache<CacheKey, CacheEntry>,
+    max_capacity: u64,
+    // Byte cap; when set it replaces the entry-count cap.
+    max_bytes: Option<u64>,
+    ttl: Duration,
+    adaptive: Option<AdaptiveTtl>,
+    ttl_jitter: f64,
+    admission: AdmissionPolicy,
+    // Recent misses per key, when `admission.min_requests` asks for more than one
+    frequency: Option<Arc<FrequencySketch>>,
+    mode: CacheMode,
+    normalization: CacheKeyNormalization,
+    fuzzy: Option<Arc<NgramIndex>>,
+    embedder: Option<Arc<dyn Embedder>>,
+    vectors: Option<Arc<VectorIndex>>,
+    counters: Arc<CacheCounters>,
+    pending: Arc<PendingWrites>,
+    flights: Arc<Flights>,
 }
 
 impl SemanticCache {
     pub fn new(max_capacity: u64, ttl_secs: u64) -> Self {
-        let inner = Cache::builder()
-            .max_capacity(max_capacity)
-            .time_to_live(Duration::from_secs(ttl_secs))
-            .build();
-        Self { inner }
+        let ttl = Duration::from_secs(ttl_secs);
+        let inner = Self::build_inner(max_capacity, None, EntryExpiry { ttl, adaptive: None, jitter: 0.0 });
+        Self {
+            inner,
+            max_capacity,
+            max_bytes: None,
+            ttl,
+            adaptive: None,
+            ttl_jitter: 0.0,
+            admission: AdmissionPolicy::default(),
+            frequency: None,
+            mode: CacheMode::Exact,
+            normalization: CacheKeyNormalization::None,
+            fuzzy: None,
+            embedder: None,
+            vectors: None,
+            counters: Arc::new(CacheCounters::default()),
+            pending: Arc::new(PendingWrites::default()),
+            flights: Arc::new(Flights::default()),
+        }
+    }
+
+    pub fn with_mode(mut self, mode: CacheMode) -> Self {
+        self.fuzzy = match mode {
+            CacheMode::FuzzyNgram { .. } => Some(Arc::new(NgramIndex::new(FUZZY_INDEX_CAPACITY))),
+            _ => None,
+        };
+        self.vectors = match mode {
+            CacheMode::Embedding { .. } => Some(Arc::new(VectorIndex::new(FUZZY_INDEX_CAPACITY))),
+            _ => None,
+        };
+        if self.vectors.is_some() && self.embedder.is_none() {
+            self.embedder = Some(Arc::new(HashingEmbedder::new(DEFAULT_LOCAL_DIMS)));
+        }
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_key_normalization(mut self, normalization: CacheKeyNormalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    // Exact-match key: the namespace and the canonical conversation, each message's content
+    // normalized. None for requests without prompt text.
+    fn key(&self, req: &LlmRequest) -> Option<CacheKey> {
+        let normalization = self.normalization;
+        Some(hash_key(&namespace(req), &req.canonical_text_with(|content| normalization.apply(content))?))
+    }
+
+    // Embedder used by `CacheMode::Embedding` (defaults to the local hashing embedder).
+    pub fn with_embedder(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+
+    // Switches to hit-driven TTLs (see AdaptiveTtl). Rebuilds the store, so call it at setup.
+    pub fn with_adaptive_ttl(mut self, adaptive: AdaptiveTtl) -> Self {
+        self.adaptive = Some(adaptive);
+        self.rebuild();
+        self
+    }
+
+    // Spreads each entry's TTL randomly over ±`percent`% of it. Rebuilds the store, so call
+    // it at setup.
+    pub fn with_ttl_jitter(mut self, percent: u8) -> Self {
+        self.ttl_jitter = f64::from(percent.min(99)) / 100.0;
+        self.rebuild();
+        self
+    }
+
+    // Caps the cache by serialized size instead of entry count; moka evicts entries to stay
+    // under `max_bytes`. Rebuilds the store, so call it at setup.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self.rebuild();
+        self
+    }
+
+    fn rebuild(&mut self) {
+        let expiry = EntryExpiry { ttl: self.ttl, adaptive: self.adaptive, jitter: self.ttl_jitter };
+        self.inner = Self::build_inner(self.max_capacity, self.max_bytes, expiry);
+    }
+
+    fn build_inner(max_capacity: u64, max_bytes: Option<u64>, expiry: EntryExpiry) -> Cache<CacheKey, CacheEntry> {
+        let builder = Cache::builder().expire_after(expiry);
+        match max_bytes {
+            Some(bytes) => builder
+                .max_capacity(bytes)
+                .weigher(|_: &CacheKey, entry: &CacheEntry| entry_weight(entry))
+                .build(),
+            None => builder.max_capacity(max_capacity).build(),
+        }
+    }
+
+    pub fn memory(&self) -> CacheMemory {
+        let bytes = self.inner.iter().map(|(_, v)| entry_weight(&v) as u64).sum();
+        CacheMemory { entries: self.inner.entry_count(), bytes, max_bytes: self.max_bytes }
+    }
+
+    pub fn with_admission(mut self, admission: AdmissionPolicy) -> Self {
+        self.admission = admission;
+        self.frequency = (admission.min_requests > 1).then(|| {
+            let window = match admission.frequency_window_secs {
+                0 => self.ttl,
+                secs => Duration::from_secs(secs),
+            };
+            Arc::new(FrequencySketch::new(self.max_capacity, window.as_millis() as u64))
+        });
+        self
+    }
+
+    // Whether `resp` goes into the cache: admitted by the policy, and not hinted uncacheable.
+    pub fn admits(&self, req: &LlmRequest, resp: &LlmResponse, cost_usd: f64) -> bool {
+        resp.cache_ttl != Some(Duration::ZERO) && self.admission.admits(resp.latency_ms, cost_usd) && self.frequent_enough(req)
+    }
+
+    fn frequent_enough(&self, req: &LlmRequest) -> bool {
+        let Some(frequency) = &self.frequency else { return true };
+        let Some(key) = self.key(req) else { return false };
+        frequency.estimate(&key) >= self.admission.min_requests
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    // Lifetime `resp` gets at least when stored: its provider's hint, capped at the cache TTL,
+    // or the TTL less the most jitter can take off it.
+    pub fn ttl_for(&self, resp: &LlmResponse) -> Duration {
+        resp.cache_ttl.map_or(self.ttl.mul_f64(1.0 - self.ttl_jitter), |hint| hint.min(self.ttl))
+    }
+
+    pub async fn get(&self, req: &LlmRequest) -> Option<LlmResponse> {
+        self.get_entry(req).await.map(|e| e.response)
+    }
+
+    pub async fn get_entry(&self, req: &LlmRequest) -> Option<CacheEntry> {
+        let entry = self.lookup(req).await;
+        let counter = if entry.is_some() { &self.counters.hits } else { &self.counters.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let (None, Some(frequency), Some(key)) = (&entry, &self.frequency, self.key(req)) {
+            frequency.record(&key);
+        }
+        entry
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.counters.hits.load(Ordering::Relaxed);
+        let misses = self.counters.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        CacheStats {
+            hits,
+            misses,
+            entries: self.inner.entry_count(),
+            hit_ratio: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+            degraded_hits: self.counters.degraded_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn record_degraded_hit(&self) {
+        self.counters.degraded_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn lookup(&self, req: &LlmRequest) -> Option<CacheEntry> {
+        let key = self.key(req)?;
+        if let Some(entry) = self.inner.get(&key).await {
+            return Some(entry);
+        }
+
+        // Exact miss: try the closest recently cached prompt within the same namespace
+        let (namespace, prompt) = (namespace(req), req.transcript()?);
+        let similar = match self.mode {
+            CacheMode::Exact => return None,
+            CacheMode::FuzzyNgram { threshold } => {
+                self.fuzzy.as_ref()?.best_match(&namespace, &prompt, threshold)?
+            }
+            CacheMode::Embedding { threshold } => {
+                let vector = self.embed(&prompt).await?;
+                self.vectors.as_ref()?.best_match(&namespace, &vector, threshold)?
+            }
+        };
+        self.inner.get(&similar).await
+    }
+
+    // Single-flight slot for a request that missed (see Flights), under its exact cache key.
+    // None for requests without prompt text.
+    pub fn join_flight(&self, req: &LlmRequest) -> Option<Flight> {
+        let key = self.key(req)?;
+        let flight = self.flights.join(key);
+        if matches!(flight, Flight::Follower(_)) {
+            self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(flight)
+    }
+
+    // No-op for requests without prompt text (there is nothing to key on).
+    pub async fn put(&self, req: &LlmRequest, response: LlmResponse) {
+        self.put_entry(CacheEntry::new(Arc::new(req.clone()), response)).await;
+    }
+
+    // Seeds the cache with known answers, bypassing the admission policy. Entries expire like
+    // any other; ones without prompt text are skipped. Returns how many were stored.
+    pub async fn preload(&self, entries: Vec<(LlmRequest, LlmResponse)>) -> usize {
+        let mut stored = 0;
+        for (req, response) in entries {
+            if req.canonical_text().is_some() {
+                self.put(&req, response).await;
+                stored += 1;
+            }
+        }
+        stored
     }
 
-    pub async fn get(&self, prompt: &str) -> Option<LlmResponse> {
-        let key = self.hash_key(prompt);
-        self.inner.get(&key).await
+    // Like `put`, without making the caller wait. The write counts as pending until it lands.
+    pub fn put_in_background(&self, req: &LlmRequest, response: LlmResponse) {
+        let guard = self.begin_write();
+        let cache = self.clone();
+        let entry = CacheEntry::new(Arc::new(req.clone()), response);
+        tokio::spawn(async move {
+            let _guard = guard;
+            cache.put_entry(entry).await;
+        });
     }
 
-    pub async fn put(&self, prompt: &str, response: LlmResponse) {
-        let key = self.hash_key(prompt);
-        self.inner.insert(key, response).await;
+    // Marks a write done outside the request path as pending until the guard is dropped.
+    pub fn begin_write(&self) -> WriteGuard {
+        self.pending.count.fetch_add(1, Ordering::AcqRel);
+        WriteGuard { pending: self.pending.clone() }
     }
 
-    fn hash_key(&self, prompt: &str) -> String {
-        // Normalize: trim, lowercase (optional, depending on strictness)
-        // For now, strict hashing of the prompt content.
-        // In a real semantic cache, we might want to use embeddings, 
-        // but the requirement said "Hash determinístico do prompt".
-        let hash = blake3::hash(prompt.as_bytes());
-        hash.to_hex().to_string()
+    // Waits for pending background writes, up to `timeout`. False if some were still
+    // running when it expired.
+    pub async fn drain_writes(&self, timeout: Duration) -> bool {
+        let drained = async {
+            loop {
+                // Registered before the check, so a write finishing in between still wakes us
+                let done = self.pending.done.notified();
+                if self.pending.count.load(Ordering::Acquire) == 0 {
+                    return;
+                }
+                done.await;
+            }
+        };
+        tokio::time::timeout(timeout, drained).await.is_ok()
+    }
+
+    // Waits for background writes like `drain_writes`, then applies moka's pending
+    // maintenance (evictions, expirations), so what a snapshot or `entry_count` sees next is
+    // settled. Called on shutdown before the final snapshot.
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        let drained = self.drain_writes(timeout).await;
+        self.inner.run_pending_tasks().await;
+        drained
+    }
+
+    pub fn pending_writes(&self) -> usize {
+        self.pending.count.load(Ordering::Acquire)
+    }
+
+    // Popular entries (at least `min_hits`) expiring within `ahead`. Each returned entry is
+    // claimed, so overlapping scans don't refresh it twice; see `refresh`/`release_refresh`.
+    pub fn refresh_candidates_at(&self, now: Instant, min_hits: u32, ahead: Duration) -> Vec<CacheEntry> {
+        self.inner
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|e| e.hit_count() >= min_hits && e.time_to_live_at(now) <= ahead)
+            .filter(|e| !e.refreshing.swap(true, Ordering::AcqRel))
+            .collect()
+    }
+
+    // Replaces `old` with a freshly generated response. The hit count carries over, so the
+    // new entry keeps its full TTL (no adaptive probation) and stays eligible for refresh.
+    pub async fn refresh(&self, old: &CacheEntry, response: LlmResponse) {
+        let entry = CacheEntry { hits: old.hits.clone(), ..CacheEntry::new(old.request.clone(), response) };
+        self.put_entry(entry).await;
+    }
+
+    async fn put_entry(&self, entry: CacheEntry) {
+        let req = entry.request.clone();
+        let (Some(key), Some(prompt)) = (self.key(&req), req.transcript()) else { return };
+        let namespace = namespace(&req);
+        if let Some(index) = &self.fuzzy {
+            index.insert(&namespace, &prompt, key);
+        }
+        if let Some(index) = &self.vectors {
+            if let Some(vector) = self.embed(&prompt).await {
+                index.insert(&namespace, vector, key);
+            }
+        }
+        self.inner.insert(key, entry).await;
+    }
+
+    // Evicts the entry stored for exactly this request (not a fuzzy or embedding match).
+    // False when there was none.
+    pub async fn remove(&self, req: &LlmRequest) -> bool {
+        let Some(key) = self.key(req) else { return false };
+        if let Some(index) = &self.fuzzy {
+            index.remove(&key);
+        }
+        if let Some(index) = &self.vectors {
+            index.remove(&key);
+        }
+        self.inner.remove(&key).await.is_some()
+    }
+
+    // Evicts every entry. Writes still pending in the background may land afterwards.
+    pub async fn clear(&self) {
+        if let Some(index) = &self.fuzzy {
+            index.clear();
+        }
+        if let Some(index) = &self.vectors {
+            index.clear();
+        }
+        self.inner.invalidate_all();
+        self.inner.run_pending_tasks().await;
+    }
+
+    // Entry count with pending evictions applied, unlike the estimate in `stats`.
+    pub async fn entry_count(&self) -> u64 {
+        self.inner.run_pending_tasks().await;
+        self.inner.entry_count()
+    }
+
+    // Embedding failures degrade to exact-only caching for that prompt.
+    async fn embed(&self, prompt: &str) -> Option<Vec<f32>> {
+        match self.embedder.as_ref()?.embed(prompt).await {
+            Ok(vector) => Some(vector),
+            Err(e) => {
+                warn!("Prompt embedding failed: {}", e);
+                None
+            }
+        }
+    }
+
+}
+
+fn entry_weight(entry: &CacheEntry) -> u32 {
+    entry.size_bytes.saturating_add(std::mem::size_of::<CacheKey>() as u32)
+}
+
+// Cache partition of a request: the same prompt under a different model, sampling
+// temperature, completion count or any other parameter forwarded to the provider
+// (`max_tokens`, `stop`, `tools`, `response_format`, ...) is a different answer, so it must
+// never be served across namespaces.
+fn namespace(req: &LlmRequest) -> String {
+    let mut namespace = match req.temperature {
+        Some(t) => format!("{}@{}", req.model, t),
+        None => req.model.clone(),
+    };
+    // An answer with one completion can't serve a request for several, or the other way round
+    if req.choice_count() != 1 {
+        namespace += &format!("#n{}", req.choice_count());
+    }
+    // Sorted by name, so equal parameters give the same namespace whatever order the
+    // `extra_params` HashMap iterates in. `session` is a routing hint, never forwarded.
+    let mut params: BTreeMap<&str, serde_json::Value> = req
+        .extra_params
+        .iter()
+        .filter(|(name, _)| name.as_str() != "session")
+        .map(|(name, value)| (name.as_str(), value.clone()))
+        .collect();
+    if let Some(max_tokens) = req.max_tokens {
+        params.insert("max_tokens", max_tokens.into());
+    }
+    if let Some(format) = &req.response_format {
+        params.insert("response_format", format.clone());
+    }
+    if !params.is_empty() {
+        let canonical = serde_json::to_string(&params).unwrap_or_default();
+        namespace += &format!("#{}", &blake3::hash(canonical.as_bytes()).to_hex()[..16]);
+    }
+    namespace
+}
+
+fn hash_key(namespace: &str, prompt: &str) -> CacheKey {
+    // Strict hash of the namespace and prompt content; the exact-match fast path for every mode.
+    // The NUL separator keeps ("ab", "c") and ("a", "bc") apart.
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(namespace.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(prompt.as_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: serde_json::Value) -> LlmRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn response(content: &str) -> LlmResponse {
+        serde_json::from_value(serde_json::json!({"content": content, "usage": {}, "provider": "p", "latency_ms": 1})).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwarded_params_partition_the_cache() {
+        let cache = SemanticCache::new(100, 60);
+        let truncated = request(serde_json::json!({"model": "m", "prompt": "hi", "max_tokens": 5, "stop": ["\n"]}));
+        cache.put(&truncated, response("short")).await;
+
+        for other in [
+            serde_json::json!({"model": "m", "prompt": "hi"}),
+            serde_json::json!({"model": "m", "prompt": "hi", "max_tokens": 5}),
+            serde_json::json!({"model": "m", "prompt": "hi", "max_tokens": 5, "stop": ["."]}),
+            serde_json::json!({"model": "m", "prompt": "hi", "max_tokens": 5, "stop": ["\n"], "tools": [{"type": "function"}]}),
+        ] {
+            assert!(cache.get(&request(other.clone())).await.is_none(), "{} hit", other);
+        }
+        assert_eq!(cache.get(&truncated).await.unwrap().content, "short");
+    }
+
+    #[test]
+    fn namespace_ignores_param_order_and_the_session_hint() {
+        let body = serde_json::json!({"model": "m", "prompt": "hi", "stop": ["\n"], "top_p": 0.9, "user": "u", "seed": 1});
+        let expected = namespace(&request(body.clone()));
+        for _ in 0..16 {
+            assert_eq!(namespace(&request(body.clone())), expected);
+        }
+        let mut with_session = body;
+        with_session["session"] = "s1".into();
+        assert_eq!(namespace(&request(with_session)), expected);
+    }
+
+    #[test]
+    fn only_slow_or_expensive_responses_are_admitted() {
+        let cache = SemanticCache::new(100, 60)
+            .with_admission(AdmissionPolicy { min_latency_ms: 500, min_cost_usd: 0.01, ..AdmissionPolicy::default() });
+        let req = request(serde_json::json!({"model": "m", "prompt": "hi"}));
+        let answered_in = |latency_ms| LlmResponse { latency_ms, ..response("a") };
+
+        assert!(!cache.admits(&req, &answered_in(1), 0.000_1));
+        assert!(cache.admits(&req, &answered_in(2_000), 0.000_1));
+        assert!(cache.admits(&req, &answered_in(1), 0.05));
+        assert!(SemanticCache::new(100, 60).admits(&req, &answered_in(1), 0.0), "no thresholds admit everything");
+    }
+
+    #[tokio::test]
+    async fn re_hit_entries_outlive_one_hit_wonders() {
+        let cache = SemanticCache::new(100, 60)
+            .with_adaptive_ttl(AdaptiveTtl { probation_secs: 1, extend_on_hit_secs: 60, max_ttl_secs: 600 });
+        let popular = request(serde_json::json!({"model": "m", "prompt": "popular"}));
+        let one_off = request(serde_json::json!({"model": "m", "prompt": "one-off"}));
+        cache.put(&popular, response("p")).await;
+        cache.put(&one_off, response("o")).await;
+
+        for _ in 0..3 {
+            assert!(cache.get(&popular).await.is_some());
+        }
+        // Promoted to the TTL, then extended by each further hit
+        assert_eq!(cache.get_entry(&popular).await.unwrap().lifetime().as_secs(), 60 + 3 * 60);
+
+        tokio::time::sleep(Duration::from_millis(1_200)).await;
+        assert!(cache.get(&one_off).await.is_none(), "probation ran out");
+        assert!(cache.get(&popular).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn fuzzy_mode_matches_near_identical_prompts() {
+        let cache = SemanticCache::new(100, 60).with_mode(CacheMode::FuzzyNgram { threshold: 0.8 });
+        let prompt = |text: &str| request(serde_json::json!({"model": "m", "prompt": text}));
+        cache.put(&prompt("Please tell me the capital city of France"), response("Paris")).await;
+
+        for near in ["please tell me the capital city of France!", "Please tell me the capital city of France today"] {
+            assert_eq!(cache.get(&prompt(near)).await.map(|r| r.content).as_deref(), Some("Paris"), "{near}");
+        }
+        for different in ["Please tell me the capital city of Spain", "Write a poem about the sea"] {
+            assert!(cache.get(&prompt(different)).await.is_none(), "{different}");
+        }
+    }
+
+    #[tokio::test]
+    async fn models_do_not_share_entries() {
+        let cache = SemanticCache::new(100, 60);
+        cache.put(&request(serde_json::json!({"model": "big", "prompt": "hi"})), response("big answer")).await;
+
+        assert!(cache.get(&request(serde_json::json!({"model": "small", "prompt": "hi"}))).await.is_none());
+        let same = cache.get(&request(serde_json::json!({"model": "big", "prompt": "hi"}))).await;
+        assert_eq!(same.unwrap().content, "big answer");
+    }
+
+    #[tokio::test]
+    async fn stats_count_hits_and_misses() {
+        let cache = SemanticCache::new(100, 60);
+        assert_eq!(cache.stats().hit_ratio, 0.0);
+        let req = request(serde_json::json!({"model": "m", "prompt": "hi"}));
+        assert!(cache.get(&req).await.is_none());
+        cache.put(&req, response("a")).await;
+        for _ in 0..3 {
+            assert!(cache.get(&req).await.is_some());
+        }
+        cache.inner.run_pending_tasks().await;
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (3, 1, 1));
+        assert_eq!(stats.hit_ratio, 0.75);
+    }
+
+    #[tokio::test]
+    async fn large_entries_are_evicted_to_stay_under_the_byte_cap() {
+        let cache = SemanticCache::new(1_000, 60).with_max_bytes(50_000);
+        let large = "x".repeat(10_000);
+        for i in 0..20 {
+            cache.put(&request(serde_json::json!({"model": "m", "prompt": format!("prompt {i}")})), response(&large)).await;
+        }
+        cache.inner.run_pending_tasks().await;
+
+        let memory = cache.memory();
+        assert_eq!(memory.max_bytes, Some(50_000));
+        assert!(memory.bytes <= 50_000 && memory.bytes > 0, "{} bytes", memory.bytes);
+        assert!(memory.entries < 20);
+    }
+
+    #[tokio::test]
+    async fn entry_weight_counts_the_request() {
+        let cache = SemanticCache::new(100, 60);
+        let short = request(serde_json::json!({"model": "m", "prompt": "hi"}));
+        let long = request(serde_json::json!({"model": "m", "prompt": "x".repeat(10_000)}));
+        cache.put(&short, response("same answer")).await;
+        cache.inner.run_pending_tasks().await;
+        let before = cache.memory().bytes;
+        cache.put(&long, response("same answer")).await;
+        cache.inner.run_pending_tasks().await;
+        let added = cache.memory().bytes - before;
+        assert!(added > 10_000, "{added} bytes");
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_writes_in_flight() {
+        let cache = SemanticCache::new(100, 60);
+        let req = request(serde_json::json!({"model": "m", "prompt": "expensive"}));
+        // A write still running when shutdown starts
+        let guard = cache.begin_write();
+        let writer = cache.clone();
+        let written = req.clone();
+        tokio::spawn(async move {
+            let _guard = guard;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            writer.put(&written, response("kept")).await;
+        });
+        assert_eq!(cache.pending_writes(), 1);
+
+        assert!(cache.flush(Duration::from_secs(2)).await);
+        assert_eq!(cache.pending_writes(), 0);
+        assert_eq!(cache.get(&req).await.unwrap().content, "kept");
+    }
+
+    #[tokio::test]
+    async fn draining_gives_up_at_the_timeout() {
+        let cache = SemanticCache::new(100, 60);
+        let _stuck = cache.begin_write();
+        assert!(!cache.drain_writes(Duration::from_millis(50)).await);
+        assert_eq!(cache.pending_writes(), 1);
+    }
+
+    #[tokio::test]
+    async fn aggressive_key_normalization_shares_entries_across_case_and_spacing() {
+        let prompt = |text: &str| request(serde_json::json!({"model": "m", "prompt": text}));
+        let variants = ["What is  Rust?", "  what is rust?\n", "WHAT\tIS RUST?"];
+
+        let strict = SemanticCache::new(100, 60);
+        strict.put(&prompt("What is Rust?"), response("answer")).await;
+        for variant in variants {
+            assert!(strict.get(&prompt(variant)).await.is_none(), "{variant:?} hit without normalization");
+        }
+
+        let collapsing = SemanticCache::new(100, 60).with_key_normalization(CacheKeyNormalization::CollapseWhitespace);
+        collapsing.put(&prompt("What is Rust?"), response("answer")).await;
+        for variant in variants {
+            assert_eq!(collapsing.get(&prompt(variant)).await.unwrap().content, "answer", "{variant:?}");
+        }
+        assert!(collapsing.get(&prompt("What is Go?")).await.is_none());
+    }
+
+    #[test]
+    fn each_normalization_mode_builds_on_the_last() {
+        let text = "  Hello   World ";
+        assert_eq!(CacheKeyNormalization::None.apply(text), text);
+        assert_eq!(CacheKeyNormalization::Trim.apply(text), "Hello   World");
+        assert_eq!(CacheKeyNormalization::TrimLowercase.apply(text), "hello   world");
+        assert_eq!(CacheKeyNormalization::CollapseWhitespace.apply(text), "hello world");
+    }
+
+    #[tokio::test]
+    async fn a_shorter_ttl_hint_expires_first() {
+        let cache = SemanticCache::new(100, 60);
+        let short = request(serde_json::json!({"model": "m", "prompt": "short"}));
+        let long = request(serde_json::json!({"model": "m", "prompt": "long"}));
+        cache.put(&short, LlmResponse { cache_ttl: Some(Duration::from_millis(200)), ..response("a") }).await;
+        cache.put(&long, LlmResponse { cache_ttl: Some(Duration::from_secs(30)), ..response("b") }).await;
+        assert!(cache.get(&short).await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(cache.get(&short).await.is_none());
+        assert_eq!(cache.get(&long).await.unwrap().content, "b");
+    }
+
+    #[test]
+    fn ttl_hints_fall_back_to_the_global_ttl_and_never_exceed_it() {
+        let cache = SemanticCache::new(100, 60);
+        assert_eq!(cache.ttl_for(&response("a")), Duration::from_secs(60));
+        let hinted = LlmResponse { cache_ttl: Some(Duration::from_secs(5)), ..response("a") };
+        assert_eq!(cache.ttl_for(&hinted), Duration::from_secs(5));
+        let too_long = LlmResponse { cache_ttl: Some(Duration::from_secs(600)), ..response("a") };
+        assert_eq!(cache.ttl_for(&too_long), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn digest_keys_round_trip_without_colliding() {
+        let cache = SemanticCache::new(10_000, 60);
+        let req = |i: usize| request(serde_json::json!({"model": "m", "prompt": format!("unique_{i}")}));
+        for i in 0..2_000 {
+            cache.put(&req(i), response(&i.to_string())).await;
+        }
+        for i in 0..2_000 {
+            assert_eq!(cache.get(&req(i)).await.unwrap().content, i.to_string());
+        }
+        let keys: std::collections::HashSet<CacheKey> = (0..2_000).map(|i| hash_key("m", &format!("unique_{i}"))).collect();
+        assert_eq!(keys.len(), 2_000);
+
+        // Same input, same key; the separator keeps namespace and prompt apart
+        assert_eq!(hash_key("m", "hi"), hash_key("m", "hi"));
+        assert_ne!(hash_key("ab", "c"), hash_key("a", "bc"));
+        assert_eq!(std::mem::size_of::<CacheKey>(), 32);
+    }
+
+    #[tokio::test]
+    async fn prompts_are_stored_only_once_seen_min_requests_times() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let mut state = crate::test_support::state(vec![upstream.provider("a")]);
+        state.cache = Arc::new(SemanticCache::new(100, 60).with_admission(AdmissionPolicy { min_requests: 2, ..AdmissionPolicy::default() }));
+        let state = Arc::new(state);
+        let ask = |prompt: &str| {
+            let req = crate::test_support::request(prompt, serde_json::json!({}));
+            let state = state.clone();
+            async move {
+                let response = crate::test_support::complete(&state, axum::http::HeaderMap::new(), req).await;
+                assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+                response.headers()["x-cache"].to_str().unwrap().to_string()
+            }
+        };
+
+        assert_eq!(ask("popular").await, "MISS");
+        assert_eq!(ask("popular").await, "MISS", "seen once, so not stored");
+        assert_eq!(ask("popular").await, "HIT", "stored after the second miss");
+        assert_eq!(upstream.calls(), 2);
+        assert_eq!(ask("one-off").await, "MISS");
+        assert!(state.cache.get(&crate::test_support::request("one-off", serde_json::json!({}))).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn fast_cheap_answers_are_recomputed_and_slow_ones_cached() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let mut state = crate::test_support::state(vec![upstream.provider("a")]);
+        state.cache = Arc::new(SemanticCache::new(100, 60).with_admission(AdmissionPolicy { min_latency_ms: 300, ..AdmissionPolicy::default() }));
+        let state = Arc::new(state);
+        let req = |prompt: &str| crate::test_support::request(prompt, serde_json::json!({}));
+        let ask = |prompt: &str| crate::test_support::complete(&state, axum::http::HeaderMap::new(), req(prompt));
+
+        ask("fast").await;
+        upstream.delay(Duration::from_millis(400));
+        ask("slow").await;
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        assert!(state.cache.get(&req("fast")).await.is_none());
+        assert!(state.cache.get(&req("slow")).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn jittered_ttls_spread_expiries_across_the_range() {
+        let lifetimes = |cache: SemanticCache| async move {
+            let mut secs = Vec::new();
+            for i in 0..200 {
+                let req = request(serde_json::json!({"model": "m", "prompt": format!("spike {i}")}));
+                cache.put(&req, response("a")).await;
+                secs.push(cache.get_entry(&req).await.unwrap().time_to_live_at(Instant::now()).as_secs_f64());
+            }
+            let (min, max) = secs.iter().fold((f64::MAX, f64::MIN), |(lo, hi), &s| (lo.min(s), hi.max(s)));
+            (min, max)
+        };
+
+        let (min, max) = lifetimes(SemanticCache::new(1_000, 100)).await;
+        assert!(max - min < 1.0, "without jitter every entry gets the TTL: {min}..{max}");
+
+        let jittered = SemanticCache::new(1_000, 100).with_ttl_jitter(20);
+        assert_eq!(jittered.ttl_for(&response("a")), Duration::from_secs(80));
+        let (min, max) = lifetimes(jittered).await;
+        assert!(min >= 79.0 && max <= 120.0, "{min}..{max}");
+        assert!(max - min > 20.0, "expiries spread over ±20%: {min}..{max}");
+    }
+
+    #[tokio::test]
+    async fn conversations_that_read_alike_get_their_own_entries() {
+        let cache = SemanticCache::new(100, 60).with_key_normalization(CacheKeyNormalization::CollapseWhitespace);
+        let one = request(serde_json::json!({"model": "m", "messages": [{"role": "user", "content": "hi\nassistant: hello"}]}));
+        let two = request(serde_json::json!({"model": "m", "messages": [{"role": "user", "content": "hi"}, {"role": "assistant", "content": "hello"}]}));
+        cache.put(&one, response("one")).await;
+        assert!(cache.get(&two).await.is_none());
+
+        // Normalization still applies to each message's content
+        let spaced = request(serde_json::json!({"model": "m", "messages": [{"role": "user", "content": " HI "}, {"role": "assistant", "content": "hello  "}]}));
+        cache.put(&two, response("two")).await;
+        assert_eq!(cache.get(&spaced).await.unwrap().content, "two");
     }
 }
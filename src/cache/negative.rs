@@ -0,0 +1,116 @@
+use super::{hash_key, namespace, CacheKey};
+use crate::balancer::breaker;
+use crate::balancer::stats::ProviderStats;
+use crate::error::ApiError;
+use crate::model::LlmRequest;
+use moka::future::Cache;
+use serde::Deserialize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Off unless configured. Keep `ttl_secs` well below the success cache's: a remembered failure
+// is only a guard against hammering providers with a request they just rejected.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct NegativeCacheConfig {
+    pub ttl_secs: u64,
+    pub capacity: u64,
+}
+
+impl Default for NegativeCacheConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 10, capacity: 1_000 }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Failure {
+    error: ApiError,
+    // Providers that were tried; a success on any of them since `failed_at_ms` voids the entry
+    tried: Vec<Arc<ProviderStats>>,
+    failed_at_ms: u64,
+}
+
+// Requests that recently failed on every provider tried, by exact cache key, so identical
+// requests get the same error back instead of re-hitting the providers.
+#[derive(Clone)]
+pub struct NegativeCache {
+    inner: Cache<CacheKey, Arc<Failure>>,
+}
+
+impl NegativeCache {
+    pub fn new(config: NegativeCacheConfig) -> Self {
+        let inner = Cache::builder()
+            .max_capacity(config.capacity)
+            .time_to_live(Duration::from_secs(config.ttl_secs.max(1)))
+            .build();
+        Self { inner }
+    }
+
+    pub async fn get(&self, req: &LlmRequest) -> Option<ApiError> {
+        let key = key(req)?;
+        let failure = self.inner.get(&key).await?;
+        let recovered = failure
+            .tried
+            .iter()
+            .any(|stats| stats.last_success_ms.load(Ordering::Relaxed) > failure.failed_at_ms);
+        if recovered {
+            self.inner.invalidate(&key).await;
+            return None;
+        }
+        Some(failure.error.clone())
+    }
+
+    pub async fn insert(&self, req: &LlmRequest, error: ApiError, tried: Vec<Arc<ProviderStats>>) {
+        let Some(key) = key(req) else { return };
+        let failure = Failure { error, tried, failed_at_ms: breaker::now_millis() };
+        self.inner.insert(key, Arc::new(failure)).await;
+    }
+}
+
+fn key(req: &LlmRequest) -> Option<CacheKey> {
+    Some(hash_key(&namespace(req), &req.canonical_text()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockUpstream};
+    use axum::http::{HeaderMap, StatusCode};
+
+    #[tokio::test]
+    async fn a_repeated_failing_prompt_reaches_the_provider_once() {
+        let upstream = MockUpstream::start().await;
+        upstream.fail_with(500);
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.negative_cache = Some(NegativeCache::new(NegativeCacheConfig::default()));
+        let state = Arc::new(state);
+        let failing = test_support::request("bad", serde_json::json!({}));
+
+        for _ in 0..3 {
+            let response = test_support::complete(&state, HeaderMap::new(), failing.clone()).await;
+            assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        }
+        assert_eq!(upstream.calls(), 1);
+
+        // A success on the provider since voids the remembered failure
+        upstream.fail_with(0);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let other = test_support::request("good", serde_json::json!({}));
+        assert_eq!(test_support::complete(&state, HeaderMap::new(), other).await.status(), StatusCode::OK);
+        let response = test_support::complete(&state, HeaderMap::new(), failing).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(upstream.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn remembered_failures_expire_with_the_ttl() {
+        let cache = NegativeCache::new(NegativeCacheConfig { ttl_secs: 1, capacity: 10 });
+        let req = test_support::request("bad", serde_json::json!({}));
+        cache.insert(&req, ApiError::upstream("all_providers_failed", "All providers failed"), Vec::new()).await;
+        assert_eq!(cache.get(&req).await.unwrap().code, "all_providers_failed");
+        tokio::time::sleep(Duration::from_millis(1_100)).await;
+        assert!(cache.get(&req).await.is_none());
+    }
+}
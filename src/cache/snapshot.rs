@@ -0,0 +1,238 @@
+use super::{CacheEntry, SemanticCache};
+use crate::model::{LlmRequest, LlmResponse};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// Periodic dump of the cache to disk, restored at startup so a restart doesn't cold-start.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SnapshotConfig {
+    pub path: PathBuf,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+// One line of a snapshot file. Times are wall-clock (Unix ms) so they survive the restart.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotEntry {
+    request: LlmRequest,
+    response: LlmResponse,
+    inserted_at_ms: u64,
+    expires_at_ms: u64,
+    #[serde(default)]
+    hits: u32,
+}
+
+fn unix_ms(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+impl SemanticCache {
+    // Writes every live entry as newline-delimited JSON. The file is replaced atomically, so
+    // a crash mid-write leaves the previous snapshot intact. Returns how many were written.
+    pub fn save_snapshot(&self, path: &Path) -> Result<usize> {
+        let (now, wall) = (Instant::now(), SystemTime::now());
+        let mut out = String::new();
+        let mut written = 0;
+        for (_, entry) in self.inner.iter() {
+            let ttl = entry.time_to_live_at(now);
+            if ttl.is_zero() {
+                continue;
+            }
+            let line = SnapshotEntry {
+                request: (*entry.request).clone(),
+                response: entry.response.clone(),
+                inserted_at_ms: unix_ms(wall).saturating_sub(entry.age().as_millis() as u64),
+                expires_at_ms: unix_ms(wall + ttl),
+                hits: entry.hit_count(),
+            };
+            out.push_str(&serde_json::to_string(&line)?);
+            out.push('\n');
+            written += 1;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, out).with_context(|| format!("cannot write cache snapshot {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("cannot replace cache snapshot {}", path.display()))?;
+        Ok(written)
+    }
+
+    // Loads a snapshot written by `save_snapshot`. Entries keep their remaining TTL and hit
+    // count; expired ones are skipped, and so are lines that don't parse (a hand-edited or
+    // older-format file loses those entries, not the rest). A missing file restores nothing.
+    pub async fn restore_snapshot(&self, path: &Path) -> Result<usize> {
+        let raw = match std::fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).with_context(|| format!("cannot read cache snapshot {}", path.display())),
+        };
+        let (now, now_ms) = (Instant::now(), unix_ms(SystemTime::now()));
+        let (mut restored, mut corrupt) = (0, 0);
+        for (i, line) in raw.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let line: SnapshotEntry = match serde_json::from_str(line) {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Skipping invalid cache snapshot entry at {}:{}: {}", path.display(), i + 1, e);
+                    corrupt += 1;
+                    continue;
+                }
+            };
+            if line.expires_at_ms <= now_ms || line.request.canonical_text().is_none() {
+                continue;
+            }
+            let age = Duration::from_millis(now_ms.saturating_sub(line.inserted_at_ms));
+            let entry = CacheEntry {
+                inserted_at: now.checked_sub(age).unwrap_or(now),
+                hits: Arc::new(AtomicU32::new(line.hits)),
+                restored_ttl: Some(Duration::from_millis(line.expires_at_ms - now_ms)),
+                ..CacheEntry::new(Arc::new(line.request), line.response)
+            };
+            self.put_entry(entry).await;
+            restored += 1;
+        }
+        if corrupt > 0 {
+            warn!("Skipped {} invalid entries in cache snapshot {}", corrupt, path.display());
+        }
+        Ok(restored)
+    }
+}
+
+pub fn spawn_snapshots(cache: Arc<SemanticCache>, config: SnapshotConfig) {
+    let period = Duration::from_secs(config.interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        // The first tick is immediate, and the cache was only just restored
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let (cache, path) = (cache.clone(), config.path.clone());
+            match tokio::task::spawn_blocking(move || cache.save_snapshot(&path)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => warn!("Cache snapshot failed: {:#}", e),
+                Err(e) => warn!("Cache snapshot task failed: {}", e),
+            }
+        }
+    });
+}
+
+// Final snapshot at shutdown, after background writes have drained.
+pub fn save_on_shutdown(cache: &SemanticCache, config: &SnapshotConfig) {
+    match cache.save_snapshot(&config.path) {
+        Ok(written) => info!("Saved {} cache entries to {}", written, config.path.display()),
+        Err(e) => warn!("Cache snapshot at shutdown failed: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn snapshot_path() -> PathBuf {
+        std::env::temp_dir().join(format!("llm-edge-snapshot-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn a_restored_cache_serves_what_was_saved() {
+        let path = snapshot_path();
+        let cache = SemanticCache::new(100, 60);
+        let req = test_support::request("saved", serde_json::json!({}));
+        cache.put(&req, test_support::response("a", "kept answer")).await;
+        cache.get(&req).await;
+        cache.inner.run_pending_tasks().await;
+        assert_eq!(cache.save_snapshot(&path).unwrap(), 1);
+
+        let restarted = SemanticCache::new(100, 300);
+        assert_eq!(restarted.restore_snapshot(&path).await.unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+        let entry = restarted.get_entry(&req).await.unwrap();
+        assert_eq!(entry.response.content, "kept answer");
+        // Keeps its remaining TTL, not the restarted cache's
+        let ttl = entry.time_to_live_at(Instant::now());
+        assert!(ttl > Duration::from_secs(55) && ttl <= Duration::from_secs(60), "{ttl:?}");
+        assert!(entry.hit_count() >= 1);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_skipped_on_load() {
+        let path = snapshot_path();
+        let now_ms = unix_ms(SystemTime::now());
+        let entry = |prompt: &str, expires_at_ms: u64| SnapshotEntry {
+            request: test_support::request(prompt, serde_json::json!({})),
+            response: test_support::response("a", prompt),
+            inserted_at_ms: now_ms - 60_000,
+            expires_at_ms,
+            hits: 0,
+        };
+        let lines: Vec<String> = [entry("lapsed", now_ms - 1), entry("live", now_ms + 30_000)]
+            .iter()
+            .map(|e| serde_json::to_string(e).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let cache = SemanticCache::new(100, 300);
+        assert_eq!(cache.restore_snapshot(&path).await.unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+        assert!(cache.get(&test_support::request("lapsed", serde_json::json!({}))).await.is_none());
+        let live = cache.get_entry(&test_support::request("live", serde_json::json!({}))).await.unwrap();
+        assert!(live.time_to_live_at(Instant::now()) <= Duration::from_secs(30));
+
+        // Restoring from nothing is a cold start, not an error
+        assert_eq!(cache.restore_snapshot(&snapshot_path()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn corrupt_lines_are_skipped_on_load() {
+        let path = snapshot_path();
+        let now_ms = unix_ms(SystemTime::now());
+        let entry = |prompt: &str| SnapshotEntry {
+            request: test_support::request(prompt, serde_json::json!({})),
+            response: test_support::response("a", prompt),
+            inserted_at_ms: now_ms,
+            expires_at_ms: now_ms + 30_000,
+            hits: 0,
+        };
+        let lines = [
+            serde_json::to_string(&entry("before")).unwrap(),
+            "{\"request\": {\"model\": ".to_string(),
+            serde_json::to_string(&entry("after")).unwrap(),
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let cache = SemanticCache::new(100, 300);
+        assert_eq!(cache.restore_snapshot(&path).await.unwrap(), 2);
+        std::fs::remove_file(&path).unwrap();
+        for prompt in ["before", "after"] {
+            assert!(cache.get(&test_support::request(prompt, serde_json::json!({}))).await.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn flushed_background_writes_make_it_into_the_shutdown_snapshot() {
+        let config = SnapshotConfig { path: snapshot_path(), interval_secs: 60 };
+        let cache = SemanticCache::new(100, 60);
+        let prompts = ["one", "two", "three"];
+        for prompt in prompts {
+            let req = test_support::request(prompt, serde_json::json!({}));
+            cache.put_in_background(&req, test_support::response("a", prompt));
+        }
+        assert!(cache.flush(Duration::from_secs(2)).await);
+        assert_eq!(cache.inner.entry_count(), 3, "flush leaves no maintenance pending");
+        save_on_shutdown(&cache, &config);
+
+        let restarted = SemanticCache::new(100, 60);
+        assert_eq!(restarted.restore_snapshot(&config.path).await.unwrap(), 3);
+        std::fs::remove_file(&config.path).unwrap();
+        for prompt in prompts {
+            let hit = restarted.get(&test_support::request(prompt, serde_json::json!({}))).await;
+            assert_eq!(hit.unwrap().content, prompt);
+        }
+    }
+}
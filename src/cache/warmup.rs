@@ -0,0 +1,95 @@
+use crate::model::{LlmRequest, LlmResponse, TokenUsage};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+// Reported as the `provider` of preloaded answers.
+const WARMUP_PROVIDER: &str = "warmup";
+
+// One line of a warmup file: the request as a client would send it, and the answer to
+// serve for it.
+#[derive(Debug, Deserialize)]
+struct WarmupEntry {
+    request: LlmRequest,
+    content: String,
+    #[serde(default)]
+    usage: TokenUsage,
+}
+
+// Reads newline-delimited JSON entries for `SemanticCache::preload`. Blank lines are skipped;
+// any malformed line fails the whole file, naming the line.
+pub fn load(path: &Path) -> Result<Vec<(LlmRequest, LlmResponse)>> {
+    let raw = std::fs::read_to_string(path).with_context(|| format!("cannot read cache warmup file {}", path.display()))?;
+    raw.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let entry: WarmupEntry = serde_json::from_str(line)
+                .with_context(|| format!("invalid cache warmup entry at {}:{}", path.display(), i + 1))?;
+            let response = LlmResponse {
+                content: entry.content,
+                usage: entry.usage,
+                provider: WARMUP_PROVIDER.to_string(),
+                latency_ms: 0,
+                reasoning: None,
+                cache_ttl: None,
+                choices: Vec::new(),
+            };
+            Ok((entry.request, response))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::SemanticCache;
+    use crate::test_support::{self, MockUpstream};
+    use axum::http::HeaderMap;
+    use std::sync::Arc;
+
+    fn warmup_file(contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("llm-edge-warmup-{}.jsonl", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn preloaded_prompts_are_hits_without_a_provider_call() {
+        let path = warmup_file(concat!(
+            r#"{"request": {"model": "m", "prompt": "opening hours?"}, "content": "9 to 5"}"#,
+            "\n\n",
+            r#"{"request": {"model": "m", "prompt": "refunds?"}, "content": "Within 30 days"}"#,
+            "\n",
+        ));
+        let entries = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        assert_eq!(state.cache.preload(entries).await, 2);
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("opening hours?", serde_json::json!({}))).await;
+        assert_eq!(response.headers()[&crate::access_log::CACHE_HEADER], "hit");
+        assert_eq!(response.headers()[&crate::access_log::PROVIDER_HEADER], WARMUP_PROVIDER);
+        assert_eq!(upstream.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn preloaded_entries_expire_with_the_cache_ttl() {
+        let cache = SemanticCache::new(100, 1);
+        let req = test_support::request("opening hours?", serde_json::json!({}));
+        cache.preload(vec![(req.clone(), test_support::response(WARMUP_PROVIDER, "9 to 5"))]).await;
+        assert!(cache.get(&req).await.is_some());
+        tokio::time::sleep(std::time::Duration::from_millis(1_100)).await;
+        assert!(cache.get(&req).await.is_none());
+    }
+
+    #[test]
+    fn malformed_lines_name_their_position() {
+        let path = warmup_file("{\"request\": {\"model\": \"m\", \"prompt\": \"hi\"}, \"content\": \"ok\"}\nnot json\n");
+        let error = load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(format!("{error}").ends_with(":2"), "{error}");
+    }
+}
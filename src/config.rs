@@ -0,0 +1,480 @@
+use crate::auth::AuthConfig;
+use crate::budget::BudgetConfig;
+use crate::rate_limit::ClientRateLimit;
+use crate::retry_budget::RetryBudgetConfig;
+use crate::backoff::BackoffConfig;
+use crate::telemetry::OtlpConfig;
+use crate::shadow::ShadowConfig;
+use crate::refresh::RefreshAhead;
+use crate::cache::embedding::EmbedderConfig;
+use crate::cache::expiry::AdaptiveTtl;
+use crate::cache::negative::NegativeCacheConfig;
+use crate::idempotency::IdempotencyConfig;
+use crate::compression::CompressionConfig;
+use crate::moderation::ModerationConfig;
+use crate::queue::QueueConfig;
+use crate::audit::AuditConfig;
+use crate::router::health::BreakerProbeConfig;
+use crate::router::outlier::OutlierConfig;
+use crate::cache::snapshot::SnapshotConfig;
+use crate::cache::{AdmissionPolicy, CacheKeyNormalization, CacheMode, SemanticCache};
+use crate::gateway::GatewayOptions;
+use crate::model::ProviderConfig;
+use crate::router::strategy::RouteStrategy;
+use crate::router::virtual_models::{self, VirtualModels};
+use crate::router::{Router, ScoringWeights};
+use anyhow::{bail, Context, Result};
+use axum::http::{HeaderName, HeaderValue};
+use notify::{RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Editors often write a file in several steps; wait for writes to settle before reloading.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+pub const CONFIG_ENV_VAR: &str = "LLM_EDGE_CONFIG";
+pub const DEFAULT_CONFIG_PATH: &str = "config/providers.yaml";
+
+// Top-level gateway configuration, loaded from YAML or TOML (chosen by file extension).
+// Request-handling knobs (`GatewayOptions`) sit at the top level next to `providers`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GatewayConfig {
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: SocketAddr,
+    // How long shutdown waits for in-flight requests before dropping them.
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+    // Larger request bodies are rejected with 413 before being buffered.
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    // Per-client token bucket; no limit when absent.
+    #[serde(default)]
+    pub rate_limit: Option<ClientRateLimit>,
+    // Spend ceiling per time window; unlimited when absent.
+    #[serde(default)]
+    pub budget: Option<BudgetConfig>,
+    // Ceiling on failover retries relative to traffic; retries are unbudgeted when absent.
+    #[serde(default)]
+    pub retry_budget: Option<RetryBudgetConfig>,
+    // Delay between failover retries; retries go out immediately when absent.
+    #[serde(default)]
+    pub retry_backoff: Option<BackoffConfig>,
+    #[serde(flatten)]
+    pub options: GatewayOptions,
+    // Read at startup only; reloads update providers, not weights.
+    #[serde(default)]
+    pub scoring: ScoringWeights,
+    // Read at startup only, like `scoring`.
+    #[serde(default)]
+    pub routing_strategy: RouteStrategy,
+    // Last resort when every provider is unhealthy (see Router::with_degraded_fallback).
+    #[serde(default)]
+    pub allow_degraded_fallback: bool,
+    // Region this gateway runs in; requests prefer providers there (see Router::with_region).
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub control_plane: Option<ControlPlaneConfig>,
+    // Provider that answered requests are mirrored to for comparison; off when absent.
+    #[serde(default)]
+    pub shadow: Option<ShadowConfig>,
+    // Replays answers to retried requests carrying an `Idempotency-Key`; off when absent.
+    #[serde(default)]
+    pub idempotency: Option<IdempotencyConfig>,
+    // Brotli or gzip for responses to clients that accept it; off when absent.
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    // Keyword blocklist checked before routing; off when absent.
+    #[serde(default)]
+    pub moderation: Option<ModerationConfig>,
+    // Requests finding every provider at `max_concurrency` wait for a slot; off when absent.
+    #[serde(default)]
+    pub queue: Option<QueueConfig>,
+    // Ejects providers far slower or more failing than the rest; off when absent.
+    #[serde(default)]
+    pub outlier_detection: Option<OutlierConfig>,
+    // Last requests kept in memory for `GET /admin/requests`; off when absent.
+    #[serde(default)]
+    pub audit_log: Option<AuditConfig>,
+    // Gateway-sent half-open probes for providers with an open breaker; off when absent.
+    #[serde(default)]
+    pub breaker_probe: Option<BreakerProbeConfig>,
+    // Trace export; spans are only logged when absent.
+    #[serde(default)]
+    pub otlp: Option<OtlpConfig>,
+    // Model aliases resolved to qualifying providers (see router::virtual_models).
+    #[serde(default)]
+    pub virtual_models: VirtualModels,
+    pub providers: Vec<ProviderConfig>,
+}
+
+// Remote source of provider configs. When set it owns the provider list: the file's
+// `providers` only serve until the first successful poll, and edits to them aren't watched
+// (see GatewayConfig::watches_providers).
+#[derive(Debug, Clone, Deserialize)]
+pub struct ControlPlaneConfig {
+    pub url: String,
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+fn default_poll_interval_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub capacity: u64,
+    // Byte cap on serialized entries; replaces `capacity` when set.
+    pub max_bytes: Option<u64>,
+    pub ttl_secs: u64,
+    // Entries' TTLs are spread over ±this percentage of `ttl_secs` (0 = all the same).
+    pub ttl_jitter_percent: u8,
+    pub mode: CacheMode,
+    // Applied to prompts before hashing; `none` keeps keys exact.
+    pub key_normalization: CacheKeyNormalization,
+    // Backs `mode: embedding`; ignored by the other modes.
+    pub embedder: EmbedderConfig,
+    pub admission: AdmissionPolicy,
+    pub adaptive_ttl: Option<AdaptiveTtl>,
+    pub refresh_ahead: Option<RefreshAhead>,
+    // Short-lived memory of requests that failed on every provider; off when absent.
+    pub negative: Option<NegativeCacheConfig>,
+    // Request/answer pairs loaded into the cache at startup (see cache::warmup).
+    pub warmup_file: Option<PathBuf>,
+    // Periodic snapshot to disk, restored at startup; off when absent.
+    pub snapshot: Option<SnapshotConfig>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            max_bytes: None,
+            ttl_secs: 60 * 5,
+            ttl_jitter_percent: 0,
+            mode: CacheMode::Exact,
+            key_normalization: CacheKeyNormalization::None,
+            embedder: EmbedderConfig::default(),
+            admission: AdmissionPolicy::default(),
+            adaptive_ttl: None,
+            refresh_ahead: None,
+            negative: None,
+            warmup_file: None,
+            snapshot: None,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn build(&self) -> SemanticCache {
+        let cache = SemanticCache::new(self.capacity, self.ttl_secs)
+            .with_admission(self.admission)
+            .with_embedder(self.embedder.build())
+            .with_mode(self.mode)
+            .with_key_normalization(self.key_normalization);
+        let cache = match self.adaptive_ttl {
+            Some(adaptive) => cache.with_adaptive_ttl(adaptive),
+            None => cache,
+        };
+        let cache = match self.ttl_jitter_percent {
+            0 => cache,
+            percent => cache.with_ttl_jitter(percent),
+        };
+        match self.max_bytes {
+            Some(bytes) => cache.with_max_bytes(bytes),
+            None => cache,
+        }
+    }
+}
+
+fn default_bind_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8080))
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+fn default_max_body_bytes() -> usize {
+    2 * 1024 * 1024
+}
+
+impl GatewayConfig {
+    // Path from `--config <path>`, then $LLM_EDGE_CONFIG, then the default location.
+    pub fn path_from_env() -> String {
+        let args: Vec<String> = std::env::args().collect();
+        args.iter()
+            .position(|a| a == "--config")
+            .and_then(|i| args.get(i + 1).cloned())
+            .or_else(|| std::env::var(CONFIG_ENV_VAR).ok())
+            .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string())
+    }
+
+    // Whether provider edits in the config file are hot-reloaded. Not with a control plane:
+    // two sources swapping in their own lists would undo each other's changes, so the remote
+    // one wins.
+    pub fn watches_providers(&self) -> bool {
+        self.control_plane.is_none()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config file {}", path.display()))?;
+        let mut config = Self::parse(&raw, path)?;
+        config.resolve_secrets()?;
+        config.validate()?;
+        virtual_models::expand(&config.virtual_models, &mut config.providers);
+        Ok(config)
+    }
+
+    fn parse(raw: &str, path: &Path) -> Result<Self> {
+        let parsed = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(raw).map_err(anyhow::Error::from),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(raw).map_err(anyhow::Error::from),
+            other => bail!("unsupported config format {:?} for {} (use .yaml or .toml)", other, path.display()),
+        };
+        parsed.with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    fn resolve_secrets(&mut self) -> Result<()> {
+        for key in &mut self.auth.api_keys {
+            *key = interpolate_env(key).context("auth: api_keys")?;
+        }
+        for key in &mut self.auth.admin_keys {
+            *key = interpolate_env(key).context("auth: admin_keys")?;
+        }
+        if let EmbedderConfig::Http { api_key, .. } = &mut self.cache.embedder {
+            *api_key = interpolate_env(api_key).context("cache embedder: api_key")?;
+        }
+        resolve_provider_secrets(&mut self.providers)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.auth.disabled && self.auth.api_keys.iter().chain(&self.auth.admin_keys).all(|k| k.is_empty()) {
+            bail!("auth.api_keys is empty; configure client keys or set auth.disabled for local development");
+        }
+        if let Some(negative) = &self.cache.negative {
+            if negative.ttl_secs >= self.cache.ttl_secs {
+                bail!("cache.negative.ttl_secs must be shorter than cache.ttl_secs");
+            }
+        }
+        if self.cache.ttl_jitter_percent >= 100 {
+            bail!("cache.ttl_jitter_percent must be below 100");
+        }
+        if let Some(backoff) = &self.retry_backoff {
+            if backoff.multiplier < 1.0 || backoff.base_ms > backoff.max_ms {
+                bail!("retry_backoff needs multiplier >= 1 and base_ms <= max_ms");
+            }
+        }
+        if let Some(fallback) = &self.options.fallback_response {
+            if !(200..=599).contains(&fallback.status) {
+                bail!("fallback_response.status must be an HTTP status between 200 and 599");
+            }
+        }
+        if let Some(outlier) = &self.outlier_detection {
+            if outlier.latency_factor <= 1.0 || outlier.error_rate_margin <= 0.0 {
+                bail!("outlier_detection needs latency_factor > 1 and error_rate_margin > 0");
+            }
+        }
+        if let Some(shadow) = &self.shadow {
+            if !self.providers.iter().any(|p| p.id == shadow.provider_id) {
+                bail!("shadow.provider_id {:?} is not a configured provider", shadow.provider_id);
+            }
+        }
+        validate_providers(&self.providers)?;
+        virtual_models::validate(&self.virtual_models, &self.providers)
+    }
+}
+
+pub fn resolve_provider_secrets(providers: &mut [ProviderConfig]) -> Result<()> {
+    for p in providers {
+        for key in p.api_key.iter_mut() {
+            *key = interpolate_env(key).with_context(|| format!("provider {:?}: api_key", p.id))?;
+        }
+        for (name, value) in p.headers.iter_mut() {
+            *value = interpolate_env(value).with_context(|| format!("provider {:?}: header {}", p.id, name))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_providers(providers: &[ProviderConfig]) -> Result<()> {
+    if providers.is_empty() {
+        bail!("config defines no providers");
+    }
+    for p in providers {
+        if p.model_map.is_empty() {
+            bail!("provider {:?} has an empty model_map", p.id);
+        }
+        if p.endpoint.is_empty() && p.base_url.is_none() {
+            bail!("provider {:?} needs an endpoint or a base_url", p.id);
+        }
+        if p.health_check.is_some() && p.health_check_url().is_none() {
+            bail!("provider {:?} has a relative health_check url but no base_url", p.id);
+        }
+        for (name, value) in &p.headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() || HeaderValue::from_str(value).is_err() {
+                bail!("provider {:?} has an invalid header {:?}", p.id, name);
+            }
+        }
+        if p.ewma_alpha.is_some_and(|a| !(a > 0.0 && a <= 1.0)) {
+            bail!("provider {:?} has an ewma_alpha outside (0, 1]", p.id);
+        }
+        if p.weight.is_some_and(|w| !(w.is_finite() && w >= 0.0)) {
+            bail!("provider {:?} has a negative or non-finite weight", p.id);
+        }
+        if p.breaker_error_rate.is_some_and(|r| !(r > 0.0 && r < 1.0)) {
+            bail!("provider {:?} has a breaker_error_rate outside (0, 1)", p.id);
+        }
+    }
+    Ok(())
+}
+
+// Expands `${VAR}` references from the environment so secrets needn't live in the file.
+// A reference to an unset variable is an error rather than silently becoming empty.
+pub fn interpolate_env(value: &str) -> Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            bail!("unterminated ${{...}} in {:?}", value);
+        };
+        let var = &after[..end];
+        let resolved = std::env::var(var)
+            .with_context(|| format!("environment variable {} is not set", var))?;
+        out.push_str(&resolved);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Watches the config file and hot-swaps the provider list on change, unless a control plane
+// owns it (see GatewayConfig::watches_providers). Only providers are reloaded; bind address
+// and cache settings need a restart. An invalid file is logged and
+// ignored so a bad edit never takes down a running gateway.
+pub fn spawn_watcher(path: impl Into<PathBuf>, router: Arc<Router>) -> Result<()> {
+    let path: PathBuf = path.into();
+    let file_name = path.file_name().map(|f| f.to_os_string());
+    // Watch the directory: atomic-rename saves replace the file's inode.
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let relevant = event.paths.iter().any(|p| p.file_name().map(|f| f.to_os_string()) == file_name);
+            if relevant && (event.kind.is_modify() || event.kind.is_create()) {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("cannot watch {}", dir.display()))?;
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(RELOAD_DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+
+            match GatewayConfig::load(&path) {
+                // Added since startup: the running gateway doesn't poll it yet, but it is
+                // about to own the provider list
+                Ok(config) if !config.watches_providers() => {
+                    warn!("Config change in {} not applied: control_plane now set, restart to use it", path.display());
+                }
+                Ok(config) => {
+                    info!("Config changed, reloading {} providers from {}", config.providers.len(), path.display());
+                    if let Err(e) = router.update_providers(config.providers) {
+                        warn!("Config reload from {} not applied: {}", path.display(), e);
+                    }
+                }
+                Err(e) => warn!("Ignoring invalid config reload from {}: {:#}", path.display(), e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(provider_ids: &[&str], control_plane: bool) -> String {
+        let mut raw = String::from("auth:\n  disabled: true\n");
+        if control_plane {
+            raw.push_str("control_plane:\n  url: http://127.0.0.1:9/providers\n");
+        }
+        raw.push_str("providers:\n");
+        for id in provider_ids {
+            raw.push_str(&format!(
+                "  - {{ id: {id}, name: {id}, endpoint: http://127.0.0.1:9/v1, api_key: k, cost_per_1k_input: 0.0, cost_per_1k_output: 0.0, model_map: {{ m: m }} }}\n"
+            ));
+        }
+        raw
+    }
+
+    fn provider_ids(router: &Router) -> Vec<String> {
+        router.providers().iter().map(|p| p.config.id.clone()).collect()
+    }
+
+    #[test]
+    fn a_control_plane_turns_off_the_file_watcher() {
+        let parse = |raw: &str| GatewayConfig::parse(raw, Path::new("gateway.yaml")).unwrap();
+        assert!(parse(&yaml(&["a"], false)).watches_providers());
+        assert!(!parse(&yaml(&["a"], true)).watches_providers());
+    }
+
+    #[tokio::test]
+    async fn file_edits_stop_applying_once_a_control_plane_is_configured() {
+        let dir = std::env::temp_dir().join(format!("llm-edge-watch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gateway.yaml");
+        std::fs::write(&path, yaml(&["a"], false)).unwrap();
+        let router = Arc::new(Router::new(GatewayConfig::load(&path).unwrap().providers).unwrap());
+        spawn_watcher(&path, router.clone()).unwrap();
+
+        let settle = || tokio::time::sleep(RELOAD_DEBOUNCE * 4);
+        std::fs::write(&path, yaml(&["a", "b"], false)).unwrap();
+        settle().await;
+        assert_eq!(provider_ids(&router), ["a", "b"]);
+        // The remote list wins from here on; the file's is left alone
+        std::fs::write(&path, yaml(&["c"], true)).unwrap();
+        settle().await;
+        assert_eq!(provider_ids(&router), ["a", "b"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn header_values_expand_environment_references() {
+        std::env::set_var("LLM_EDGE_TEST_ORG", "org-from-env");
+        let mut providers = vec![ProviderConfig {
+            id: "a".to_string(),
+            headers: std::collections::HashMap::from([("OpenAI-Organization".to_string(), "${LLM_EDGE_TEST_ORG}".to_string())]),
+            ..ProviderConfig::default()
+        }];
+        resolve_provider_secrets(&mut providers).unwrap();
+        assert_eq!(providers[0].headers["OpenAI-Organization"], "org-from-env");
+
+        providers[0].headers.insert("X-Missing".to_string(), "${LLM_EDGE_TEST_UNSET}".to_string());
+        assert!(resolve_provider_secrets(&mut providers).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::config::{resolve_provider_secrets, validate_providers, ControlPlaneConfig};
+use crate::model::ProviderConfig;
+use crate::router::virtual_models::{self, VirtualModels};
+use crate::router::Router;
+use anyhow::{bail, Context, Result};
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// The control plane may serve either a bare provider list or `{ "providers": [...] }`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RemoteConfig {
+    List(Vec<ProviderConfig>),
+    Wrapped { providers: Vec<ProviderConfig> },
+}
+
+// Pulls provider configs from a remote endpoint and applies them through the
+// stats-preserving `Router::update_providers`. Change detection uses the server's ETag when
+// it sends one and a hash of the body otherwise, so an unchanged config never causes a swap.
+pub struct ControlPlaneClient {
+    config: ControlPlaneConfig,
+    // Applied to every fetched list, like to the local file's.
+    virtual_models: VirtualModels,
+    client: reqwest::Client,
+    // Version of the config last applied
+    etag: Option<String>,
+    body_hash: Option<blake3::Hash>,
+    // Version of the last `Poll::Updated`, until it's applied. One the router rejects is
+    // never committed, so the next poll fetches it again rather than calling it unchanged.
+    pending: Option<(Option<String>, blake3::Hash)>,
+}
+
+pub enum Poll {
+    Unchanged,
+    Updated(Vec<ProviderConfig>),
+}
+
+impl ControlPlaneClient {
+    pub fn new(config: ControlPlaneConfig, virtual_models: VirtualModels) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("cannot build control-plane HTTP client")?;
+        Ok(Self { config, virtual_models, client, etag: None, body_hash: None, pending: None })
+    }
+
+    pub async fn poll(&mut self) -> Result<Poll> {
+        let mut request = self.client.get(&self.config.url);
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let resp = request.send().await.context("control plane unreachable")?;
+
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Poll::Unchanged);
+        }
+        if !resp.status().is_success() {
+            bail!("control plane returned HTTP {}", resp.status());
+        }
+
+        let etag = resp.headers().get(ETAG).and_then(|v| v.to_str().ok()).map(str::to_string);
+        let body = resp.bytes().await.context("cannot read control-plane response")?;
+        let hash = blake3::hash(&body);
+        if self.body_hash == Some(hash) {
+            self.etag = etag;
+            return Ok(Poll::Unchanged);
+        }
+
+        let mut providers = match serde_json::from_slice(&body).context("invalid control-plane config")? {
+            RemoteConfig::List(p) | RemoteConfig::Wrapped { providers: p } => p,
+        };
+        resolve_provider_secrets(&mut providers)?;
+        validate_providers(&providers)?;
+        virtual_models::validate(&self.virtual_models, &providers)?;
+        virtual_models::expand(&self.virtual_models, &mut providers);
+
+        self.pending = Some((etag, hash));
+        Ok(Poll::Updated(providers))
+    }
+
+    // Marks the config of the last `Poll::Updated` as the current one.
+    fn commit(&mut self) {
+        if let Some((etag, hash)) = self.pending.take() {
+            self.etag = etag;
+            self.body_hash = Some(hash);
+        }
+    }
+
+    async fn apply(&mut self, router: &Router) {
+        match self.poll().await {
+            Ok(Poll::Updated(providers)) => {
+                info!("Control plane config changed, reloading {} providers", providers.len());
+                match router.update_providers(providers) {
+                    Ok(()) => self.commit(),
+                    Err(e) => warn!("Control plane config not applied: {}", e),
+                }
+            }
+            Ok(Poll::Unchanged) => {}
+            Err(e) => warn!("Control plane poll failed ({}): {:#}", self.config.url, e),
+        }
+    }
+}
+
+// Fetches once before returning (so the gateway starts on the remote config when it's
+// reachable) and then keeps polling in the background. Failures keep the current providers.
+pub async fn start(config: ControlPlaneConfig, virtual_models: VirtualModels, router: Arc<Router>) -> Result<()> {
+    let interval = Duration::from_secs(config.poll_interval_secs.max(1));
+    let mut client = ControlPlaneClient::new(config, virtual_models)?;
+    client.apply(&router).await;
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await; // first tick fires immediately
+        loop {
+            ticker.tick().await;
+            client.apply(&router).await;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::{header, HeaderMap};
+    use axum::response::{IntoResponse, Response};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Served {
+        // (ETag, body) currently served
+        version: Mutex<(String, String)>,
+        not_modified: AtomicUsize,
+    }
+
+    impl Served {
+        fn set(&self, etag: &str, ids: &[&str]) {
+            let providers: Vec<_> = ids
+                .iter()
+                .map(|id| {
+                    serde_json::json!({
+                        "id": id, "name": id, "endpoint": "http://127.0.0.1:9/v1/chat/completions", "api_key": "k",
+                        "cost_per_1k_input": 0.0, "cost_per_1k_output": 0.0, "model_map": {"m": "m"}
+                    })
+                })
+                .collect();
+            *self.version.lock().unwrap() = (etag.to_string(), serde_json::json!({"providers": providers}).to_string());
+        }
+    }
+
+    async fn serve(State(served): State<Arc<Served>>, headers: HeaderMap) -> Response {
+        let (etag, body) = served.version.lock().unwrap().clone();
+        if headers.get(header::IF_NONE_MATCH).is_some_and(|v| v == etag.as_str()) {
+            served.not_modified.fetch_add(1, Ordering::SeqCst);
+            return axum::http::StatusCode::NOT_MODIFIED.into_response();
+        }
+        ([(header::ETAG, etag)], body).into_response()
+    }
+
+    async fn control_plane() -> (Arc<Served>, ControlPlaneClient) {
+        let served = Arc::new(Served::default());
+        let app = axum::Router::new().route("/providers", axum::routing::get(serve)).with_state(served.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/providers", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let config = ControlPlaneConfig { url, poll_interval_secs: 1 };
+        (served, ControlPlaneClient::new(config, VirtualModels::default()).unwrap())
+    }
+
+    fn ids(router: &Router) -> Vec<String> {
+        router.providers().iter().map(|p| p.config.id.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn an_unchanged_config_leaves_the_table_alone() {
+        let (served, mut client) = control_plane().await;
+        let router = Router::new(Vec::new()).unwrap();
+        served.set("\"v1\"", &["a"]);
+        client.apply(&router).await;
+        assert_eq!(ids(&router), ["a"]);
+        let table = router.providers();
+
+        // Same ETag: the server answers 304
+        client.apply(&router).await;
+        assert_eq!(served.not_modified.load(Ordering::SeqCst), 1);
+        // New ETag, same body: the hash says unchanged
+        served.set("\"v2\"", &["a"]);
+        client.apply(&router).await;
+        assert!(Arc::ptr_eq(&table, &router.providers()));
+    }
+
+    #[tokio::test]
+    async fn a_new_config_swaps_the_table() {
+        let (served, mut client) = control_plane().await;
+        let router = Router::new(Vec::new()).unwrap();
+        served.set("\"v1\"", &["a"]);
+        client.apply(&router).await;
+        served.set("\"v2\"", &["a", "b"]);
+        client.apply(&router).await;
+        assert_eq!(ids(&router), ["a", "b"]);
+        assert_eq!(served.not_modified.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn a_config_the_router_did_not_take_is_fetched_again() {
+        let (served, mut client) = control_plane().await;
+        served.set("\"v1\"", &["a"]);
+        // Polled but never committed, as when `update_providers` fails
+        assert!(matches!(client.poll().await.unwrap(), Poll::Updated(_)));
+        assert!(matches!(client.poll().await.unwrap(), Poll::Updated(_)));
+        assert_eq!(served.not_modified.load(Ordering::SeqCst), 0);
+        client.commit();
+        assert!(matches!(client.poll().await.unwrap(), Poll::Unchanged));
+    }
+}
@@ -0,0 +1,135 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// Request header carrying chargeback tags, e.g. `X-Cost-Tags: team=search, env=prod`.
+pub const COST_TAGS_HEADER: &str = "x-cost-tags";
+// Bounds what a single request can add to the tag table.
+const MAX_TAGS_PER_REQUEST: usize = 16;
+const MAX_TAG_LEN: usize = 128;
+// Bounds the tag table itself: once it holds this many tags, spend under new ones goes to
+// OVERFLOW_TAG instead.
+const MAX_TAGS: usize = 1_000;
+const OVERFLOW_TAG: &str = "other";
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq)]
+pub struct TagCost {
+    pub requests: u64,
+    pub cost_usd: f64,
+}
+
+// Provider spend attributed to free-form tags. A request with several tags counts in full
+// under each of them, so per-tag totals can add up to more than the overall spend. Tags past
+// the first MAX_TAGS share the OVERFLOW_TAG entry, counted once per request.
+#[derive(Debug, Default)]
+pub struct CostTracker {
+    by_tag: Mutex<HashMap<String, TagCost>>,
+}
+
+impl CostTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, tags: &[String], cost_usd: f64) {
+        if tags.is_empty() {
+            return;
+        }
+        let Ok(mut by_tag) = self.by_tag.lock() else { return };
+        let mut overflowed = false;
+        for tag in tags {
+            let tag = if by_tag.contains_key(tag) || by_tag.len() < MAX_TAGS {
+                tag.as_str()
+            } else if !overflowed {
+                overflowed = true;
+                OVERFLOW_TAG
+            } else {
+                continue;
+            };
+            let entry = by_tag.entry(tag.to_string()).or_default();
+            entry.requests += 1;
+            entry.cost_usd += cost_usd;
+        }
+    }
+
+    // Snapshot sorted by tag, for stable output.
+    pub fn by_tag(&self) -> BTreeMap<String, TagCost> {
+        match self.by_tag.lock() {
+            Ok(by_tag) => by_tag.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            Err(_) => BTreeMap::new(),
+        }
+    }
+}
+
+// Comma-separated, trimmed, de-duplicated tags from the header value. Overlong tags are
+// dropped rather than truncated so they can't merge into someone else's tag.
+pub fn parse_tags(header: Option<&str>) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in header.unwrap_or_default().split(',').map(str::trim) {
+        if tag.is_empty() || tag.len() > MAX_TAG_LEN || tags.iter().any(|t| t == tag) {
+            continue;
+        }
+        tags.push(tag.to_string());
+        if tags.len() == MAX_TAGS_PER_REQUEST {
+            break;
+        }
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockUpstream};
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn tags_are_trimmed_deduplicated_and_bounded() {
+        assert_eq!(parse_tags(Some(" team=search, env=prod,,team=search ")), ["team=search", "env=prod"]);
+        assert!(parse_tags(None).is_empty());
+        let long = "x".repeat(MAX_TAG_LEN + 1);
+        assert_eq!(parse_tags(Some(&format!("{long}, ok"))), ["ok"]);
+        let many: Vec<String> = (0..40).map(|i| format!("t{i}")).collect();
+        assert_eq!(parse_tags(Some(&many.join(","))).len(), MAX_TAGS_PER_REQUEST);
+    }
+
+    #[test]
+    fn tags_past_the_cap_share_the_overflow_entry() {
+        let costs = CostTracker::new();
+        for i in 0..MAX_TAGS {
+            costs.record(&[format!("t{i}")], 1.0);
+        }
+        costs.record(&["late-1".to_string(), "late-2".to_string(), "t0".to_string()], 1.0);
+        costs.record(&["late-3".to_string()], 1.0);
+
+        let by_tag = costs.by_tag();
+        assert_eq!(by_tag.len(), MAX_TAGS + 1);
+        assert!(!by_tag.contains_key("late-1"));
+        // Counted once per request however many of its tags overflowed
+        assert_eq!(by_tag[OVERFLOW_TAG], TagCost { requests: 2, cost_usd: 2.0 });
+        // Tags already in the table keep counting
+        assert_eq!(by_tag["t0"], TagCost { requests: 2, cost_usd: 2.0 });
+    }
+
+    #[tokio::test]
+    async fn spend_is_reported_per_tag() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        for (i, tags) in ["team=search", "team=search, env=prod", "team=ads"].into_iter().enumerate() {
+            let mut headers = HeaderMap::new();
+            headers.insert(COST_TAGS_HEADER, tags.parse().unwrap());
+            let req = test_support::request(&format!("prompt {i}"), serde_json::json!({}));
+            assert!(test_support::complete(&state, headers, req).await.status().is_success());
+        }
+        test_support::complete(&state, HeaderMap::new(), test_support::request("untagged", serde_json::json!({}))).await;
+
+        // 10 tokens each way at $1/1k
+        let per_request = 0.02;
+        let by_tag = crate::admin::handle_costs_by_tag(State(state)).await.0;
+        let summary: Vec<(&str, u64, f64)> =
+            by_tag.iter().map(|(tag, cost)| (tag.as_str(), cost.requests, (cost.cost_usd / per_request).round())).collect();
+        assert_eq!(summary, [("env=prod", 1, 1.0), ("team=ads", 1, 1.0), ("team=search", 2, 2.0)]);
+    }
+}
@@ -0,0 +1,180 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+use thiserror::Error;
+
+// Why a provider call failed. The split matters for health tracking: only provider-side
+// failures count against the circuit breaker (see `is_provider_fault`).
+#[derive(Debug, Clone, Error)]
+pub enum ProviderError {
+    #[error("timeout after {after_ms}ms")]
+    Timeout { after_ms: u64 },
+    // No connection within the provider's connect timeout: unreachable, not slow
+    #[error("connect timeout after {after_ms}ms")]
+    ConnectTimeout { after_ms: u64 },
+    #[error("connection failed: {0}")]
+    Connect(String),
+    // `retry_after_ms` is the upstream's `Retry-After` on a 429 or 503, if it sent one
+    #[error("HTTP {status}")]
+    Status { status: u16, retry_after_ms: Option<u64> },
+    // Malformed or truncated response body
+    #[error("invalid response: {0}")]
+    Decode(String),
+    // JSON output was requested but the content doesn't parse
+    #[error("content is not valid JSON: {0}")]
+    InvalidJson(String),
+}
+
+impl ProviderError {
+    // `connect_timeout_ms` is the configured connect timeout, if any; reqwest reports its
+    // expiry as an error that is both a connect and a timeout error.
+    pub fn from_reqwest(e: reqwest::Error, timeout_ms: u64, connect_timeout_ms: Option<u64>) -> Self {
+        if let (true, true, Some(after_ms)) = (e.is_connect(), e.is_timeout(), connect_timeout_ms) {
+            ProviderError::ConnectTimeout { after_ms }
+        } else if e.is_timeout() {
+            ProviderError::Timeout { after_ms: timeout_ms }
+        } else if e.is_connect() || e.is_request() {
+            ProviderError::Connect(e.to_string())
+        } else {
+            ProviderError::Decode(e.to_string())
+        }
+    }
+
+    // Timeouts, connection problems, 5xx and broken bodies are the provider's problem, and so
+    // are 401/403: every key was refused (see Provider::send), so no request will get through.
+    // Other 4xx and off-format content mean the request itself was rejected, which says
+    // nothing about provider health. A 429 means "slow down" rather than "broken" (see
+    // `is_rate_limited`).
+    pub fn is_provider_fault(&self) -> bool {
+        match self {
+            ProviderError::Status { status, .. } => *status >= 500 || matches!(status, 401 | 403),
+            // The provider is up; it's the model's answer to this prompt that failed
+            ProviderError::InvalidJson(_) => false,
+            _ => true,
+        }
+    }
+
+    // Failures of the connection rather than of the request, which a second try at the same
+    // provider may not hit (see ProviderConfig::same_provider_retries).
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ProviderError::Timeout { .. } | ProviderError::ConnectTimeout { .. } | ProviderError::Connect(_))
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(self, ProviderError::Status { status: 429, .. })
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ProviderError::Status { retry_after_ms, .. } => retry_after_ms.map(Duration::from_millis),
+            _ => None,
+        }
+    }
+}
+
+// A gateway error response in OpenAI's shape, `{"error": {"message", "type", "code"}}`, so
+// OpenAI SDK clients can parse it. `details` adds fields next to those, e.g. the failed
+// attempts behind a 502.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub kind: &'static str,
+    pub code: &'static str,
+    pub message: String,
+    details: Map<String, Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, kind: &'static str, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, kind, code, message: message.into(), details: Map::new() }
+    }
+
+    pub fn invalid_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request_error", code, message)
+    }
+
+    // No provider could take the request (503)
+    pub fn unavailable(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "server_error", code, message)
+    }
+
+    // Providers were tried and failed (502)
+    pub fn upstream(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_GATEWAY, "upstream_error", code, message)
+    }
+
+    pub fn with_detail(mut self, key: &str, value: impl serde::Serialize) -> Self {
+        self.details.insert(key.to_string(), serde_json::to_value(value).unwrap_or_default());
+        self
+    }
+
+    pub fn body(&self) -> Value {
+        let mut error = self.details.clone();
+        error.insert("message".to_string(), json!(self.message));
+        error.insert("type".to_string(), json!(self.kind));
+        error.insert("code".to_string(), json!(self.code));
+        json!({ "error": error })
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockUpstream};
+    use axum::http::HeaderMap;
+    use std::sync::Arc;
+
+    async fn error_of(response: Response) -> (StatusCode, Value) {
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_follow_the_openai_shape() {
+        let error = ApiError::invalid_request("invalid_body", "missing model").with_detail("field", "model");
+        let (status, body) = error_of(error.into_response()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({"error": {"message": "missing model", "type": "invalid_request_error", "code": "invalid_body", "field": "model"}})
+        );
+    }
+
+    #[tokio::test]
+    async fn no_provider_is_a_503_with_an_error_body() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        state.router.set_enabled("a", false);
+        let req = test_support::request("hi", json!({}));
+        let (status, body) = error_of(test_support::complete(&state, HeaderMap::new(), req).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"]["type"], "server_error");
+        assert_eq!(body["error"]["code"], "no_providers_available");
+        assert_eq!(body["error"]["message"], "No providers available");
+        assert_eq!(upstream.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn provider_failures_are_a_502_listing_the_attempts() {
+        let upstream = MockUpstream::start().await;
+        upstream.fail_with(500);
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let req = test_support::request("hi", json!({}));
+        let (status, body) = error_of(test_support::complete(&state, HeaderMap::new(), req).await).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["error"]["type"], "upstream_error");
+        assert_eq!(body["error"]["code"], "all_providers_failed");
+        assert!(body["error"]["message"].is_string());
+        assert!(!body["error"]["attempts"].as_array().unwrap().is_empty());
+    }
+}
@@ -1,76 +1,1483 @@
 use crate::model::{LlmRequest, LlmResponse, TokenUsage};
-use crate::router::Router;
+use crate::router::{Provider, Router, Selection};
+use crate::router::decision::RoutingDecision;
+use crate::balancer::stats::{InFlightGuard, ProviderStats};
 use crate::cache::SemanticCache;
+use crate::cache::flight::Flight;
+use crate::cache::negative::NegativeCache;
+use crate::auth::{self, ClientAuth};
+use crate::access_log::{self, AccessLog};
+use crate::budget::SpendBudget;
+use crate::retry_budget::RetryBudget;
+use crate::backoff::{Backoff, BackoffConfig};
+use crate::costs::{self, CostTracker};
+use crate::tokens::TokenEstimator;
+use crate::error::{ApiError, ProviderError};
+use crate::validate::ValidRequest;
+use crate::streaming::{self, ReplayChunking};
+use crate::shadow::Shadow;
+use crate::idempotency::IdempotencyStore;
+use crate::moderation::{ModerationPolicy, ModerationResult};
+use crate::queue::WaitQueue;
+use crate::audit::AuditLog;
 use axum::{
-    extract::{State, Json},
-    response::{IntoResponse, Response},
-    http::StatusCode,
+    extract::{rejection::JsonRejection, State, Json},
+    response::{IntoResponse, Response, sse::{Event, Sse}},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
 };
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::sync::Arc;
-use std::time::Instant;
-use tracing::{info, error};
+use std::time::{Duration, Instant};
+use tracing::{field, info, info_span, warn, error, Instrument, Span};
+
+const STRIP_REASONING_HEADER: &str = "x-strip-reasoning";
+pub const FALLBACK_HEADER: &str = "x-llm-edge-fallback";
+pub const DEGRADED_HEADER: &str = "x-llm-degraded";
+const FALLBACK_PROVIDER: &str = "fallback";
 
 pub struct AppState {
     pub router: Arc<Router>,
     pub cache: Arc<SemanticCache>,
+    pub options: GatewayOptions,
+    pub costs: Arc<CostTracker>,
+    pub estimator: Arc<TokenEstimator>,
+    pub budget: Option<SpendBudget>,
+    pub retry_budget: Option<RetryBudget>,
+    pub backoff: Option<BackoffConfig>,
+    pub negative_cache: Option<NegativeCache>,
+    pub shadow: Option<Arc<Shadow>>,
+    pub idempotency: Option<IdempotencyStore>,
+    pub moderation: Option<Arc<dyn ModerationPolicy>>,
+    pub queue: Option<WaitQueue>,
+    pub audit: Option<AuditLog>,
+    pub auth: Arc<ClientAuth>,
+}
+
+// Request-handling knobs that aren't owned by the router or the cache.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GatewayOptions {
+    // Additional providers to try after the first one fails.
+    pub max_retries: usize,
+    // Fraction of successful requests that emit full per-request logs (0.0..=1.0).
+    // Stats are updated for every request and errors are always logged.
+    pub telemetry_sample_rate: f64,
+    // Fraction of routed requests that emit a structured routing-decision record
+    // (see RoutingDecision). 0.0 disables them.
+    pub routing_log_sample_rate: f64,
+    // Requests with a higher `temperature` skip the cache entirely (no lookup, no store),
+    // since their answers are meant to vary. Requests that don't set one are cacheable.
+    pub max_cacheable_temperature: f32,
+    // Learn a per-model correction for input token estimates from reported usage.
+    pub auto_correct_token_estimates: bool,
+    // Estimated USD cap on a request dispatched to several providers at once (the primary
+    // plus a shadow mirror); past it the fan-out degrades to the primary alone (see
+    // Router::cap_fanout).
+    pub max_fanout_cost_usd: Option<f64>,
+    // Requests asking for more output tokens than this are rejected with 400.
+    pub max_tokens_ceiling: Option<u32>,
+    // Items in one `/v1/chat/completions/batch` call, and how many of them run at once.
+    pub max_batch_items: usize,
+    pub batch_concurrency: usize,
+    // Canned answer instead of the error when no provider could serve a request.
+    pub fallback_response: Option<FallbackResponse>,
+    // Requests taking longer end to end are logged at warn, the rest at debug (see AccessLog).
+    pub slow_request_threshold_ms: Option<u64>,
+    // How stored answers are cut into chunks for streaming clients.
+    pub stream_replay_chunking: ReplayChunking,
+}
+
+// Served with `status` and an `x-llm-edge-fallback: true` header when every provider is
+// unavailable, failed or at capacity. Never cached or charged.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FallbackResponse {
+    pub content: String,
+    #[serde(default = "default_fallback_status")]
+    pub status: u16,
+}
+
+fn default_fallback_status() -> u16 {
+    200
+}
+
+impl Default for GatewayOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 1,
+            telemetry_sample_rate: 1.0,
+            routing_log_sample_rate: 0.0,
+            max_cacheable_temperature: 0.0,
+            auto_correct_token_estimates: false,
+            max_fanout_cost_usd: None,
+            max_tokens_ceiling: None,
+            max_batch_items: 64,
+            batch_concurrency: 8,
+            fallback_response: None,
+            slow_request_threshold_ms: None,
+            stream_replay_chunking: ReplayChunking::Word,
+        }
+    }
+}
+
+impl GatewayOptions {
+    pub fn sample_telemetry(&self) -> bool {
+        sample(self.telemetry_sample_rate)
+    }
+
+    pub fn sample_routing_log(&self) -> bool {
+        sample(self.routing_log_sample_rate)
+    }
+
+    // Pinned requests want one provider's answer, so they never read or fill the cache.
+    pub fn is_cacheable(&self, req: &LlmRequest) -> bool {
+        req.temperature.is_none_or(|t| t <= self.max_cacheable_temperature) && req.provider.is_none()
+    }
+}
+
+fn sample(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    rate > 0.0 && rand::thread_rng().gen_bool(rate)
+}
+
+#[derive(Debug, Serialize)]
+struct FailedAttempt {
+    provider: String,
+    error: String,
+    #[serde(skip)]
+    stats: Arc<ProviderStats>,
+    #[serde(skip)]
+    retry_after: Option<Duration>,
+    #[serde(skip)]
+    failed_at: Instant,
+}
+
+impl FailedAttempt {
+    fn new(provider: &Provider, error: &ProviderError) -> Self {
+        Self {
+            provider: provider.config.name.clone(),
+            error: error.to_string(),
+            stats: provider.stats.clone(),
+            retry_after: error.retry_after(),
+            failed_at: Instant::now(),
+        }
+    }
+}
+
+// `error` for a request no provider could serve, or the configured fallback in its place.
+fn unavailable(state: &AppState, req: &LlmRequest, error: ApiError) -> Response {
+    let Some(fallback) = &state.options.fallback_response else { return error.into_response() };
+    warn!("Serving the fallback response for {} instead of {}", req.model, error.code);
+    let status = StatusCode::from_u16(fallback.status).unwrap_or(StatusCode::OK);
+    let resp = LlmResponse {
+        content: fallback.content.clone(),
+        usage: TokenUsage::default(),
+        provider: FALLBACK_PROVIDER.to_string(),
+        latency_ms: 0,
+        reasoning: None,
+        cache_ttl: None,
+        choices: Vec::new(),
+    };
+    let headers = [(FALLBACK_HEADER, "true"), (header::CACHE_CONTROL.as_str(), "no-store")];
+    if req.is_streaming() {
+        return (status, headers, Sse::new(replay_events(&resp, state.options.stream_replay_chunking))).into_response();
+    }
+    (status, headers, Json(resp)).into_response()
+}
+
+// Headers telling clients (and intermediary caches) whether the response came from
+// the gateway cache, how old it is, and how long it stays fresh.
+// `ttl` is None when the response was not stored, in which case clients are told not to reuse it.
+fn cache_headers(hit: bool, age: Duration, ttl: Option<Duration>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let age_secs = age.as_secs();
+    headers.insert("x-cache", HeaderValue::from_static(if hit { "HIT" } else { "MISS" }));
+    headers.insert(header::AGE, HeaderValue::from(age_secs));
+    let cache_control = match ttl {
+        Some(ttl) => format!("max-age={}", ttl.as_secs().saturating_sub(age_secs)),
+        None => "no-store".to_string(),
+    };
+    if let Ok(v) = HeaderValue::from_str(&cache_control) {
+        headers.insert(header::CACHE_CONTROL, v);
+    }
+    headers
 }
 
 pub async fn handle_chat_completions(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<LlmRequest>,
+    headers: HeaderMap,
+    payload: Result<Json<LlmRequest>, JsonRejection>,
 ) -> Response {
-    let start = Instant::now();
-
-    // 1. Cache Lookup (O(1))
-    if let Some(cached_resp) = state.cache.get(&req.prompt).await {
-        info!("Cache hit for prompt");
-        return (StatusCode::OK, Json(cached_resp)).into_response();
-    }
-
-    // 2. Router Selection (O(1))
-    let provider_opt = state.router.select(&req);
-    
-    match provider_opt {
-        Some(provider) => {
-            // 3. Provider Call
-            let call_start = Instant::now();
-            
-            let call_result = provider.call(&req).await;
-            
-            let latency_duration = call_start.elapsed();
-            
-            match call_result {
-                Ok(mut resp) => {
-                    // 4. Update Stats
-                    provider.stats.record_success(latency_duration);
-                    
-                    resp.latency_ms = latency_duration.as_millis() as u64;
-                    
-                    // 5. Update Cache (async/background in real impl)
-                    // For prototype, we wait or spawn. Moka is fast.
-                    state.cache.put(&req.prompt, resp.clone()).await;
-                    
-                    let total_time = start.elapsed();
-                    // Overhead = Total - Latency
-                    let overhead = total_time.saturating_sub(latency_duration);
-                    
-                    info!(
-                        "Request processed in {:?} (Latency: {:?}, Overhead: {:?}) Provider: {}", 
-                        total_time, latency_duration, overhead, provider.config.name
-                    );
-
-                    (StatusCode::OK, Json(resp)).into_response()
+    // A retry of a request that went through gets the stored answer (see IdempotencyStore).
+    // Streams aren't stored.
+    let claim = match (&state.idempotency, &payload) {
+        (Some(store), Ok(Json(req))) if !req.is_streaming() => match store.claim(&headers, req).await {
+            Ok(claim) => claim,
+            Err(response) => return response,
+        },
+        _ => None,
+    };
+    let response = logged_completion(state, &headers, payload).await;
+    match claim {
+        Some(claim) => claim.finish(response).await,
+        None => response,
+    }
+}
+
+// One completion with its access log line; also runs each item of a batch.
+pub(crate) async fn logged_completion(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    payload: Result<Json<LlmRequest>, JsonRejection>,
+) -> Response {
+    let sampled = state.options.sample_telemetry();
+    let mut log = AccessLog::new(headers);
+    let slow_threshold_ms = state.options.slow_request_threshold_ms;
+    let mut response = chat_completion(state.clone(), headers, payload, sampled, &mut log).await;
+    // The fallback answer isn't any provider's
+    if response.status().is_success() && !response.headers().contains_key(FALLBACK_HEADER) {
+        log.attribute(response.headers_mut());
+    }
+    // Successes follow telemetry sampling; errors and slow requests are always logged
+    if sampled || !response.status().is_success() || log.is_slow(slow_threshold_ms) {
+        log.emit(response.status(), slow_threshold_ms);
+    }
+    if let Some(audit) = &state.audit {
+        audit.record(&log, response.status());
+    }
+    response
+}
+
+async fn chat_completion(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    payload: Result<Json<LlmRequest>, JsonRejection>,
+    sampled: bool,
+    log: &mut AccessLog,
+) -> Response {
+    let Json(mut req) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => {
+            let code = if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE { "payload_too_large" } else { "invalid_body" };
+            return ApiError::new(rejection.status(), "invalid_request_error", code, rejection.body_text()).into_response();
+        }
+    };
+    log.model = req.model.clone();
+    log.stream = req.is_streaming();
+    if let Some(audit) = &state.audit {
+        (log.prompt_hash, log.prompt) = audit.prompt_of(&req);
+    }
+    let tags = costs::parse_tags(headers.get(costs::COST_TAGS_HEADER).and_then(|v| v.to_str().ok()));
+    let strip_reasoning = strip_reasoning_override(headers);
+    if let Some(provider) = headers.get(access_log::PROVIDER_HEADER).and_then(|v| v.to_str().ok()) {
+        req.provider = Some(provider.trim().to_string());
+    }
+
+    if let Err(error) = ValidRequest::try_from((&req, &*state.router, &state.options)) {
+        return error.into_response();
+    }
+    if req.provider.is_some() && !state.auth.is_admin(auth::bearer_token(headers)) {
+        let error = ApiError::new(StatusCode::FORBIDDEN, "invalid_request_error", "provider_pinning_forbidden", "Pinning a provider needs an admin key");
+        return error.into_response();
+    }
+    if let Some(moderation) = &state.moderation {
+        if let ModerationResult::Deny { reason } = moderation.check(&req).await {
+            info!("Request for {} denied by moderation: {}", req.model, reason);
+            return ApiError::new(StatusCode::FORBIDDEN, "invalid_request_error", "content_blocked", reason).into_response();
+        }
+    }
+
+    // 1. Cache Lookup (O(1)), partitioned by model
+    let cacheable = state.options.is_cacheable(&req);
+    let cached_entry = if cacheable {
+        let lookup = info_span!("cache_lookup", hit = field::Empty);
+        let entry = state.cache.get_entry(&req).instrument(lookup.clone()).await;
+        lookup.record("hit", entry.is_some());
+        entry
+    } else {
+        None
+    };
+    if let Some(mut entry) = cached_entry {
+        if strip_reasoning == Some(true) {
+            entry.response.reasoning = None;
+        }
+        log.cache_hit = true;
+        log.usage = Some(entry.response.usage.clone());
+        log.provider = Some(entry.response.provider.clone());
+        log.cost_usd = Some(0.0);
+        let mut headers = cache_headers(true, entry.age(), Some(entry.lifetime()));
+        // Cache-only mode: nothing upstream could refresh this answer, so it may be stale
+        if state.router.is_degraded() {
+            state.cache.record_degraded_hit();
+            headers.insert(DEGRADED_HEADER, HeaderValue::from_static("true"));
+        }
+        // Entries hold the full text regardless of how they were produced, so either
+        // delivery mode can be served from the same entry.
+        if req.is_streaming() {
+            return (headers, Sse::new(replay_events(&entry.response, state.options.stream_replay_chunking))).into_response();
+        }
+        return (StatusCode::OK, headers, Json(entry.response)).into_response();
+    }
+
+    // Failed everywhere moments ago: answer with the same error until a provider recovers
+    if let Some(negative) = state.negative_cache.as_ref().filter(|_| req.provider.is_none()) {
+        if let Some(error) = negative.get(&req).await {
+            return unavailable(&state, &req, error);
+        }
+    }
+
+    // An identical request is already waiting on a provider: share its answer rather than
+    // make another call. Served like a hit; if the leader gets no answer, go upstream.
+    let mut flight = None;
+    if cacheable && !req.is_streaming() {
+        match state.cache.join_flight(&req) {
+            Some(Flight::Follower(follower)) => {
+                if let Some(answer) = follower.answer().await {
+                    let mut resp = answer.response;
+                    if strip_reasoning == Some(true) {
+                        resp.reasoning = None;
+                    }
+                    log.cache_hit = true;
+                    log.usage = Some(resp.usage.clone());
+                    log.provider = Some(resp.provider.clone());
+                    log.cost_usd = Some(0.0);
+                    let headers = cache_headers(true, Duration::ZERO, answer.cached.then(|| state.cache.ttl_for(&resp)));
+                    return (StatusCode::OK, headers, Json(resp)).into_response();
+                }
+            }
+            Some(Flight::Leader(leader)) => flight = Some(leader),
+            None => {}
+        }
+    }
+
+    // Over budget: cache hits above stay free, anything that would cost money is refused
+    if let Some(budget) = &state.budget {
+        if let Some(retry_after_ms) = budget.exhausted() {
+            let retry_after = retry_after_ms.div_ceil(1000).to_string();
+            let error = ApiError::new(budget.status(), "insufficient_quota", "budget_exhausted", "Spend budget exhausted for the current window");
+            return ([(header::RETRY_AFTER, retry_after)], error).into_response();
+        }
+    }
+
+    // No provider could serve it even when healthy: the client's fault, not an outage
+    if let Some(missing) = state.router.missing_capability(&req).filter(|_| req.provider.is_none()) {
+        let message = format!("No provider for model `{}` supports {}", req.model, missing);
+        return ApiError::invalid_request("unsupported_capability", message).into_response();
+    }
+
+    // 2. Router Selection (O(1)), ranked so we can fall back on failure
+    let input_tokens = state.estimator.estimate(&req);
+    let select = || {
+        let selection = info_span!("select", candidates = field::Empty);
+        let candidates = selection.in_scope(|| state.router.select_candidates(&req, auth::bearer_token(headers), input_tokens));
+        selection.record("candidates", candidates.providers.len());
+        candidates
+    };
+    let Selection { providers: mut candidates, mut degraded } = match &req.provider {
+        Some(id) => match pinned_provider(&state.router, &req, id) {
+            Ok(provider) => Selection { providers: vec![provider], degraded: false },
+            Err(error) => return error.into_response(),
+        },
+        None => select(),
+    };
+    // Selection is done: from here on the request waits for a slot
+    log.enqueued_at = Instant::now();
+    // Everyone able to serve it is full: wait for a slot, if queueing is configured
+    if let Some(queue) = state.queue.as_ref().filter(|_| candidates.is_empty()) {
+        let mut saturated = state.router.saturated_for(&req, input_tokens);
+        if !saturated.is_empty() {
+            let position = match queue.enter() {
+                Ok(position) => position,
+                Err(error) => return unavailable(&state, &req, error),
+            };
+            log.enqueued_at = position.entered_at();
+            while candidates.is_empty() && !saturated.is_empty() {
+                if let Err(error) = position.wait(&saturated).await {
+                    warn!("Gave up waiting for capacity for model {}", req.model);
+                    return unavailable(&state, &req, error);
+                }
+                Selection { providers: candidates, degraded } = select();
+                saturated = state.router.saturated_for(&req, input_tokens);
+            }
+        }
+    }
+    if candidates.is_empty() {
+        error!("No healthy provider found for model {}", req.model);
+        return unavailable(&state, &req, ApiError::unavailable("no_providers_available", "No providers available"));
+    }
+    if let Some(retry_budget) = &state.retry_budget {
+        retry_budget.record_request();
+    }
+
+    let mut decision = state
+        .options
+        .sample_routing_log()
+        .then(|| RoutingDecision::capture(&state.router, &req, &candidates, input_tokens));
+
+    if req.is_streaming() {
+        return stream_chat_completion(state, req, tags, Selection { providers: candidates, degraded }, decision, sampled, log).await;
+    }
+
+    let mut attempts = Vec::new();
+    let mut backoff = state.backoff.map(Backoff::new);
+    for provider in candidates {
+        if attempts.len() > state.options.max_retries {
+            break;
+        }
+        back_off(&mut backoff, &attempts).await;
+        let waiting_since = queued_since(log.enqueued_at, &attempts);
+        // Saturated since selection: move on without counting it as a failed attempt
+        let Some(in_flight) = provider.try_acquire() else { continue };
+        if !retry_allowed(&state, &attempts) {
+            break;
+        }
+        // Selection only saw the breaker half-open; another request may have taken the probe.
+        // The degraded fallback's pick is sent regardless.
+        if !degraded && !provider.try_acquire_probe() {
+            refund_retry(&state, &attempts);
+            continue;
+        }
+        log.provider = Some(provider.config.name.clone());
+
+        // 3. Provider Call
+        let call_start = Instant::now();
+        provider.stats.record_queue_wait(call_start.duration_since(waiting_since));
+        
+        let upstream = upstream_span(&provider);
+        let call_result = provider.call(&req).instrument(upstream.clone()).await;
+        drop(in_flight);
+        
+        let latency_duration = call_start.elapsed();
+        record_upstream(upstream, latency_duration, call_result.as_ref().err());
+        log.upstream = Some(log.upstream.unwrap_or_default() + latency_duration);
+        
+        match call_result {
+            Ok(mut resp) => {
+                // 4. Update Stats
+                provider.stats.record_success(latency_duration);
+                
+                resp.latency_ms = latency_duration.as_millis() as u64;
+                // The provider's setting strips what's cached and shared with followers too; the
+                // request's override only its own copy. Usage (and so cost) still counts reasoning tokens.
+                let reasoning = resp.reasoning.take();
+                if !provider.config.strip_reasoning {
+                    resp.reasoning = reasoning.clone();
+                }
+                
+                // 5. Update Cache in the background (drained on shutdown).
+                // Only admit responses that are worth keeping (see AdmissionPolicy).
+                state.estimator.observe(&req, resp.usage.prompt_tokens);
+                let completion = resp.completion_text();
+                state.estimator.fill_missing(&req, &mut resp.usage, &completion);
+                let cost = provider.charge(&resp.usage);
+                state.costs.record(&tags, cost);
+                if let Some(budget) = &state.budget {
+                    budget.record(cost);
+                }
+                if let Some(decision) = decision.take() {
+                    decision.succeeded(&provider.config.name, attempts.len() + 1, resp.latency_ms, cost);
+                }
+                if let Some(shadow) = &state.shadow {
+                    shadow.mirror(&state.router, &req, &provider, &resp, state.options.max_fanout_cost_usd);
+                }
+                let cached = cacheable && state.cache.admits(&req, &resp, cost);
+                if cached {
+                    state.cache.put_in_background(&req, resp.clone());
+                }
+                if let Some(leader) = flight.take() {
+                    leader.complete(&resp, cached);
+                }
+                log.usage = Some(resp.usage.clone());
+                log.cost_usd = Some(cost);
+
+                let ttl = cached.then(|| state.cache.ttl_for(&resp));
+                let headers = cache_headers(false, Duration::ZERO, ttl);
+                resp.reasoning = reasoning.filter(|_| !strip_reasoning.unwrap_or(provider.config.strip_reasoning));
+                return (StatusCode::OK, headers, Json(resp)).into_response();
+            },
+            Err(e) => {
+                provider.stats.record_failure(&e);
+                error!("Provider call failed: {} (provider: {})", e, provider.config.name);
+                attempts.push(FailedAttempt::new(&provider, &e));
+            }
+        }
+    }
+
+    if let Some(decision) = decision {
+        decision.failed(attempts.len());
+    }
+    all_providers_failed(&state, &req, attempts).await
+}
+
+// The provider a pinned request names, if it can serve it right now. Never falls back to
+// another one: a pinned request is asking for that provider's answer.
+fn pinned_provider(router: &Router, req: &LlmRequest, id: &str) -> Result<Arc<Provider>, ApiError> {
+    let Some(provider) = router.providers().iter().find(|p| p.config.id == id).cloned() else {
+        return Err(ApiError::invalid_request("unknown_provider", format!("No provider with id `{}`", id)));
+    };
+    if !provider.supports_model(&req.model) || !provider.supports_params(req) {
+        let message = format!("Provider `{}` doesn't serve this request for model `{}`", id, req.model);
+        return Err(ApiError::invalid_request("provider_unsupported_model", message));
+    }
+    if !provider.has_capacity() || !provider.is_available() {
+        return Err(ApiError::invalid_request("provider_unavailable", format!("Provider `{}` is unavailable", id)));
+    }
+    Ok(provider)
+}
+
+// Per-request override of ProviderConfig::strip_reasoning.
+fn strip_reasoning_override(headers: &HeaderMap) -> Option<bool> {
+    match headers.get(STRIP_REASONING_HEADER)?.to_str().ok()?.trim() {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+// One provider attempt, for trace export (see telemetry).
+fn upstream_span(provider: &Provider) -> Span {
+    info_span!("upstream", provider = %provider.config.name, latency_ms = field::Empty, error = field::Empty)
+}
+
+// Closes the span: its end time is when this is called.
+fn record_upstream(span: Span, latency: Duration, error: Option<&ProviderError>) {
+    span.record("latency_ms", latency.as_millis() as u64);
+    if let Some(e) = error {
+        span.record("error", field::display(e));
+    }
+}
+
+// Whether another provider may be tried. The first attempt is always allowed; later ones
+// spend from the retry budget, if one is configured.
+fn retry_allowed(state: &AppState, attempts: &[FailedAttempt]) -> bool {
+    match &state.retry_budget {
+        Some(budget) if !attempts.is_empty() && !budget.try_retry() => {
+            warn!("Retry budget exhausted, failing fast after {} attempt(s)", attempts.len());
+            false
+        }
+        _ => true,
+    }
+}
+
+// Undoes retry_allowed for a provider that was skipped without being called.
+fn refund_retry(state: &AppState, attempts: &[FailedAttempt]) {
+    if let Some(budget) = state.retry_budget.as_ref().filter(|_| !attempts.is_empty()) {
+        budget.refund();
+    }
+}
+
+// Where a provider call's queue wait starts: the first one waited since the request was
+// enqueued, a failover only since the attempt before it gave up (its backoff included).
+fn queued_since(enqueued_at: Instant, attempts: &[FailedAttempt]) -> Instant {
+    attempts.last().map_or(enqueued_at, |attempt| attempt.failed_at)
+}
+
+// Waits before a retry, once per failed attempt (saturated providers skipped in between
+// don't wait again). The last failure's `Retry-After`, if any, sets the delay.
+async fn back_off(backoff: &mut Option<Backoff>, attempts: &[FailedAttempt]) {
+    let Some(backoff) = backoff else { return };
+    if let Some(last) = attempts.last().filter(|_| attempts.len() > backoff.retries() as usize) {
+        backoff.wait(last.retry_after).await;
+    }
+}
+
+// Every attempted provider failed. Remembered in the negative cache, if enabled, so the same
+// request isn't sent to them again right away.
+async fn all_providers_failed(state: &AppState, req: &LlmRequest, attempts: Vec<FailedAttempt>) -> Response {
+    match (&req.provider, attempts.is_empty()) {
+        // The pinned provider filled up (or lost its probe) after the check in pinned_provider
+        (Some(id), true) => {
+            let message = format!("Provider `{}` is unavailable", id);
+            return ApiError::invalid_request("provider_unavailable", message).into_response();
+        }
+        // Nothing was attempted: every candidate was at its concurrency limit
+        (None, true) => {
+            return unavailable(state, req, ApiError::unavailable("providers_at_capacity", "All providers at capacity"));
+        }
+        _ => {}
+    }
+    let error = ApiError::upstream("all_providers_failed", "All providers failed").with_detail("attempts", &attempts);
+    if req.provider.is_some() {
+        return error.into_response();
+    }
+    if let Some(negative) = &state.negative_cache {
+        let tried = attempts.into_iter().map(|a| a.stats).collect();
+        negative.insert(req, error.clone(), tried).await;
+    }
+    unavailable(state, req, error)
+}
+
+// Streaming path: relays provider deltas to the client as SSE. Nothing is sent until a
+// provider yields its first delta, so providers are tried in rank order until one gets that
+// far; after that, upstream failures surface as stream errors. The concatenated content is
+// cached only once the upstream finishes cleanly.
+async fn stream_chat_completion(
+    state: Arc<AppState>,
+    req: LlmRequest,
+    tags: Vec<String>,
+    selection: Selection,
+    decision: Option<RoutingDecision>,
+    sampled: bool,
+    log: &mut AccessLog,
+) -> Response {
+    let Selection { providers: candidates, degraded } = selection;
+    let mut attempts = Vec::new();
+    let mut backoff = state.backoff.map(Backoff::new);
+    for provider in candidates {
+        if attempts.len() > state.options.max_retries {
+            break;
+        }
+        back_off(&mut backoff, &attempts).await;
+        let waiting_since = queued_since(log.enqueued_at, &attempts);
+        let Some(in_flight) = provider.try_acquire() else { continue };
+        if !retry_allowed(&state, &attempts) {
+            break;
+        }
+        // Selection only saw the breaker half-open; another request may have taken the probe.
+        // The degraded fallback's pick is sent regardless.
+        if !degraded && !provider.try_acquire_probe() {
+            refund_retry(&state, &attempts);
+            continue;
+        }
+        log.provider = Some(provider.config.name.clone());
+        let call_start = Instant::now();
+        provider.stats.record_queue_wait(call_start.duration_since(waiting_since));
+
+        // Spans the wait for the first delta; the rest of the stream is the request span's
+        let span = upstream_span(&provider);
+        let started = async {
+            match provider.call_stream(&req).await {
+                Ok(mut upstream) => match upstream.next().await {
+                    Some(Err(e)) => Err(e),
+                    // Put the first delta back in front of the rest
+                    first => Ok(stream::iter(first).chain(upstream).boxed()),
                 },
-                Err(e) => {
-                    provider.stats.record_failure();
-                    error!("Provider call failed: {}", e);
-                    (StatusCode::BAD_GATEWAY, format!("Provider error: {}", e)).into_response()
+                Err(e) => Err(e),
+            }
+        }
+        .instrument(span.clone())
+        .await;
+        record_upstream(span, call_start.elapsed(), started.as_ref().err());
+        log.upstream = Some(log.upstream.unwrap_or_default() + call_start.elapsed());
+        match started {
+            Ok(upstream) => {
+                provider.stats.record_ttft(call_start.elapsed());
+                let relay = StreamRelay {
+                    upstream,
+                    state: state.clone(),
+                    provider,
+                    req,
+                    tags,
+                    decision,
+                    attempts: attempts.len() + 1,
+                    span: Span::current(),
+                    content: String::new(),
+                    call_start,
+                    finished: false,
+                    sampled,
+                    _in_flight: in_flight,
+                };
+                return Sse::new(relay.into_events()).into_response();
+            }
+            Err(e) => {
+                provider.stats.record_failure(&e);
+                error!("Provider stream failed before first token: {} (provider: {})", e, provider.config.name);
+                attempts.push(FailedAttempt::new(&provider, &e));
+            }
+        }
+    }
+
+    if let Some(decision) = decision {
+        decision.failed(attempts.len());
+    }
+    all_providers_failed(&state, &req, attempts).await
+}
+
+struct StreamRelay {
+    upstream: BoxStream<'static, Result<String, ProviderError>>,
+    state: Arc<AppState>,
+    provider: Arc<Provider>,
+    // Original request, the cache key for the completed stream
+    req: LlmRequest,
+    // Chargeback tags the completed stream's cost is attributed to
+    tags: Vec<String>,
+    decision: Option<RoutingDecision>,
+    attempts: usize,
+    // Request span; the body is polled after the handler returned, outside of it.
+    span: Span,
+    // Everything relayed so far, cached when the stream completes
+    content: String,
+    call_start: Instant,
+    finished: bool,
+    sampled: bool,
+    _in_flight: InFlightGuard,
+}
+
+impl StreamRelay {
+    fn into_events(self) -> impl Stream<Item = Result<Event, Infallible>> + Send {
+        stream::unfold(Some(self), |relay| {
+            let span = relay.as_ref().map(|r| r.span.clone()).unwrap_or_else(Span::none);
+            Self::next_event(relay).instrument(span)
+        })
+    }
+
+    async fn next_event(relay: Option<Self>) -> Option<(Result<Event, Infallible>, Option<Self>)> {
+        let mut relay = relay?;
+        match relay.upstream.next().await {
+            Some(Ok(delta)) => {
+                relay.content.push_str(&delta);
+                let event = chunk_event(&relay.provider.config.name, &delta);
+                Some((Ok(event), Some(relay)))
+            }
+            Some(Err(e)) => {
+                relay.finished = true;
+                relay.provider.stats.record_failure(&e);
+                if let Some(decision) = relay.decision.take() {
+                    decision.failed(relay.attempts);
                 }
+                error!("Provider stream failed: {} (provider: {})", e, relay.provider.config.name);
+                let body = ApiError::upstream("stream_interrupted", e.to_string()).body();
+                Some((Ok(Event::default().data(body.to_string())), None))
+            }
+            None => {
+                relay.finish().await;
+                Some((Ok(Event::default().data("[DONE]")), None))
             }
         }
-        None => {
-            error!("No healthy provider found for model {}", req.model);
-            (StatusCode::SERVICE_UNAVAILABLE, "No providers available").into_response()
+    }
+
+    async fn finish(&mut self) {
+        self.finished = true;
+        let latency = self.call_start.elapsed();
+        self.provider.stats.record_success(latency);
+
+        let mut usage = TokenUsage::default();
+        self.state.estimator.fill_missing(&self.req, &mut usage, &self.content);
+        let resp = LlmResponse {
+            content: std::mem::take(&mut self.content),
+            usage,
+            provider: self.provider.config.name.clone(),
+            latency_ms: latency.as_millis() as u64,
+            reasoning: None,
+            // Headers aren't kept for streams, so only the provider's configured TTL applies
+            cache_ttl: self.provider.cache_ttl(None),
+            choices: Vec::new(),
+        };
+        let cost = self.provider.charge(&resp.usage);
+        self.state.costs.record(&self.tags, cost);
+        if let Some(budget) = &self.state.budget {
+            budget.record(cost);
+        }
+        if let Some(decision) = self.decision.take() {
+            decision.succeeded(&self.provider.config.name, self.attempts, resp.latency_ms, cost);
+        }
+        if let Some(shadow) = &self.state.shadow {
+            shadow.mirror(&self.state.router, &self.req, &self.provider, &resp, self.state.options.max_fanout_cost_usd);
+        }
+        if self.state.options.is_cacheable(&self.req) && self.state.cache.admits(&self.req, &resp, cost) {
+            self.state.cache.put(&self.req, resp).await;
+        }
+        if self.sampled {
+            info!("Stream completed in {:?} Provider: {}", latency, self.provider.config.name);
+        }
+    }
+}
+
+impl Drop for StreamRelay {
+    fn drop(&mut self) {
+        // Dropped before completion means the client went away; dropping `upstream` closes
+        // the provider connection and `_in_flight` releases the slot. The provider answered,
+        // so it counts as a success, which also resolves a half-open probe.
+        if !self.finished {
+            let _span = self.span.enter();
+            self.provider.stats.record_success(self.call_start.elapsed());
+            info!("Client disconnected mid-stream (provider: {})", self.provider.config.name);
+        }
+    }
+}
+
+// Serves a stored response to a streaming client: the content in chunks, then [DONE].
+fn replay_events(resp: &LlmResponse, chunking: ReplayChunking) -> impl Stream<Item = Result<Event, Infallible>> + Send {
+    let events: Vec<_> = streaming::replay_chunks(&resp.content, chunking)
+        .into_iter()
+        .map(|chunk| Ok(chunk_event(&resp.provider, chunk)))
+        .chain([Ok(Event::default().data("[DONE]"))])
+        .collect();
+    stream::iter(events)
+}
+
+fn chunk_event(provider: &str, delta: &str) -> Event {
+    let chunk = serde_json::json!({
+        "object": "chat.completion.chunk",
+        "provider": provider,
+        "choices": [{ "index": 0, "delta": { "content": delta } }],
+    });
+    Event::default().data(chunk.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use crate::queue::QueueConfig;
+    use crate::test_support::{self, MockUpstream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    // Counts access log lines
+    struct AccessLines(Arc<AtomicUsize>);
+
+    impl<S: tracing::Subscriber> Layer<S> for AccessLines {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            if event.metadata().target() == crate::access_log::TARGET {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
         }
     }
+
+    // Text of an SSE response's content deltas, and whether it ended with [DONE]
+    async fn streamed_text(response: Response) -> (String, bool) {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let data: Vec<&str> = body.lines().filter_map(|line| line.strip_prefix("data: ")).collect();
+        let text = data
+            .iter()
+            .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+            .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str().map(str::to_string))
+            .collect();
+        (text, data.last() == Some(&"[DONE]"))
+    }
+
+    #[tokio::test]
+    async fn failover_queue_wait_excludes_the_failed_attempt() {
+        let upstream = MockUpstream::start().await;
+        upstream.fail_with(500);
+        upstream.delay(Duration::from_millis(100));
+        let state = Arc::new(test_support::state(vec![upstream.provider("a"), upstream.provider("b")]));
+
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(upstream.calls(), 2);
+        // Both calls were dispatched right away; the second didn't wait on the first's 100ms
+        for provider in state.router.providers().iter() {
+            assert!(provider.stats.ewma_queue_wait_us.value() < 50_000.0, "{}", provider.config.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn time_in_the_wait_queue_counts_as_queue_wait() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(100));
+        let mut state = test_support::state(vec![ProviderConfig { max_concurrency: Some(1), ..upstream.provider("a") }]);
+        state.queue = Some(WaitQueue::new(QueueConfig { max_wait_ms: 1_000, max_waiting: 8 }));
+        let state = Arc::new(state);
+
+        let first = test_support::complete(&state, HeaderMap::new(), test_support::request("one", serde_json::json!({})));
+        let second = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            test_support::complete(&state, HeaderMap::new(), test_support::request("two", serde_json::json!({}))).await
+        };
+        let (first, second) = tokio::join!(first, second);
+        assert!(first.status().is_success() && second.status().is_success());
+        // The second waited ~80ms of the first's call: 0.125 of it on top of a ~0 first sample
+        let wait_us = state.router.providers()[0].stats.ewma_queue_wait_us.value();
+        assert!((5_000.0..20_000.0).contains(&wait_us), "{wait_us}");
+    }
+
+    #[tokio::test]
+    async fn cache_headers_tell_hits_from_misses() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let req = || test_support::request("hi", serde_json::json!({}));
+
+        let miss = test_support::complete(&state, HeaderMap::new(), req()).await;
+        assert_eq!(miss.headers()["x-cache"], "MISS");
+        assert_eq!(miss.headers()[header::AGE], "0");
+        assert_eq!(miss.headers()[header::CACHE_CONTROL], "max-age=300");
+
+        tokio::time::sleep(Duration::from_millis(1_100)).await;
+        let hit = test_support::complete(&state, HeaderMap::new(), req()).await;
+        assert_eq!(hit.headers()["x-cache"], "HIT");
+        assert_eq!(hit.headers()[header::AGE], "1");
+        assert_eq!(hit.headers()[header::CACHE_CONTROL], "max-age=299");
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_buffered_answer_is_replayed_to_a_streaming_request() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+
+        let buffered = test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        assert!(buffered.status().is_success());
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+
+        let streamed =
+            test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({"stream": true}))).await;
+        assert_eq!(streamed.headers()["x-cache"], "HIT");
+        assert!(streamed.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/event-stream"));
+        let (text, done) = streamed_text(streamed).await;
+        assert_eq!(text, test_support::CONTENT);
+        assert!(done);
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn sampling_logs_a_share_of_successes_and_every_error() {
+        let lines = Arc::new(AtomicUsize::new(0));
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(AccessLines(lines.clone())));
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.options.telemetry_sample_rate = 0.1;
+        let state = Arc::new(state);
+
+        for _ in 0..500 {
+            let response = test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+            assert!(response.status().is_success());
+        }
+        let successes_logged = lines.swap(0, Ordering::Relaxed);
+        // ~50 expected; this range misses with odds well under one in a million
+        assert!((15..=100).contains(&successes_logged), "{successes_logged}");
+
+        upstream.fail_with(500);
+        for i in 0..20 {
+            let req = test_support::request(&format!("error {i}"), serde_json::json!({}));
+            assert!(!test_support::complete(&state, HeaderMap::new(), req).await.status().is_success());
+        }
+        assert_eq!(lines.load(Ordering::Relaxed), 20);
+    }
+
+    #[tokio::test]
+    async fn cancelled_requests_give_their_permits_back() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(500));
+        let state = Arc::new(test_support::state(vec![ProviderConfig { max_concurrency: Some(2), ..upstream.provider("a") }]));
+        let provider = state.router.providers()[0].clone();
+
+        for i in 0..20 {
+            let req = test_support::request(&format!("cancelled {i}"), serde_json::json!({"stream": i % 2 == 0}));
+            let call = test_support::complete(&state, HeaderMap::new(), req);
+            assert!(tokio::time::timeout(Duration::from_millis(20), call).await.is_err());
+        }
+        assert_eq!(provider.stats.in_flight.load(Ordering::Relaxed), 0);
+
+        upstream.delay(Duration::ZERO);
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("after", serde_json::json!({}))).await;
+        assert!(response.status().is_success());
+        assert_eq!(provider.stats.in_flight.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn high_temperature_requests_bypass_the_cache() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let send = |temperature: f64| {
+            let req = test_support::request("hi", serde_json::json!({"temperature": temperature}));
+            test_support::complete(&state, HeaderMap::new(), req)
+        };
+
+        for _ in 0..2 {
+            let response = send(1.5).await;
+            assert_eq!(response.headers()["x-cache"], "MISS");
+            assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        }
+        assert_eq!(upstream.calls(), 2);
+
+        // Deterministic requests are still cached
+        send(0.0).await;
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        assert_eq!(send(0.0).await.headers()["x-cache"], "HIT");
+        assert_eq!(upstream.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn slow_providers_time_out_as_recorded_failures() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(500));
+        let state = Arc::new(test_support::state(vec![ProviderConfig { timeout_ms: Some(100), ..upstream.provider("a") }]));
+
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("timeout after 100ms"));
+        let stats = &state.router.providers()[0].stats;
+        assert_eq!(stats.consec_errors.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.error_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn stripped_reasoning_still_counts_toward_usage() {
+        let upstream = MockUpstream::start().await;
+        upstream.think("let me think");
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let answer = |prompt: &str, strip: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(strip) = strip {
+                headers.insert(STRIP_REASONING_HEADER, strip.parse().unwrap());
+            }
+            let req = test_support::request(prompt, serde_json::json!({}));
+            async {
+                let response = test_support::complete(&state, headers, req).await;
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let kept = answer("kept", None).await;
+        assert_eq!(kept["reasoning"], "let me think");
+        let stripped = answer("stripped", Some("true")).await;
+        assert!(stripped.get("reasoning").is_none());
+        assert_eq!(stripped["content"], test_support::CONTENT);
+        assert_eq!(stripped["usage"]["completion_tokens"], test_support::COMPLETION_TOKENS);
+        assert!(state.router.providers()[0].stats.total_cost() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn corrected_token_estimates_decide_what_fits_a_context_window() {
+        let (small, large) = (MockUpstream::start().await, MockUpstream::start().await);
+        let mut state = test_support::state(vec![
+            ProviderConfig { max_context_tokens: Some(100), cost_per_1k_input: 0.1, cost_per_1k_output: 0.1, ..small.provider("small") },
+            large.provider("large"),
+        ]);
+        state.estimator = Arc::new(TokenEstimator::new(true));
+        let state = Arc::new(state);
+        let req = |i: usize| test_support::request(&format!("{i} {}", "word ".repeat(40)), serde_json::json!({}));
+        assert!(crate::tokens::raw_estimate(&req(0)) < 100);
+
+        assert_eq!(test_support::complete(&state, HeaderMap::new(), req(0)).await.status(), StatusCode::OK);
+        assert_eq!((small.calls(), large.calls()), (1, 0));
+        // Providers have been counting four times the raw estimate
+        for _ in 0..50 {
+            state.estimator.observe(&req(0), 4 * crate::tokens::raw_estimate(&req(0)) as u32);
+        }
+        assert!(state.estimator.estimate(&req(1)) > 100);
+        assert_eq!(test_support::complete(&state, HeaderMap::new(), req(1)).await.status(), StatusCode::OK);
+        assert_eq!((small.calls(), large.calls()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn stripping_for_one_client_keeps_reasoning_in_the_cache() {
+        let upstream = MockUpstream::start().await;
+        upstream.think("let me think");
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let answer = |strip: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(strip) = strip {
+                headers.insert(STRIP_REASONING_HEADER, strip.parse().unwrap());
+            }
+            async {
+                let response = test_support::complete(&state, headers, test_support::request("hi", serde_json::json!({}))).await;
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        assert!(answer(Some("true")).await.get("reasoning").is_none());
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        // Served from the cache, with the reasoning the first client didn't want
+        assert_eq!(answer(None).await["reasoning"], "let me think");
+        assert!(answer(Some("true")).await.get("reasoning").is_none());
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn providers_set_to_strip_reasoning_keep_it_out_of_the_cache() {
+        let upstream = MockUpstream::start().await;
+        upstream.think("let me think");
+        let config = ProviderConfig { strip_reasoning: true, ..upstream.provider("a") };
+        let state = Arc::new(test_support::state(vec![config]));
+        let mut headers = HeaderMap::new();
+        headers.insert(STRIP_REASONING_HEADER, "false".parse().unwrap());
+        let response = test_support::complete(&state, headers, test_support::request("hi", serde_json::json!({}))).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        // The override gets this client the reasoning, but the shared copy goes without
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["reasoning"], "let me think");
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        let cached = state.cache.get(&test_support::request("hi", serde_json::json!({}))).await.unwrap();
+        assert_eq!(cached.reasoning, None);
+    }
+
+    #[tokio::test]
+    async fn streams_fail_over_before_the_first_token() {
+        let (failing, healthy) = (MockUpstream::start().await, MockUpstream::start().await);
+        failing.fail_with(503);
+        // The failing provider is cheaper, so it is tried first
+        let state = Arc::new(test_support::state(vec![
+            ProviderConfig { cost_per_1k_input: 0.1, cost_per_1k_output: 0.1, ..failing.provider("failing") },
+            healthy.provider("healthy"),
+        ]));
+
+        let req = test_support::request("hi", serde_json::json!({"stream": true}));
+        let response = test_support::complete(&state, HeaderMap::new(), req).await;
+        assert!(response.status().is_success());
+        assert_eq!(streamed_text(response).await, (test_support::CONTENT.to_string(), true));
+        assert_eq!((failing.calls(), healthy.calls()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn streams_fail_over_when_the_upstream_drops_before_the_first_delta() {
+        let (broken, healthy) = (MockUpstream::start().await, MockUpstream::start().await);
+        // Accepted with 200 and SSE headers, so only reading the stream shows it's broken
+        broken.break_streams();
+        let state = Arc::new(test_support::state(vec![
+            ProviderConfig { cost_per_1k_input: 0.1, cost_per_1k_output: 0.1, ..broken.provider("broken") },
+            healthy.provider("healthy"),
+        ]));
+
+        let req = test_support::request("hi", serde_json::json!({"stream": true}));
+        let response = test_support::complete(&state, HeaderMap::new(), req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[&access_log::PROVIDER_HEADER], "healthy");
+        assert_eq!(streamed_text(response).await, (test_support::CONTENT.to_string(), true));
+        assert_eq!((broken.calls(), healthy.calls()), (1, 1));
+        assert_eq!(state.router.providers()[0].stats.error_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn skipping_a_provider_whose_probe_is_taken_spends_no_retry() {
+        use crate::retry_budget::RetryBudgetConfig;
+        let (failing, probing, healthy) = (MockUpstream::start().await, MockUpstream::start().await, MockUpstream::start().await);
+        failing.fail_with(500);
+        let mut state = test_support::state(vec![
+            ProviderConfig { cost_per_1k_input: 0.1, cost_per_1k_output: 0.1, ..failing.provider("failing") },
+            ProviderConfig { cost_per_1k_input: 0.5, cost_per_1k_output: 0.5, ..probing.provider("probing") },
+            healthy.provider("healthy"),
+        ]);
+        // Room for exactly one retry over the window
+        state.retry_budget = Some(RetryBudget::new(RetryBudgetConfig { ratio: 0.0, min_retries_per_sec: 0.1 }));
+        let state = Arc::new(state);
+        // Half-open, with another request already holding the probe
+        let probing_provider = &state.router.providers()[1];
+        let (now, cooldown) = (crate::balancer::breaker::now_millis(), probing_provider.breaker_cooldown_ms());
+        probing_provider.stats.breaker.on_failure_at(now, true);
+        assert!(probing_provider.stats.breaker.try_acquire_at(now + cooldown, cooldown));
+
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[&access_log::PROVIDER_HEADER], "healthy");
+        assert_eq!((failing.calls(), probing.calls(), healthy.calls()), (1, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn a_pinned_provider_lost_after_the_check_is_reported_unavailable() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.options.fallback_response = Some(FallbackResponse { content: "try again later".to_string(), status: 200 });
+        let state = Arc::new(state);
+        // Half-open passes pinned_provider, but another request holds the probe
+        let provider = &state.router.providers()[0];
+        let (now, cooldown) = (crate::balancer::breaker::now_millis(), provider.breaker_cooldown_ms());
+        provider.stats.breaker.on_failure_at(now, true);
+        assert!(provider.stats.breaker.try_acquire_at(now + cooldown, cooldown));
+
+        let req = test_support::request("hi", serde_json::json!({"provider": "a"}));
+        let response = test_support::complete(&state, HeaderMap::new(), req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.headers().get(FALLBACK_HEADER).is_none());
+        let body: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["error"]["code"], "provider_unavailable");
+        assert_eq!(upstream.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected_with_413() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let app = axum::Router::new()
+            .route("/v1/chat/completions", axum::routing::post(handle_chat_completions))
+            .layer(axum::extract::DefaultBodyLimit::max(1_024))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let small = serde_json::json!({"model": "m", "prompt": "hi"});
+        assert_eq!(client.post(&url).json(&small).send().await.unwrap().status(), reqwest::StatusCode::OK);
+
+        let large = serde_json::json!({"model": "m", "prompt": "x".repeat(4_096)});
+        let response = client.post(&url).json(&large).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "payload_too_large");
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn max_tokens_over_the_ceiling_is_a_400() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.options.max_tokens_ceiling = Some(100);
+        let state = Arc::new(state);
+
+        let within = test_support::request("hi", serde_json::json!({"max_tokens": 100}));
+        assert_eq!(test_support::complete(&state, HeaderMap::new(), within).await.status(), StatusCode::OK);
+        let over = test_support::request("hi", serde_json::json!({"max_tokens": 101}));
+        let response = test_support::complete(&state, HeaderMap::new(), over).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("invalid_max_tokens"));
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn json_mode_falls_back_past_providers_answering_prose() {
+        let (prose, json) = (MockUpstream::start().await, MockUpstream::start().await);
+        json.answer_with(r#"{"answer": 42}"#);
+        let state = Arc::new(test_support::state(vec![prose.provider("prose"), json.provider("json")]));
+        // The prose provider is tried first
+        state.router.providers()[0].stats.record_success(Duration::from_millis(10));
+        state.router.providers()[1].stats.record_success(Duration::from_millis(500));
+
+        let req = test_support::request("hi", serde_json::json!({"response_format": {"type": "json_object"}}));
+        let response = test_support::complete(&state, HeaderMap::new(), req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[&access_log::PROVIDER_HEADER], "json");
+        let body: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["content"], r#"{"answer": 42}"#);
+        assert_eq!((prose.calls(), json.calls()), (1, 1));
+
+        // Off-format content is the model's doing, not a sign the provider is down
+        let prose_stats = &state.router.providers()[0].stats;
+        assert_eq!(prose_stats.consec_errors.load(Ordering::Relaxed), 0);
+
+        // Without JSON mode, prose is a fine answer
+        let plain = test_support::request("hello", serde_json::json!({}));
+        assert_eq!(test_support::complete(&state, HeaderMap::new(), plain).await.status(), StatusCode::OK);
+        assert_eq!(prose.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_providers_are_skipped_without_opening_the_breaker() {
+        let (limited, spare) = (MockUpstream::start().await, MockUpstream::start().await);
+        limited.fail_with(429);
+        let state = Arc::new(test_support::state(vec![limited.provider("limited"), spare.provider("spare")]));
+        state.router.providers()[0].stats.record_success(Duration::from_millis(10));
+        state.router.providers()[1].stats.record_success(Duration::from_millis(500));
+
+        for i in 0..2 * crate::balancer::breaker::DEFAULT_FAILURE_THRESHOLD {
+            let req = test_support::request(&format!("prompt {i}"), serde_json::json!({}));
+            let response = test_support::complete(&state, HeaderMap::new(), req).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[&access_log::PROVIDER_HEADER], "spare");
+        }
+        // Only the first request found out; the rest went straight to the spare
+        assert_eq!((limited.calls(), spare.calls()), (1, 10));
+        let provider = &state.router.providers()[0];
+        assert!(provider.is_throttled());
+        assert_eq!(provider.breaker_state(), crate::balancer::breaker::BreakerState::Closed);
+        assert_eq!(provider.stats.consec_errors.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn every_choice_is_returned_and_cached_under_its_n() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let body = |response: Response| async move {
+            serde_json::from_slice::<serde_json::Value>(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap()
+        };
+        let ask = |n: Option<u32>| {
+            let extra = n.map_or(serde_json::json!({}), |n| serde_json::json!({"n": n}));
+            test_support::complete(&state, HeaderMap::new(), test_support::request("hi", extra))
+        };
+
+        let three = body(ask(Some(3)).await).await;
+        let expected = [test_support::CONTENT.to_string(), format!("{} 1", test_support::CONTENT), format!("{} 2", test_support::CONTENT)];
+        assert_eq!(three["choices"], serde_json::json!(expected));
+        assert_eq!(three["content"], test_support::CONTENT);
+        assert_eq!(three["usage"]["completion_tokens"], test_support::COMPLETION_TOKENS);
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+
+        // Served from the cache, with all three
+        let again = ask(Some(3)).await;
+        assert_eq!(again.headers()["x-cache"], "HIT");
+        assert_eq!(body(again).await["choices"], serde_json::json!(expected));
+        assert_eq!(upstream.calls(), 1);
+
+        // A single completion is its own entry
+        let one = ask(None).await;
+        assert_eq!(one.headers()["x-cache"], "MISS");
+        assert!(body(one).await.get("choices").is_none());
+        assert_eq!(upstream.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn streams_record_time_to_first_token_apart_from_total_latency() {
+        let upstream = MockUpstream::start().await;
+        upstream.answer_with("one two three four");
+        upstream.delay(Duration::from_millis(100));
+        upstream.pace(Duration::from_millis(100));
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+
+        let streamed = test_support::request("hi", serde_json::json!({"stream": true}));
+        let response = test_support::complete(&state, HeaderMap::new(), streamed.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let provider = &state.router.providers()[0];
+        let stats = provider.stats.snapshot();
+        assert!((100_000..300_000).contains(&stats.ewma_ttft_us), "ttft {}us", stats.ewma_ttft_us);
+        assert!(stats.ewma_latency_us >= 400_000, "latency {}us", stats.ewma_latency_us);
+        // Streams are ranked by when output starts, everything else by the whole call
+        assert_eq!(provider.latency_ms_for(&streamed), stats.ewma_ttft_us as f64 / 1000.0);
+        let buffered = test_support::request("hi", serde_json::json!({}));
+        assert!(provider.latency_ms_for(&buffered) >= 400.0);
+    }
+
+    #[tokio::test]
+    async fn the_fallback_answers_when_every_provider_is_down() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        let provider = state.router.providers()[0].clone();
+        provider.stats.breaker.on_failure_at(crate::balancer::breaker::now_millis(), true);
+        let req = || test_support::request("hi", serde_json::json!({}));
+
+        // Without one configured, the error stands
+        let unconfigured = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        unconfigured.router.set_enabled("a", false);
+        let response = test_support::complete(&unconfigured, HeaderMap::new(), req()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().get(FALLBACK_HEADER).is_none());
+
+        state.options.fallback_response = Some(FallbackResponse { content: "try again later".to_string(), status: 200 });
+        let state = Arc::new(state);
+        let response = test_support::complete(&state, HeaderMap::new(), req()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[FALLBACK_HEADER], "true");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        let body: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["content"], "try again later");
+        assert_eq!(upstream.calls(), 0);
+
+        // Also once the providers tried have all failed; never cached for later
+        provider.stats.record_success(Duration::from_millis(5));
+        upstream.fail_with(500);
+        let response = test_support::complete(&state, HeaderMap::new(), req()).await;
+        assert_eq!(response.headers()[FALLBACK_HEADER], "true");
+        upstream.fail_with(0);
+        let response = test_support::complete(&state, HeaderMap::new(), req()).await;
+        assert!(response.headers().get(FALLBACK_HEADER).is_none());
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        assert_eq!(state.cache.get(&req()).await.unwrap().content, test_support::CONTENT);
+    }
+
+    #[tokio::test]
+    async fn the_fallback_status_is_configurable() {
+        let mut state = test_support::state(vec![MockUpstream::start().await.provider("a")]);
+        state.router.set_enabled("a", false);
+        state.options.fallback_response = Some(FallbackResponse { content: "down".to_string(), status: 503 });
+        let response = test_support::complete(&Arc::new(state), HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[FALLBACK_HEADER], "true");
+    }
+
+    #[tokio::test]
+    async fn cached_answers_replay_to_streaming_clients_in_chunks() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.options.stream_replay_chunking = ReplayChunking::Sentence;
+        let state = Arc::new(state);
+        let cached = "First sentence. Second one! And a third?";
+        state.cache.put(&test_support::request("hi", serde_json::json!({})), test_support::response("a", cached)).await;
+
+        let streamed =
+            test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({"stream": true}))).await;
+        assert_eq!(streamed.headers()["x-cache"], "HIT");
+        let body = axum::body::to_bytes(streamed.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let data: Vec<&str> = body.lines().filter_map(|line| line.strip_prefix("data: ")).collect();
+        let deltas: Vec<String> = data[..data.len() - 1]
+            .iter()
+            .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+            .map(|chunk| chunk["choices"][0]["delta"]["content"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(deltas, ["First sentence. ", "Second one! ", "And a third?"]);
+        assert_eq!(data.last(), Some(&"[DONE]"));
+        assert_eq!(upstream.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn the_degraded_fallback_dispatches_past_open_breakers() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![]);
+        state.router = Arc::new(Router::new(vec![upstream.provider("a")]).unwrap().with_degraded_fallback(true));
+        let state = Arc::new(state);
+        state.router.providers()[0].stats.breaker.on_failure_at(crate::balancer::breaker::now_millis(), true);
+
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let streamed = test_support::request("hello", serde_json::json!({"stream": true}));
+        let response = test_support::complete(&state, HeaderMap::new(), streamed).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(streamed_text(response).await, (test_support::CONTENT.to_string(), true));
+        assert_eq!(upstream.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn cache_hits_are_marked_degraded_while_no_provider_is_up() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let ask = |prompt: &str| test_support::complete(&state, HeaderMap::new(), test_support::request(prompt, serde_json::json!({})));
+        ask("cached").await;
+        assert!(state.cache.drain_writes(Duration::from_secs(1)).await);
+        let healthy_hit = ask("cached").await;
+        assert_eq!(healthy_hit.headers()["x-cache"], "HIT");
+        assert!(healthy_hit.headers().get(DEGRADED_HEADER).is_none());
+
+        state.router.providers()[0].stats.breaker.on_failure_at(crate::balancer::breaker::now_millis(), true);
+        assert!(state.router.is_degraded());
+        let degraded_hit = ask("cached").await;
+        assert_eq!(degraded_hit.status(), StatusCode::OK);
+        assert_eq!(degraded_hit.headers()[DEGRADED_HEADER], "true");
+        assert_eq!(ask("novel").await.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(state.cache.stats().degraded_hits, 1);
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn streaming_requests_route_only_to_streaming_providers() {
+        use crate::model::Capability;
+        let (buffered, streaming) = (MockUpstream::start().await, MockUpstream::start().await);
+        let with = |upstream: &MockUpstream, id: &str, capabilities: &[Capability]| ProviderConfig {
+            capabilities: Some(capabilities.iter().copied().collect()),
+            ..upstream.provider(id)
+        };
+        let state = Arc::new(test_support::state(vec![
+            with(&buffered, "buffered", &[Capability::JsonMode]),
+            with(&streaming, "streaming", &[Capability::Streaming]),
+        ]));
+        // The buffered-only provider is the faster one
+        state.router.providers()[0].stats.record_success(Duration::from_millis(10));
+        state.router.providers()[1].stats.record_success(Duration::from_millis(500));
+
+        for i in 0..5 {
+            let req = test_support::request(&format!("stream {i}"), serde_json::json!({"stream": true}));
+            let response = test_support::complete(&state, HeaderMap::new(), req).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[&access_log::PROVIDER_HEADER], "streaming");
+            axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        }
+        assert_eq!((buffered.calls(), streaming.calls()), (0, 5));
+        let plain = test_support::complete(&state, HeaderMap::new(), test_support::request("plain", serde_json::json!({}))).await;
+        assert_eq!(plain.status(), StatusCode::OK);
+        assert_eq!(plain.headers()[&access_log::PROVIDER_HEADER], "buffered");
+        assert_eq!((buffered.calls(), streaming.calls()), (1, 5));
+
+        // Nobody offers tools
+        let tools = test_support::request("call", serde_json::json!({"tools": [{"type": "function", "function": {"name": "f"}}]}));
+        let response = test_support::complete(&state, HeaderMap::new(), tools).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["error"]["code"], "unsupported_capability");
+        assert_eq!((buffered.calls(), streaming.calls()), (1, 5));
+    }
 }
@@ -0,0 +1,215 @@
+use crate::auth;
+use crate::error::ApiError;
+use crate::gateway::FALLBACK_HEADER;
+use crate::model::LlmRequest;
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use moka::future::Cache;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LEN: usize = 255;
+
+// Off unless configured. `window_secs` is how long a key is remembered after its answer.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    pub window_secs: u64,
+    pub capacity: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self { window_secs: 60 * 60, capacity: 10_000 }
+    }
+}
+
+#[derive(Debug)]
+enum Slot {
+    // The first request with the key hasn't been answered yet
+    Pending { fingerprint: blake3::Hash },
+    Done { fingerprint: blake3::Hash, status: StatusCode, headers: HeaderMap, body: Bytes },
+}
+
+impl Slot {
+    fn fingerprint(&self) -> &blake3::Hash {
+        match self {
+            Slot::Pending { fingerprint } | Slot::Done { fingerprint, .. } => fingerprint,
+        }
+    }
+}
+
+// Successful answers by client-supplied `Idempotency-Key`, so a client retrying a request
+// that did go through gets the stored answer instead of paying for a second call. Keys are
+// scoped to the caller's API key and bound to the request body they first came with.
+#[derive(Clone)]
+pub struct IdempotencyStore {
+    inner: Cache<String, Arc<Slot>>,
+}
+
+// Held while the first request with a key is answered. Dropping it without `finish` (an
+// error, a cancelled request) forgets the key, so a retry goes upstream again.
+pub struct IdempotencyClaim {
+    inner: Cache<String, Arc<Slot>>,
+    key: String,
+    fingerprint: blake3::Hash,
+    finished: bool,
+}
+
+impl IdempotencyStore {
+    pub fn new(config: IdempotencyConfig) -> Self {
+        let inner = Cache::builder()
+            .max_capacity(config.capacity)
+            .time_to_live(Duration::from_secs(config.window_secs.max(1)))
+            .build();
+        Self { inner }
+    }
+
+    // Ok(None) for requests without a key. Err is the response to send instead of calling
+    // upstream: the stored answer, or a conflict when the key is taken.
+    pub async fn claim(&self, headers: &HeaderMap, req: &LlmRequest) -> Result<Option<IdempotencyClaim>, Response> {
+        let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else { return Ok(None) };
+        let client_key = match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key,
+            _ => {
+                let message = format!("`Idempotency-Key` must be 1 to {} visible ASCII characters", MAX_KEY_LEN);
+                return Err(ApiError::invalid_request("invalid_idempotency_key", message).into_response());
+            }
+        };
+        let key = blake3::hash(format!("{}\0{}", auth::bearer_token(headers).unwrap_or_default(), client_key).as_bytes())
+            .to_hex()
+            .to_string();
+        let fingerprint = blake3::hash(req.canonical_json().as_bytes());
+
+        let entry = self.inner.entry(key.clone()).or_insert(Arc::new(Slot::Pending { fingerprint })).await;
+        if entry.is_fresh() {
+            return Ok(Some(IdempotencyClaim { inner: self.inner.clone(), key, fingerprint, finished: false }));
+        }
+        let slot = entry.into_value();
+        if *slot.fingerprint() != fingerprint {
+            let error = ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_request_error",
+                "idempotency_key_reused",
+                "`Idempotency-Key` was already used with a different request",
+            );
+            return Err(error.into_response());
+        }
+        match &*slot {
+            Slot::Pending { .. } => {
+                let error = ApiError::new(
+                    StatusCode::CONFLICT,
+                    "invalid_request_error",
+                    "idempotency_request_in_progress",
+                    "A request with this `Idempotency-Key` is still being processed",
+                );
+                Err(error.into_response())
+            }
+            Slot::Done { status, headers, body, .. } => {
+                let mut response = (*status, headers.clone(), Body::from(body.clone())).into_response();
+                response.headers_mut().insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+                Err(response)
+            }
+        }
+    }
+}
+
+impl IdempotencyClaim {
+    // Stores `response` under the key if it succeeded and passes it on. A fallback answer
+    // isn't stored, so a retry still gets a real one.
+    pub async fn finish(mut self, response: Response) -> Response {
+        if !response.status().is_success() || response.headers().contains_key(FALLBACK_HEADER) {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        let slot = Slot::Done { fingerprint: self.fingerprint, status: parts.status, headers: parts.headers.clone(), body: body.clone() };
+        self.inner.insert(self.key.clone(), Arc::new(slot)).await;
+        self.finished = true;
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+impl Drop for IdempotencyClaim {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let (inner, key) = (self.inner.clone(), std::mem::take(&mut self.key));
+        tokio::spawn(async move { inner.invalidate(&key).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: serde_json::Value) -> LlmRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn with_key(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_str(key).unwrap());
+        headers.insert("authorization", HeaderValue::from_static("Bearer client"));
+        headers
+    }
+
+    async fn answered(store: &IdempotencyStore, headers: &HeaderMap, req: &LlmRequest, body: &'static str) {
+        let claim = store.claim(headers, req).await.unwrap().unwrap();
+        claim.finish((StatusCode::OK, body).into_response()).await;
+    }
+
+    #[tokio::test]
+    async fn retry_gets_the_stored_answer() {
+        let store = IdempotencyStore::new(IdempotencyConfig::default());
+        let req = request(serde_json::json!({"model": "m", "prompt": "hi"}));
+        answered(&store, &with_key("k"), &req, "first").await;
+
+        let replay = store.claim(&with_key("k"), &req).await.err().unwrap();
+        assert_eq!(replay.status(), StatusCode::OK);
+        assert_eq!(replay.headers()[REPLAYED_HEADER], "true");
+        let body = axum::body::to_bytes(replay.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"first");
+    }
+
+    #[tokio::test]
+    async fn key_reused_with_another_body_is_rejected() {
+        let store = IdempotencyStore::new(IdempotencyConfig::default());
+        answered(&store, &with_key("k"), &request(serde_json::json!({"model": "m", "prompt": "a"})), "a").await;
+        let other = request(serde_json::json!({"model": "m", "prompt": "b"}));
+        let rejected = store.claim(&with_key("k"), &other).await.err().unwrap();
+        assert_eq!(rejected.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn extra_params_order_does_not_change_the_fingerprint() {
+        let store = IdempotencyStore::new(IdempotencyConfig::default());
+        let body = serde_json::json!({
+            "model": "m", "prompt": "hi", "stop": ["\n"], "top_p": 0.9, "user": "u1",
+            "presence_penalty": 0.5, "seed": 7, "frequency_penalty": 0.1,
+        });
+        answered(&store, &with_key("k"), &request(body.clone()), "first").await;
+        // Each request gets its own HashMap, iterating in its own order
+        for _ in 0..16 {
+            let replay = store.claim(&with_key("k"), &request(body.clone())).await.err().unwrap();
+            assert_eq!(replay.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_are_scoped_to_the_caller() {
+        let store = IdempotencyStore::new(IdempotencyConfig::default());
+        let req = request(serde_json::json!({"model": "m", "prompt": "hi"}));
+        answered(&store, &with_key("k"), &req, "first").await;
+        let mut other_client = with_key("k");
+        other_client.insert("authorization", HeaderValue::from_static("Bearer other"));
+        assert!(store.claim(&other_client, &req).await.unwrap().is_some());
+    }
+}
@@ -3,3 +3,30 @@ pub mod router;
 pub mod balancer;
 pub mod cache;
 pub mod gateway;
+pub mod batch;
+pub mod config;
+pub mod control_plane;
+pub mod costs;
+pub mod budget;
+pub mod retry_budget;
+pub mod backoff;
+pub mod tokens;
+pub mod error;
+pub mod validate;
+pub mod streaming;
+pub mod admin;
+pub mod auth;
+pub mod rate_limit;
+pub mod refresh;
+pub mod request_id;
+pub mod access_log;
+pub mod shutdown;
+pub mod shadow;
+pub mod idempotency;
+pub mod compression;
+pub mod moderation;
+pub mod queue;
+pub mod audit;
+pub mod telemetry;
+#[cfg(test)]
+pub(crate) mod test_support;
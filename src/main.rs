@@ -1,56 +1,182 @@
-use axum::{routing::post, Router as AxumRouter};
+use axum::{extract::DefaultBodyLimit, middleware, routing::{delete, get, post}, Router as AxumRouter};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use llm_edge::model::ProviderConfig;
+use std::future::IntoFuture;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use llm_edge::config::GatewayConfig;
 use llm_edge::router::Router;
-use llm_edge::cache::SemanticCache;
 use llm_edge::gateway::{AppState, handle_chat_completions};
-use std::collections::HashMap;
+use llm_edge::batch::handle_batch_completions;
+use llm_edge::admin::{handle_cache_clear, handle_cache_invalidate, handle_cache_memory, handle_cache_stats, handle_costs_by_tag, handle_drain, handle_health, handle_metrics, handle_models, handle_providers, handle_ready, handle_recent_requests, handle_route_preview, handle_selftest, handle_token_estimates, handle_undrain, handle_version};
+use llm_edge::auth::{require_admin, require_api_key, ClientAuth};
+use llm_edge::rate_limit::{limit_clients, RateLimiter};
+use llm_edge::request_id::propagate_request_id;
+use llm_edge::budget::SpendBudget;
+use llm_edge::retry_budget::RetryBudget;
+use llm_edge::cache::negative::NegativeCache;
+use llm_edge::idempotency::IdempotencyStore;
+use llm_edge::compression::compress_responses;
+use llm_edge::moderation::{KeywordBlocklist, ModerationPolicy};
+use llm_edge::queue::WaitQueue;
+use llm_edge::audit::AuditLog;
+use llm_edge::cache::snapshot;
+use llm_edge::shadow::Shadow;
+use llm_edge::costs::CostTracker;
+use llm_edge::tokens::TokenEstimator;
+use llm_edge::shutdown::{self, track_requests, InFlightRequests};
+
+// How long shutdown waits for background cache writes to land.
+const CACHE_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
-async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+async fn main() -> anyhow::Result<()> {
+    let config_path = GatewayConfig::path_from_env();
+    let config = GatewayConfig::load(&config_path)?;
 
-    // Mock Configuration
-    let mut model_map = HashMap::new();
-    model_map.insert("gpt-4".to_string(), "gpt-4-turbo".to_string());
-    
-    let p1 = ProviderConfig {
-        id: "p1".to_string(),
-        name: "MockOpenAI".to_string(),
-        endpoint: "http://localhost:3001/chat/completions".to_string(),
-        api_key: "sk-xxx".to_string(),
-        cost_per_1k_input: 0.01,
-        cost_per_1k_output: 0.03,
-        model_map: model_map.clone(),
-    };
+    // Initialize tracing
+    llm_edge::telemetry::init(config.otlp.as_ref());
 
-    let p2 = ProviderConfig {
-        id: "p2".to_string(),
-        name: "MockAnthropic".to_string(),
-        endpoint: "http://localhost:3002/chat/completions".to_string(),
-        api_key: "ant-xxx".to_string(),
-        cost_per_1k_input: 0.012, // Slightly more expensive
-        cost_per_1k_output: 0.035,
-        model_map: model_map,
-    };
+    let watch_providers = config.watches_providers();
+    let router = Arc::new(
+        Router::with_weights(config.providers, config.scoring)?
+            .with_strategy(config.routing_strategy)
+            .with_degraded_fallback(config.allow_degraded_fallback)
+            .with_virtual_models(&config.virtual_models)
+            .with_region(config.region.clone()),
+    );
+    let cache = Arc::new(config.cache.build());
+    if let Some(snapshot) = &config.cache.snapshot {
+        // An unreadable snapshot means a cold start, not a gateway that won't come up
+        match cache.restore_snapshot(&snapshot.path).await {
+            Ok(restored) => info!("Restored {} cache entries from {}", restored, snapshot.path.display()),
+            Err(e) => warn!("Cache snapshot not restored: {:#}", e),
+        }
+        snapshot::spawn_snapshots(cache.clone(), snapshot.clone());
+    }
+    // After the snapshot, so curated answers replace restored ones
+    if let Some(path) = &config.cache.warmup_file {
+        let stored = cache.preload(llm_edge::cache::warmup::load(path)?).await;
+        info!("Preloaded {} cache entries from {}", stored, path.display());
+    }
 
-    let router = Router::new(vec![p1, p2]);
-    let cache = SemanticCache::new(10_000, 60 * 5); // 10k items, 5 min TTL
+    if watch_providers {
+        llm_edge::config::spawn_watcher(&config_path, router.clone())?;
+    } else {
+        info!("Providers come from the control plane; edits to {} are not watched", config_path);
+    }
+    llm_edge::router::health::spawn_health_checks(router.clone());
+    if let Some(outlier) = config.outlier_detection {
+        llm_edge::router::outlier::spawn_outlier_detection(router.clone(), outlier);
+    }
+    if let Some(probe) = config.breaker_probe {
+        llm_edge::router::health::spawn_breaker_probes(router.clone(), probe);
+    }
+    if let Some(control_plane) = config.control_plane {
+        llm_edge::control_plane::start(control_plane, config.virtual_models.clone(), router.clone()).await?;
+    }
 
+    let client_auth = Arc::new(ClientAuth::new(&config.auth));
     let app_state = Arc::new(AppState {
-        router: Arc::new(router),
-        cache: Arc::new(cache),
+        router,
+        cache: cache.clone(),
+        estimator: Arc::new(TokenEstimator::new(config.options.auto_correct_token_estimates)),
+        options: config.options,
+        costs: Arc::new(CostTracker::new()),
+        budget: config.budget.map(SpendBudget::new),
+        retry_budget: config.retry_budget.map(RetryBudget::new),
+        backoff: config.retry_backoff,
+        negative_cache: config.cache.negative.map(NegativeCache::new),
+        shadow: config.shadow.map(|shadow| Arc::new(Shadow::new(shadow))),
+        idempotency: config.idempotency.map(IdempotencyStore::new),
+        moderation: config.moderation.map(|m| Arc::new(KeywordBlocklist::from(m)) as Arc<dyn ModerationPolicy>),
+        queue: config.queue.map(WaitQueue::new),
+        auth: client_auth.clone(),
+        audit: config.audit_log.map(AuditLog::new),
     });
+    if let Some(policy) = config.cache.refresh_ahead {
+        llm_edge::refresh::spawn_refresher(app_state.clone(), policy);
+    }
 
+    let in_flight = Arc::new(InFlightRequests::new());
     let app = AxumRouter::new()
         .route("/v1/chat/completions", post(handle_chat_completions))
+        .route("/v1/chat/completions/batch", post(handle_batch_completions))
+        .route("/v1/models", get(handle_models))
+        .route("/version", get(handle_version))
+        .route("/costs/by-tag", get(handle_costs_by_tag))
+        .route("/tokens/estimates", get(handle_token_estimates))
+        .route("/cache/stats", get(handle_cache_stats))
+        .route("/cache/memory", get(handle_cache_memory));
+    // Operator endpoints: any client key gets past require_api_key, only admin keys past this
+    let admin = AxumRouter::new()
+        .route("/admin/selftest", post(handle_selftest))
+        .route("/admin/route-preview", post(handle_route_preview))
+        .route("/admin/providers", get(handle_providers))
+        .route("/admin/requests", get(handle_recent_requests))
+        .route("/admin/providers/:id/drain", post(handle_drain))
+        .route("/admin/providers/:id/undrain", post(handle_undrain))
+        .route("/metrics", get(handle_metrics))
+        .route("/cache", delete(handle_cache_clear))
+        .route("/cache/entry", delete(handle_cache_invalidate))
+        .route_layer(middleware::from_fn_with_state(client_auth.clone(), require_admin));
+    let mut app = app.merge(admin);
+    // Layers run outermost-last: auth rejects unknown clients before they consume rate budget.
+    if let Some(limit) = config.rate_limit {
+        app = app.route_layer(middleware::from_fn_with_state(Arc::new(RateLimiter::new(limit)), limit_clients));
+    }
+    let mut app = app
+        .route_layer(middleware::from_fn_with_state(client_auth, require_api_key))
+        // Probes for load balancers and orchestrators: added after the route layers so they
+        // need no client key and don't spend rate budget.
+        .route("/health", get(handle_health))
+        .route("/ready", get(handle_ready))
+        .layer(DefaultBodyLimit::max(config.max_body_bytes));
+    if let Some(compression) = config.compression {
+        app = app.layer(middleware::from_fn_with_state(Arc::new(compression), compress_responses));
+    }
+    let app = app
+        .layer(middleware::from_fn(propagate_request_id))
+        .layer(middleware::from_fn_with_state(in_flight.clone(), track_requests))
         .with_state(app_state);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    println!("LLM Gateway listening on {}", addr);
+    let addr = config.bind_addr;
+    println!("LLM Gateway listening on {} (config: {})", addr, config_path);
     
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    // Peer addresses let the rate limiter key unauthenticated clients by IP.
+    // On a signal, stop accepting connections and let in-flight requests finish, for up to
+    // the grace period.
+    let signalled = Arc::new(Notify::new());
+    let on_signal = {
+        let (signalled, in_flight) = (signalled.clone(), in_flight.clone());
+        async move {
+            shutdown::signal().await;
+            info!("Shutting down, draining {} in-flight requests", in_flight.count());
+            signalled.notify_one();
+        }
+    };
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(on_signal)
+        .into_future();
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    tokio::select! {
+        served = server => {
+            served?;
+            info!("In-flight requests drained");
+        }
+        _ = async { signalled.notified().await; tokio::time::sleep(grace).await } => {
+            warn!("Grace period of {:?} elapsed, dropping {} in-flight requests", grace, in_flight.count());
+        }
+    }
+
+    let pending = cache.pending_writes();
+    if !cache.flush(CACHE_DRAIN_TIMEOUT).await {
+        warn!("Shutting down with {} of {} cache writes unfinished", cache.pending_writes(), pending);
+    }
+    if let Some(config) = &config.cache.snapshot {
+        snapshot::save_on_shutdown(&cache, config);
+    }
+    Ok(())
 }
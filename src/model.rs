@@ -1,48 +1,580 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LlmRequest {
     pub model: String,
-    pub prompt: String, // Simplifying for now, usually a list of messages
+    // Legacy single-prompt form, kept for backward compatibility with `messages`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<Message>>,
     #[serde(default)]
     pub max_tokens: Option<u32>,
     #[serde(default)]
     pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    // OpenAI structured outputs, e.g. `{"type": "json_object"}`; forwarded to the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
+    // Number of completions to generate (OpenAI's `n`); forwarded to the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    // Latency budget: providers whose latency EWMA is higher are skipped. Gateway-only,
+    // never forwarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_latency_ms: Option<u64>,
+    // Preferred provider region, overriding the gateway's `region`. Gateway-only, never
+    // forwarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    // Provider id to route to, bypassing scoring and the cache (also `X-LLM-Provider`).
+    // Gateway-only, never forwarded; needs an admin key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    // Every other field, forwarded to providers as is. The typed fields above take precedence
+    // over an extra with the same name (see Provider::build_body).
     #[serde(flatten)]
     pub extra_params: HashMap<String, serde_json::Value>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+}
+
+impl LlmRequest {
+    // Canonical text of the conversation, used for cache keys: the (role, content) pairs as
+    // JSON, so no content can pass for a message boundary. A bare `prompt` counts as the
+    // single user message it's sent as. None means the request carries no input at all.
+    pub fn canonical_text(&self) -> Option<String> {
+        self.canonical_text_with(Cow::Borrowed)
+    }
+
+    // Like `canonical_text`, with each message's content normalized first.
+    pub fn canonical_text_with<'a>(&'a self, normalize: impl Fn(&'a str) -> Cow<'a, str>) -> Option<String> {
+        let pairs: Vec<(&str, Cow<str>)> = match (&self.messages, &self.prompt) {
+            (Some(messages), _) if !messages.is_empty() => {
+                messages.iter().map(|m| (m.role.as_str(), normalize(&m.content))).collect()
+            }
+            (_, Some(prompt)) => vec![("user", normalize(prompt))],
+            _ => return None,
+        };
+        serde_json::to_string(&pairs).ok()
+    }
+
+    // Readable form of the conversation ("role: content" lines, or the bare prompt), for
+    // logs, similarity matching and token estimates. Two conversations can read the same,
+    // so it's never used as a key.
+    pub fn transcript(&self) -> Option<String> {
+        match (&self.messages, &self.prompt) {
+            (Some(messages), _) if !messages.is_empty() => Some(
+                messages
+                    .iter()
+                    .map(|m| format!("{}: {}", m.role, m.content))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            (_, Some(prompt)) => Some(prompt.clone()),
+            _ => None,
+        }
+    }
+
+    // The conversation in chat form; a bare `prompt` becomes a single user message.
+    pub fn to_messages(&self) -> Vec<Message> {
+        match (&self.messages, &self.prompt) {
+            (Some(messages), _) if !messages.is_empty() => messages.clone(),
+            (_, Some(prompt)) => vec![Message { role: "user".to_string(), content: prompt.clone() }],
+            _ => Vec::new(),
+        }
+    }
+
+    // The request as JSON with object keys sorted, so equal requests serialize the same
+    // whatever order `extra_params` (a HashMap) iterates in.
+    pub fn canonical_json(&self) -> String {
+        serde_json::to_value(self).unwrap_or_default().to_string()
+    }
+
+    // Completions requested; 1 unless `n` says otherwise.
+    pub fn choice_count(&self) -> u32 {
+        self.n.unwrap_or(1)
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.stream.unwrap_or(false)
+    }
+
+    pub fn uses_logit_bias(&self) -> bool {
+        self.extra_params.get("logit_bias").is_some_and(|v| !v.is_null())
+    }
+
+    // Whether the client asked for JSON output (`json_object` or `json_schema`).
+    pub fn wants_json(&self) -> bool {
+        let kind = self.response_format.as_ref().and_then(|f| f.get("type")).and_then(|t| t.as_str());
+        matches!(kind, Some("json_object" | "json_schema"))
+    }
+
+    // Features a provider must have to serve this request (see ProviderConfig::capabilities).
+    pub fn required_capabilities(&self) -> Vec<Capability> {
+        let tools = ["tools", "functions"].iter().any(|k| self.extra_params.get(*k).is_some_and(|v| !v.is_null()));
+        [
+            (self.is_streaming(), Capability::Streaming),
+            (tools, Capability::Tools),
+            (self.wants_json(), Capability::JsonMode),
+        ]
+        .into_iter()
+        .filter_map(|(required, capability)| required.then_some(capability))
+        .collect()
+    }
+}
+
+// A request feature not every provider has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Streaming,
+    // Function calling: `tools` or the legacy `functions`
+    Tools,
+    // `response_format` of type `json_object` or `json_schema`
+    JsonMode,
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Capability::Streaming => "streaming",
+            Capability::Tools => "tools",
+            Capability::JsonMode => "json_mode",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LlmResponse {
     pub content: String,
     pub usage: TokenUsage,
     pub provider: String,
     pub latency_ms: u64,
+    // Reasoning/thinking the model produced besides the answer; None when the provider sent
+    // none or it was stripped (see ProviderConfig::strip_reasoning).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    // The provider's hint for how long the answer may be cached (see Provider::cache_ttl);
+    // None leaves it to the cache's TTL. Never sent to clients.
+    #[serde(skip)]
+    pub cache_ttl: Option<Duration>,
+    // Every completion's text, `content` being the first, when the request asked for `n` > 1
+    // and the provider returned more than one; empty otherwise.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl LlmResponse {
+    // All generated text, for estimating completion tokens.
+    pub fn completion_text(&self) -> String {
+        if self.choices.is_empty() {
+            self.content.clone()
+        } else {
+            self.choices.concat()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TokenUsage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    // Portion of `completion_tokens` spent on reasoning, when the provider reports it.
+    // Already included in `completion_tokens`, so cost is unaffected by stripping.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub reasoning_tokens: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(key) => vec![key],
+        OneOrMany::Many(keys) => keys,
+    })
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProviderConfig {
     pub id: String,
     pub name: String,
+    // Full chat URL. Optional when `base_url` is set (see `endpoint_url`).
+    #[serde(default)]
     pub endpoint: String,
-    pub api_key: String,
+    // Alternative to `endpoint`: base URL plus a path, which defaults per `provider_type`.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub provider_type: Option<ProviderType>,
+    // Path override appended to `base_url`; `{model}` expands to the provider-side model name.
+    #[serde(default, alias = "chat_path")]
+    pub path: Option<String>,
+    // Model listing path appended to `base_url`, defaulting per `provider_type` (see `models_url`).
+    #[serde(default)]
+    pub models_path: Option<String>,
+    // One key or a list; several are rotated round-robin (see router::keys::KeyRing).
+    #[serde(deserialize_with = "one_or_many")]
+    pub api_key: Vec<String>,
+    // Extra headers sent with every request (e.g. `OpenAI-Organization`, Azure `api-key`).
+    // Values may reference `${VAR}` like `api_key`; naming an auth header replaces it.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
     pub cost_per_1k_input: f64,
     pub cost_per_1k_output: f64,
     pub model_map: HashMap<String, String>, // Client Model -> Provider Model Name
+    // Traffic ramp for newly added providers: the routing weight grows linearly
+    // from 0 to 1 over this many seconds after the provider is added.
+    #[serde(default)]
+    pub ramp_up_secs: Option<u64>,
+    // The same ramp after the breaker closes again, so a recovered provider isn't flooded
+    // on the strength of its pre-outage latency. 30s when unset, 0 disables it.
+    #[serde(default)]
+    pub recovery_ramp_secs: Option<u64>,
+    // Share of traffic under RouteStrategy::Weighted, relative to the other candidates'
+    // weights (default 1.0). 0 only takes requests on failover.
+    #[serde(default)]
+    pub weight: Option<f64>,
+    // Where the provider is served from, matched against the preferred region of requests.
+    #[serde(default)]
+    pub region: Option<String>,
+    // Organization system prompt injected into every request sent to this provider.
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub system_prompt_strategy: SystemPromptStrategy,
+    // How streamed chunks from this provider are flushed to clients.
+    #[serde(default)]
+    pub stream_buffering: StreamBuffering,
+    // How long the circuit breaker stays open before a half-open probe (default 30s).
+    #[serde(default)]
+    pub breaker_cooldown_secs: Option<u64>,
+    // Also open the breaker when more than this fraction of calls failed over the last 10s
+    // (at least 10 calls), catching providers that fail often but rarely twice in a row.
+    #[serde(default)]
+    pub breaker_error_rate: Option<f64>,
+    // Weight of each new sample in the latency EWMAs, in (0, 1] (default 0.125). Higher
+    // reacts faster to latency shifts, lower smooths out noise.
+    #[serde(default)]
+    pub ewma_alpha: Option<f64>,
+    #[serde(default)]
+    pub logit_bias: LogitBiasSupport,
+    // Features the provider supports; requests needing one it lacks are routed elsewhere.
+    // Unset means all of them. Sorted, so the config hash doesn't depend on set order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<BTreeSet<Capability>>,
+    // Context window of the provider's model. Requests whose estimated input plus
+    // `max_tokens` exceed it are routed elsewhere.
+    #[serde(default)]
+    pub max_context_tokens: Option<u32>,
+    // Cap on simultaneous requests to this provider; saturated providers are skipped.
+    #[serde(default)]
+    pub max_concurrency: Option<u64>,
+    // Reaction to the provider's `x-ratelimit-*` headers.
+    #[serde(default)]
+    pub rate_limit: RateLimitHandling,
+    // Whole-request timeout for calls to this provider (default 5s). Slow models or large
+    // `max_tokens` may need more.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    // Limit on establishing the connection alone, so an unreachable provider fails fast
+    // while a slow generation can still use the whole `timeout_ms`.
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    // Connection pool of this provider's client. Idle keep-alive connections kept open
+    // (reqwest's default: no limit); a provider taking bursts in the hundreds needs at
+    // least that many to avoid reconnecting, one that closes connections early fewer.
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    // How long an idle connection is kept for reuse (reqwest's default: 90s). Set it below
+    // the provider's own keep-alive timeout so requests don't land on closed connections.
+    #[serde(default)]
+    pub pool_idle_timeout_ms: Option<u64>,
+    // Extra tries at this provider after a connection reset or timeout, before the gateway
+    // fails over. The call still counts once toward the provider's stats and breaker.
+    #[serde(default)]
+    pub same_provider_retries: u32,
+    // Drop reasoning/thinking content from responses before they are returned or cached.
+    // Clients can override it for their own copy with the `x-strip-reasoning` header.
+    #[serde(default)]
+    pub strip_reasoning: bool,
+    // Active probe for providers that can answer 200 while their model is unavailable.
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>,
+    // How long this provider's answers stay cached, for time-sensitive data; can only
+    // shorten the cache's `ttl_secs`.
+    #[serde(default)]
+    pub cache_ttl_secs: Option<u64>,
+    // Response header carrying a per-answer cache TTL in seconds (e.g. `x-cache-ttl`), which
+    // takes precedence over `cache_ttl_secs`; 0 keeps the answer out of the cache.
+    #[serde(default)]
+    pub cache_ttl_header: Option<String>,
+}
+
+// Periodic GET against the provider; it is taken out of rotation while the probe fails
+// (non-2xx, unreachable, or a body that doesn't satisfy `expect`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthCheck {
+    // Absolute URL, or a path appended to `base_url`.
+    pub url: String,
+    #[serde(default = "default_health_check_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub expect: Option<BodyPredicate>,
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    10
+}
+
+// The JSON value at `pointer` (RFC 6901, e.g. `/status`) must equal `equals`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyPredicate {
+    pub pointer: String,
+    pub equals: serde_json::Value,
+}
+
+impl BodyPredicate {
+    pub fn matches(&self, body: &serde_json::Value) -> bool {
+        body.pointer(&self.pointer) == Some(&self.equals)
+    }
+}
+
+// What to do with a client's `logit_bias` for this provider.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogitBiasSupport {
+    // Provider understands it; forward as-is.
+    #[default]
+    Forward,
+    // Provider doesn't support it; drop the parameter (logged) and serve the request anyway.
+    Strip,
+    // Provider doesn't support it; never route requests carrying `logit_bias` here.
+    Exclude,
+}
+
+impl ProviderConfig {
+    // Dollar cost of a response given this provider's per-1k token pricing.
+    pub fn cost_for(&self, usage: &TokenUsage) -> f64 {
+        usage.prompt_tokens as f64 / 1000.0 * self.cost_per_1k_input
+            + usage.completion_tokens as f64 / 1000.0 * self.cost_per_1k_output
+    }
+
+    // Health-check URL: `url` as is when absolute, else joined to `base_url`.
+    pub fn health_check_url(&self) -> Option<String> {
+        let check = self.health_check.as_ref()?;
+        if check.url.starts_with("http://") || check.url.starts_with("https://") {
+            return Some(check.url.clone());
+        }
+        Some(join_url(self.base_url.as_deref()?, &check.url))
+    }
+
+    // URL a request for provider-side `model` is sent to. With `base_url` the path comes from
+    // `path`, else from the provider type's default (OpenAI-style when untyped); otherwise
+    // `endpoint` is used verbatim.
+    pub fn endpoint_url(&self, model: &str) -> String {
+        let Some(base) = &self.base_url else { return self.endpoint.clone() };
+        let template = match &self.path {
+            Some(path) => path.as_str(),
+            None => self.provider_type.unwrap_or(ProviderType::OpenAI).default_chat_path(),
+        };
+        join_url(base, &template.replace("{model}", model))
+    }
+
+    // URL of the provider's model listing. With `base_url` the path comes from `models_path`
+    // or the type's default; a full `endpoint` ending in the type's default chat path has it
+    // swapped for the models one. None for other endpoints (e.g. Azure deployments), which
+    // need `base_url` and `models_path`.
+    pub fn models_url(&self) -> Option<String> {
+        let provider_type = self.provider_type.unwrap_or(ProviderType::OpenAI);
+        if let Some(base) = &self.base_url {
+            let path = self.models_path.as_deref().unwrap_or(provider_type.default_models_path());
+            return Some(join_url(base, path));
+        }
+        let (url, query) = self.endpoint.split_once('?').map_or((self.endpoint.as_str(), None), |(u, q)| (u, Some(q)));
+        let models = join_url(url.strip_suffix(provider_type.default_chat_path())?, provider_type.default_models_path());
+        Some(match query {
+            Some(query) => format!("{}?{}", models, query),
+            None => models,
+        })
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+fn join_url(base: &str, path: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+// How a provider-level system prompt combines with one supplied by the client.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemPromptStrategy {
+    // Insert the provider prompt as its own first system message, keeping the client's.
+    #[default]
+    Prepend,
+    // Fold both into a single leading system message (provider text first).
+    Concatenate,
+    // Only inject when the client didn't send a system message of its own.
+    PreferClient,
+}
+
+// Streaming flush strategy: `Immediate` forwards every upstream chunk as soon as it arrives
+// (lowest latency); `Coalesce` batches chunks arriving within `window_ms` of the first one
+// into a single flush (fewer, larger events).
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamBuffering {
+    #[default]
+    Immediate,
+    Coalesce { window_ms: u64 },
+}
+
+// How the router uses the remaining quota a provider reports in its rate-limit headers.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitHandling {
+    Ignore,
+    // Penalize the provider in proportion to how far its remaining quota (as a fraction of
+    // the limit) has dropped below `below`, so it loses traffic before it starts returning 429s.
+    Deprioritize { below: f64 },
+}
+
+impl Default for RateLimitHandling {
+    fn default() -> Self {
+        Self::Deprioritize { below: 0.1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum ProviderType {
     OpenAI,
     Anthropic,
     Local,
     Ollama,
 }
+
+impl ProviderType {
+    pub fn default_chat_path(&self) -> &'static str {
+        match self {
+            ProviderType::OpenAI | ProviderType::Local => "/v1/chat/completions",
+            ProviderType::Anthropic => "/v1/messages",
+            ProviderType::Ollama => "/api/chat",
+        }
+    }
+
+    pub fn default_models_path(&self) -> &'static str {
+        match self {
+            ProviderType::OpenAI | ProviderType::Local | ProviderType::Anthropic => "/v1/models",
+            ProviderType::Ollama => "/api/tags",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(base_url: Option<&str>, provider_type: Option<ProviderType>, path: Option<&str>) -> ProviderConfig {
+        ProviderConfig {
+            endpoint: "https://fixed.example/chat".to_string(),
+            base_url: base_url.map(str::to_string),
+            provider_type,
+            path: path.map(str::to_string),
+            ..ProviderConfig::default()
+        }
+    }
+
+    #[test]
+    fn endpoint_urls_follow_the_provider_type() {
+        let base = Some("https://api.example/");
+        for (provider_type, url) in [
+            (None, "https://api.example/v1/chat/completions"),
+            (Some(ProviderType::OpenAI), "https://api.example/v1/chat/completions"),
+            (Some(ProviderType::Anthropic), "https://api.example/v1/messages"),
+            (Some(ProviderType::Ollama), "https://api.example/api/chat"),
+        ] {
+            assert_eq!(config(base, provider_type, None).endpoint_url("m"), url, "{provider_type:?}");
+        }
+
+        let azure = config(base, None, Some("openai/deployments/{model}/chat/completions?api-version=1"));
+        assert_eq!(azure.endpoint_url("gpt"), "https://api.example/openai/deployments/gpt/chat/completions?api-version=1");
+        // Without a base URL the endpoint is used as configured
+        assert_eq!(config(None, Some(ProviderType::Ollama), None).endpoint_url("m"), "https://fixed.example/chat");
+    }
+
+    #[test]
+    fn api_key_takes_one_key_or_a_list() {
+        let parse = |api_key: serde_json::Value| -> Vec<String> {
+            let config: ProviderConfig = serde_json::from_value(serde_json::json!({
+                "id": "a", "name": "a", "endpoint": "http://127.0.0.1:9/v1", "api_key": api_key,
+                "cost_per_1k_input": 0.0, "cost_per_1k_output": 0.0, "model_map": {}
+            }))
+            .unwrap();
+            config.api_key
+        };
+        assert_eq!(parse("k1".into()), ["k1"]);
+        assert_eq!(parse(serde_json::json!(["k1", "k2"])), ["k1", "k2"]);
+    }
+
+    #[test]
+    fn models_urls_come_from_the_base_url_or_the_endpoint() {
+        let base = Some("https://api.example");
+        assert_eq!(config(base, None, None).models_url().unwrap(), "https://api.example/v1/models");
+        assert_eq!(config(base, Some(ProviderType::Ollama), None).models_url().unwrap(), "https://api.example/api/tags");
+        let azure = ProviderConfig { models_path: Some("openai/models?api-version=1".to_string()), ..config(base, None, None) };
+        assert_eq!(azure.models_url().unwrap(), "https://api.example/openai/models?api-version=1");
+
+        // A full OpenAI-style endpoint has its chat path swapped, query kept
+        let legacy = ProviderConfig { endpoint: "https://legacy.example/v1/chat/completions?key=1".to_string(), ..config(None, None, None) };
+        assert_eq!(legacy.endpoint_url("m"), "https://legacy.example/v1/chat/completions?key=1");
+        assert_eq!(legacy.models_url().unwrap(), "https://legacy.example/v1/models?key=1");
+        assert!(config(None, None, None).models_url().is_none());
+    }
+
+    #[test]
+    fn chat_path_is_accepted_next_to_a_base_url() {
+        let config: ProviderConfig = serde_json::from_value(serde_json::json!({
+            "id": "azure", "name": "azure", "base_url": "https://res.openai.azure.example",
+            "chat_path": "/openai/deployments/{model}/chat/completions", "api_key": "k",
+            "cost_per_1k_input": 0.0, "cost_per_1k_output": 0.0, "model_map": {"gpt-4": "prod-gpt4"}
+        }))
+        .unwrap();
+        assert_eq!(config.endpoint_url("prod-gpt4"), "https://res.openai.azure.example/openai/deployments/prod-gpt4/chat/completions");
+    }
+
+    #[test]
+    fn canonical_text_keeps_message_boundaries() {
+        let conversation = |messages: &[(&str, &str)]| LlmRequest {
+            messages: Some(messages.iter().map(|(role, content)| Message { role: role.to_string(), content: content.to_string() }).collect()),
+            ..LlmRequest::default()
+        };
+        let one = conversation(&[("user", "hi\nassistant: hello")]);
+        let two = conversation(&[("user", "hi"), ("assistant", "hello")]);
+        // Both read the same, but aren't the same conversation
+        assert_eq!(one.transcript(), two.transcript());
+        assert_ne!(one.canonical_text(), two.canonical_text());
+        assert_ne!(conversation(&[("user: hi", "")]).canonical_text(), conversation(&[("user", " hi")]).canonical_text());
+
+        let prompt = LlmRequest { prompt: Some("hi".to_string()), ..LlmRequest::default() };
+        assert_eq!(prompt.canonical_text(), conversation(&[("user", "hi")]).canonical_text());
+        assert_eq!(LlmRequest::default().canonical_text(), None);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::model::LlmRequest;
+use futures::future::{self, BoxFuture};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModerationResult {
+    Allow,
+    // The reason is returned to the client in the 403.
+    Deny { reason: String },
+}
+
+// Decides whether a request may reach a provider at all. Runs after validation and before
+// the cache and routing, so a denied request costs nothing and is never answered from cache.
+// `check` returns a boxed future so policies can call out (e.g. to a moderation API) while
+// staying usable as `dyn ModerationPolicy`.
+//
+// Install one through `AppState::moderation`; the gateway binary builds a KeywordBlocklist
+// from `moderation` in the config.
+pub trait ModerationPolicy: Send + Sync {
+    fn check<'a>(&'a self, req: &'a LlmRequest) -> BoxFuture<'a, ModerationResult>;
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModerationConfig {
+    // Words or phrases, matched case-insensitively against whole words of each message's content.
+    #[serde(default)]
+    pub blocked_keywords: Vec<String>,
+}
+
+// Denies requests whose messages contain a blocked word or phrase. "ass" blocks "ass" but
+// not "class"; punctuation and case are ignored on both sides.
+#[derive(Debug, Clone, Default)]
+pub struct KeywordBlocklist {
+    // Normalized and padded with spaces, so matching is a substring search on word boundaries
+    keywords: Vec<String>,
+}
+
+impl KeywordBlocklist {
+    pub fn new(keywords: &[String]) -> Self {
+        let keywords = keywords
+            .iter()
+            .map(|k| normalize(k))
+            .filter(|k| !k.trim().is_empty())
+            .collect();
+        Self { keywords }
+    }
+}
+
+impl From<ModerationConfig> for KeywordBlocklist {
+    fn from(config: ModerationConfig) -> Self {
+        Self::new(&config.blocked_keywords)
+    }
+}
+
+impl ModerationPolicy for KeywordBlocklist {
+    fn check<'a>(&'a self, req: &'a LlmRequest) -> BoxFuture<'a, ModerationResult> {
+        // Each message on its own: role labels aren't words the user wrote, and a phrase
+        // shouldn't match across the end of one message and the start of the next
+        let texts: Vec<String> = req.to_messages().iter().map(|m| normalize(&m.content)).collect();
+        let result = if self.keywords.iter().any(|k| texts.iter().any(|t| t.contains(k.as_str()))) {
+            ModerationResult::Deny { reason: "Request contains a blocked term".to_string() }
+        } else {
+            ModerationResult::Allow
+        };
+        Box::pin(future::ready(result))
+    }
+}
+
+// Lowercase words separated by single spaces, with a space at each end.
+fn normalize(text: &str) -> String {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    format!(" {} ", words.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(keywords: &[&str], req: serde_json::Value) -> ModerationResult {
+        let blocklist = KeywordBlocklist::new(&keywords.iter().map(|k| k.to_string()).collect::<Vec<_>>());
+        futures::executor::block_on(blocklist.check(&serde_json::from_value(req).unwrap()))
+    }
+
+    fn conversation(messages: &[(&str, &str)]) -> serde_json::Value {
+        let messages: Vec<_> = messages.iter().map(|(role, content)| serde_json::json!({"role": role, "content": content})).collect();
+        serde_json::json!({"model": "m", "messages": messages})
+    }
+
+    #[test]
+    fn matches_whole_words_of_message_content() {
+        let denied = |r: ModerationResult| r != ModerationResult::Allow;
+        assert!(denied(check(&["secret plan"], serde_json::json!({"model": "m", "prompt": "The SECRET, plan!"}))));
+        assert!(!denied(check(&["ass"], serde_json::json!({"model": "m", "prompt": "a class act"}))));
+        assert!(denied(check(&["leak"], conversation(&[("system", "be nice"), ("user", "leak it")]))));
+    }
+
+    #[test]
+    fn role_labels_are_not_content() {
+        // The transcript reads "user: hello\nassistant: hi"
+        assert_eq!(check(&["user", "assistant"], conversation(&[("user", "hello"), ("assistant", "hi")])), ModerationResult::Allow);
+        assert_eq!(check(&["hello assistant"], conversation(&[("user", "hello"), ("assistant", "hi")])), ModerationResult::Allow);
+        assert_ne!(check(&["user"], conversation(&[("user", "the user said")])), ModerationResult::Allow);
+    }
+}
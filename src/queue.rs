@@ -0,0 +1,157 @@
+use crate::error::ApiError;
+use crate::router::Provider;
+use futures::future;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Off unless configured: without it a request finding every provider at `max_concurrency`
+// fails right away.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    // Longest a request waits for a slot before getting 503
+    pub max_wait_ms: u64,
+    // Requests waiting at once; more are refused immediately
+    pub max_waiting: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self { max_wait_ms: 1_000, max_waiting: 256 }
+    }
+}
+
+// Lets requests that find every provider saturated wait briefly for a slot instead of
+// failing, which absorbs short bursts above the fleet's combined `max_concurrency`.
+#[derive(Debug)]
+pub struct WaitQueue {
+    config: QueueConfig,
+    waiting: AtomicUsize,
+}
+
+// A request's place in the queue, held across its waits so they share one deadline.
+pub struct QueuePosition<'a> {
+    queue: &'a WaitQueue,
+    entered_at: Instant,
+    deadline: Instant,
+}
+
+impl WaitQueue {
+    pub fn new(config: QueueConfig) -> Self {
+        Self { config, waiting: AtomicUsize::new(0) }
+    }
+
+    pub fn enter(&self) -> Result<QueuePosition<'_>, ApiError> {
+        let max = self.config.max_waiting;
+        self.waiting
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |n| (n < max).then_some(n + 1))
+            .map_err(|_| ApiError::unavailable("queue_full", "All providers at capacity and the wait queue is full"))?;
+        let entered_at = Instant::now();
+        Ok(QueuePosition { queue: self, entered_at, deadline: entered_at + Duration::from_millis(self.config.max_wait_ms) })
+    }
+
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
+
+impl QueuePosition<'_> {
+    // Start of the request's queue wait, which ends when it is dispatched
+    pub fn entered_at(&self) -> Instant {
+        self.entered_at
+    }
+
+    // Returns once one of `saturated` frees a slot (the caller then selects again, and may
+    // lose the slot to another request and wait again), or errs at the deadline.
+    pub async fn wait(&self, saturated: &[Arc<Provider>]) -> Result<(), ApiError> {
+        let timeout = || ApiError::unavailable("queue_timeout", "Timed out waiting for provider capacity");
+        if saturated.is_empty() {
+            return Err(timeout());
+        }
+        let mut freed: Vec<_> = saturated.iter().map(|p| Box::pin(p.stats.capacity_freed.notified())).collect();
+        // Registered before re-checking, so a slot freed in between still wakes us
+        for notified in &mut freed {
+            notified.as_mut().enable();
+        }
+        if saturated.iter().any(|p| p.has_capacity()) {
+            return Ok(());
+        }
+        tokio::time::timeout_at(self.deadline.into(), future::select_all(freed)).await.map(|_| ()).map_err(|_| timeout())
+    }
+}
+
+impl Drop for QueuePosition<'_> {
+    fn drop(&mut self) {
+        self.queue.waiting.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use crate::test_support::{self, MockUpstream};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::Response;
+
+    // `prompts` sent at once through a gateway whose only provider takes `max_concurrency`
+    async fn burst(upstream: &MockUpstream, max_concurrency: u64, queue: QueueConfig, prompts: usize) -> Vec<Response> {
+        let mut state = test_support::state(vec![ProviderConfig { max_concurrency: Some(max_concurrency), ..upstream.provider("a") }]);
+        state.queue = Some(WaitQueue::new(queue));
+        let state = Arc::new(state);
+        let calls: Vec<_> = (0..prompts)
+            .map(|i| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    test_support::complete(&state, HeaderMap::new(), test_support::request(&format!("prompt {i}"), serde_json::json!({}))).await
+                })
+            })
+            .collect();
+        let mut responses = Vec::new();
+        for call in calls {
+            responses.push(call.await.unwrap());
+        }
+        assert_eq!(state.queue.as_ref().unwrap().waiting(), 0);
+        responses
+    }
+
+    async fn error_code(response: Response) -> String {
+        let body: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        body["error"]["code"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn a_short_burst_queues_and_drains() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(100));
+        let responses = burst(&upstream, 2, QueueConfig { max_wait_ms: 2_000, max_waiting: 8 }, 6).await;
+        assert!(responses.iter().all(|r| r.status() == StatusCode::OK));
+        assert_eq!(upstream.calls(), 6);
+    }
+
+    #[tokio::test]
+    async fn waiting_past_the_deadline_is_a_503() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(500));
+        let mut responses = burst(&upstream, 1, QueueConfig { max_wait_ms: 50, max_waiting: 8 }, 2).await;
+        let statuses: Vec<_> = responses.iter().map(|r| r.status()).collect();
+        assert!(statuses.contains(&StatusCode::OK), "{statuses:?}");
+        let timed_out = responses.remove(statuses.iter().position(|s| *s == StatusCode::SERVICE_UNAVAILABLE).unwrap());
+        assert_eq!(error_code(timed_out).await, "queue_timeout");
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_refuses_right_away() {
+        let upstream = MockUpstream::start().await;
+        upstream.delay(Duration::from_millis(300));
+        let started = Instant::now();
+        let mut responses = burst(&upstream, 1, QueueConfig { max_wait_ms: 5_000, max_waiting: 0 }, 2).await;
+        let refused = responses.iter().position(|r| r.status() == StatusCode::SERVICE_UNAVAILABLE).unwrap();
+        assert_eq!(error_code(responses.remove(refused)).await, "queue_full");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,177 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use crate::auth::bearer_token;
+use crate::error::ApiError;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Buckets are spread over independently locked shards so concurrent clients rarely
+// contend on the same lock.
+const SHARDS: usize = 16;
+// Past this many buckets a shard drops the ones that have refilled completely
+// (a full bucket behaves exactly like a missing one).
+const MAX_BUCKETS_PER_SHARD: usize = 4096;
+
+// Token bucket per client: `burst` requests at once, refilled at `requests_per_sec`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ClientRateLimit {
+    pub requests_per_sec: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+// Per-client key: digest of the bearer key, or of the peer IP for requests without one.
+// Digests keep client secrets out of the table.
+type ClientKey = [u8; 32];
+
+pub struct RateLimiter {
+    limit: ClientRateLimit,
+    shards: Vec<Mutex<HashMap<ClientKey, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: ClientRateLimit) -> Self {
+        Self { limit, shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect() }
+    }
+
+    // Takes `cost` tokens for `client`, all or none; on refusal returns how long until they
+    // are available. A cost above the burst is never granted.
+    pub fn try_acquire_at(&self, client: &ClientKey, cost: u32, now: Instant) -> Result<(), Duration> {
+        let burst = self.limit.burst.max(1) as f64;
+        let cost = cost as f64;
+        let rate = self.limit.requests_per_sec;
+        let Ok(mut shard) = self.shards[shard_of(client)].lock() else { return Ok(()) };
+
+        if shard.len() >= MAX_BUCKETS_PER_SHARD && !shard.contains_key(client) {
+            shard.retain(|_, b| b.tokens + now.saturating_duration_since(b.updated_at).as_secs_f64() * rate < burst);
+        }
+        let bucket = shard.entry(*client).or_insert(Bucket { tokens: burst, updated_at: now });
+        let elapsed = now.saturating_duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            return Ok(());
+        }
+        if rate <= 0.0 || cost > burst {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64((cost - bucket.tokens) / rate))
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.limit.burst.max(1)
+    }
+}
+
+// The calling client's bucket, for handlers whose requests count as more than one (a batch
+// counts once per item). `limit_clients` adds it to the request's extensions after taking
+// the request's own token.
+#[derive(Clone)]
+pub struct ClientBucket {
+    limiter: Arc<RateLimiter>,
+    client: ClientKey,
+}
+
+impl ClientBucket {
+    pub fn try_acquire(&self, cost: u32) -> Result<(), Duration> {
+        self.limiter.try_acquire_at(&self.client, cost, Instant::now())
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.limiter.burst()
+    }
+}
+
+fn shard_of(client: &ClientKey) -> usize {
+    let mut h = DefaultHasher::new();
+    client.hash(&mut h);
+    (h.finish() as usize) % SHARDS
+}
+
+fn client_key(request: &Request) -> ClientKey {
+    let bearer = bearer_token(request.headers());
+    match bearer {
+        Some(key) => *blake3::hash(key.as_bytes()).as_bytes(),
+        None => {
+            let ip = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+                .unwrap_or_default();
+            *blake3::hash(format!("ip:{}", ip).as_bytes()).as_bytes()
+        }
+    }
+}
+
+pub async fn limit_clients(State(limiter): State<Arc<RateLimiter>>, mut request: Request, next: Next) -> Response {
+    let client = client_key(&request);
+    match limiter.try_acquire_at(&client, 1, Instant::now()) {
+        Ok(()) => {
+            request.extensions_mut().insert(ClientBucket { limiter, client });
+            next.run(request).await
+        }
+        Err(wait) => rate_limited(wait),
+    }
+}
+
+pub fn rate_limited(wait: Duration) -> Response {
+    let retry_after = wait.as_secs_f64().ceil().min(u32::MAX as f64) as u64;
+    let mut resp = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limit_error", "rate_limit_exceeded", "Rate limit exceeded").into_response();
+    resp.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_are_taken_all_or_nothing() {
+        let limiter = RateLimiter::new(ClientRateLimit { requests_per_sec: 1.0, burst: 5 });
+        let (client, now) = ([1; 32], Instant::now());
+        assert!(limiter.try_acquire_at(&client, 3, now).is_ok());
+        // 2 left: 3 more are 1s of refill away, and taking them fails without spending any
+        assert_eq!(limiter.try_acquire_at(&client, 3, now), Err(Duration::from_secs(1)));
+        assert!(limiter.try_acquire_at(&client, 2, now).is_ok());
+        assert!(limiter.try_acquire_at(&client, 1, now).is_err());
+        assert_eq!(limiter.try_acquire_at(&client, 6, now + Duration::from_secs(60)), Err(Duration::MAX));
+        // Other clients have their own bucket
+        assert!(limiter.try_acquire_at(&[2; 32], 5, now).is_ok());
+    }
+
+    #[tokio::test]
+    async fn clients_past_their_burst_get_429() {
+        let limiter = Arc::new(RateLimiter::new(ClientRateLimit { requests_per_sec: 1.0, burst: 5 }));
+        let app = axum::Router::new()
+            .route("/", axum::routing::get(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn_with_state(limiter, limit_clients));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await });
+
+        let client = reqwest::Client::new();
+        let responses = futures::future::join_all((0..20).map(|_| client.get(&url).bearer_auth("a").send())).await;
+        let limited: Vec<_> = responses.into_iter().map(Result::unwrap).filter(|r| r.status() == reqwest::StatusCode::TOO_MANY_REQUESTS).collect();
+        assert!((14..=15).contains(&limited.len()), "{} limited", limited.len());
+        assert!(limited.iter().all(|r| r.headers()["retry-after"] == "1"));
+        // Other keys, and clients without one, have buckets of their own
+        assert_eq!(client.get(&url).bearer_auth("b").send().await.unwrap().status(), reqwest::StatusCode::OK);
+        assert_eq!(client.get(&url).send().await.unwrap().status(), reqwest::StatusCode::OK);
+    }
+}
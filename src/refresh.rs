@@ -0,0 +1,158 @@
+use crate::cache::CacheEntry;
+use crate::gateway::AppState;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// Warm-standby refresh: entries with at least `min_hits` hits are regenerated in the
+// background once they are within `ahead_secs` of expiring, so popular prompts never
+// actually go stale or miss.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RefreshAhead {
+    pub min_hits: u32,
+    pub ahead_secs: u64,
+}
+
+// Chargeback tag refresh spend is recorded under in `/costs/by-tag`; no client asked for it.
+pub const REFRESH_COST_TAG: &str = "cache-refresh";
+
+pub fn spawn_refresher(state: Arc<AppState>, policy: RefreshAhead) {
+    let ahead = Duration::from_secs(policy.ahead_secs);
+    // Scan several times per window so an entry is caught well before it expires
+    let period = (ahead / 4).max(Duration::from_secs(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            for entry in state.cache.refresh_candidates_at(Instant::now(), policy.min_hits, ahead) {
+                let state = state.clone();
+                let guard = state.cache.begin_write();
+                tokio::spawn(async move {
+                    let _guard = guard;
+                    refresh_entry(&state, entry).await
+                });
+            }
+        }
+    });
+}
+
+async fn refresh_entry(state: &AppState, entry: CacheEntry) {
+    // Spending on a refresh nobody asked for is the first thing to stop when over budget; the
+    // entry then expires and the next client request decides whether it's worth paying for.
+    if state.budget.as_ref().is_some_and(|budget| budget.exhausted().is_some()) {
+        entry.release_refresh();
+        return;
+    }
+    let mut req = (*entry.request).clone();
+    req.stream = None;
+
+    let acquired = state
+        .router
+        .select(&req)
+        .and_then(|p| p.try_acquire().map(|guard| (p, guard)))
+        .filter(|(p, _)| p.try_acquire_probe());
+    let Some((provider, _in_flight)) = acquired else {
+        // Nobody available right now; the next scan tries again if it hasn't expired.
+        entry.release_refresh();
+        return;
+    };
+
+    let call_start = Instant::now();
+    match provider.call(&req).await {
+        Ok(mut resp) => {
+            let latency = call_start.elapsed();
+            provider.stats.record_success(latency);
+            let cost = provider.charge(&resp.usage);
+            state.costs.record(&[REFRESH_COST_TAG.to_string()], cost);
+            if let Some(budget) = &state.budget {
+                budget.record(cost);
+            }
+            resp.latency_ms = latency.as_millis() as u64;
+            if provider.config.strip_reasoning {
+                resp.reasoning = None;
+            }
+            info!("Refreshed popular cache entry ahead of expiry (provider: {})", provider.config.name);
+            state.cache.refresh(&entry, resp).await;
+        }
+        Err(e) => {
+            provider.stats.record_failure(&e);
+            warn!("Cache refresh failed: {} (provider: {})", e, provider.config.name);
+            entry.release_refresh();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::budget::{BudgetConfig, SpendBudget};
+    use crate::test_support::{self, MockUpstream};
+
+    // A popular entry about to expire, claimed for refresh like the scan does
+    async fn due_entry(state: &AppState) -> CacheEntry {
+        let req = test_support::request("popular", serde_json::json!({}));
+        state.cache.put(&req, test_support::response("old", "stale answer")).await;
+        assert!(state.cache.get(&req).await.is_some());
+        let mut due = state.cache.refresh_candidates_at(Instant::now(), 1, Duration::from_secs(3_600));
+        assert_eq!(due.len(), 1);
+        due.remove(0)
+    }
+
+    fn budget(max_spend_usd: f64) -> Option<SpendBudget> {
+        Some(SpendBudget::new(BudgetConfig { max_spend_usd, window_secs: 3_600, status: 402 }))
+    }
+
+    #[tokio::test]
+    async fn refresh_spend_is_recorded() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.budget = budget(1.0);
+        let entry = due_entry(&state).await;
+
+        refresh_entry(&state, entry).await;
+        assert_eq!(upstream.calls(), 1);
+        let cached = state.cache.get(&test_support::request("popular", serde_json::json!({}))).await.unwrap();
+        assert_eq!(cached.content, test_support::CONTENT);
+        // 20 tokens at $1 per 1k
+        assert!((state.budget.as_ref().unwrap().spent_usd() - 0.02).abs() < 1e-9);
+        assert!((state.costs.by_tag()[REFRESH_COST_TAG].cost_usd - 0.02).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn no_refresh_once_the_budget_is_spent() {
+        let upstream = MockUpstream::start().await;
+        let mut state = test_support::state(vec![upstream.provider("a")]);
+        state.budget = budget(0.01);
+        state.budget.as_ref().unwrap().record(0.01);
+        let entry = due_entry(&state).await;
+
+        refresh_entry(&state, entry).await;
+        assert_eq!(upstream.calls(), 0);
+        // Released, so the next scan may pick it up again once there is budget
+        assert_eq!(state.cache.refresh_candidates_at(Instant::now(), 1, Duration::from_secs(3_600)).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_scan_refreshes_popular_entries_keeping_their_full_weight() {
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let req = test_support::request(&"long prompt ".repeat(1_000), serde_json::json!({}));
+        state.cache.put(&req, test_support::response("old", "stale answer")).await;
+        assert!(state.cache.get(&req).await.is_some());
+        let weight = state.cache.memory().bytes;
+        assert!(weight > 12_000, "{weight} bytes");
+
+        spawn_refresher(state.clone(), RefreshAhead { min_hits: 1, ahead_secs: 3_600 });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while state.cache.get(&req).await.unwrap().content != test_support::CONTENT {
+            assert!(Instant::now() < deadline, "entry never refreshed");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(upstream.calls(), 1);
+        // The new entry still accounts for the request it was regenerated from
+        let refreshed = state.cache.memory().bytes;
+        assert!(refreshed > 12_000 && refreshed.abs_diff(weight) < 100, "{weight} -> {refreshed} bytes");
+    }
+}
@@ -0,0 +1,36 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info_span, Instrument};
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+// Longest client-supplied id we pass through; anything else gets a fresh one.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Identifies each request for log correlation: an incoming `X-Request-Id` is reused,
+// otherwise a UUID is generated. The handler runs inside a `request` span carrying the id,
+// so every log line it (and anything it awaits) emits is tagged, and the id is echoed back
+// in the response headers.
+pub async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let header = HeaderValue::from_str(&id).ok();
+    if let Some(value) = &header {
+        request.headers_mut().insert(REQUEST_ID_HEADER.clone(), value.clone());
+    }
+
+    let span = info_span!("request", request_id = %id);
+    let mut response = next.run(request).instrument(span).await;
+    if let Some(value) = header {
+        response.headers_mut().insert(REQUEST_ID_HEADER.clone(), value);
+    }
+    response
+}
@@ -0,0 +1,58 @@
+use crate::balancer::breaker;
+use crate::balancer::window::{WindowCounter, WINDOW_SECS};
+use serde::Deserialize;
+
+// Caps retries (attempts after a request's first provider failed) at a fraction of recent
+// requests, so an upstream outage doesn't multiply load on the providers still standing.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct RetryBudgetConfig {
+    // Retries allowed per request over the window.
+    pub ratio: f64,
+    // Retries allowed regardless of traffic, so a quiet gateway can still fail over.
+    pub min_retries_per_sec: f64,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self { ratio: 0.1, min_retries_per_sec: 1.0 }
+    }
+}
+
+// Requests and retries over the last WINDOW_SECS.
+#[derive(Debug)]
+pub struct RetryBudget {
+    config: RetryBudgetConfig,
+    requests: WindowCounter,
+    retries: WindowCounter,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        Self { config, requests: WindowCounter::new(), retries: WindowCounter::new() }
+    }
+
+    pub fn record_request(&self) {
+        self.requests.record_at(breaker::now_millis());
+    }
+
+    // Spends a retry if the window has room for one; false means fail fast instead.
+    pub fn try_retry(&self) -> bool {
+        self.try_retry_at(breaker::now_millis())
+    }
+
+    pub fn try_retry_at(&self, now_ms: u64) -> bool {
+        let allowed = self.config.ratio * self.requests.count_at(now_ms) as f64
+            + self.config.min_retries_per_sec * WINDOW_SECS as f64;
+        if (self.retries.count_at(now_ms) as f64) >= allowed {
+            return false;
+        }
+        self.retries.record_at(now_ms);
+        true
+    }
+
+    // Gives back a retry that was spent but never sent.
+    pub fn refund(&self) {
+        self.retries.unrecord_at(breaker::now_millis());
+    }
+}
@@ -0,0 +1,101 @@
+use super::{Provider, Router, ScoreBreakdown};
+use crate::model::LlmRequest;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::info;
+
+// Log target for decision records, so they can be routed to their own sink
+// (e.g. `RUST_LOG=routing_decision=info`).
+pub const LOG_TARGET: &str = "routing_decision";
+
+#[derive(Debug, Serialize)]
+pub struct CandidateScore {
+    pub provider: String,
+    #[serde(flatten)]
+    pub score: ScoreBreakdown,
+}
+
+// One routing decision as a training/tuning sample: request features, every candidate's
+// score components at selection time (best first), and what the choice realized.
+#[derive(Debug, Serialize)]
+pub struct RoutingDecision {
+    pub model: String,
+    pub estimated_input_tokens: u64,
+    pub stream: bool,
+    pub candidates: Vec<CandidateScore>,
+    // None when every attempted provider failed
+    pub winner: Option<String>,
+    pub attempts: usize,
+    pub realized_latency_ms: Option<u64>,
+    pub realized_cost_usd: Option<f64>,
+}
+
+impl RoutingDecision {
+    // Scores are captured right after `select_ranked`, before the call moves any stats.
+    pub fn capture(router: &Router, req: &LlmRequest, candidates: &[Arc<Provider>], estimated_input_tokens: u64) -> Self {
+        Self {
+            model: req.model.clone(),
+            estimated_input_tokens,
+            stream: req.is_streaming(),
+            candidates: candidates
+                .iter()
+                .map(|p| CandidateScore { provider: p.config.name.clone(), score: router.score_breakdown(p, req, estimated_input_tokens) })
+                .collect(),
+            winner: None,
+            attempts: 0,
+            realized_latency_ms: None,
+            realized_cost_usd: None,
+        }
+    }
+
+    pub fn succeeded(mut self, winner: &str, attempts: usize, latency_ms: u64, cost_usd: f64) {
+        self.winner = Some(winner.to_string());
+        self.attempts = attempts;
+        self.realized_latency_ms = Some(latency_ms);
+        self.realized_cost_usd = Some(cost_usd);
+        self.emit();
+    }
+
+    pub fn failed(mut self, attempts: usize) {
+        self.attempts = attempts;
+        self.emit();
+    }
+
+    fn emit(&self) {
+        if let Ok(record) = serde_json::to_string(self) {
+            info!(target: LOG_TARGET, "{}", record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use std::collections::HashMap;
+
+    #[test]
+    fn records_carry_a_score_breakdown_per_candidate() {
+        let config = |id: &str, cost| ProviderConfig {
+            id: id.to_string(),
+            name: id.to_string(),
+            endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+            cost_per_1k_input: cost,
+            cost_per_1k_output: cost,
+            model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+            ..ProviderConfig::default()
+        };
+        let router = Router::new(vec![config("costly", 0.01), config("cheap", 0.001)]).unwrap();
+        let req: LlmRequest = serde_json::from_value(serde_json::json!({"model": "m", "prompt": "hi"})).unwrap();
+        let candidates = router.select_ranked(&req);
+
+        let record = serde_json::to_value(RoutingDecision::capture(&router, &req, &candidates, 42)).unwrap();
+        assert_eq!((record["model"].as_str(), record["estimated_input_tokens"].as_u64()), (Some("m"), Some(42)));
+        let scored = record["candidates"].as_array().unwrap();
+        assert_eq!(scored.iter().map(|c| c["provider"].as_str().unwrap()).collect::<Vec<_>>(), ["cheap", "costly"]);
+        for candidate in scored {
+            let parts = ["latency_component", "cost_component", "load_component"].map(|k| candidate[k].as_f64().unwrap());
+            assert!((parts.iter().sum::<f64>() - candidate["total"].as_f64().unwrap()).abs() < 1e-9);
+        }
+    }
+}
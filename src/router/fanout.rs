@@ -0,0 +1,68 @@
+use super::{Provider, Router};
+use crate::model::{LlmRequest, TokenUsage};
+use std::sync::Arc;
+
+impl Router {
+    // Expected price of sending `req` to `provider`: the estimated input plus `max_tokens` of
+    // output, or the scoring weights' `expected_output_tokens` when the request doesn't cap it.
+    pub fn estimate_cost(&self, provider: &Provider, req: &LlmRequest, input_tokens: u64) -> f64 {
+        let usage = TokenUsage {
+            prompt_tokens: input_tokens.min(u32::MAX as u64) as u32,
+            completion_tokens: req.max_tokens.unwrap_or(self.weights.expected_output_tokens),
+            ..TokenUsage::default()
+        };
+        provider.config.cost_for(&usage)
+    }
+
+    // Trims a fan-out (one request sent to several providers at once, e.g. a shadow mirror
+    // next to the primary call) to the leading providers whose combined estimated cost fits
+    // under `cap_usd`. The first provider is always kept, so an over-cap fan-out degrades to
+    // a single call rather than failing.
+    pub fn cap_fanout(&self, providers: Vec<Arc<Provider>>, req: &LlmRequest, input_tokens: u64, cap_usd: Option<f64>) -> Vec<Arc<Provider>> {
+        let Some(cap) = cap_usd else { return providers };
+        let mut total = 0.0;
+        let mut kept = Vec::with_capacity(providers.len());
+        for provider in providers {
+            total += self.estimate_cost(&provider, req, input_tokens);
+            if !kept.is_empty() && total > cap {
+                break;
+            }
+            kept.push(provider);
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use std::collections::HashMap;
+
+    fn provider(id: &str, cost_per_1k: f64) -> ProviderConfig {
+        ProviderConfig {
+            id: id.to_string(),
+            name: id.to_string(),
+            endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+            cost_per_1k_input: cost_per_1k,
+            cost_per_1k_output: cost_per_1k,
+            model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+            ..ProviderConfig::default()
+        }
+    }
+
+    #[test]
+    fn over_cap_fanout_degrades_to_the_first_provider() {
+        let router = Router::new(vec![provider("a", 1.0), provider("b", 1.0), provider("c", 1.0)]).unwrap();
+        let providers = router.providers().to_vec();
+        let req: LlmRequest = serde_json::from_value(serde_json::json!({"model": "m", "prompt": "hi", "max_tokens": 100})).unwrap();
+        let ids = |kept: Vec<Arc<Provider>>| kept.iter().map(|p| p.config.id.clone()).collect::<Vec<_>>();
+
+        // 100 input + 100 output tokens at $1/1k: $0.20 per provider
+        assert!((router.estimate_cost(&providers[0], &req, 100) - 0.2).abs() < 1e-9);
+        assert_eq!(ids(router.cap_fanout(providers.clone(), &req, 100, None)), ["a", "b", "c"]);
+        assert_eq!(ids(router.cap_fanout(providers.clone(), &req, 100, Some(0.45))), ["a", "b"]);
+        // Even the first alone is over the cap; it is still sent
+        assert_eq!(ids(router.cap_fanout(providers, &req, 100, Some(0.01))), ["a"]);
+    }
+}
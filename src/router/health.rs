@@ -0,0 +1,214 @@
+use super::{Provider, Router};
+use crate::balancer::breaker::{self, BreakerState};
+use crate::balancer::stats::InFlightGuard;
+use crate::model::LlmRequest;
+use serde::Deserialize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// How often the prober looks for providers whose health check is due.
+const SCAN_PERIOD: Duration = Duration::from_secs(1);
+
+// Active breaker probing, off unless configured: a provider whose breaker cooldown has
+// elapsed gets its half-open probe as a tiny completion from the gateway, instead of waiting
+// for a client request to take the gamble, so an idle provider recovers (or stays out) on
+// its own.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BreakerProbeConfig {
+    // How often providers are looked at; a probe goes out at most once per breaker cooldown
+    pub interval_secs: u64,
+    pub prompt: String,
+}
+
+impl Default for BreakerProbeConfig {
+    fn default() -> Self {
+        Self { interval_secs: 5, prompt: "ping".to_string() }
+    }
+}
+
+impl Provider {
+    // Runs the configured health check once; Err describes why the provider is unhealthy.
+    pub async fn check_health(&self) -> Result<(), String> {
+        let (Some(check), Some(url)) = (&self.config.health_check, self.config.health_check_url()) else {
+            return Ok(());
+        };
+        let mut request = self.client.get(url);
+        for (name, value) in self.auth_headers(self.keys.pick_at(breaker::now_millis())) {
+            request = request.header(name, value);
+        }
+        let resp = request.send().await.map_err(|e| self.describe_error(e).to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("HTTP {}", resp.status().as_u16()));
+        }
+        let Some(expect) = &check.expect else { return Ok(()) };
+        let body: serde_json::Value = resp.json().await.map_err(|e| self.describe_error(e).to_string())?;
+        if expect.matches(&body) {
+            Ok(())
+        } else {
+            let actual = body.pointer(&expect.pointer).unwrap_or(&serde_json::Value::Null);
+            Err(format!("{} is {} (expected {})", expect.pointer, actual, expect.equals))
+        }
+    }
+}
+
+// Background prober for providers with a `health_check`. Reads the live routing table on
+// every scan, so checks added or removed by a config reload take effect without a restart.
+pub fn spawn_health_checks(router: Arc<Router>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SCAN_PERIOD);
+        loop {
+            ticker.tick().await;
+            let now = breaker::now_millis();
+            for provider in router.providers().iter() {
+                let Some(check) = &provider.config.health_check else { continue };
+                let last = provider.stats.last_health_check_ms.load(Ordering::Relaxed);
+                if last != 0 && now.saturating_sub(last) < check.interval_secs * 1000 {
+                    continue;
+                }
+                provider.stats.last_health_check_ms.store(now.max(1), Ordering::Relaxed);
+                let provider = provider.clone();
+                tokio::spawn(async move { run_check(&provider).await });
+            }
+        }
+    });
+}
+
+// One completion with `max_tokens: 1` for any model the provider maps. It counts like client
+// traffic: success closes the breaker, a provider-side failure reopens it for another
+// cooldown, and the tokens are charged to the provider.
+async fn probe_breaker(provider: &Provider, model: String, prompt: &str, in_flight: InFlightGuard) {
+    let req = LlmRequest {
+        model,
+        prompt: Some(prompt.to_string()),
+        max_tokens: Some(1),
+        temperature: Some(0.0),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let result = provider.call(&req).await;
+    drop(in_flight);
+    match result {
+        Ok(resp) => {
+            provider.stats.record_success(started.elapsed());
+            provider.charge(&resp.usage);
+            info!("Breaker probe to {} succeeded, closing its breaker", provider.config.name);
+        }
+        Err(e) => {
+            provider.stats.record_failure(&e);
+            warn!("Breaker probe to {} failed: {}", provider.config.name, e);
+        }
+    }
+}
+
+// Reads the live routing table on every scan, like the health prober. Only providers that
+// would otherwise be routed to are probed: draining ones and those failing their health
+// check are left alone.
+pub fn spawn_breaker_probes(router: Arc<Router>, config: BreakerProbeConfig) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            for provider in router.providers().iter() {
+                let due = provider.breaker_state() == BreakerState::HalfOpen && provider.has_capacity();
+                if !due || provider.is_draining() || !provider.passes_health_check() {
+                    continue;
+                }
+                let Some(model) = provider.config.model_map.keys().next().cloned() else { continue };
+                let Some(in_flight) = provider.try_acquire() else { continue };
+                // Claims the half-open slot, so client requests skip the provider meanwhile
+                if !provider.try_acquire_probe() {
+                    continue;
+                }
+                let (provider, prompt) = (provider.clone(), config.prompt.clone());
+                tokio::spawn(async move { probe_breaker(&provider, model, &prompt, in_flight).await });
+            }
+        }
+    });
+}
+
+async fn run_check(provider: &Provider) {
+    let result = provider.check_health().await;
+    let was_failing = provider.stats.health_check_failing.swap(result.is_err(), Ordering::Relaxed);
+    // Log transitions only
+    match result {
+        Err(e) if !was_failing => warn!("Health check failing, removing {} from rotation: {}", provider.config.name, e),
+        Ok(()) if was_failing => info!("Health check passing again, restoring {}", provider.config.name),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BodyPredicate, HealthCheck, ProviderConfig};
+    use std::sync::Mutex;
+
+    // Serves `/health` with whatever status the returned handle holds, always as a 200
+    async fn health_endpoint() -> (String, Arc<Mutex<&'static str>>) {
+        let status = Arc::new(Mutex::new("loading"));
+        let shared = status.clone();
+        let app = axum::Router::new().route(
+            "/health",
+            axum::routing::get(move || {
+                let status = *shared.lock().unwrap();
+                async move { axum::Json(serde_json::json!({"model": {"status": status}})) }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, status)
+    }
+
+    #[tokio::test]
+    async fn a_loading_body_fails_the_check_despite_the_200() {
+        let (url, status) = health_endpoint().await;
+        let expect = BodyPredicate { pointer: "/model/status".to_string(), equals: serde_json::json!("ready") };
+        let provider = Provider::new(ProviderConfig {
+            id: "a".to_string(),
+            name: "a".to_string(),
+            health_check: Some(HealthCheck { url, interval_secs: 10, expect: Some(expect) }),
+            ..ProviderConfig::default()
+        })
+        .unwrap();
+
+        run_check(&provider).await;
+        assert!(!provider.passes_health_check());
+        assert_eq!(provider.check_health().await, Err("/model/status is \"loading\" (expected \"ready\")".to_string()));
+
+        *status.lock().unwrap() = "ready";
+        run_check(&provider).await;
+        assert!(provider.passes_health_check());
+    }
+
+    #[tokio::test]
+    async fn idle_providers_recover_through_the_background_probe() {
+        use crate::test_support::MockUpstream;
+        let (recovered, down, drained) = (MockUpstream::start().await, MockUpstream::start().await, MockUpstream::start().await);
+        down.fail_with(500);
+        let config = |upstream: &MockUpstream, id: &str| ProviderConfig { breaker_cooldown_secs: Some(1), ..upstream.provider(id) };
+        let router = Arc::new(
+            Router::new(vec![config(&recovered, "recovered"), config(&down, "down"), config(&drained, "drained")]).unwrap(),
+        );
+        for provider in router.providers().iter() {
+            provider.stats.breaker.on_failure_at(breaker::now_millis(), true);
+        }
+        router.set_enabled("drained", false);
+        spawn_breaker_probes(router.clone(), BreakerProbeConfig { interval_secs: 1, ..BreakerProbeConfig::default() });
+
+        // No client traffic at all
+        let providers = router.providers();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while (providers[0].breaker_state() != BreakerState::Closed || down.calls() == 0) && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(providers[0].breaker_state(), BreakerState::Closed);
+        assert_eq!(recovered.calls(), 1);
+        assert!(down.calls() >= 1);
+        assert_ne!(providers[1].breaker_state(), BreakerState::Closed, "a failed probe keeps it out");
+        assert_eq!(drained.calls(), 0);
+    }
+}
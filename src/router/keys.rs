@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// How long a key rejected with 401/403 sits out before it is tried again.
+const REJECTED_KEY_COOLDOWN_MS: u64 = 60_000;
+
+// A provider's API keys, handed out round-robin so their rate limits add up. Keys the
+// provider rejected are skipped until their cooldown passes.
+#[derive(Debug)]
+pub struct KeyRing {
+    keys: Vec<String>,
+    next: AtomicUsize,
+    rejected_until_ms: Vec<AtomicU64>,
+}
+
+impl KeyRing {
+    pub fn new(keys: &[String]) -> Self {
+        Self {
+            keys: keys.to_vec(),
+            next: AtomicUsize::new(0),
+            rejected_until_ms: keys.iter().map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn key(&self, index: usize) -> &str {
+        &self.keys[index]
+    }
+
+    // Index of the next key to use. When every key is cooling down, the one whose cooldown
+    // ends first, so requests still go out. None when there are no keys.
+    pub fn pick_at(&self, now_ms: u64) -> Option<usize> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let usable = (0..self.keys.len())
+            .map(|offset| (start + offset) % self.keys.len())
+            .find(|&i| self.rejected_until_ms[i].load(Ordering::Relaxed) <= now_ms);
+        usable.or_else(|| (0..self.keys.len()).min_by_key(|&i| self.rejected_until_ms[i].load(Ordering::Relaxed)))
+    }
+
+    pub fn reject_at(&self, index: usize, now_ms: u64) {
+        self.rejected_until_ms[index].store(now_ms + REJECTED_KEY_COOLDOWN_MS, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(keys: &[&str]) -> KeyRing {
+        KeyRing::new(&keys.iter().map(|k| k.to_string()).collect::<Vec<_>>())
+    }
+
+    fn picks(ring: &KeyRing, now_ms: u64, n: usize) -> Vec<usize> {
+        (0..n).map(|_| ring.pick_at(now_ms).unwrap()).collect()
+    }
+
+    #[test]
+    fn keys_are_handed_out_round_robin() {
+        let ring = ring(&["k1", "k2", "k3"]);
+        assert_eq!(picks(&ring, 0, 6), [0, 1, 2, 0, 1, 2]);
+        assert!(KeyRing::new(&[]).pick_at(0).is_none());
+    }
+
+    #[test]
+    fn rejected_keys_sit_out_their_cooldown() {
+        let ring = ring(&["k1", "k2", "k3"]);
+        ring.reject_at(1, 1_000);
+        assert!(picks(&ring, 2_000, 6).iter().all(|&i| i != 1));
+        // Back in rotation once the cooldown has passed
+        assert!(picks(&ring, 1_000 + REJECTED_KEY_COOLDOWN_MS, 3).contains(&1));
+
+        // With every key benched, the one back soonest still goes out
+        ring.reject_at(0, 5_000);
+        ring.reject_at(1, 3_000);
+        ring.reject_at(2, 4_000);
+        assert_eq!(picks(&ring, 6_000, 3), [1, 1, 1]);
+    }
+}
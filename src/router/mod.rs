@@ -1,28 +1,230 @@
-use crate::model::{LlmRequest, ProviderConfig, LlmResponse};
-use crate::balancer::stats::ProviderStats;
+use crate::model::{Capability, LlmRequest, ProviderConfig, LlmResponse, LogitBiasSupport, ProviderType, RateLimitHandling, SystemPromptStrategy, TokenUsage};
+use crate::balancer::stats::{InFlightGuard, ProviderStats, MAX_SCORED_ERROR_RATE};
+use crate::balancer::breaker::{self, BreakerState};
+use crate::balancer::quota::RateLimitQuota;
+use crate::error::ProviderError;
+use crate::streaming;
+use crate::tokens;
+use strategy::RouteStrategy;
+use policy::{DefaultPolicy, RoutingPolicy};
+use preview::Exclusion;
+use keys::KeyRing;
+use virtual_models::VirtualModels;
+use std::collections::HashMap;
+use futures::stream::{BoxStream, StreamExt};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use arc_swap::ArcSwap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tracing::warn;
 
+pub mod decision;
+pub mod fanout;
+pub mod health;
+pub mod keys;
+pub mod outlier;
+pub mod policy;
+pub mod preview;
+pub mod reasoning;
+pub mod strategy;
+pub mod transform;
+pub mod virtual_models;
+
+// Per in-flight request penalty floor, so congestion counts even before any latency sample.
+const MIN_IN_FLIGHT_PENALTY_MS: f64 = 1.0;
+const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+// Pause before the first same-provider retry, and the step it grows by per retry.
+const SAME_PROVIDER_RETRY_DELAY_MS: u64 = 100;
+// Latency-equivalent penalty for a provider whose reported quota is exhausted.
+const EXHAUSTED_QUOTA_PENALTY_MS: f64 = 10_000.0;
+
+// Weights for the "lowest score wins" ranking:
+//   score = latency_weight * latency_ms + cost_weight * expected_cost_per_1k + load_weight * load_factor
+// `latency_ms` is the latency EWMA (time to first token for streams) plus the in-flight
+// congestion and low-quota penalties, in ms;
+// `expected_cost_per_1k` is USD per 1k tokens of the request's expected mix: its estimated
+// input plus `max_tokens` of output (`expected_output_tokens` when unset), each at its own
+// rate; `load_factor` is in-flight calls over `max_concurrency` (0 without a limit). With the
+// defaults, $0.001/1k weighs the same as 100ms of latency, and a provider at its concurrency
+// limit the same as one 500ms slower.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct ScoringWeights {
+    pub latency_weight: f64,
+    pub cost_weight: f64,
+    pub expected_output_tokens: u32,
+    pub load_weight: f64,
+}
+
+impl Default for ScoringWeights {
+    fn default() -> Self {
+        Self { latency_weight: 1.0, cost_weight: 100_000.0, expected_output_tokens: 512, load_weight: 500.0 }
+    }
+}
+
+// Terms that make up a provider's routing score (see ScoringWeights): the raw inputs, then
+// the weighted latency, cost and load components, which sum to `total`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ScoreBreakdown {
+    pub latency_ms: f64,
+    pub congestion_ms: f64,
+    // Recent completions per second, when there were enough to measure
+    pub goodput_rps: Option<f64>,
+    pub quota_penalty_ms: f64,
+    // Failed fraction of recent calls (see ProviderStats::error_rate)
+    pub error_rate: f64,
+    pub cost_per_1k_input: f64,
+    pub cost_per_1k_output: f64,
+    // Input and output rates blended by the request's expected token mix
+    pub expected_cost_per_1k: f64,
+    // Share of `max_concurrency` in use (see Provider::load_factor)
+    pub load_factor: f64,
+    pub latency_component: f64,
+    pub cost_component: f64,
+    pub load_component: f64,
+    pub total: f64,
+}
+
 #[derive(Debug)]
 pub struct Provider {
     pub config: ProviderConfig,
     pub stats: Arc<ProviderStats>,
+    // When this provider entered the routing table (drives the traffic ramp).
+    pub added_at: Instant,
+    // Built once per config, never per call, so connections and TLS sessions are pooled
+    // across requests. Client construction errors surface here rather than mid-request.
+    client: reqwest::Client,
+    keys: KeyRing,
 }
 
 impl Provider {
-    pub fn new(config: ProviderConfig) -> Self {
-        Self {
+    pub fn new(config: ProviderConfig) -> Result<Self, reqwest::Error> {
+        let stats = Arc::new(ProviderStats::new());
+        stats.configure(&config);
+        Ok(Self {
+            client: build_client(&config)?,
+            keys: KeyRing::new(&config.api_key),
+            config,
+            stats,
+            added_at: Instant::now(),
+        })
+    }
+
+    // New config for an existing provider: keeps its live stats (EWMA, breaker) and ramp start.
+    // Rejected keys get another chance, since the reload may have replaced them.
+    pub fn reconfigured(&self, config: ProviderConfig) -> Result<Self, reqwest::Error> {
+        self.stats.configure(&config);
+        Ok(Self {
+            client: build_client(&config)?,
+            keys: KeyRing::new(&config.api_key),
             config,
-            stats: Arc::new(ProviderStats::new()),
+            stats: self.stats.clone(),
+            added_at: self.added_at,
+        })
+    }
+
+    pub fn timeout_ms(&self) -> u64 {
+        self.config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS)
+    }
+
+    pub fn ramp_weight(&self) -> f64 {
+        self.ramp_weight_at(Instant::now()).min(self.recovery_weight_at(breaker::now_millis()))
+    }
+
+    // Effective traffic weight in [0, 1]. Providers without a ramp are always at full weight;
+    // otherwise the weight grows linearly with the time elapsed since `added_at`.
+    pub fn ramp_weight_at(&self, now: Instant) -> f64 {
+        let ramp = match self.config.ramp_up_secs {
+            Some(secs) if secs > 0 => Duration::from_secs(secs),
+            _ => return 1.0,
+        };
+        let elapsed = now.saturating_duration_since(self.added_at);
+        (elapsed.as_secs_f64() / ramp.as_secs_f64()).min(1.0)
+    }
+
+    // Like `ramp_weight_at`, growing from the breaker's last recovery (see recovery_ramp_secs).
+    pub fn recovery_weight_at(&self, now_ms: u64) -> f64 {
+        let ramp_ms = self.config.recovery_ramp_secs.map_or(breaker::DEFAULT_RECOVERY_RAMP_MS, |s| s * 1000);
+        match self.stats.breaker.recovered_at_ms() {
+            Some(at) if ramp_ms > 0 => (now_ms.saturating_sub(at) as f64 / ramp_ms as f64).min(1.0),
+            _ => 1.0,
+        }
+    }
+
+    pub fn breaker_cooldown_ms(&self) -> u64 {
+        self.config
+            .breaker_cooldown_secs
+            .map(|s| s * 1000)
+            .unwrap_or(breaker::DEFAULT_COOLDOWN_MS)
+    }
+
+    pub fn breaker_state(&self) -> BreakerState {
+        self.stats.breaker.state_at(breaker::now_millis(), self.breaker_cooldown_ms())
+    }
+
+    // Latency EWMA in milliseconds; 0 before the first sample.
+    pub fn latency_ms(&self) -> f64 {
+        self.stats.ewma_latency_us.value() / 1000.0
+    }
+
+    // What a client waits for before output starts: time to first token for streams, once
+    // the provider has streamed at all, else the full latency.
+    pub fn latency_ms_for(&self, req: &LlmRequest) -> f64 {
+        let ttft_ms = self.stats.ewma_ttft_us.value() / 1000.0;
+        if req.is_streaming() && ttft_ms > 0.0 {
+            ttft_ms
+        } else {
+            self.latency_ms()
+        }
+    }
+
+    pub fn passes_health_check(&self) -> bool {
+        !self.stats.health_check_failing.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.stats.draining.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    // Rate limited by the provider recently (see ProviderStats::record_failure).
+    pub fn is_throttled(&self) -> bool {
+        self.stats.is_throttled_at(breaker::now_millis())
+    }
+
+    // Whether routing may pick this provider, whatever the request. Side-effect free: a
+    // half-open breaker counts as available, and only the request actually dispatched claims
+    // its probe (`try_acquire_probe`).
+    pub fn is_available(&self) -> bool {
+        self.unavailability().is_none()
+    }
+
+    // Why `is_available` says no, if it does: the first check that fails.
+    pub fn unavailability(&self) -> Option<Exclusion> {
+        if self.is_draining() {
+            Some(Exclusion::Draining)
+        } else if !self.passes_health_check() {
+            Some(Exclusion::HealthCheckFailing)
+        } else if self.is_throttled() {
+            Some(Exclusion::RateLimited)
+        } else if self.is_ejected() {
+            Some(Exclusion::Ejected)
+        } else if self.breaker_state() == BreakerState::Open {
+            Some(Exclusion::CircuitOpen)
+        } else {
+            None
         }
     }
 
-    pub fn is_healthy(&self) -> bool {
-        // Simple circuit breaker check
-        // If consecutive errors > 5, consider unhealthy. 
-        // Real implementation would have half-open state and recovery timeout.
-        self.stats.consec_errors.load(std::sync::atomic::Ordering::Relaxed) < 5
+    // Called right before dispatch. Closed breakers pass; once an open one's cooldown elapses,
+    // a single half-open probe is let through (see CircuitBreaker), and the caller must report
+    // its outcome with record_success or record_failure.
+    pub fn try_acquire_probe(&self) -> bool {
+        self.stats.breaker.try_acquire_at(breaker::now_millis(), self.breaker_cooldown_ms())
+    }
+
+    pub fn in_region(&self, region: &str) -> bool {
+        self.config.region.as_deref() == Some(region)
     }
 
     pub fn supports_model(&self, model: &str) -> bool {
@@ -30,116 +232,1387 @@ impl Provider {
         self.config.model_map.contains_key(model)
     }
 
-    pub async fn call(&self, req: &LlmRequest) -> Result<LlmResponse, String> {
-        // Real HTTP call
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(5))
-            .build()
-            .unwrap_or_default();
-        
-        let target_model = self.config.model_map.get(&req.model).unwrap_or(&req.model).clone();
-        
-        // Forwarding request - in real app, we'd transform the body
-        let mut body = serde_json::to_value(req).unwrap_or(serde_json::Value::Null);
-        if let serde_json::Value::Object(ref mut map) = body {
-            map.insert("model".to_string(), serde_json::Value::String(target_model));
-        }
-
-        let resp = client.post(&self.config.endpoint)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| e.to_string())?;
+    pub fn has_capacity(&self) -> bool {
+        match self.config.max_concurrency {
+            Some(limit) => self.stats.in_flight.load(std::sync::atomic::Ordering::Relaxed) < limit,
+            None => true,
+        }
+    }
+
+    // In-flight calls as a fraction of `max_concurrency`; 0 for providers without a limit.
+    pub fn load_factor(&self) -> f64 {
+        match self.config.max_concurrency {
+            Some(limit) if limit > 0 => {
+                (self.stats.in_flight.load(std::sync::atomic::Ordering::Relaxed) as f64 / limit as f64).min(1.0)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // Claims a concurrency slot for one call; None when the provider is saturated.
+    pub fn try_acquire(&self) -> Option<InFlightGuard> {
+        self.stats.try_track_in_flight(self.config.max_concurrency)
+    }
+
+    // Extra latency-equivalent score (ms) for a provider running low on rate-limit quota.
+    // Grows linearly from 0 at the configured threshold to EXHAUSTED_QUOTA_PENALTY_MS at zero.
+    pub fn quota_penalty_ms(&self) -> f64 {
+        let RateLimitHandling::Deprioritize { below } = self.config.rate_limit else { return 0.0 };
+        match self.stats.quota.headroom_at(breaker::now_millis()) {
+            Some(headroom) if below > 0.0 && headroom < below => {
+                (1.0 - headroom / below) * EXHAUSTED_QUOTA_PENALTY_MS
+            }
+            _ => 0.0,
+        }
+    }
+
+    // Cost of a completed call at this provider's prices, added to its running total.
+    pub fn charge(&self, usage: &TokenUsage) -> f64 {
+        let cost = self.config.cost_for(usage);
+        self.stats.record_cost(cost);
+        cost
+    }
+
+    // Credentials for the provider type, minus any the config's `headers` replace. Configured
+    // headers themselves are client defaults (see build_client).
+    fn auth_headers(&self, key: Option<usize>) -> Vec<(&'static str, String)> {
+        let key = key.map_or("", |i| self.keys.key(i));
+        let mut headers = transform::auth_headers(self.config.provider_type, key);
+        headers.retain(|(name, _)| !self.config.headers.keys().any(|k| k.eq_ignore_ascii_case(name)));
+        headers
+    }
+
+    fn describe_error(&self, e: reqwest::Error) -> ProviderError {
+        ProviderError::from_reqwest(e, self.timeout_ms(), self.config.connect_timeout_ms)
+    }
+
+    // Whether this provider can honor the request's optional parameters.
+    // Several completions per request (`n`) only exist in the OpenAI wire format.
+    pub fn supports_params(&self, req: &LlmRequest) -> bool {
+        let excluded_logit_bias = req.uses_logit_bias() && self.config.logit_bias == LogitBiasSupport::Exclude;
+        let multiple_choices = req.choice_count() > 1
+            && matches!(self.config.provider_type, Some(ProviderType::Anthropic | ProviderType::Ollama));
+        !excluded_logit_bias && !multiple_choices && self.has_capabilities(req)
+    }
+
+    pub fn has_capabilities(&self, req: &LlmRequest) -> bool {
+        let Some(capabilities) = &self.config.capabilities else { return true };
+        req.required_capabilities().iter().all(|c| capabilities.contains(c))
+    }
+
+    // Whether the request fits the provider's context window, given its estimated input size.
+    pub fn fits_context(&self, req: &LlmRequest, input_tokens: u64) -> bool {
+        self.config
+            .max_context_tokens
+            .is_none_or(|limit| input_tokens + req.max_tokens.unwrap_or(0) as u64 <= limit as u64)
+    }
+
+    pub async fn call(&self, req: &LlmRequest) -> Result<LlmResponse, ProviderError> {
+        let body = self.build_body(req, false);
+        let resp = self.send(req, &body, false).await?;
+        let cache_ttl = self.cache_ttl(Some(resp.headers()));
+
+        let body: serde_json::Value = resp.json().await.map_err(|e| self.describe_error(e))?;
+        let completion = LlmResponse { cache_ttl, ..parse_completion(&body, &self.config.name, self.config.provider_type) };
+        // Checked before the answer can be cached or returned, so the gateway falls back to
+        // the next provider instead. Streams are relayed as they arrive and can't be checked.
+        if req.wants_json() {
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(completion.content.trim()) {
+                return Err(ProviderError::InvalidJson(e.to_string()));
+            }
+        }
+        Ok(completion)
+    }
+
+    // Streaming variant of `call`: resolves once the upstream accepted the request and yields
+    // content deltas, flushed according to the provider's buffering strategy. Dropping the
+    // stream closes the upstream connection.
+    pub async fn call_stream(&self, req: &LlmRequest) -> Result<BoxStream<'static, Result<String, ProviderError>>, ProviderError> {
+        let body = self.build_body(req, true);
+        let resp = self.send(req, &body, true).await?;
+
+        // A stream may run for as long as it keeps producing; the timeout only bounds each
+        // wait for the next chunk.
+        let timeout_ms = self.timeout_ms();
+        let provider_type = self.config.provider_type;
+        let bytes = resp.bytes_stream().map(move |r| r.map_err(|e| ProviderError::from_reqwest(e, timeout_ms, None)));
+        let bytes = streaming::idle_timeout(bytes, Duration::from_millis(timeout_ms), move || ProviderError::Timeout { after_ms: timeout_ms });
+        // Ollama streams newline-delimited JSON rather than SSE
+        let events = match provider_type {
+            Some(ProviderType::Ollama) => streaming::ndjson_lines(bytes).boxed(),
+            _ => streaming::sse_data(bytes).boxed(),
+        };
+        let deltas = events.filter_map(move |item| async move {
+            match item {
+                Ok(payload) => match transform::stream_error(&payload) {
+                    Some(message) => Some(Err(ProviderError::Decode(format!("stream error: {}", message)))),
+                    None => transform::stream_delta(&payload, provider_type).map(Ok),
+                },
+                Err(e) => Some(Err(e)),
+            }
+        });
+        Ok(streaming::buffer_chunks(deltas, self.config.stream_buffering))
+    }
+
+    // How long the cache may keep an answer: the `cache_ttl_header` of the upstream response
+    // when present and numeric, else `cache_ttl_secs`.
+    pub fn cache_ttl(&self, headers: Option<&reqwest::header::HeaderMap>) -> Option<Duration> {
+        let from_header = self
+            .config
+            .cache_ttl_header
+            .as_deref()
+            .zip(headers)
+            .and_then(|(name, headers)| headers.get(name)?.to_str().ok()?.trim().parse().ok());
+        from_header.or(self.config.cache_ttl_secs).map(Duration::from_secs)
+    }
+
+    // Provider-side name for the client's model.
+    fn target_model<'a>(&'a self, req: &'a LlmRequest) -> &'a str {
+        self.config.model_map.get(&req.model).unwrap_or(&req.model)
+    }
+
+    fn build_body(&self, req: &LlmRequest, stream: bool) -> serde_json::Value {
+        use serde_json::Value;
+
+        // Client extras (`stop`, `top_p`, `user`, ...) go first so the typed fields below win
+        // over an extra of the same name. `session` is a gateway-only routing hint (see
+        // RouteStrategy::ConsistentHash) and unsupported logit_bias is stripped.
+        let strip_logit_bias = req.uses_logit_bias() && self.config.logit_bias == LogitBiasSupport::Strip;
+        if strip_logit_bias {
+            warn!("Stripping unsupported logit_bias for provider {}", self.config.name);
+        }
+        let mut map: serde_json::Map<String, Value> = req
+            .extra_params
+            .iter()
+            .filter(|(key, _)| key.as_str() != "session" && !(strip_logit_bias && key.as_str() == "logit_bias"))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        map.insert("model".to_string(), Value::String(self.target_model(req).to_string()));
+        // Chat-completions upstreams expect `messages`; normalize legacy `prompt` requests.
+        map.remove("prompt");
+        map.insert("messages".to_string(), serde_json::to_value(req.to_messages()).unwrap_or_default());
+        // Unset parameters are left out rather than sent as null, which some servers reject
+        if let Some(max_tokens) = req.max_tokens {
+            map.insert("max_tokens".to_string(), Value::from(max_tokens));
+        }
+        if let Some(temperature) = req.temperature {
+            // Via the shortest decimal form, so 0.2 arrives as 0.2 rather than 0.20000000298
+            let temperature = temperature.to_string().parse::<f64>().unwrap_or(temperature as f64);
+            map.insert("temperature".to_string(), Value::from(temperature));
+        }
+        if let Some(format) = &req.response_format {
+            map.insert("response_format".to_string(), format.clone());
+        }
+        if let Some(n) = req.n {
+            map.insert("n".to_string(), Value::from(n));
+        }
+        map.insert("stream".to_string(), Value::Bool(stream));
+
+        let mut body = Value::Object(map);
+        if let Some(system_prompt) = &self.config.system_prompt {
+            apply_system_prompt(&mut body, system_prompt, self.config.system_prompt_strategy);
+        }
+        transform::request_body(body, self.config.provider_type)
+    }
+
+    // A key rejected with 401/403 is benched and the call repeated with the next one, at most
+    // once per key.
+    async fn send(&self, req: &LlmRequest, body: &serde_json::Value, stream: bool) -> Result<reqwest::Response, ProviderError> {
+        let mut keys_left = self.keys.len();
+        loop {
+            let key = self.keys.pick_at(breaker::now_millis());
+            let resp = self.send_retrying(req, body, key, stream).await?;
+            let status = resp.status().as_u16();
+            match key {
+                Some(index) if matches!(status, 401 | 403) => {
+                    warn!("{} rejected API key #{} (HTTP {})", self.config.name, index + 1, status);
+                    self.keys.reject_at(index, breaker::now_millis());
+                    keys_left -= 1;
+                    if keys_left > 0 {
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            return self.check_status(resp);
+        }
+    }
+
+    // Repeats a send that failed on the connection up to `same_provider_retries` times, with a
+    // short pause growing per try. HTTP errors are answers and are never repeated here.
+    async fn send_retrying(&self, req: &LlmRequest, body: &serde_json::Value, key: Option<usize>, stream: bool) -> Result<reqwest::Response, ProviderError> {
+        let mut retry = 0;
+        loop {
+            match self.send_with_key(req, body, key, stream).await {
+                Err(e) if e.is_transient() && retry < self.config.same_provider_retries => {
+                    retry += 1;
+                    warn!("Retrying {} after {} (retry {} of {})", self.config.name, e, retry, self.config.same_provider_retries);
+                    tokio::time::sleep(Duration::from_millis(SAME_PROVIDER_RETRY_DELAY_MS * retry as u64)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Buffered calls get the provider's timeout for the whole exchange, body included; a
+    // stream only until its response headers arrive (see call_stream for the rest).
+    async fn send_with_key(&self, req: &LlmRequest, body: &serde_json::Value, key: Option<usize>, stream: bool) -> Result<reqwest::Response, ProviderError> {
+        let timeout = Duration::from_millis(self.timeout_ms());
+        let mut request = self.client.post(self.config.endpoint_url(self.target_model(req)));
+        for (name, value) in self.auth_headers(key) {
+            request = request.header(name, value);
+        }
+        let request = request.json(body);
+        let resp = if stream {
+            tokio::time::timeout(timeout, request.send())
+                .await
+                .map_err(|_| ProviderError::Timeout { after_ms: self.timeout_ms() })?
+        } else {
+            request.timeout(timeout).send().await
+        }
+        .map_err(|e| self.describe_error(e))?;
+
+        // Throttled responses carry the headers too, so record before checking the status.
+        let quota = RateLimitQuota::from_headers(resp.headers());
+        self.stats.quota.record_at(breaker::now_millis(), &quota);
+        Ok(resp)
+    }
 
+    fn check_status(&self, resp: reqwest::Response) -> Result<reqwest::Response, ProviderError> {
         if !resp.status().is_success() {
-            return Err(format!("HTTP {}", resp.status()));
-        }
-
-        // Just consuming body to ensure complete request
-        let _text = resp.text().await.map_err(|e| e.to_string())?;
-
-        // Return a generic response for the prototype
-        Ok(crate::model::LlmResponse {
-            content: "Content from provider".to_string(),
-            usage: crate::model::TokenUsage {
-                prompt_tokens: 0,
-                completion_tokens: 0,
-                total_tokens: 0,
-            },
-            provider: self.config.name.clone(),
-            latency_ms: 0, // Placeholder, set by caller
+            let status = resp.status().as_u16();
+            // Only the delay-seconds form; HTTP-date values are rare from API providers
+            let retry_after_ms = matches!(status, 429 | 503)
+                .then(|| resp.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim().parse::<u64>().ok())
+                .flatten()
+                .map(|secs| secs.saturating_mul(1000));
+            return Err(ProviderError::Status { status, retry_after_ms });
+        }
+        Ok(resp)
+    }
+}
+
+fn build_client(config: &ProviderConfig) -> Result<reqwest::Client, reqwest::Error> {
+    // No overall timeout here: it would cut off long streams (see Provider::send_with_key)
+    let mut builder = reqwest::Client::builder();
+    if let Some(connect) = config.connect_timeout_ms {
+        builder = builder.connect_timeout(Duration::from_millis(connect));
+    }
+    if let Some(max_idle) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(idle) = config.pool_idle_timeout_ms {
+        builder = builder.pool_idle_timeout(Duration::from_millis(idle));
+    }
+    // Invalid entries are rejected by config validation; anything left is skipped.
+    let headers: reqwest::header::HeaderMap = config
+        .headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes()).ok()?;
+            Some((name, reqwest::header::HeaderValue::from_str(value).ok()?))
         })
+        .collect();
+    builder.default_headers(headers).build()
+}
+
+// Builds an LlmResponse from a completion body in the provider type's schema. Missing fields
+// degrade to empty content / zero usage rather than failing the call.
+pub fn parse_completion(body: &serde_json::Value, provider: &str, provider_type: Option<ProviderType>) -> LlmResponse {
+    let (content, reasoning) = reasoning::split_reasoning(body, provider_type);
+    // Usage already covers every choice
+    let usage = transform::parse_usage(body, provider_type);
+    let choices = reasoning::choice_answers(body, provider_type);
+    let choices = if choices.len() > 1 { choices } else { Vec::new() };
+
+    LlmResponse {
+        content,
+        usage,
+        provider: provider.to_string(),
+        latency_ms: 0, // Placeholder, set by caller
+        reasoning,
+        cache_ttl: None,
+        choices,
+    }
+}
+
+// Injects the provider's system prompt into an outgoing chat body as a leading system message.
+pub fn apply_system_prompt(body: &mut serde_json::Value, system_prompt: &str, strategy: SystemPromptStrategy) {
+    use serde_json::{json, Value};
+
+    let Some(Value::Array(messages)) = body.get_mut("messages") else { return };
+    let client_system = messages
+        .iter()
+        .position(|m| m.get("role").and_then(Value::as_str) == Some("system"));
+
+    match (strategy, client_system) {
+        (SystemPromptStrategy::PreferClient, Some(_)) => {}
+        (SystemPromptStrategy::Concatenate, Some(idx)) => {
+            let client_text = messages[idx].get("content").and_then(Value::as_str).unwrap_or("").to_string();
+            messages.remove(idx);
+            messages.insert(0, json!({
+                "role": "system",
+                "content": format!("{}\n\n{}", system_prompt, client_text),
+            }));
+        }
+        _ => messages.insert(0, json!({ "role": "system", "content": system_prompt })),
     }
+}
 
+// Ranked candidates for a request. `degraded` marks the last-resort pick of
+// `Router::with_degraded_fallback`: a provider that isn't available, its breaker possibly
+// open, which the gateway sends to anyway instead of gating it on a probe.
+pub struct Selection {
+    pub providers: Vec<Arc<Provider>>,
+    pub degraded: bool,
 }
 
 pub struct Router {
     // Shared list of providers, swappable atomically.
     providers: ArcSwap<Vec<Arc<Provider>>>,
+    weights: ScoringWeights,
+    strategy: RouteStrategy,
+    policy: Box<dyn RoutingPolicy>,
+    degraded_fallback: bool,
+    // `max_latency_ms` of virtual models, by alias
+    latency_slas: HashMap<String, u64>,
+    // Default preferred region for requests that don't name one
+    region: Option<String>,
 }
 
 impl Router {
-    pub fn new(configs: Vec<ProviderConfig>) -> Self {
-        let providers_vec: Vec<Arc<Provider>> = configs
+    pub fn new(configs: Vec<ProviderConfig>) -> Result<Self, reqwest::Error> {
+        Self::with_weights(configs, ScoringWeights::default())
+    }
+
+    pub fn with_weights(configs: Vec<ProviderConfig>, weights: ScoringWeights) -> Result<Self, reqwest::Error> {
+        let providers_vec = configs
             .into_iter()
-            .map(|c| Arc::new(Provider::new(c)))
-            .collect();
-        Self {
+            .map(|c| Provider::new(c).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
             providers: ArcSwap::from(Arc::new(providers_vec)),
+            weights,
+            strategy: RouteStrategy::default(),
+            policy: Box::new(DefaultPolicy),
+            degraded_fallback: false,
+            latency_slas: HashMap::new(),
+            region: None,
+        })
+    }
+
+    pub fn with_strategy(mut self, strategy: RouteStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    // Custom choice of the first provider to try (see RoutingPolicy).
+    pub fn with_policy(mut self, policy: Box<dyn RoutingPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // When no provider is healthy, route to the unhealthy one with the fewest consecutive
+    // errors rather than to none. Drained providers stay out regardless.
+    pub fn with_degraded_fallback(mut self, enabled: bool) -> Self {
+        self.degraded_fallback = enabled;
+        self
+    }
+
+    // Latency SLAs of virtual models. The rest of their definition is applied to provider
+    // model maps when configs are loaded (see virtual_models::expand).
+    pub fn with_virtual_models(mut self, virtual_models: &VirtualModels) -> Self {
+        self.latency_slas = virtual_models
+            .iter()
+            .filter_map(|(alias, v)| v.max_latency_ms.map(|ms| (alias.clone(), ms)))
+            .collect();
+        self
+    }
+
+    // Prefer providers in `region`: others only lead when no provider there is eligible, and
+    // otherwise follow the local ones for failover. A request's own `region` overrides it.
+    pub fn with_region(mut self, region: Option<String>) -> Self {
+        self.region = region;
+        self
+    }
+
+    // Latency budget for `req`: its own `max_latency_ms`, else its virtual model's.
+    pub fn latency_sla(&self, req: &LlmRequest) -> Option<u64> {
+        req.max_latency_ms.or_else(|| self.latency_slas.get(&req.model).copied())
+    }
+
+    // No provider can take traffic: the gateway is down to answering from cache.
+    pub fn is_degraded(&self) -> bool {
+        !self.providers().iter().any(|p| p.is_available())
+    }
+
+    // A capability `req` needs that no provider serving its model has, whatever their health;
+    // such a request can never be routed. When each is available but never all in one
+    // provider, the first of them.
+    pub fn missing_capability(&self, req: &LlmRequest) -> Option<Capability> {
+        let required = req.required_capabilities();
+        let list = self.providers.load();
+        let serving: Vec<&Arc<Provider>> = list.iter().filter(|p| p.supports_model(&req.model)).collect();
+        if required.is_empty() || serving.is_empty() || serving.iter().any(|p| p.has_capabilities(req)) {
+            return None;
         }
+        let offered = |c: &Capability| serving.iter().any(|p| p.config.capabilities.as_ref().is_none_or(|caps| caps.contains(c)));
+        required.iter().find(|c| !offered(c)).or(required.first()).copied()
+    }
+
+    // Current snapshot of the routing table.
+    pub fn providers(&self) -> Arc<Vec<Arc<Provider>>> {
+        self.providers.load_full()
+    }
+
+    // Takes a provider out of rotation (or puts it back) without a config change. Requests
+    // already dispatched to it finish normally; the flag lives in its stats, so it survives
+    // reloads. False when no provider has that id.
+    pub fn set_enabled(&self, provider_id: &str, enabled: bool) -> bool {
+        let list = self.providers.load();
+        let Some(provider) = list.iter().find(|p| p.config.id == provider_id) else { return false };
+        provider.stats.draining.store(!enabled, std::sync::atomic::Ordering::Relaxed);
+        true
+    }
+
+    // Stable hash of the loaded provider configs, so replicas can be compared. Going through
+    // `serde_json::Value` sorts map keys, making the hash independent of HashMap order.
+    pub fn config_hash(&self) -> String {
+        let list = self.providers.load();
+        let configs: Vec<&ProviderConfig> = list.iter().map(|p| &p.config).collect();
+        let canonical = serde_json::to_value(configs).unwrap_or_default().to_string();
+        blake3::hash(canonical.as_bytes()).to_hex().to_string()
     }
 
     pub fn select(&self, req: &LlmRequest) -> Option<Arc<Provider>> {
+        self.select_ranked(req).into_iter().next()
+    }
+
+    // All usable providers for the request, best (lowest score) first, sized by the
+    // uncorrected token estimate. The gateway walks this list when falling back after a
+    // failed call.
+    pub fn select_ranked(&self, req: &LlmRequest) -> Vec<Arc<Provider>> {
+        self.select_ranked_for(req, None, tokens::raw_estimate(req))
+    }
+
+    // Providers that would serve `req` but are at `max_concurrency` right now, i.e. what a
+    // request finding no candidates can wait for (see queue::WaitQueue).
+    pub fn saturated_for(&self, req: &LlmRequest, input_tokens: u64) -> Vec<Arc<Provider>> {
+        self.providers
+            .load()
+            .iter()
+            .filter(|p| p.supports_model(&req.model) && p.supports_params(req) && p.fits_context(req, input_tokens))
+            .filter(|p| !p.has_capacity() && p.is_available())
+            .cloned()
+            .collect()
+    }
+
+    // Like `select_ranked`, with the caller's API key as the fallback sticky key for
+    // RouteStrategy::ConsistentHash and the request's input size as estimated by the caller
+    // (see TokenEstimator::estimate).
+    pub fn select_ranked_for(&self, req: &LlmRequest, client: Option<&str>, input_tokens: u64) -> Vec<Arc<Provider>> {
+        self.select_candidates(req, client, input_tokens).providers
+    }
+
+    // `select_ranked_for`, saying whether the list is the degraded fallback's pick.
+    pub fn select_candidates(&self, req: &LlmRequest, client: Option<&str>, input_tokens: u64) -> Selection {
         // Snapshot the current list of providers
         let list = self.providers.load();
 
         // 1. Filter candidates
-        let candidates = list.iter().filter(|p| {
-            p.supports_model(&req.model) && p.is_healthy()
-        });
+        let usable: Vec<&Arc<Provider>> = list.iter().filter(|p| {
+            p.supports_model(&req.model) && p.supports_params(req) && p.fits_context(req, input_tokens) && p.has_capacity()
+        }).collect();
+        // Latency SLA: providers whose latency EWMA is over budget are left out
+        let sla = self.latency_sla(req);
+        let within_sla = |p: &&Arc<Provider>| sla.is_none_or(|max_ms| p.latency_ms() <= max_ms as f64);
+        let eligible: Vec<&Arc<Provider>> = usable.iter().copied().filter(within_sla).filter(|p| p.is_available()).collect();
+        if let (true, Some(max_ms)) = (eligible.is_empty(), sla) {
+            // Nobody meets it: the fastest go first rather than failing the request
+            let mut fastest: Vec<Arc<Provider>> =
+                usable.iter().copied().filter(|p| !within_sla(p) && p.is_available()).cloned().collect();
+            if !fastest.is_empty() {
+                warn!("No provider for {} within the {}ms latency SLA, routing to the fastest", req.model, max_ms);
+                fastest.sort_by(|a, b| a.latency_ms().total_cmp(&b.latency_ms()));
+                return Selection { providers: fastest, degraded: false };
+            }
+        }
+        if eligible.is_empty() && self.degraded_fallback {
+            let least_bad = usable
+                .into_iter()
+                .filter(|p| !p.is_draining())
+                .min_by_key(|p| p.stats.consec_errors.load(std::sync::atomic::Ordering::Relaxed));
+            return Selection { providers: least_bad.cloned().into_iter().collect(), degraded: true };
+        }
 
-        // 2. Score candidates
-        // Scoring strategy: Normalize(Cost) + Normalize(Latency_EWMA)
-        // For O(1) we iterate once and keep the best.
-        
-        // This is a simplified "lowest score wins" strategy.
-        // We can tune weights.
-        let mut best_candidate: Option<Arc<Provider>> = None;
-        let mut best_score = f64::MAX;
-
-        for provider in candidates {
-            // Latency in seconds (approx) for scoring
-            let latency_score = provider.stats.ewma_latency_us.load(std::sync::atomic::Ordering::Relaxed) as f64 / 1000.0;
-            
-            // Cost per 1k input tokens (as a proxy for generic cost)
-            let cost_score = provider.config.cost_per_1k_input * 1000.0; // Weight cost heavily?
-
-            // Total Score formula needs tuning. 
-            // Let's say: Score = Latency (ms) + Cost ($ * 100000)
-            // Example: 100ms + $0.001*100000 (100) = 200
-            let score = latency_score + (cost_score * 100.0);
-
-            if score < best_score {
-                best_score = score;
-                best_candidate = Some(provider.clone());
-            }
-        }
-        
-        // If no healthy provider found, maybe try unhealthy ones (fallback)? 
-        // For now, adhere to strict health check.
-        
-        best_candidate
-    }
-    
-    pub fn update_providers(&self, new_configs: Vec<ProviderConfig>) {
-        // In a real app we might want to preserve stats for existing providers.
-        // This simple replacement resets stats, which might be bad.
-        // TODO: Merge stats.
-        let new_list: Vec<Arc<Provider>> = new_configs
+        // Region affinity: remote providers are ranked separately and go after the local ones
+        let region = req.region.as_deref().or(self.region.as_deref());
+        let (eligible, remote): (Vec<&Arc<Provider>>, Vec<&Arc<Provider>>) = match region {
+            Some(region) if eligible.iter().any(|p| p.in_region(region)) => {
+                eligible.into_iter().partition(|p| p.in_region(region))
+            }
+            _ => (eligible, Vec::new()),
+        };
+
+        // Providers still ramping up (newly added, or just recovered from an open breaker) only
+        // take part with probability equal to their weight, so their share of traffic grows
+        // over the ramp window. If that leaves nobody, fall back to every eligible provider
+        // rather than failing the request.
+        let mut rng = rand::thread_rng();
+        let ramped: Vec<&Arc<Provider>> = eligible.iter().copied().filter(|p| {
+            let weight = p.ramp_weight();
+            weight >= 1.0 || rng.gen_bool(weight)
+        }).collect();
+        let candidates = if ramped.is_empty() { eligible } else { ramped };
+
+        // 2. Score candidates (lowest score wins, see ScoringWeights)
+        let mut scored: Vec<(f64, Arc<Provider>)> = candidates
             .into_iter()
-            .map(|c| Arc::new(Provider::new(c))) // Resets stats
+            .map(|p| (self.score(p, req, input_tokens), p.clone()))
             .collect();
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let (scores, mut ranked): (Vec<f64>, Vec<Arc<Provider>>) = scored.into_iter().unzip();
+
+        // 3. Strategy picks who goes first; the rest stay in score order for fallback
+        let first = match self.strategy {
+            RouteStrategy::LowestScore => None,
+            // The pinned provider, when it is usable right now
+            RouteStrategy::ConsistentHash => {
+                let serving = list.iter().filter(|p| p.supports_model(&req.model));
+                strategy::sticky_key(req, client)
+                    .and_then(|key| strategy::rendezvous(key, serving))
+                    .and_then(|pinned| ranked.iter().position(|p| p.config.id == pinned.config.id))
+            }
+            RouteStrategy::P2c => Some(strategy::two_choices(&scores, &mut rng)),
+            RouteStrategy::Weighted => {
+                let weights: Vec<f64> = ranked.iter().map(|p| p.config.weight.unwrap_or(1.0)).collect();
+                strategy::weighted(&weights, &mut rng)
+            }
+        };
+        if let Some(pos) = first.filter(|&pos| pos > 0) {
+            let first = ranked.remove(pos);
+            ranked.insert(0, first);
+        }
+        let mut remote: Vec<(f64, Arc<Provider>)> =
+            remote.into_iter().map(|p| (self.score(p, req, input_tokens), p.clone())).collect();
+        remote.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ranked.extend(remote.into_iter().map(|(_, p)| p));
+
+        // 4. The policy has the final say on who goes first
+        let providers = match self.policy_pick(&ranked, req) {
+            Some(pos) => {
+                let first = ranked.remove(pos);
+                ranked.insert(0, first);
+                ranked
+            }
+            None => Vec::new(),
+        };
+        Selection { providers, degraded: false }
+    }
+
+    // Position in `ranked` of the policy's pick. A pick from outside the list keeps the
+    // built-in order.
+    fn policy_pick(&self, ranked: &[Arc<Provider>], req: &LlmRequest) -> Option<usize> {
+        if ranked.is_empty() {
+            return None;
+        }
+        let pick = self.policy.select(ranked, req)?;
+        Some(ranked.iter().position(|p| Arc::ptr_eq(p, &pick)).unwrap_or(0))
+    }
+
+    fn score(&self, provider: &Provider, req: &LlmRequest, input_tokens: u64) -> f64 {
+        self.score_breakdown(provider, req, input_tokens).total
+    }
+
+    // Individual terms of a provider's score for `req`, whose input is estimated at
+    // `input_tokens`, for decision logging.
+    pub fn score_breakdown(&self, provider: &Provider, req: &LlmRequest, input_tokens: u64) -> ScoreBreakdown {
+        let latency_ms = provider.latency_ms_for(req);
+
+        // Congestion: the time for the requests already in flight to clear before ours gets
+        // served. Each costs at most one latency period, and less at the provider's observed
+        // goodput (1000 / rps ms each), so providers that clear many requests in parallel are
+        // favored under load. Goodput is capped by demand, hence only ever lowers the estimate.
+        // This reacts immediately, before the EWMA catches up with a provider that is
+        // queueing internally.
+        let in_flight = provider.stats.in_flight.load(std::sync::atomic::Ordering::Relaxed) as f64;
+        let goodput_rps = provider.stats.goodput.rate_at(breaker::now_millis());
+        let per_request_ms = match goodput_rps {
+            Some(rps) => latency_ms.min(1000.0 / rps),
+            None => latency_ms,
+        };
+        let congestion_ms = in_flight * per_request_ms.max(MIN_IN_FLIGHT_PENALTY_MS);
+        let quota_penalty_ms = provider.quota_penalty_ms();
+
+        // Output usually costs several times as much as input, so a provider that is cheap on
+        // input only wins requests that are mostly input.
+        let cost_per_1k_input = provider.config.cost_per_1k_input;
+        let cost_per_1k_output = provider.config.cost_per_1k_output;
+        let output_tokens = req.max_tokens.unwrap_or(self.weights.expected_output_tokens) as f64;
+        let total_tokens = input_tokens as f64 + output_tokens;
+        let expected_cost_per_1k = if total_tokens > 0.0 {
+            (cost_per_1k_input * input_tokens as f64 + cost_per_1k_output * output_tokens) / total_tokens
+        } else {
+            cost_per_1k_input
+        };
+
+        // Each failure costs another attempt elsewhere, so time is scaled by the expected
+        // attempts per success. A provider failing every other call counts as twice as slow.
+        let error_rate = provider.stats.error_rate();
+        let attempts = 1.0 / (1.0 - error_rate.min(MAX_SCORED_ERROR_RATE));
+
+        let latency_component = self.weights.latency_weight * (latency_ms + congestion_ms + quota_penalty_ms) * attempts;
+        let cost_component = self.weights.cost_weight * expected_cost_per_1k;
+        // Approaching `max_concurrency` means queueing soon, before the latency EWMA shows it
+        let load_factor = provider.load_factor();
+        let load_component = self.weights.load_weight * load_factor;
+        ScoreBreakdown {
+            latency_ms,
+            congestion_ms,
+            goodput_rps,
+            quota_penalty_ms,
+            error_rate,
+            cost_per_1k_input,
+            cost_per_1k_output,
+            expected_cost_per_1k,
+            load_factor,
+            latency_component,
+            cost_component,
+            load_component,
+            total: latency_component + cost_component + load_component,
+        }
+    }
+
+    pub fn update_providers(&self, new_configs: Vec<ProviderConfig>) -> Result<(), reqwest::Error> {
+        // Providers are matched by id: existing ones carry their stats over so EWMA and
+        // breaker state survive a reload, new ones start fresh, missing ones are dropped.
+        // In-flight requests keep their own Arc<Provider> and finish against the old config.
+        // On error the current table stays in place.
+        let current = self.providers.load();
+        let new_list = new_configs
+            .into_iter()
+            .map(|c| match current.iter().find(|p| p.config.id == c.id) {
+                Some(existing) => existing.reconfigured(c).map(Arc::new),
+                None => Provider::new(c).map(Arc::new),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
         self.providers.store(Arc::new(new_list));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider_config(id: &str) -> ProviderConfig {
+        ProviderConfig {
+            id: id.to_string(),
+            name: id.to_string(),
+            endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+            api_key: vec!["key".to_string()],
+            model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+            ..ProviderConfig::default()
+        }
+    }
+
+    fn request() -> LlmRequest {
+        serde_json::from_value(serde_json::json!({"model": "m", "prompt": "hi"})).unwrap()
+    }
+
+    #[test]
+    fn selection_leaves_the_half_open_probe_to_dispatch() {
+        let router = Router::new(vec![ProviderConfig { breaker_cooldown_secs: Some(1), ..provider_config("a") }]).unwrap();
+        let provider = router.providers()[0].clone();
+        provider.stats.breaker.on_failure_at(breaker::now_millis(), true);
+        assert!(!provider.is_available() && router.select(&request()).is_none());
+
+        std::thread::sleep(Duration::from_millis(1_050));
+        // Selecting, previewing or filtering any number of times claims nothing
+        for _ in 0..3 {
+            assert!(router.select(&request()).is_some());
+        }
+        assert!(provider.try_acquire_probe());
+        assert!(!provider.try_acquire_probe(), "only one dispatch gets the probe");
+
+        provider.stats.record_success(Duration::from_millis(5));
+        assert_eq!(provider.breaker_state(), BreakerState::Closed);
+        assert!(provider.try_acquire_probe());
+    }
+
+    #[test]
+    fn ramp_weight_grows_to_full_over_the_window() {
+        let ramped = Provider::new(ProviderConfig { ramp_up_secs: Some(600), ..provider_config("a") }).unwrap();
+        let at = |secs| ramped.ramp_weight_at(ramped.added_at + Duration::from_secs(secs));
+        assert!(at(0) < 0.01);
+        assert!((at(150) - 0.25).abs() < 1e-9);
+        assert!((at(300) - 0.5).abs() < 1e-9);
+        assert_eq!(at(600), 1.0);
+        assert_eq!(at(6_000), 1.0);
+
+        let immediate = Provider::new(provider_config("b")).unwrap();
+        assert_eq!(immediate.ramp_weight_at(immediate.added_at), 1.0);
+    }
+
+    #[test]
+    fn provider_system_prompt_is_injected_alongside_the_clients() {
+        let req: LlmRequest = serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "messages": [{"role": "system", "content": "be brief"}, {"role": "user", "content": "hi"}]
+        }))
+        .unwrap();
+        let messages = |strategy| {
+            let config = ProviderConfig {
+                system_prompt: Some("be safe".to_string()),
+                system_prompt_strategy: strategy,
+                ..provider_config("a")
+            };
+            Provider::new(config).unwrap().build_body(&req, false)["messages"].clone()
+        };
+        let system = |content: &str| serde_json::json!({"role": "system", "content": content});
+        let user = serde_json::json!({"role": "user", "content": "hi"});
+
+        assert_eq!(messages(SystemPromptStrategy::Prepend), serde_json::json!([system("be safe"), system("be brief"), user]));
+        assert_eq!(messages(SystemPromptStrategy::Concatenate), serde_json::json!([system("be safe\n\nbe brief"), user]));
+        assert_eq!(messages(SystemPromptStrategy::PreferClient), serde_json::json!([system("be brief"), user]));
+    }
+
+    #[test]
+    fn in_flight_requests_deprioritize_a_provider() {
+        let router = Router::new(vec![provider_config("a"), provider_config("b")]).unwrap();
+        let busy = router.providers()[0].clone();
+        let _in_flight: Vec<_> = (0..4).map(|_| busy.try_acquire().unwrap()).collect();
+
+        let idle = &router.providers()[1];
+        assert!(router.score(&busy, &request(), 10) > router.score(idle, &request(), 10));
+        for _ in 0..10 {
+            assert_eq!(router.select(&request()).unwrap().config.id, "b");
+        }
+    }
+
+    #[test]
+    fn logit_bias_avoids_providers_that_exclude_it() {
+        let config = |id, logit_bias| ProviderConfig { logit_bias, ..provider_config(id) };
+        let router = Router::new(vec![
+            config("exclude", LogitBiasSupport::Exclude),
+            config("strip", LogitBiasSupport::Strip),
+        ])
+        .unwrap();
+        let biased: LlmRequest =
+            serde_json::from_value(serde_json::json!({"model": "m", "prompt": "hi", "logit_bias": {"50256": -100}})).unwrap();
+        for _ in 0..10 {
+            assert_eq!(router.select(&biased).unwrap().config.id, "strip");
+        }
+        let strip = &router.providers()[1];
+        assert!(strip.build_body(&biased, false).get("logit_bias").is_none());
+        assert!(router.providers()[0].supports_params(&request()));
+
+        let forward = Provider::new(provider_config("forward")).unwrap();
+        assert_eq!(forward.build_body(&biased, false)["logit_bias"], serde_json::json!({"50256": -100}));
+    }
+
+    #[test]
+    fn completions_are_parsed_with_fallbacks_for_missing_fields() {
+        let body = serde_json::json!({
+            "id": "mock-response",
+            "object": "chat.completion",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "hello"}, "finish_reason": "stop"}],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}
+        });
+        let resp = parse_completion(&body, "a", None);
+        assert_eq!((resp.content.as_str(), resp.provider.as_str()), ("hello", "a"));
+        assert_eq!((resp.usage.prompt_tokens, resp.usage.completion_tokens, resp.usage.total_tokens), (12, 3, 15));
+
+        let resp = parse_completion(&serde_json::json!({"choices": []}), "a", None);
+        assert_eq!(resp.content, "");
+        assert_eq!(resp.usage.total_tokens, 0);
+    }
+
+    #[tokio::test]
+    async fn provider_calls_return_the_upstream_answer() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let provider = Provider::new(upstream.provider("a")).unwrap();
+        let resp = provider.call(&request()).await.unwrap();
+        assert_eq!(resp.content, crate::test_support::CONTENT);
+        assert_eq!(resp.usage.prompt_tokens, crate::test_support::PROMPT_TOKENS);
+        assert_eq!(resp.usage.completion_tokens, crate::test_support::COMPLETION_TOKENS);
+    }
+
+    #[test]
+    fn reloads_keep_the_stats_of_unchanged_ids() {
+        let router = Router::new(vec![provider_config("kept"), provider_config("removed")]).unwrap();
+        let kept = router.providers()[0].clone();
+        kept.stats.record_success(Duration::from_millis(40));
+        kept.stats.breaker.on_failure_at(breaker::now_millis(), true);
+
+        let reloaded = ProviderConfig { cost_per_1k_output: 2.0, ..provider_config("kept") };
+        router.update_providers(vec![reloaded, provider_config("added")]).unwrap();
+        let providers = router.providers();
+        let ids: Vec<&str> = providers.iter().map(|p| p.config.id.as_str()).collect();
+        assert_eq!(ids, ["kept", "added"]);
+        assert_eq!(providers[0].config.cost_per_1k_output, 2.0);
+        assert!(Arc::ptr_eq(&providers[0].stats, &kept.stats));
+        assert_eq!(providers[0].breaker_state(), BreakerState::Open);
+        assert_eq!(providers[1].stats.request_count.load(std::sync::atomic::Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn raising_the_cost_weight_flips_the_selection() {
+        let configs = || {
+            vec![
+                ProviderConfig { cost_per_1k_input: 0.01, cost_per_1k_output: 0.01, ..provider_config("fast") },
+                ProviderConfig { cost_per_1k_input: 0.001, cost_per_1k_output: 0.001, ..provider_config("cheap") },
+            ]
+        };
+        let selected = |cost_weight| {
+            let weights = ScoringWeights { cost_weight, ..ScoringWeights::default() };
+            let router = Router::with_weights(configs(), weights).unwrap();
+            for (provider, ms) in router.providers().iter().zip([50, 500]) {
+                for _ in 0..10 {
+                    provider.stats.record_success(Duration::from_millis(ms));
+                }
+            }
+            router.select(&request()).unwrap().config.id.clone()
+        };
+        assert_eq!(selected(1_000.0), "fast");
+        assert_eq!(selected(1_000_000.0), "cheap");
+    }
+
+    #[test]
+    fn low_reported_quota_deprioritizes_a_provider() {
+        let router = Router::new(vec![provider_config("low"), provider_config("ample")]).unwrap();
+        let report = |remaining: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert("x-ratelimit-limit-requests", "1000".parse().unwrap());
+            headers.insert("x-ratelimit-remaining-requests", remaining.parse().unwrap());
+            headers.insert("x-ratelimit-reset-requests", "1m0s".parse().unwrap());
+            RateLimitQuota::from_headers(&headers)
+        };
+        let providers = router.providers();
+        providers[0].stats.quota.record_at(breaker::now_millis(), &report("5"));
+        providers[1].stats.quota.record_at(breaker::now_millis(), &report("900"));
+
+        assert!(providers[0].quota_penalty_ms() > 0.0);
+        assert_eq!(providers[1].quota_penalty_ms(), 0.0);
+        for _ in 0..10 {
+            assert_eq!(router.select(&request()).unwrap().config.id, "ample");
+        }
+    }
+
+    #[tokio::test]
+    async fn calls_share_the_provider_client_connections() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let provider = Provider::new(upstream.provider("a")).unwrap();
+        for _ in 0..5 {
+            provider.call(&request()).await.unwrap();
+        }
+        // One client per provider, so sequential calls reuse one pooled connection
+        assert_eq!(upstream.calls(), 5);
+        assert_eq!(upstream.connections(), 1);
+        // A reload builds a new client
+        let reloaded = provider.reconfigured(upstream.provider("a")).unwrap();
+        reloaded.call(&request()).await.unwrap();
+        assert_eq!(upstream.connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn unreachable_providers_fail_at_the_connect_timeout() {
+        // A listener that never accepts: once its backlog is full, further connects hang
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        while let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(100), tokio::net::TcpStream::connect(addr)).await {
+            backlog.push(stream);
+        }
+
+        let config = ProviderConfig {
+            endpoint: format!("http://{addr}/v1/chat/completions"),
+            connect_timeout_ms: Some(200),
+            timeout_ms: Some(10_000),
+            ..provider_config("a")
+        };
+        let provider = Provider::new(config).unwrap();
+        let started = Instant::now();
+        let error = provider.call(&request()).await.unwrap_err();
+        assert!(matches!(error, ProviderError::ConnectTimeout { after_ms: 200 }), "{error}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn streams_outlast_the_timeout_while_chunks_keep_coming() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        upstream.answer_with("one two three four five");
+        let config = ProviderConfig { timeout_ms: Some(300), ..upstream.provider("a") };
+        let provider = Provider::new(config).unwrap();
+
+        // Five chunks 150ms apart: well past the timeout in total, never idle for it
+        upstream.pace(Duration::from_millis(150));
+        let started = Instant::now();
+        let chunks: Vec<_> = provider.call_stream(&request()).await.unwrap().collect().await;
+        assert!(started.elapsed() > Duration::from_millis(300));
+        let content: String = chunks.into_iter().map(Result::unwrap).collect();
+        assert_eq!(content, "one two three four five");
+
+        // A chunk that takes longer than the timeout ends the stream
+        upstream.pace(Duration::from_millis(500));
+        let chunks: Vec<_> = provider.call_stream(&request()).await.unwrap().collect().await;
+        assert!(matches!(chunks.as_slice(), [Ok(_), Err(ProviderError::Timeout { after_ms: 300 })]), "{chunks:?}");
+
+        // Buffered calls still get the timeout for the whole exchange
+        upstream.delay(Duration::from_millis(500));
+        let error = provider.call(&request()).await.unwrap_err();
+        assert!(matches!(error, ProviderError::Timeout { after_ms: 300 }), "{error}");
+    }
+
+    #[test]
+    fn only_available_providers_are_worth_waiting_for() {
+        let configs = ["full", "throttled", "ejected"].map(|id| ProviderConfig { max_concurrency: Some(1), ..provider_config(id) });
+        let router = Router::new(configs.to_vec()).unwrap();
+        let providers = router.providers();
+        let _in_flight: Vec<_> = providers.iter().map(|p| p.try_acquire().unwrap()).collect();
+        providers[1].stats.record_failure(&ProviderError::Status { status: 429, retry_after_ms: Some(60_000) });
+        providers[2].stats.ejected_until_ms.store(breaker::now_millis() + 60_000, std::sync::atomic::Ordering::Relaxed);
+
+        let saturated: Vec<_> = router.saturated_for(&request(), 0).iter().map(|p| p.config.id.clone()).collect();
+        assert_eq!(saturated, ["full"]);
+        assert_eq!(providers[1].unavailability(), Some(Exclusion::RateLimited));
+        assert_eq!(providers[2].unavailability(), Some(Exclusion::Ejected));
+    }
+
+    #[test]
+    fn higher_goodput_wins_under_load_at_equal_latency() {
+        let router = Router::new(vec![provider_config("a"), provider_config("b")]).unwrap();
+        let (fast, slow) = (router.providers()[0].clone(), router.providers()[1].clone());
+        // Same idle latency, but `a` has cleared many more requests in the window
+        for _ in 0..50 {
+            fast.stats.record_success(Duration::from_millis(400));
+        }
+        slow.stats.record_success(Duration::from_millis(400));
+        assert!(fast.stats.goodput.rate_at(breaker::now_millis()).is_some());
+
+        let _in_flight: Vec<_> = [&fast, &slow].iter().flat_map(|p| (0..4).map(|_| p.try_acquire().unwrap())).collect();
+        assert!(router.score(&fast, &request(), 10) < router.score(&slow, &request(), 10));
+        for _ in 0..10 {
+            assert_eq!(router.select(&request()).unwrap().config.id, "a");
+        }
+    }
+
+    #[test]
+    fn saturated_providers_send_excess_requests_elsewhere() {
+        let limited = ProviderConfig { max_concurrency: Some(1), ..provider_config("a") };
+        let router = Router::new(vec![limited, provider_config("b")]).unwrap();
+        let (a, b) = (router.providers()[0].clone(), router.providers()[1].clone());
+        a.stats.record_success(Duration::from_millis(10));
+        b.stats.record_success(Duration::from_millis(500));
+        assert_eq!(router.select(&request()).unwrap().config.id, "a");
+
+        let slot = a.try_acquire().unwrap();
+        assert!(a.try_acquire().is_none());
+        for _ in 0..10 {
+            assert_eq!(router.select(&request()).unwrap().config.id, "b");
+        }
+        drop(slot);
+        assert_eq!(router.select(&request()).unwrap().config.id, "a");
+    }
+
+    #[test]
+    fn prompts_over_a_context_limit_skip_that_provider() {
+        let small = ProviderConfig { max_context_tokens: Some(50), ..provider_config("small") };
+        let router = Router::new(vec![small, provider_config("large")]).unwrap();
+        router.providers()[0].stats.record_success(Duration::from_millis(10));
+        router.providers()[1].stats.record_success(Duration::from_millis(500));
+        let prompt = |words: usize, max_tokens: Option<u32>| -> LlmRequest {
+            serde_json::from_value(serde_json::json!({"model": "m", "prompt": "word ".repeat(words), "max_tokens": max_tokens}))
+                .unwrap()
+        };
+
+        assert_eq!(router.select(&prompt(10, None)).unwrap().config.id, "small");
+        assert_eq!(router.select(&prompt(100, None)).unwrap().config.id, "large");
+        // The requested output counts against the window too
+        assert_eq!(router.select(&prompt(10, Some(45))).unwrap().config.id, "large");
+
+        let only_small = Router::new(vec![ProviderConfig { max_context_tokens: Some(50), ..provider_config("small") }]).unwrap();
+        assert!(only_small.select(&prompt(100, None)).is_none());
+    }
+
+    #[test]
+    fn degraded_fallback_picks_the_least_bad_unhealthy_provider() {
+        let unhealthy = |router: &Router| {
+            // Both breakers open, `b` having failed fewer times in a row
+            for (provider, failures) in router.providers().iter().zip([8, breaker::DEFAULT_FAILURE_THRESHOLD]) {
+                for _ in 0..failures {
+                    provider.stats.record_failure(&ProviderError::Status { status: 500, retry_after_ms: None });
+                }
+            }
+        };
+        let strict = Router::new(vec![provider_config("a"), provider_config("b")]).unwrap();
+        unhealthy(&strict);
+        assert!(strict.select(&request()).is_none());
+
+        let lenient = Router::new(vec![provider_config("a"), provider_config("b")]).unwrap().with_degraded_fallback(true);
+        unhealthy(&lenient);
+        assert_eq!(lenient.select(&request()).unwrap().config.id, "b");
+    }
+
+    #[tokio::test]
+    async fn configured_headers_go_out_with_every_call() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let headers = HashMap::from([("OpenAI-Organization".to_string(), "org-1".to_string())]);
+        let provider = Provider::new(ProviderConfig { headers, ..upstream.provider("a") }).unwrap();
+        provider.call(&request()).await.unwrap();
+        let sent = upstream.last_headers();
+        assert_eq!(sent["openai-organization"], "org-1");
+        assert_eq!(sent["authorization"], "Bearer key");
+
+        // Naming the auth header replaces the credentials instead of sending both
+        let headers = HashMap::from([("authorization".to_string(), "Token custom".to_string())]);
+        let provider = Provider::new(ProviderConfig { headers, ..upstream.provider("b") }).unwrap();
+        provider.call(&request()).await.unwrap();
+        let sent = upstream.last_headers();
+        assert_eq!(sent.get_all("authorization").iter().collect::<Vec<_>>(), ["Token custom"]);
+    }
+
+    #[test]
+    fn expected_output_length_decides_between_inverted_prices() {
+        let router = Router::new(vec![
+            ProviderConfig { cost_per_1k_input: 0.001, cost_per_1k_output: 0.03, ..provider_config("cheap_input") },
+            ProviderConfig { cost_per_1k_input: 0.01, cost_per_1k_output: 0.002, ..provider_config("cheap_output") },
+        ])
+        .unwrap();
+        for provider in router.providers().iter() {
+            provider.stats.record_success(Duration::from_millis(100));
+        }
+        let req = |words: usize, max_tokens: u32| -> LlmRequest {
+            serde_json::from_value(serde_json::json!({"model": "m", "prompt": "word ".repeat(words), "max_tokens": max_tokens}))
+                .unwrap()
+        };
+        assert_eq!(router.select(&req(10, 4_000)).unwrap().config.id, "cheap_output");
+        assert_eq!(router.select(&req(4_000, 1)).unwrap().config.id, "cheap_input");
+    }
+
+    #[tokio::test]
+    async fn calls_rotate_keys_and_retry_past_a_rejected_one() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let keys = vec!["k1".to_string(), "k2".to_string(), "k3".to_string()];
+        let provider = Provider::new(ProviderConfig { api_key: keys, ..upstream.provider("a") }).unwrap();
+        let mut used = Vec::new();
+        for _ in 0..3 {
+            provider.call(&request()).await.unwrap();
+            used.push(upstream.last_headers()["authorization"].to_str().unwrap().to_string());
+        }
+        assert_eq!(used, ["Bearer k1", "Bearer k2", "Bearer k3"]);
+
+        // The 401 is retried with the next key, and the rejected one isn't used again
+        upstream.reject_key("k1");
+        provider.call(&request()).await.unwrap();
+        assert_eq!(upstream.calls(), 5);
+        for _ in 0..4 {
+            provider.call(&request()).await.unwrap();
+            assert_ne!(upstream.last_headers()["authorization"], "Bearer k1");
+        }
+        assert_eq!(upstream.calls(), 9);
+    }
+
+    // Local stand-in for Ollama's /api/chat: a single JSON answer, or NDJSON lines when
+    // streaming. Prompts containing "crash" end their stream with an error line. Keeps the
+    // last request body for inspection.
+    async fn ollama() -> (ProviderConfig, Arc<std::sync::Mutex<serde_json::Value>>) {
+        use axum::response::IntoResponse;
+        let last = Arc::new(std::sync::Mutex::new(serde_json::Value::Null));
+        let seen = last.clone();
+        let chat = move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let crash = body.to_string().contains("crash");
+            *seen.lock().unwrap() = body.clone();
+            async move {
+                if !body["stream"].as_bool().unwrap() {
+                    let answer = serde_json::json!({
+                        "model": "llama3", "done": true, "prompt_eval_count": 7, "eval_count": 3,
+                        "message": {"role": "assistant", "content": "local answer"}
+                    });
+                    return axum::Json(answer).into_response();
+                }
+                let mut lines = vec![
+                    serde_json::json!({"message": {"role": "assistant", "content": "local "}, "done": false}),
+                    serde_json::json!({"message": {"role": "assistant", "content": "answer"}, "done": false}),
+                ];
+                if crash {
+                    lines.push(serde_json::json!({"error": "model crashed"}));
+                }
+                lines.push(serde_json::json!({"message": {"role": "assistant", "content": ""}, "done": true}));
+                lines.iter().map(|line| format!("{line}\n")).collect::<String>().into_response()
+            }
+        };
+        let app = axum::Router::new().route("/api/chat", axum::routing::post(chat));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let config = ProviderConfig {
+            provider_type: Some(ProviderType::Ollama),
+            base_url: Some(base_url),
+            model_map: HashMap::from([("m".to_string(), "llama3".to_string())]),
+            ..provider_config("ollama")
+        };
+        (config, last)
+    }
+
+    #[tokio::test]
+    async fn ollama_providers_speak_the_native_chat_api() {
+        let (config, last) = ollama().await;
+        let provider = Provider::new(config).unwrap();
+        let req: LlmRequest =
+            serde_json::from_value(serde_json::json!({"model": "m", "prompt": "hi", "max_tokens": 20, "temperature": 0.5})).unwrap();
+        let resp = provider.call(&req).await.unwrap();
+        assert_eq!(resp.content, "local answer");
+        assert_eq!((resp.usage.prompt_tokens, resp.usage.completion_tokens), (7, 3));
+
+        let sent = last.lock().unwrap().clone();
+        assert_eq!(sent["model"], "llama3");
+        assert_eq!(sent["stream"], false);
+        assert_eq!(sent["options"]["num_predict"], 20);
+        assert_eq!(sent["options"]["temperature"], 0.5);
+        assert_eq!(sent["messages"][0]["content"], "hi");
+    }
+
+    #[tokio::test]
+    async fn ollama_ndjson_streams_become_deltas() {
+        let (config, _) = ollama().await;
+        let provider = Provider::new(config).unwrap();
+        let streamed = |prompt: &str| -> LlmRequest {
+            serde_json::from_value(serde_json::json!({"model": "m", "prompt": prompt, "stream": true})).unwrap()
+        };
+
+        let deltas: Vec<_> = provider.call_stream(&streamed("hi")).await.unwrap().collect().await;
+        let text: String = deltas.into_iter().map(Result::unwrap).collect();
+        assert_eq!(text, "local answer");
+
+        // An error line ends the stream as a failure instead of passing for an empty delta
+        let deltas: Vec<_> = provider.call_stream(&streamed("crash")).await.unwrap().collect().await;
+        assert!(matches!(deltas.last(), Some(Err(ProviderError::Decode(message))) if message.contains("model crashed")));
+    }
+
+    #[test]
+    fn providers_over_the_latency_sla_are_left_out() {
+        let config = |id: &str, cost| ProviderConfig {
+            cost_per_1k_input: cost,
+            cost_per_1k_output: cost,
+            model_map: HashMap::from([("m".to_string(), "m".to_string()), ("quick".to_string(), "m".to_string())]),
+            ..provider_config(id)
+        };
+        let weights = ScoringWeights { cost_weight: 1_000_000.0, ..ScoringWeights::default() };
+        let sla = virtual_models::VirtualModel {
+            models: vec!["m".to_string()],
+            providers: Vec::new(),
+            max_cost_per_1k: None,
+            min_context_tokens: None,
+            max_latency_ms: Some(200),
+        };
+        let router = Router::with_weights(vec![config("cheap", 0.001), config("fast", 0.01)], weights)
+            .unwrap()
+            .with_virtual_models(&HashMap::from([("quick".to_string(), sla)]));
+        for (provider, ms) in router.providers().iter().zip([400, 100]) {
+            for _ in 0..10 {
+                provider.stats.record_success(Duration::from_millis(ms));
+            }
+        }
+        let req = |extra: serde_json::Value| crate::test_support::request("hi", extra);
+        let ids = |ranked: Vec<Arc<Provider>>| ranked.iter().map(|p| p.config.id.clone()).collect::<Vec<_>>();
+
+        // Cost decides without an SLA
+        assert_eq!(router.select(&req(serde_json::json!({}))).unwrap().config.id, "cheap");
+        assert_eq!(ids(router.select_ranked(&req(serde_json::json!({"max_latency_ms": 200})))), ["fast"]);
+        assert_eq!(ids(router.select_ranked(&req(serde_json::json!({"model": "quick"})))), ["fast"]);
+        // Nobody within it: the fastest first instead of nothing
+        assert_eq!(ids(router.select_ranked(&req(serde_json::json!({"max_latency_ms": 50})))), ["fast", "cheap"]);
+    }
+
+    #[test]
+    fn a_ttl_header_overrides_the_configured_cache_ttl() {
+        let config = ProviderConfig {
+            cache_ttl_secs: Some(60),
+            cache_ttl_header: Some("x-cache-ttl".to_string()),
+            ..provider_config("a")
+        };
+        let router = Router::new(vec![config]).unwrap();
+        let provider = &router.providers()[0];
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(provider.cache_ttl(Some(&headers)), Some(Duration::from_secs(60)));
+        headers.insert("x-cache-ttl", "5".parse().unwrap());
+        assert_eq!(provider.cache_ttl(Some(&headers)), Some(Duration::from_secs(5)));
+        headers.insert("x-cache-ttl", "0".parse().unwrap());
+        assert_eq!(provider.cache_ttl(Some(&headers)), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn the_preferred_region_wins_while_it_has_a_healthy_provider() {
+        let in_region = |id: &str, region: &str| ProviderConfig { region: Some(region.to_string()), ..provider_config(id) };
+        let router = Router::new(vec![in_region("us", "us-east"), in_region("eu", "eu-west")])
+            .unwrap()
+            .with_region(Some("us-east".to_string()));
+        let (us, eu) = (router.providers()[0].clone(), router.providers()[1].clone());
+        // The remote provider is much faster, and still only comes second
+        us.stats.record_success(Duration::from_millis(300));
+        eu.stats.record_success(Duration::from_millis(10));
+        let ids = |req: &LlmRequest| router.select_ranked(req).iter().map(|p| p.config.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&request()), ["us", "eu"]);
+
+        // A request naming its own region overrides the gateway's
+        let mut eu_req = request();
+        eu_req.region = Some("eu-west".to_string());
+        assert_eq!(ids(&eu_req), ["eu", "us"]);
+
+        // Cross-region only once nothing local is healthy
+        us.stats.breaker.on_failure_at(breaker::now_millis(), true);
+        assert_eq!(ids(&request()), ["eu"]);
+    }
+
+    #[test]
+    fn a_nearly_saturated_cheap_provider_loses_to_the_next_one() {
+        let configs = || {
+            vec![
+                ProviderConfig { cost_per_1k_input: 0.001, cost_per_1k_output: 0.001, max_concurrency: Some(10), ..provider_config("cheap") },
+                ProviderConfig { cost_per_1k_input: 0.003, cost_per_1k_output: 0.003, ..provider_config("pricey") },
+            ]
+        };
+        let selected = |load_weight| {
+            let weights = ScoringWeights { load_weight, ..ScoringWeights::default() };
+            let router = Router::with_weights(configs(), weights).unwrap();
+            let (cheap, pricey) = (router.providers()[0].clone(), router.providers()[1].clone());
+            assert_eq!(router.select(&request()).unwrap().config.id, "cheap");
+            // Equally busy, so only the cheap one's limit tells them apart
+            let _in_flight: Vec<_> = [&cheap, &pricey].iter().flat_map(|p| (0..9).map(|_| p.try_acquire().unwrap())).collect();
+            let breakdown = router.score_breakdown(&cheap, &request(), 10);
+            assert!((breakdown.load_factor - 0.9).abs() < 1e-9);
+            assert_eq!(breakdown.load_component, load_weight * breakdown.load_factor);
+            router.select(&request()).unwrap().config.id.clone()
+        };
+        assert_eq!(selected(ScoringWeights::default().load_weight), "pricey");
+        assert_eq!(selected(0.0), "cheap");
+    }
+
+    #[test]
+    fn a_just_recovered_provider_gets_a_growing_share_of_traffic() {
+        let recovering = ProviderConfig { recovery_ramp_secs: Some(1), ..provider_config("a") };
+        let router = Router::new(vec![recovering, provider_config("b")]).unwrap();
+        let (a, b) = (router.providers()[0].clone(), router.providers()[1].clone());
+        // `a` is far faster, so it takes everything at full weight
+        a.stats.record_success(Duration::from_millis(10));
+        b.stats.record_success(Duration::from_millis(500));
+        let share = || (0..1_000).filter(|_| router.select(&request()).unwrap().config.id == "a").count();
+        assert_eq!(share(), 1_000);
+
+        let now = breaker::now_millis();
+        a.stats.breaker.on_failure_at(now, true);
+        a.stats.breaker.on_success_at(now);
+        let early = share();
+        assert!(early < 100, "{early} of 1000 right after recovery");
+        std::thread::sleep(Duration::from_millis(500));
+        let halfway = share();
+        assert!((300..700).contains(&halfway), "{halfway} of 1000 halfway through the ramp");
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(share(), 1_000);
+    }
+
+    // A front for `upstream` that drops its first `drops` connections unanswered, as a
+    // connection reset would. Counts the connections it accepts.
+    async fn flaky(upstream: &crate::test_support::MockUpstream, drops: usize) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        let target = upstream.endpoint.trim_start_matches("http://").split('/').next().unwrap().to_string();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = upstream.endpoint.replace(&target, &listener.local_addr().unwrap().to_string());
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                if accepted.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < drops {
+                    continue;
+                }
+                let target = target.clone();
+                tokio::spawn(async move {
+                    let mut server = tokio::net::TcpStream::connect(target).await.unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                });
+            }
+        });
+        (endpoint, connections)
+    }
+
+    #[tokio::test]
+    async fn connection_resets_are_retried_on_the_same_provider() {
+        use std::sync::atomic::Ordering::SeqCst;
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let provider = |endpoint: &str, same_provider_retries| {
+            Provider::new(ProviderConfig { endpoint: endpoint.to_string(), same_provider_retries, ..upstream.provider("a") }).unwrap()
+        };
+
+        let (endpoint, connections) = flaky(&upstream, 1).await;
+        let resp = provider(&endpoint, 1).call(&request()).await.unwrap();
+        assert_eq!(resp.content, crate::test_support::CONTENT);
+        assert_eq!(connections.load(SeqCst), 2);
+
+        // Without retries, or once they run out, the reset is the call's error
+        let (endpoint, connections) = flaky(&upstream, 1).await;
+        assert!(provider(&endpoint, 0).call(&request()).await.unwrap_err().is_transient());
+        assert_eq!(connections.load(SeqCst), 1);
+        let (endpoint, connections) = flaky(&upstream, 5).await;
+        assert!(provider(&endpoint, 2).call(&request()).await.unwrap_err().is_transient());
+        assert_eq!(connections.load(SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn http_errors_are_not_retried_on_the_same_provider() {
+        let upstream = crate::test_support::MockUpstream::start().await;
+        let provider = Provider::new(ProviderConfig { same_provider_retries: 3, ..upstream.provider("a") }).unwrap();
+        for status in [400, 500] {
+            upstream.fail_with(status);
+            let err = provider.call(&request()).await.unwrap_err();
+            assert!(matches!(err, ProviderError::Status { status: s, .. } if s == status), "{err:?}");
+        }
+        assert_eq!(upstream.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_retried_call_counts_once_in_the_provider_stats() {
+        use crate::test_support;
+        use axum::http::StatusCode;
+        let upstream = test_support::MockUpstream::start().await;
+        let (recovering, _) = flaky(&upstream, 1).await;
+        let (dead, connections) = flaky(&upstream, usize::MAX).await;
+        let config = |id: &str, endpoint: String| ProviderConfig { endpoint, same_provider_retries: 2, ..upstream.provider(id) };
+        for (endpoint, status) in [(recovering, StatusCode::OK), (dead, StatusCode::BAD_GATEWAY)] {
+            let state = Arc::new(test_support::state(vec![config("a", endpoint)]));
+            let response = test_support::complete(&state, axum::http::HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+            assert_eq!(response.status(), status);
+            let stats = state.router.providers()[0].stats.snapshot();
+            if status == StatusCode::OK {
+                assert_eq!((stats.request_count, stats.error_count), (1, 0));
+            } else {
+                assert_eq!((stats.request_count, stats.error_count, stats.consec_errors), (0, 1, 1));
+            }
+        }
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn pool_settings_apply_to_the_provider_client() {
+        use crate::test_support::MockUpstream;
+        // No idle connections kept: every call opens its own
+        let upstream = MockUpstream::start().await;
+        let provider = Provider::new(ProviderConfig { pool_max_idle_per_host: Some(0), ..upstream.provider("a") }).unwrap();
+        for _ in 0..3 {
+            provider.call(&request()).await.unwrap();
+        }
+        assert_eq!(upstream.connections(), 3);
+
+        // Idle connections are closed after the timeout, and the next call reconnects
+        let upstream = MockUpstream::start().await;
+        let provider = Provider::new(ProviderConfig { pool_idle_timeout_ms: Some(100), ..upstream.provider("a") }).unwrap();
+        provider.call(&request()).await.unwrap();
+        provider.call(&request()).await.unwrap();
+        assert_eq!(upstream.connections(), 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        provider.call(&request()).await.unwrap();
+        assert_eq!(upstream.connections(), 2);
     }
 }
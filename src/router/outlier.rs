@@ -0,0 +1,232 @@
+use super::{Provider, Router};
+use crate::balancer::breaker;
+use crate::balancer::error_rate::MIN_SAMPLES;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Passive outlier detection, off unless configured: every `interval_secs` the fleet is
+// compared against its median, and a provider far off it sits out for `ejection_secs`.
+// Catches a provider that got slow without failing, which the breaker never sees.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct OutlierConfig {
+    pub interval_secs: u64,
+    pub ejection_secs: u64,
+    // Ejected when its latency EWMA is above this many times the fleet median
+    pub latency_factor: f64,
+    // Ejected when its 10s error rate is this far above the fleet median (0.3 = 30 points)
+    pub error_rate_margin: f64,
+    // Fewer providers with samples than this is no fleet to compare against
+    pub min_providers: usize,
+    // Never more than this share of the routing table ejected at once
+    pub max_ejection_percent: u32,
+}
+
+impl Default for OutlierConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 10,
+            ejection_secs: 30,
+            latency_factor: 3.0,
+            error_rate_margin: 0.3,
+            min_providers: 3,
+            max_ejection_percent: 50,
+        }
+    }
+}
+
+impl Provider {
+    // Sitting out after outlier detection ejected it (see spawn_outlier_detection).
+    pub fn is_ejected(&self) -> bool {
+        self.stats.is_ejected_at(breaker::now_millis())
+    }
+
+    fn calls(&self) -> u64 {
+        self.stats.request_count.load(Ordering::Relaxed) + self.stats.error_count.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Detector {
+    // Provider id -> calls made when it was ejected. An ejected provider gets no traffic, so
+    // its stats are frozen; it is only judged again after MIN_SAMPLES calls since.
+    ejected: HashMap<String, u64>,
+}
+
+impl Detector {
+    fn scan(&mut self, router: &Router, config: &OutlierConfig) {
+        let providers = router.providers();
+        let now = breaker::now_millis();
+
+        let mut ejected_now = 0;
+        let mut judged: Vec<&Arc<Provider>> = Vec::new();
+        for p in providers.iter() {
+            if p.stats.is_ejected_at(now) {
+                ejected_now += 1;
+                continue;
+            }
+            if let Some(&calls) = self.ejected.get(&p.config.id) {
+                if p.calls() < calls + MIN_SAMPLES {
+                    continue;
+                }
+                info!("{} is back from outlier ejection", p.config.name);
+                self.ejected.remove(&p.config.id);
+            }
+            if !p.is_draining() && p.latency_ms() > 0.0 {
+                judged.push(p);
+            }
+        }
+        // Providers removed by a reload
+        self.ejected.retain(|id, _| providers.iter().any(|p| &p.config.id == id));
+        if judged.len() < config.min_providers.max(2) {
+            return;
+        }
+
+        let median_latency = median(judged.iter().map(|p| p.latency_ms()).collect());
+        let median_errors = median(judged.iter().map(|p| p.stats.errors.rate_at(now).unwrap_or(0.0)).collect());
+        let max_ejected = providers.len() * config.max_ejection_percent.min(100) as usize / 100;
+        for p in judged {
+            if ejected_now >= max_ejected {
+                break;
+            }
+            let latency = p.latency_ms();
+            let errors = p.stats.errors.rate_at(now);
+            let reason = if latency > median_latency * config.latency_factor {
+                format!("latency {:.0}ms against a median of {:.0}ms", latency, median_latency)
+            } else if errors.is_some_and(|rate| rate - median_errors > config.error_rate_margin) {
+                format!("error rate {:.0}% against a median of {:.0}%", errors.unwrap_or(0.0) * 100.0, median_errors * 100.0)
+            } else {
+                continue;
+            };
+            warn!("Ejecting outlier {} for {}s: {}", p.config.name, config.ejection_secs, reason);
+            p.stats.ejected_until_ms.store(now + config.ejection_secs * 1000, Ordering::Relaxed);
+            self.ejected.insert(p.config.id.clone(), p.calls());
+            ejected_now += 1;
+        }
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+// Reads the live routing table on every scan, like the health prober.
+pub fn spawn_outlier_detection(router: Arc<Router>, config: OutlierConfig) {
+    tokio::spawn(async move {
+        let mut detector = Detector::default();
+        let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            detector.scan(&router, &config);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ProviderError;
+    use crate::model::{LlmRequest, ProviderConfig};
+
+    fn router(latencies_ms: &[u64]) -> Router {
+        let configs = (0..latencies_ms.len())
+            .map(|i| ProviderConfig {
+                id: format!("p{i}"),
+                name: format!("p{i}"),
+                endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+                model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+                ..ProviderConfig::default()
+            })
+            .collect();
+        let router = Router::new(configs).unwrap();
+        for (p, &ms) in router.providers().iter().zip(latencies_ms) {
+            p.stats.record_success(Duration::from_millis(ms));
+        }
+        router
+    }
+
+    fn ranked(router: &Router) -> Vec<String> {
+        let req: LlmRequest = serde_json::from_value(serde_json::json!({"model": "m", "prompt": "hi"})).unwrap();
+        router.select_ranked(&req).iter().map(|p| p.config.id.clone()).collect()
+    }
+
+    #[test]
+    fn a_slow_but_healthy_provider_is_ejected_from_selection() {
+        let router = router(&[50, 60, 55, 800]);
+        let slow = router.providers()[3].clone();
+        assert_eq!(slow.breaker_state(), crate::balancer::breaker::BreakerState::Closed);
+        assert!(ranked(&router).contains(&"p3".to_string()));
+
+        Detector::default().scan(&router, &OutlierConfig::default());
+        assert!(slow.is_ejected());
+        assert!(router.providers()[..3].iter().all(|p| !p.is_ejected()));
+        assert!(!ranked(&router).contains(&"p3".to_string()));
+    }
+
+    #[test]
+    fn a_provider_failing_far_more_than_the_fleet_is_ejected() {
+        let router = router(&[50, 50, 50]);
+        let failing = &router.providers()[0];
+        for p in router.providers().iter() {
+            for _ in 0..MIN_SAMPLES {
+                p.stats.record_success(Duration::from_millis(50));
+            }
+        }
+        // Never enough failures in a row to trip its breaker
+        for _ in 0..MIN_SAMPLES {
+            failing.stats.record_failure(&ProviderError::Status { status: 500, retry_after_ms: None });
+            failing.stats.record_failure(&ProviderError::Status { status: 500, retry_after_ms: None });
+            failing.stats.record_success(Duration::from_millis(50));
+        }
+        assert_eq!(failing.breaker_state(), crate::balancer::breaker::BreakerState::Closed);
+
+        Detector::default().scan(&router, &OutlierConfig::default());
+        assert!(failing.is_ejected());
+        assert!(router.providers()[1..].iter().all(|p| !p.is_ejected()));
+    }
+
+    #[test]
+    fn ejections_are_capped_and_need_a_fleet() {
+        // Two slow providers, but only a quarter of the table (one of five) may sit out
+        let router = router(&[50, 50, 50, 900, 1_000]);
+        let config = OutlierConfig { max_ejection_percent: 25, ..OutlierConfig::default() };
+        Detector::default().scan(&router, &config);
+        assert_eq!(router.providers().iter().filter(|p| p.is_ejected()).count(), 1);
+
+        // Two providers are below `min_providers`
+        let pair = self::router(&[50, 1_000]);
+        Detector::default().scan(&pair, &OutlierConfig::default());
+        assert!(pair.providers().iter().all(|p| !p.is_ejected()));
+    }
+
+    #[test]
+    fn an_ejected_provider_returns_and_is_judged_on_fresh_calls() {
+        let router = router(&[50, 60, 55, 800]);
+        let config = OutlierConfig { ejection_secs: 1, ..OutlierConfig::default() };
+        let slow = router.providers()[3].clone();
+        let mut detector = Detector::default();
+        detector.scan(&router, &config);
+        assert!(slow.is_ejected());
+
+        std::thread::sleep(Duration::from_millis(1_050));
+        assert!(ranked(&router).contains(&"p3".to_string()));
+        // Its frozen stats would eject it again; it waits for calls made since
+        detector.scan(&router, &config);
+        assert!(!slow.is_ejected());
+        for _ in 0..MIN_SAMPLES {
+            slow.stats.record_success(Duration::from_millis(800));
+        }
+        detector.scan(&router, &config);
+        assert!(slow.is_ejected(), "still slow on its new calls");
+    }
+}
@@ -0,0 +1,86 @@
+use super::Provider;
+use crate::model::LlmRequest;
+use std::sync::Arc;
+
+// Picks the provider a request goes to first. `candidates` are the providers that passed
+// every filter (model, params, context, capacity, health, ramp), in the order the built-in
+// ranking would try them: by score, then reordered by the RouteStrategy. The pick is tried
+// first and the rest keep that order for failover; None sends the request nowhere (503),
+// and a provider that isn't among the candidates is ignored.
+//
+// Install one with `Router::with_policy` for selection logic the scoring can't express,
+// e.g. region affinity, a fixed failover order or a latency SLA.
+pub trait RoutingPolicy: Send + Sync {
+    fn select(&self, candidates: &[Arc<Provider>], req: &LlmRequest) -> Option<Arc<Provider>>;
+}
+
+// The built-in ranking as is: the best-ranked candidate goes first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultPolicy;
+
+impl RoutingPolicy for DefaultPolicy {
+    fn select(&self, candidates: &[Arc<Provider>], _req: &LlmRequest) -> Option<Arc<Provider>> {
+        candidates.first().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use crate::router::Router;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    struct LastPolicy;
+
+    impl RoutingPolicy for LastPolicy {
+        fn select(&self, candidates: &[Arc<Provider>], _req: &LlmRequest) -> Option<Arc<Provider>> {
+            candidates.last().cloned()
+        }
+    }
+
+    struct NoPolicy;
+
+    impl RoutingPolicy for NoPolicy {
+        fn select(&self, _candidates: &[Arc<Provider>], _req: &LlmRequest) -> Option<Arc<Provider>> {
+            None
+        }
+    }
+
+    // Three providers, ranked a, b, c by latency
+    fn router(policy: Box<dyn RoutingPolicy>) -> Router {
+        let configs = ["a", "b", "c"]
+            .iter()
+            .map(|id| ProviderConfig {
+                id: id.to_string(),
+                name: id.to_string(),
+                endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+                model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+                ..ProviderConfig::default()
+            })
+            .collect();
+        let router = Router::new(configs).unwrap().with_policy(policy);
+        for (provider, ms) in router.providers().iter().zip([10, 50, 100]) {
+            provider.stats.record_success(Duration::from_millis(ms));
+        }
+        router
+    }
+
+    fn ids(providers: &[Arc<Provider>]) -> Vec<&str> {
+        providers.iter().map(|p| p.config.id.as_str()).collect()
+    }
+
+    #[test]
+    fn a_custom_policy_picks_the_first_provider() {
+        let req = crate::test_support::request("hi", serde_json::json!({}));
+        assert_eq!(ids(&router(Box::new(DefaultPolicy)).select_ranked(&req)), ["a", "b", "c"]);
+
+        let last = router(Box::new(LastPolicy));
+        assert_eq!(last.select(&req).unwrap().config.id, "c");
+        // The rest keep their order for failover
+        assert_eq!(ids(&last.select_ranked(&req)), ["c", "a", "b"]);
+
+        assert!(router(Box::new(NoPolicy)).select(&req).is_none());
+    }
+}
@@ -0,0 +1,140 @@
+use super::{Provider, Router, ScoreBreakdown};
+use crate::balancer::breaker::BreakerState;
+use crate::model::LlmRequest;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Exclusion {
+    UnsupportedModel,
+    UnsupportedParams,
+    MissingCapability,
+    ContextTooLong,
+    AtCapacity,
+    CircuitOpen,
+    HealthCheckFailing,
+    Draining,
+    RateLimited,
+    Ejected,
+    OverLatencySla,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewCandidate {
+    pub provider: String,
+    pub selected: bool,
+    // Traffic ramp weight; below 1.0 the provider is only sometimes considered.
+    pub ramp_weight: f64,
+    pub breaker: BreakerState,
+    pub score: ScoreBreakdown,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewExcluded {
+    pub provider: String,
+    pub reason: Exclusion,
+}
+
+// Dry run of `select_ranked` for auditing: eligible providers best-first with their score
+// components, and every other provider with the reason it was filtered out. `selected`
+// marks the RoutingPolicy's pick among them.
+#[derive(Debug, Serialize)]
+pub struct RoutePreview {
+    pub model: String,
+    pub candidates: Vec<PreviewCandidate>,
+    pub excluded: Vec<PreviewExcluded>,
+}
+
+impl Router {
+    // Read-only, and it ignores the random ramp gating, so the order shown is the
+    // deterministic score order. `input_tokens` is the caller's estimate, as for
+    // `select_ranked_for`.
+    pub fn preview(&self, req: &LlmRequest, input_tokens: u64) -> RoutePreview {
+        let list = self.providers();
+        let mut candidates = Vec::new();
+        let mut excluded = Vec::new();
+
+        for p in list.iter() {
+            match exclusion(p, req, input_tokens, self.latency_sla(req)) {
+                Some(reason) => excluded.push(PreviewExcluded { provider: p.config.name.clone(), reason }),
+                None => candidates.push((p.clone(), PreviewCandidate {
+                    provider: p.config.name.clone(),
+                    selected: false,
+                    ramp_weight: p.ramp_weight(),
+                    breaker: p.breaker_state(),
+                    score: self.score_breakdown(p, req, input_tokens),
+                })),
+            }
+        }
+        candidates.sort_by(|a, b| a.1.score.total.total_cmp(&b.1.score.total));
+        let (ranked, mut candidates): (Vec<_>, Vec<_>) = candidates.into_iter().unzip();
+        if let Some(pick) = self.policy_pick(&ranked, req) {
+            candidates[pick].selected = true;
+        }
+
+        RoutePreview { model: req.model.clone(), candidates, excluded }
+    }
+}
+
+// First filter in `select_ranked` order that rejects the provider.
+fn exclusion(p: &Provider, req: &LlmRequest, input_tokens: u64, sla: Option<u64>) -> Option<Exclusion> {
+    if !p.supports_model(&req.model) {
+        Some(Exclusion::UnsupportedModel)
+    } else if !p.has_capabilities(req) {
+        Some(Exclusion::MissingCapability)
+    } else if !p.supports_params(req) {
+        Some(Exclusion::UnsupportedParams)
+    } else if !p.fits_context(req, input_tokens) {
+        Some(Exclusion::ContextTooLong)
+    } else if !p.has_capacity() {
+        Some(Exclusion::AtCapacity)
+    } else if sla.is_some_and(|max_ms| p.latency_ms() > max_ms as f64) {
+        Some(Exclusion::OverLatencySla)
+    } else {
+        p.unavailability()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use std::collections::HashMap;
+
+    fn config(id: &str, cost: f64, model: &str) -> ProviderConfig {
+        ProviderConfig {
+            id: id.to_string(),
+            name: id.to_string(),
+            endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+            cost_per_1k_input: cost,
+            cost_per_1k_output: cost,
+            model_map: HashMap::from([(model.to_string(), model.to_string())]),
+            ..ProviderConfig::default()
+        }
+    }
+
+    #[test]
+    fn candidates_are_ranked_with_breakdowns_and_exclusions_explained() {
+        let router = Router::new(vec![
+            config("mid", 0.005, "m"),
+            config("other-model", 0.001, "x"),
+            config("cheap", 0.001, "m"),
+            config("drained", 0.001, "m"),
+            config("costly", 0.01, "m"),
+        ])
+        .unwrap();
+        router.set_enabled("drained", false);
+        let req: LlmRequest = serde_json::from_value(serde_json::json!({"model": "m", "prompt": "hi"})).unwrap();
+
+        let preview = router.preview(&req, crate::tokens::raw_estimate(&req));
+        let order: Vec<(&str, bool)> = preview.candidates.iter().map(|c| (c.provider.as_str(), c.selected)).collect();
+        assert_eq!(order, [("cheap", true), ("mid", false), ("costly", false)]);
+        for c in &preview.candidates {
+            let score = &c.score;
+            assert!((score.latency_component + score.cost_component + score.load_component - score.total).abs() < 1e-9);
+        }
+        assert!(preview.candidates.windows(2).all(|w| w[0].score.total <= w[1].score.total));
+        let excluded: Vec<(&str, Exclusion)> = preview.excluded.iter().map(|e| (e.provider.as_str(), e.reason)).collect();
+        assert_eq!(excluded, [("other-model", Exclusion::UnsupportedModel), ("drained", Exclusion::Draining)]);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::model::ProviderType;
+use serde_json::Value;
+
+const THINK_OPEN: &str = "<think>";
+const THINK_CLOSE: &str = "</think>";
+
+// Splits a completion body into (answer, reasoning). Providers expose reasoning differently:
+// OpenAI-compatible APIs as `message.reasoning_content` / `message.reasoning`, Ollama as
+// `message.thinking`, Anthropic as `thinking` content blocks, and many local models inline
+// as a leading `<think>...</think>` section of the content.
+pub fn split_reasoning(body: &Value, provider_type: Option<ProviderType>) -> (String, Option<String>) {
+    let message = body.pointer("/choices/0/message").or_else(|| body.get("message"));
+    let content = message.and_then(|m| m.get("content")).or_else(|| body.get("content"));
+    split_message(message, content, provider_type)
+}
+
+// Answer of every entry in an OpenAI-style `choices` array, in order, reasoning removed.
+pub fn choice_answers(body: &Value, provider_type: Option<ProviderType>) -> Vec<String> {
+    let Some(choices) = body.get("choices").and_then(Value::as_array) else { return Vec::new() };
+    choices
+        .iter()
+        .map(|choice| {
+            let message = choice.get("message");
+            split_message(message, message.and_then(|m| m.get("content")), provider_type).0
+        })
+        .collect()
+}
+
+fn split_message(message: Option<&Value>, content: Option<&Value>, provider_type: Option<ProviderType>) -> (String, Option<String>) {
+    let mut reasoning = Vec::new();
+
+    let content = match content {
+        Some(Value::String(text)) => text.clone(),
+        // Anthropic-style content blocks
+        Some(Value::Array(blocks)) => {
+            let mut text = String::new();
+            for block in blocks {
+                match block.get("type").and_then(Value::as_str) {
+                    Some("text") => text.push_str(block.get("text").and_then(Value::as_str).unwrap_or_default()),
+                    Some("thinking") => reasoning.extend(block.get("thinking").and_then(Value::as_str).map(str::to_string)),
+                    _ => {}
+                }
+            }
+            text
+        }
+        _ => String::new(),
+    };
+
+    let field = match provider_type {
+        Some(ProviderType::Ollama) => &["thinking"][..],
+        Some(ProviderType::Anthropic) => &[][..],
+        _ => &["reasoning_content", "reasoning"][..],
+    };
+    if let Some(message) = message {
+        reasoning.extend(
+            field
+                .iter()
+                .find_map(|f| message.get(*f).and_then(Value::as_str))
+                .filter(|r| !r.is_empty())
+                .map(str::to_string),
+        );
+    }
+
+    let (content, inline) = split_think_tags(&content);
+    reasoning.extend(inline);
+    let reasoning = (!reasoning.is_empty()).then(|| reasoning.join("\n"));
+    (content, reasoning)
+}
+
+// Removes a leading `<think>...</think>` section. An unterminated section means the model was
+// cut off mid-thought, so everything after the opening tag is reasoning.
+fn split_think_tags(content: &str) -> (String, Option<String>) {
+    let trimmed = content.trim_start();
+    let Some(rest) = trimmed.strip_prefix(THINK_OPEN) else { return (content.to_string(), None) };
+    match rest.find(THINK_CLOSE) {
+        Some(end) => {
+            let thought = rest[..end].trim().to_string();
+            let answer = rest[end + THINK_CLOSE.len()..].trim_start().to_string();
+            (answer, Some(thought))
+        }
+        None => (String::new(), Some(rest.trim().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reasoning_is_split_out_per_provider_type() {
+        let openai = json!({"choices": [{"message": {"content": "answer", "reasoning_content": "thought"}}]});
+        let ollama = json!({"message": {"content": "answer", "thinking": "thought"}});
+        let anthropic = json!({"content": [{"type": "thinking", "thinking": "thought"}, {"type": "text", "text": "answer"}]});
+        let inline = json!({"choices": [{"message": {"content": "<think> thought </think>\nanswer"}}]});
+        for (body, provider_type) in [
+            (openai, None),
+            (ollama, Some(ProviderType::Ollama)),
+            (anthropic, Some(ProviderType::Anthropic)),
+            (inline, Some(ProviderType::Local)),
+        ] {
+            assert_eq!(split_reasoning(&body, provider_type), ("answer".to_string(), Some("thought".to_string())), "{body}");
+        }
+    }
+
+    #[test]
+    fn an_unterminated_think_section_is_all_reasoning() {
+        let body = json!({"choices": [{"message": {"content": "<think>still going"}}]});
+        assert_eq!(split_reasoning(&body, None), (String::new(), Some("still going".to_string())));
+        let plain = json!({"choices": [{"message": {"content": "just an answer"}}]});
+        assert_eq!(split_reasoning(&plain, None), ("just an answer".to_string(), None));
+    }
+}
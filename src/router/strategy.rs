@@ -0,0 +1,174 @@
+use super::Provider;
+use crate::model::LlmRequest;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// How `Router::select_ranked` orders eligible providers.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteStrategy {
+    // Lowest score first (see ScoringWeights).
+    #[default]
+    LowestScore,
+    // Requests sharing a sticky key (see `sticky_key`) go to the same provider, picked by
+    // rendezvous hashing over every provider serving the model, so pins survive health
+    // changes elsewhere. While that provider is unavailable, score order applies.
+    ConsistentHash,
+    // Power of two choices: the better-scored of two random eligible providers goes first.
+    // Spreads concurrent load instead of herding it onto the single best provider.
+    P2c,
+    // Canary splits: the first provider is drawn at random in proportion to each candidate's
+    // `weight`, regardless of score. The rest stay in score order for fallback.
+    Weighted,
+}
+
+// Scores this close (relative) count as a tie for P2C: latency EWMAs of equivalent providers
+// never match exactly, and a strict comparison would send everything to the marginally faster one.
+const P2C_TIE_TOLERANCE: f64 = 0.05;
+
+// Index of the provider to try first, given candidate scores sorted best-first: the better
+// of two distinct random picks, with ties broken at random so equal providers share load.
+pub fn two_choices(scores: &[f64], rng: &mut impl Rng) -> usize {
+    if scores.len() < 2 {
+        return 0;
+    }
+    let a = rng.gen_range(0..scores.len());
+    let b = (a + rng.gen_range(1..scores.len())) % scores.len();
+    let (better, worse) = (a.min(b), a.max(b));
+    let tie = scores[worse] - scores[better] <= P2C_TIE_TOLERANCE * scores[worse].abs();
+    if !tie || rng.gen_bool(0.5) {
+        better
+    } else {
+        worse
+    }
+}
+
+// Index of the provider to try first, drawn in proportion to `weights`. None when no
+// weight is positive.
+pub fn weighted(weights: &[f64], rng: &mut impl Rng) -> Option<usize> {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut point = rng.gen_range(0.0..total);
+    for (i, &weight) in weights.iter().enumerate() {
+        if point < weight {
+            return Some(i);
+        }
+        point -= weight;
+    }
+    // Float rounding can leave `point` just past the last positive weight
+    weights.iter().rposition(|&w| w > 0.0)
+}
+
+// Client-chosen affinity key: the request's `session` field, else OpenAI's `user` field,
+// else the caller's API key.
+pub fn sticky_key<'a>(req: &'a LlmRequest, client: Option<&'a str>) -> Option<&'a str> {
+    ["session", "user"]
+        .iter()
+        .find_map(|field| req.extra_params.get(*field).and_then(|v| v.as_str()))
+        .filter(|key| !key.is_empty())
+        .or(client)
+}
+
+// Highest-random-weight choice: adding or removing a provider only moves the keys that
+// hashed to it.
+pub fn rendezvous<'a>(key: &str, providers: impl Iterator<Item = &'a Arc<Provider>>) -> Option<&'a Arc<Provider>> {
+    providers.max_by_key(|p| {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(key.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(p.config.id.as_bytes());
+        let digest = hasher.finalize();
+        u64::from_le_bytes(digest.as_bytes()[..8].try_into().unwrap_or_default())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use crate::router::Router;
+    use std::collections::{HashMap, HashSet};
+
+    fn config(id: &str) -> ProviderConfig {
+        ProviderConfig {
+            id: id.to_string(),
+            name: id.to_string(),
+            endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+            model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+            ..ProviderConfig::default()
+        }
+    }
+
+    fn router(ids: &[&str], strategy: RouteStrategy) -> Router {
+        Router::new(ids.iter().map(|id| config(id)).collect()).unwrap().with_strategy(strategy)
+    }
+
+    fn session(key: &str) -> LlmRequest {
+        crate::test_support::request("hi", serde_json::json!({"session": key}))
+    }
+
+    #[test]
+    fn sessions_stick_to_one_provider() {
+        let router = router(&["a", "b", "c", "d"], RouteStrategy::ConsistentHash);
+        let mut used = HashSet::new();
+        for i in 0..20 {
+            let req = session(&format!("s{i}"));
+            let pinned = router.select(&req).unwrap().config.id.clone();
+            for _ in 0..5 {
+                assert_eq!(router.select(&req).unwrap().config.id, pinned);
+            }
+            used.insert(pinned);
+        }
+        assert!(used.len() > 1, "sessions spread over providers");
+    }
+
+    #[test]
+    fn an_unavailable_pin_falls_back_to_score_order() {
+        let router = router(&["a", "b", "c"], RouteStrategy::ConsistentHash);
+        let req = session("s1");
+        let pinned = router.select(&req).unwrap().config.id.clone();
+        router.set_enabled(&pinned, false);
+        let fallback = router.select(&req).unwrap();
+        assert_ne!(fallback.config.id, pinned);
+
+        router.set_enabled(&pinned, true);
+        assert_eq!(router.select(&req).unwrap().config.id, pinned);
+    }
+
+    #[test]
+    fn two_choices_balances_equal_providers() {
+        let router = router(&["a", "b"], RouteStrategy::P2c);
+        let req = crate::test_support::request("hi", serde_json::json!({}));
+        let mut picks: HashMap<String, usize> = HashMap::new();
+        for _ in 0..2_000 {
+            *picks.entry(router.select(&req).unwrap().config.id.clone()).or_default() += 1;
+        }
+        for id in ["a", "b"] {
+            let share = picks.get(id).copied().unwrap_or(0) as f64 / 2_000.0;
+            assert!((0.4..=0.6).contains(&share), "{id} got {share}");
+        }
+    }
+
+    #[test]
+    fn weighted_splits_follow_the_configured_weights() {
+        let configs = [("stable", Some(9.0)), ("canary", Some(1.0)), ("standby", Some(0.0))]
+            .iter()
+            .map(|&(id, weight)| ProviderConfig { weight, ..config(id) })
+            .collect();
+        let router = Router::new(configs).unwrap().with_strategy(RouteStrategy::Weighted);
+        let req = crate::test_support::request("hi", serde_json::json!({}));
+        let mut picks: HashMap<String, usize> = HashMap::new();
+        for _ in 0..5_000 {
+            *picks.entry(router.select(&req).unwrap().config.id.clone()).or_default() += 1;
+        }
+        let share = |id: &str| picks.get(id).copied().unwrap_or(0) as f64 / 5_000.0;
+        assert!((share("canary") - 0.1).abs() < 0.03, "canary got {}", share("canary"));
+        assert!((share("stable") - 0.9).abs() < 0.03, "stable got {}", share("stable"));
+        // Weight 0 only takes failover traffic
+        assert_eq!(share("standby"), 0.0);
+        assert_eq!(router.select_ranked(&req).len(), 3);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::model::{ProviderType, TokenUsage};
+use serde_json::{json, Map, Value};
+
+// Anthropic requires `max_tokens`; used when the client didn't set one.
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u64 = 1024;
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// Anthropic rejects unknown fields, so only these pass through unchanged.
+const ANTHROPIC_PASSTHROUGH: &[&str] = &["temperature", "top_p", "top_k", "stream", "metadata", "thinking"];
+// OpenAI-style sampling parameters that Ollama takes under `options`.
+const OLLAMA_OPTIONS: &[&str] = &["temperature", "top_p", "top_k", "seed"];
+
+// Rewrites an OpenAI-shaped chat body (model already mapped, `messages` normalized, system
+// prompt applied) into the schema the provider type expects. Untyped providers get it as is.
+pub fn request_body(body: Value, provider_type: Option<ProviderType>) -> Value {
+    let Value::Object(map) = body else { return body };
+    match provider_type {
+        Some(ProviderType::Anthropic) => to_anthropic(map),
+        Some(ProviderType::Ollama) => to_ollama(map),
+        Some(ProviderType::OpenAI) | Some(ProviderType::Local) | None => Value::Object(map),
+    }
+}
+
+// Messages API: system messages move to the top-level `system` field, `max_tokens` is
+// mandatory and `stop` becomes `stop_sequences`.
+fn to_anthropic(mut body: Map<String, Value>) -> Value {
+    let mut out = Map::new();
+    out.insert("model".to_string(), body.remove("model").unwrap_or_default());
+
+    let mut system = Vec::new();
+    let mut messages = Vec::new();
+    if let Some(Value::Array(list)) = body.remove("messages") {
+        for message in list {
+            match message.get("role").and_then(Value::as_str) {
+                Some("system") => system.extend(message.get("content").and_then(Value::as_str).map(str::to_string)),
+                _ => messages.push(message),
+            }
+        }
+    }
+    if !system.is_empty() {
+        out.insert("system".to_string(), Value::String(system.join("\n\n")));
+    }
+    out.insert("messages".to_string(), Value::Array(messages));
+
+    let max_tokens = body.remove("max_tokens").filter(|v| !v.is_null()).unwrap_or(json!(ANTHROPIC_DEFAULT_MAX_TOKENS));
+    out.insert("max_tokens".to_string(), max_tokens);
+    if let Some(stop) = body.remove("stop").and_then(stop_list) {
+        out.insert("stop_sequences".to_string(), stop);
+    }
+    for key in ANTHROPIC_PASSTHROUGH {
+        if let Some(value) = body.remove(*key).filter(|v| !v.is_null()) {
+            out.insert(key.to_string(), value);
+        }
+    }
+    Value::Object(out)
+}
+
+// /api/chat: sampling parameters nest under `options`, and `stream` must be explicit since
+// Ollama streams by default.
+fn to_ollama(mut body: Map<String, Value>) -> Value {
+    let mut options = Map::new();
+    for key in OLLAMA_OPTIONS {
+        if let Some(value) = body.remove(*key).filter(|v| !v.is_null()) {
+            options.insert(key.to_string(), value);
+        }
+    }
+    if let Some(max_tokens) = body.remove("max_tokens").filter(|v| !v.is_null()) {
+        options.insert("num_predict".to_string(), max_tokens);
+    }
+    if let Some(stop) = body.remove("stop").and_then(stop_list) {
+        options.insert("stop".to_string(), stop);
+    }
+
+    let stream = body.remove("stream").and_then(|v| v.as_bool()).unwrap_or(false);
+    let mut out = Map::new();
+    out.insert("model".to_string(), body.remove("model").unwrap_or_default());
+    out.insert("messages".to_string(), body.remove("messages").unwrap_or(json!([])));
+    out.insert("stream".to_string(), Value::Bool(stream));
+    if !options.is_empty() {
+        out.insert("options".to_string(), Value::Object(options));
+    }
+    // JSON mode is `format: "json"`; a JSON schema is passed as the format itself
+    if let Some(format) = body.remove("response_format") {
+        match format.get("type").and_then(Value::as_str) {
+            Some("json_object") => {
+                out.insert("format".to_string(), json!("json"));
+            }
+            Some("json_schema") => {
+                let schema = format.pointer("/json_schema/schema").cloned().unwrap_or(json!("json"));
+                out.insert("format".to_string(), schema);
+            }
+            _ => {}
+        }
+    }
+    for key in ["format", "keep_alive"] {
+        if let Some(value) = body.remove(key) {
+            out.insert(key.to_string(), value);
+        }
+    }
+    Value::Object(out)
+}
+
+// OpenAI accepts `stop` as a string or a list; the other schemas only take a list.
+fn stop_list(stop: Value) -> Option<Value> {
+    match stop {
+        Value::String(s) => Some(json!([s])),
+        Value::Array(list) if !list.is_empty() => Some(Value::Array(list)),
+        _ => None,
+    }
+}
+
+// Credential headers per provider type; none when no key is configured.
+pub fn auth_headers(provider_type: Option<ProviderType>, api_key: &str) -> Vec<(&'static str, String)> {
+    if api_key.is_empty() {
+        return Vec::new();
+    }
+    match provider_type {
+        Some(ProviderType::Anthropic) => vec![
+            ("x-api-key", api_key.to_string()),
+            ("anthropic-version", ANTHROPIC_VERSION.to_string()),
+        ],
+        _ => vec![("Authorization", format!("Bearer {}", api_key))],
+    }
+}
+
+// Token usage from a completion body: Anthropic reports `input_tokens`/`output_tokens`,
+// Ollama `prompt_eval_count`/`eval_count`, everyone else OpenAI's `usage` object.
+pub fn parse_usage(body: &Value, provider_type: Option<ProviderType>) -> TokenUsage {
+    let count = |pointer: &str| body.pointer(pointer).and_then(Value::as_u64).unwrap_or(0) as u32;
+    let (prompt, completion) = match provider_type {
+        Some(ProviderType::Anthropic) => (count("/usage/input_tokens"), count("/usage/output_tokens")),
+        Some(ProviderType::Ollama) => (count("/prompt_eval_count"), count("/eval_count")),
+        _ => {
+            let mut usage = body
+                .get("usage")
+                .and_then(|u| serde_json::from_value::<TokenUsage>(u.clone()).ok())
+                .unwrap_or_default();
+            usage.reasoning_tokens = count("/usage/completion_tokens_details/reasoning_tokens");
+            return usage;
+        }
+    };
+    TokenUsage { prompt_tokens: prompt, completion_tokens: completion, total_tokens: prompt + completion, reasoning_tokens: 0 }
+}
+
+// Error reported inside an otherwise successful stream: Ollama sends `{"error": "..."}` as
+// an NDJSON line, Anthropic an `error` event, OpenAI-compatible servers an `error` object.
+pub fn stream_error(payload: &str) -> Option<String> {
+    let chunk: Value = serde_json::from_str(payload).ok()?;
+    let error = chunk.get("error")?;
+    let message = error.as_str().or_else(|| error.get("message").and_then(Value::as_str));
+    Some(message.map_or_else(|| error.to_string(), str::to_string))
+}
+
+// Content delta of one streamed event: an OpenAI `chat.completion.chunk`, an Anthropic
+// `content_block_delta`, or an Ollama NDJSON line.
+pub fn stream_delta(payload: &str, provider_type: Option<ProviderType>) -> Option<String> {
+    let chunk: Value = serde_json::from_str(payload).ok()?;
+    let pointer = match provider_type {
+        Some(ProviderType::Anthropic) => "/delta/text",
+        Some(ProviderType::Ollama) => "/message/content",
+        _ => "/choices/0/delta/content",
+    };
+    chunk
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn openai_body() -> Value {
+        json!({
+            "model": "claude",
+            "messages": [{"role": "system", "content": "be brief"}, {"role": "user", "content": "hi"}],
+            "temperature": 0.2,
+            "stop": "\n",
+            "user": "u1",
+            "stream": false
+        })
+    }
+
+    #[test]
+    fn openai_bodies_pass_through() {
+        for provider_type in [None, Some(ProviderType::OpenAI), Some(ProviderType::Local)] {
+            assert_eq!(request_body(openai_body(), provider_type), openai_body());
+        }
+    }
+
+    #[test]
+    fn anthropic_bodies_follow_the_messages_api() {
+        assert_eq!(
+            request_body(openai_body(), Some(ProviderType::Anthropic)),
+            json!({
+                "model": "claude",
+                "system": "be brief",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": ANTHROPIC_DEFAULT_MAX_TOKENS,
+                "stop_sequences": ["\n"],
+                "temperature": 0.2,
+                "stream": false
+            })
+        );
+        let mut capped = openai_body();
+        capped["max_tokens"] = json!(64);
+        assert_eq!(request_body(capped, Some(ProviderType::Anthropic))["max_tokens"], 64);
+    }
+
+    #[test]
+    fn auth_headers_and_usage_follow_the_provider_type() {
+        assert_eq!(auth_headers(None, "k"), [("Authorization", "Bearer k".to_string())]);
+        assert_eq!(
+            auth_headers(Some(ProviderType::Anthropic), "k"),
+            [("x-api-key", "k".to_string()), ("anthropic-version", ANTHROPIC_VERSION.to_string())]
+        );
+        assert!(auth_headers(Some(ProviderType::Anthropic), "").is_empty());
+
+        let usage = parse_usage(&json!({"usage": {"input_tokens": 12, "output_tokens": 3}}), Some(ProviderType::Anthropic));
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (12, 3, 15));
+        let usage = parse_usage(&json!({"usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7}}), None);
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (5, 2, 7));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::model::ProviderConfig;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::warn;
+
+// Gateway-level model alias (e.g. `fast`, `smart`) that clients request like any model.
+// It is served by every provider that maps one of `models` and meets the constraints, each
+// with its own mapping of the first listed model it serves.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VirtualModel {
+    // Client model names (`model_map` keys), in order of preference.
+    pub models: Vec<String>,
+    // Provider ids allowed to serve the alias; any when empty.
+    #[serde(default)]
+    pub providers: Vec<String>,
+    // Providers whose input or output rate is above this are left out.
+    #[serde(default)]
+    pub max_cost_per_1k: Option<f64>,
+    // Providers declaring a smaller `max_context_tokens`, or none, are left out.
+    #[serde(default)]
+    pub min_context_tokens: Option<u32>,
+    // Latency SLA for requests to the alias (see Router::latency_sla).
+    #[serde(default)]
+    pub max_latency_ms: Option<u64>,
+}
+
+pub type VirtualModels = HashMap<String, VirtualModel>;
+
+impl VirtualModel {
+    fn admits(&self, p: &ProviderConfig) -> bool {
+        (self.providers.is_empty() || self.providers.contains(&p.id))
+            && self.max_cost_per_1k.is_none_or(|max| p.cost_per_1k_input <= max && p.cost_per_1k_output <= max)
+            && self.min_context_tokens.is_none_or(|min| p.max_context_tokens.is_some_and(|limit| limit >= min))
+    }
+
+    // The provider model serving this alias on `p`, if `p` qualifies.
+    fn resolve(&self, p: &ProviderConfig) -> Option<String> {
+        if !self.admits(p) {
+            return None;
+        }
+        self.models.iter().find_map(|model| p.model_map.get(model).cloned())
+    }
+}
+
+pub fn validate(virtual_models: &VirtualModels, providers: &[ProviderConfig]) -> Result<()> {
+    for (alias, v) in virtual_models {
+        if v.models.is_empty() {
+            bail!("virtual model {:?} lists no models", alias);
+        }
+        if let Some(id) = v.providers.iter().find(|id| !providers.iter().any(|p| &p.id == *id)) {
+            bail!("virtual model {:?} names unknown provider {:?}", alias, id);
+        }
+        if let Some(p) = providers.iter().find(|p| p.model_map.contains_key(alias)) {
+            bail!("virtual model {:?} clashes with a model_map entry of provider {:?}", alias, p.id);
+        }
+    }
+    Ok(())
+}
+
+// Adds each alias to the `model_map` of the providers that qualify, so routing only ever
+// considers those. Run on every provider list before it reaches the router.
+pub fn expand(virtual_models: &VirtualModels, providers: &mut [ProviderConfig]) {
+    for (alias, v) in virtual_models {
+        let mut served = false;
+        for p in providers.iter_mut() {
+            if let Some(target) = v.resolve(p) {
+                p.model_map.insert(alias.clone(), target);
+                served = true;
+            }
+        }
+        if !served {
+            warn!("No provider qualifies for virtual model {:?}", alias);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+
+    fn provider(id: &str, cost: f64, model_map: &[(&str, &str)], max_context_tokens: Option<u32>) -> ProviderConfig {
+        ProviderConfig {
+            id: id.to_string(),
+            name: id.to_string(),
+            endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+            cost_per_1k_input: cost,
+            cost_per_1k_output: cost,
+            model_map: model_map.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            max_context_tokens,
+            ..ProviderConfig::default()
+        }
+    }
+
+    fn providers() -> Vec<ProviderConfig> {
+        vec![
+            provider("small", 0.001, &[("mini", "small-mini")], None),
+            provider("large", 0.03, &[("big", "large-big")], Some(128_000)),
+            provider("long", 0.002, &[("mini", "long-mini"), ("big", "long-big")], Some(200_000)),
+        ]
+    }
+
+    fn aliases() -> VirtualModels {
+        let alias = |models: &[&str]| VirtualModel {
+            models: models.iter().map(|m| m.to_string()).collect(),
+            providers: Vec::new(),
+            max_cost_per_1k: None,
+            min_context_tokens: None,
+            max_latency_ms: None,
+        };
+        HashMap::from([
+            ("fast".to_string(), VirtualModel { max_cost_per_1k: Some(0.005), ..alias(&["mini"]) }),
+            ("smart".to_string(), VirtualModel { min_context_tokens: Some(150_000), ..alias(&["big", "mini"]) }),
+            ("pinned".to_string(), VirtualModel { providers: vec!["small".to_string()], ..alias(&["mini", "big"]) }),
+        ])
+    }
+
+    fn served_by(router: &Router, alias: &str) -> Vec<String> {
+        let req = crate::test_support::request("hi", serde_json::json!({"model": alias}));
+        let mut ids: Vec<String> = router.select_ranked(&req).iter().map(|p| p.config.id.clone()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn aliases_only_route_to_providers_meeting_their_constraints() {
+        let (virtual_models, mut providers) = (aliases(), providers());
+        validate(&virtual_models, &providers).unwrap();
+        expand(&virtual_models, &mut providers);
+        // Each provider serves the first listed model it maps
+        assert_eq!(providers[2].model_map["smart"], "long-big");
+        assert_eq!(providers[0].model_map["pinned"], "small-mini");
+
+        let router = Router::new(providers).unwrap();
+        assert_eq!(served_by(&router, "fast"), ["long", "small"]);
+        assert_eq!(served_by(&router, "smart"), ["long"]);
+        assert_eq!(served_by(&router, "pinned"), ["small"]);
+    }
+
+    #[test]
+    fn invalid_aliases_are_rejected() {
+        let mut clashing = aliases();
+        clashing.insert("mini".to_string(), clashing["fast"].clone());
+        assert!(validate(&clashing, &providers()).is_err());
+
+        let mut unknown = aliases();
+        unknown.get_mut("pinned").unwrap().providers.push("missing".to_string());
+        assert!(validate(&unknown, &providers()).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::cache::fuzzy::{jaccard, ngrams};
+use crate::model::{LlmRequest, LlmResponse};
+use crate::router::{Provider, Router};
+use rand::Rng;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, warn};
+
+// Similarity sums are kept in millionths so they fit an atomic integer.
+const SIMILARITY_SCALE: f64 = 1_000_000.0;
+
+// Mirrors answered requests to a candidate provider to compare it with the live ones. The
+// client never waits for or sees the shadow call.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShadowConfig {
+    pub provider_id: String,
+    // Fraction of answered requests that are mirrored (0.0..=1.0).
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f64,
+}
+
+fn default_sample_rate() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Default)]
+pub struct ShadowStats {
+    pub calls: AtomicU64,
+    pub errors: AtomicU64,
+    // Answers identical to the primary's
+    pub identical: AtomicU64,
+    // Word n-gram Jaccard similarity to the primary's answer, summed over successful calls
+    similarity_micros: AtomicU64,
+    // Shadow latency minus primary latency, summed over successful calls
+    pub latency_delta_ms: AtomicI64,
+}
+
+impl ShadowStats {
+    pub fn similarity_sum(&self) -> f64 {
+        self.similarity_micros.load(Ordering::Relaxed) as f64 / SIMILARITY_SCALE
+    }
+}
+
+pub struct Shadow {
+    config: ShadowConfig,
+    pub stats: ShadowStats,
+}
+
+impl Shadow {
+    pub fn new(config: ShadowConfig) -> Self {
+        Self { config, stats: ShadowStats::default() }
+    }
+
+    pub fn provider_id(&self) -> &str {
+        &self.config.provider_id
+    }
+
+    // Sends `req` to the shadow provider in the background, if it is sampled and the shadow
+    // provider isn't the one that answered. Skipped when the shadow provider doesn't serve
+    // the model or is at its concurrency limit, so mirroring never queues behind real traffic,
+    // and when the two calls together would exceed the fan-out cost cap.
+    pub fn mirror(self: &Arc<Self>, router: &Router, req: &LlmRequest, primary: &Arc<Provider>, answer: &LlmResponse, max_fanout_cost_usd: Option<f64>) {
+        if primary.config.id == self.config.provider_id || !rand::thread_rng().gen_bool(self.config.sample_rate.clamp(0.0, 1.0)) {
+            return;
+        }
+        let providers = router.providers();
+        let Some(shadow) = providers.iter().find(|p| p.config.id == self.config.provider_id) else { return };
+        if !shadow.supports_model(&req.model) {
+            return;
+        }
+        let input_tokens = answer.usage.prompt_tokens as u64;
+        if router.cap_fanout(vec![primary.clone(), shadow.clone()], req, input_tokens, max_fanout_cost_usd).len() < 2 {
+            return;
+        }
+        let Some(in_flight) = shadow.try_acquire() else { return };
+        let (this, shadow, req) = (self.clone(), shadow.clone(), req.clone());
+        let (primary_name, primary_content, primary_ms) = (primary.config.name.clone(), answer.content.clone(), answer.latency_ms);
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let result = shadow.call(&req).await;
+            drop(in_flight);
+            let latency = started.elapsed();
+            this.stats.calls.fetch_add(1, Ordering::Relaxed);
+            match result {
+                Ok(resp) => {
+                    shadow.stats.record_success(latency);
+                    shadow.charge(&resp.usage);
+                    let similarity = jaccard(&ngrams(&primary_content), &ngrams(&resp.content));
+                    let identical = resp.content == primary_content;
+                    let shadow_ms = latency.as_millis() as u64;
+                    if identical {
+                        this.stats.identical.fetch_add(1, Ordering::Relaxed);
+                    }
+                    this.stats.similarity_micros.fetch_add((similarity * SIMILARITY_SCALE) as u64, Ordering::Relaxed);
+                    this.stats.latency_delta_ms.fetch_add(shadow_ms as i64 - primary_ms as i64, Ordering::Relaxed);
+                    info!(
+                        target: "llm_edge::shadow",
+                        primary = %primary_name,
+                        shadow = %shadow.config.name,
+                        primary_ms,
+                        shadow_ms,
+                        similarity,
+                        identical,
+                        "shadow call"
+                    );
+                }
+                Err(e) => {
+                    shadow.stats.record_failure(&e);
+                    this.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    warn!(target: "llm_edge::shadow", "Shadow call to {} failed: {}", shadow.config.name, e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::AppState;
+    use crate::test_support::{self, MockUpstream};
+    use axum::http::HeaderMap;
+    use std::time::Duration;
+
+    // Client-facing upstream "a" and shadow "b", drained so only mirroring reaches it
+    async fn mirrored(max_fanout_cost_usd: Option<f64>) -> (Arc<AppState>, MockUpstream) {
+        let (primary, shadow) = (MockUpstream::start().await, MockUpstream::start().await);
+        let mut state = test_support::state(vec![primary.provider("a"), shadow.provider("b")]);
+        state.router.set_enabled("b", false);
+        state.shadow = Some(Arc::new(Shadow::new(ShadowConfig { provider_id: "b".to_string(), sample_rate: 1.0 })));
+        state.options.max_fanout_cost_usd = max_fanout_cost_usd;
+        let state = Arc::new(state);
+        let req = test_support::request("hi", serde_json::json!({"max_tokens": 10}));
+        assert!(test_support::complete(&state, HeaderMap::new(), req).await.status().is_success());
+        (state, shadow)
+    }
+
+    async fn settle(state: &AppState) -> u64 {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        state.shadow.as_ref().unwrap().stats.calls.load(Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn mirrors_within_the_fanout_cap() {
+        // 10 input + 10 output tokens at $1/1k: $0.02 per call, $0.04 for both
+        let (state, shadow) = mirrored(Some(0.05)).await;
+        assert_eq!(settle(&state).await, 1);
+        assert_eq!(shadow.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn over_cap_fanout_only_calls_the_primary() {
+        let (state, shadow) = mirrored(Some(0.03)).await;
+        assert_eq!(settle(&state).await, 0);
+        assert_eq!(shadow.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn slow_or_failing_shadows_leave_the_client_response_alone() {
+        let (primary, shadow) = (MockUpstream::start().await, MockUpstream::start().await);
+        shadow.answer_with("a different answer");
+        shadow.delay(Duration::from_millis(300));
+        let mut state = test_support::state(vec![primary.provider("a"), shadow.provider("b")]);
+        state.router.set_enabled("b", false);
+        state.shadow = Some(Arc::new(Shadow::new(ShadowConfig { provider_id: "b".to_string(), sample_rate: 1.0 })));
+        let state = Arc::new(state);
+        let stats = || &state.shadow.as_ref().unwrap().stats;
+
+        let started = Instant::now();
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("hi", serde_json::json!({}))).await;
+        assert!(started.elapsed() < Duration::from_millis(300), "waited on the shadow");
+        assert_eq!(response.headers()[&crate::access_log::PROVIDER_HEADER], "a");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains(test_support::CONTENT));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(shadow.calls(), 1);
+        assert_eq!(stats().calls.load(Ordering::Relaxed), 1);
+        assert_eq!(stats().identical.load(Ordering::Relaxed), 0);
+
+        shadow.fail_with(500);
+        let response = test_support::complete(&state, HeaderMap::new(), test_support::request("again", serde_json::json!({}))).await;
+        assert!(response.status().is_success());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(stats().errors.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use futures::StreamExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Requests currently being served, so shutdown can report what it is waiting for.
+#[derive(Debug, Default)]
+pub struct InFlightRequests {
+    count: AtomicUsize,
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    fn begin(self: &Arc<Self>) -> RequestGuard {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        RequestGuard { requests: self.clone() }
+    }
+}
+
+struct RequestGuard {
+    requests: Arc<InFlightRequests>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.requests.count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// Counts a request until its response is complete. Streamed bodies (SSE) outlive the
+// handler, so their guard travels with the body; sized bodies are done once the handler is.
+pub async fn track_requests(State(requests): State<Arc<InFlightRequests>>, request: Request, next: Next) -> Response {
+    let guard = requests.begin();
+    let response = next.run(request).await;
+    if response.body().size_hint().exact().is_some() {
+        return response;
+    }
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _held = &guard;
+            chunk
+        }))
+    })
+}
+
+// Resolves on Ctrl-C or SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get};
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn in_flight_requests_finish_after_the_signal_while_new_connections_are_refused() {
+        let requests = Arc::new(InFlightRequests::new());
+        let app = axum::Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    "done"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(requests.clone(), track_requests));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let signalled = Arc::new(Notify::new());
+        let on_signal = {
+            let signalled = signalled.clone();
+            async move { signalled.notified().await }
+        };
+        let server = tokio::spawn(async move { axum::serve(listener, app).with_graceful_shutdown(on_signal).await });
+
+        let in_flight = tokio::spawn(reqwest::get(format!("http://{addr}/slow")));
+        while requests.count() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        signalled.notify_one();
+
+        // The listener closes once the signal is seen
+        let refused = async {
+            while tokio::net::TcpStream::connect(addr).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), refused).await.expect("new connections still accepted");
+
+        let response = in_flight.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "done");
+        tokio::time::timeout(Duration::from_secs(1), server).await.unwrap().unwrap().unwrap();
+        assert_eq!(requests.count(), 0);
+    }
+}
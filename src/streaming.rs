@@ -0,0 +1,212 @@
+use crate::model::StreamBuffering;
+use serde::{Deserialize, Serialize};
+use bytes::Bytes;
+use futures::future;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use std::time::Duration;
+
+// Applies a provider's buffering strategy to a stream of text chunks. With `Coalesce`, the
+// first chunk opens a window and everything that arrives before it closes is flushed as one
+// item. Errors flush whatever was buffered first and then end the stream.
+pub fn buffer_chunks<S, E>(upstream: S, strategy: StreamBuffering) -> BoxStream<'static, Result<String, E>>
+where
+    S: Stream<Item = Result<String, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    let window = match strategy {
+        StreamBuffering::Immediate => return upstream.boxed(),
+        StreamBuffering::Coalesce { window_ms } => Duration::from_millis(window_ms),
+    };
+
+    // State: (upstream, error waiting to be emitted, upstream finished)
+    let init = (upstream.boxed(), None::<E>, false);
+    stream::unfold(init, move |(mut upstream, mut pending, finished)| async move {
+        if let Some(e) = pending.take() {
+            return Some((Err(e), (upstream, None, true)));
+        }
+        if finished {
+            return None;
+        }
+
+        let mut buf = match upstream.next().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => return Some((Err(e), (upstream, None, true))),
+            None => return None,
+        };
+
+        let deadline = tokio::time::Instant::now() + window;
+        let mut finished = false;
+        loop {
+            match tokio::time::timeout_at(deadline, upstream.next()).await {
+                Ok(Some(Ok(chunk))) => buf.push_str(&chunk),
+                Ok(Some(Err(e))) => {
+                    pending = Some(e);
+                    break;
+                }
+                Ok(None) => {
+                    finished = true;
+                    break;
+                }
+                // Window closed
+                Err(_) => break,
+            }
+        }
+        Some((Ok(buf), (upstream, pending, finished)))
+    })
+    .boxed()
+}
+
+// Ends the stream with `on_idle`'s error once the upstream goes `idle` without yielding
+// anything; a stream that keeps producing may run indefinitely.
+pub fn idle_timeout<S, T, E, F>(upstream: S, idle: Duration, on_idle: F) -> impl Stream<Item = Result<T, E>> + Send + 'static
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+    F: Fn() -> E + Send + 'static,
+{
+    // State: (upstream, error factory; None once the stream timed out)
+    stream::unfold((upstream.boxed(), Some(on_idle)), move |(mut upstream, on_idle)| async move {
+        let on_idle = on_idle?;
+        match tokio::time::timeout(idle, upstream.next()).await {
+            Ok(Some(item)) => Some((item, (upstream, Some(on_idle)))),
+            Ok(None) => None,
+            Err(_) => Some((Err(on_idle()), (upstream, None))),
+        }
+    })
+}
+
+// Splits a raw SSE byte stream into the payloads of its `data:` lines, ending at `[DONE]` or
+// when the upstream closes.
+pub fn sse_data<S, E>(bytes: S) -> impl Stream<Item = Result<String, E>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    lines(bytes)
+        .filter_map(|item| {
+            future::ready(match item {
+                Ok(line) => line.strip_prefix("data:").map(|data| Ok(data.trim_start().to_string())),
+                Err(e) => Some(Err(e)),
+            })
+        })
+        .take_while(|item| future::ready(!matches!(item, Ok(data) if data == "[DONE]")))
+}
+
+// Splits a newline-delimited JSON byte stream (as Ollama streams) into its non-empty lines.
+pub fn ndjson_lines<S, E>(bytes: S) -> impl Stream<Item = Result<String, E>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    lines(bytes).filter(|item| future::ready(!matches!(item, Ok(line) if line.trim().is_empty())))
+}
+
+// Lines of a byte stream without their line endings. Lines may span network chunks, so bytes
+// are buffered until a newline arrives; the stream ends after the first error.
+fn lines<S, E>(bytes: S) -> impl Stream<Item = Result<String, E>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    // State: (upstream, unparsed bytes, upstream finished)
+    let init = (bytes.boxed(), Vec::<u8>::new(), false);
+    stream::unfold(init, |(mut bytes, mut buf, mut finished)| async move {
+        loop {
+            if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                return Some((Ok(line), (bytes, buf, finished)));
+            }
+            if finished {
+                // Flush a trailing line that had no newline
+                if buf.is_empty() {
+                    return None;
+                }
+                buf.push(b'\n');
+                continue;
+            }
+            match bytes.next().await {
+                Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e), (bytes, Vec::new(), true))),
+                None => finished = true,
+            }
+        }
+    })
+}
+
+// How a stored answer is cut into SSE chunks when served to a streaming client (cache hits,
+// the fallback response). `Word` approximates the token-sized deltas of a live stream.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayChunking {
+    Whole,
+    #[default]
+    Word,
+    Sentence,
+}
+
+// Consecutive pieces of `text` that concatenate back to it exactly: each word or sentence
+// carries the whitespace after it.
+pub fn replay_chunks(text: &str, chunking: ReplayChunking) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let end = i + c.len_utf8();
+        let ends_chunk = match chunking {
+            ReplayChunking::Whole => false,
+            ReplayChunking::Word => c.is_whitespace() && next.is_some_and(|n| !n.is_whitespace()),
+            // After terminal punctuation, or at a line break
+            ReplayChunking::Sentence => {
+                let boundary = c.is_whitespace() && next.is_some_and(|n| !n.is_whitespace());
+                boundary && (text[start..i].trim_end().ends_with(['.', '!', '?']) || text[start..end].contains('\n'))
+            }
+        };
+        if ends_chunk {
+            chunks.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() || chunks.is_empty() {
+        chunks.push(&text[start..]);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    // "a" and "b" together, then "c" after a pause
+    fn upstream() -> impl Stream<Item = Result<String, Infallible>> + Send + 'static {
+        stream::iter([("a", 0), ("b", 0), ("c", 200)]).then(|(chunk, pause_ms)| async move {
+            tokio::time::sleep(Duration::from_millis(pause_ms)).await;
+            Ok(chunk.to_string())
+        })
+    }
+
+    async fn flushes(strategy: StreamBuffering) -> Vec<String> {
+        buffer_chunks(upstream(), strategy).map(Result::unwrap).collect().await
+    }
+
+    #[tokio::test]
+    async fn coalescing_flushes_less_often_than_immediate() {
+        assert_eq!(flushes(StreamBuffering::Immediate).await, ["a", "b", "c"]);
+        assert_eq!(flushes(StreamBuffering::Coalesce { window_ms: 50 }).await, ["ab", "c"]);
+        assert_eq!(flushes(StreamBuffering::Coalesce { window_ms: 1_000 }).await, ["abc"]);
+    }
+
+    #[test]
+    fn replay_chunks_concatenate_back_to_the_text() {
+        let text = "Hello there.  How are you?\nFine, thanks";
+        assert_eq!(replay_chunks(text, ReplayChunking::Whole), [text]);
+        assert_eq!(replay_chunks(text, ReplayChunking::Word), ["Hello ", "there.  ", "How ", "are ", "you?\n", "Fine, ", "thanks"]);
+        assert_eq!(replay_chunks(text, ReplayChunking::Sentence), ["Hello there.  ", "How are you?\n", "Fine, thanks"]);
+        for chunking in [ReplayChunking::Whole, ReplayChunking::Word, ReplayChunking::Sentence] {
+            assert_eq!(replay_chunks(text, chunking).concat(), text);
+        }
+    }
+}
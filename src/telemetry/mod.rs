@@ -0,0 +1,39 @@
+use serde::Deserialize;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+#[cfg(feature = "otel")]
+pub mod otlp;
+
+// Span export to an OpenTelemetry collector over OTLP/HTTP (JSON encoding). Only takes
+// effect in builds with the `otel` feature.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OtlpConfig {
+    // Collector base URL, e.g. `http://localhost:4318`; spans go to `{endpoint}/v1/traces`
+    // unless the URL already ends in that path.
+    pub endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_service_name() -> String {
+    "llm-edge".to_string()
+}
+
+// Installs the global subscriber: INFO-level logs to stdout, plus span export when
+// configured.
+pub fn init(export_to: Option<&OtlpConfig>) {
+    let logs = tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO);
+    #[cfg(feature = "otel")]
+    let export = export_to.map(|config| otlp::OtlpLayer::spawn(config).with_filter(LevelFilter::INFO));
+    #[cfg(not(feature = "otel"))]
+    let export: Option<LevelFilter> = None;
+    tracing_subscriber::registry().with(logs).with(export).init();
+
+    #[cfg(not(feature = "otel"))]
+    if export_to.is_some() {
+        tracing::warn!("`otlp` is configured but this build lacks the `otel` feature; spans are not exported");
+    }
+}
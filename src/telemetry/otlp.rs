@@ -0,0 +1,263 @@
+use super::OtlpConfig;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+// Finished spans waiting for export; more than this and new ones are dropped.
+const QUEUE_CAPACITY: usize = 4096;
+const MAX_BATCH: usize = 512;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(2);
+const TRACES_PATH: &str = "/v1/traces";
+
+// OTLP span kinds. The root span is the incoming request and `upstream` spans are provider
+// calls; everything else is internal.
+const KIND_INTERNAL: u8 = 1;
+const KIND_SERVER: u8 = 2;
+const KIND_CLIENT: u8 = 3;
+
+fn span_kind(name: &str) -> u8 {
+    match name {
+        "request" => KIND_SERVER,
+        "upstream" => KIND_CLIENT,
+        _ => KIND_INTERNAL,
+    }
+}
+
+// Per-span state kept in the registry's extensions until the span closes.
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    name: &'static str,
+    start_ns: u128,
+    attributes: Vec<(&'static str, Value)>,
+}
+
+struct Attrs<'a>(&'a mut Vec<(&'static str, Value)>);
+
+impl Attrs<'_> {
+    fn set(&mut self, field: &Field, value: Value) {
+        self.0.retain(|(name, _)| *name != field.name());
+        self.0.push((field.name(), value));
+    }
+}
+
+impl Visit for Attrs<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, json!({ "stringValue": value }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field, json!({ "boolValue": value }));
+    }
+
+    // OTLP's JSON encoding carries 64-bit integers as strings
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.set(field, json!({ "doubleValue": value }));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.set(field, json!({ "stringValue": format!("{:?}", value) }));
+    }
+}
+
+// Turns closed spans into OTLP spans and hands them to a background exporter. Export never
+// blocks the request path: a full queue drops spans, and failed exports are dropped after a
+// warning.
+pub struct OtlpLayer {
+    queue: mpsc::Sender<Value>,
+}
+
+impl OtlpLayer {
+    // Must be called inside the Tokio runtime, which runs the exporter.
+    pub fn spawn(config: &OtlpConfig) -> Self {
+        let (queue, spans) = mpsc::channel(QUEUE_CAPACITY);
+        let endpoint = config.endpoint.trim_end_matches('/');
+        let url = if endpoint.ends_with(TRACES_PATH) { endpoint.to_string() } else { format!("{}{}", endpoint, TRACES_PATH) };
+        tokio::spawn(export(url, config.service_name.clone(), spans));
+        Self { queue }
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let parent = span.parent();
+        let parent_ids = parent.as_ref().and_then(|p| p.extensions().get::<SpanData>().map(|d| (d.trace_id, d.span_id)));
+        let mut rng = rand::thread_rng();
+        let mut data = SpanData {
+            trace_id: parent_ids.map_or_else(|| rng.gen(), |(trace_id, _)| trace_id),
+            span_id: rng.gen(),
+            parent_id: parent_ids.map(|(_, span_id)| span_id),
+            name: span.metadata().name(),
+            start_ns: now_ns(),
+            attributes: Vec::new(),
+        };
+        attrs.record(&mut Attrs(&mut data.attributes));
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            values.record(&mut Attrs(&mut data.attributes));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else { return };
+        let _ = self.queue.try_send(otlp_span(data, now_ns()));
+    }
+}
+
+fn otlp_span(data: SpanData, end_ns: u128) -> Value {
+    let attributes: Vec<Value> = data.attributes.into_iter().map(|(key, value)| json!({ "key": key, "value": value })).collect();
+    let mut span = json!({
+        "traceId": hex(&data.trace_id),
+        "spanId": hex(&data.span_id),
+        "name": data.name,
+        "kind": span_kind(data.name),
+        "startTimeUnixNano": data.start_ns.to_string(),
+        "endTimeUnixNano": end_ns.to_string(),
+        "attributes": attributes,
+    });
+    if let Some(parent) = data.parent_id {
+        span["parentSpanId"] = json!(hex(&parent));
+    }
+    span
+}
+
+async fn export(url: String, service_name: String, mut spans: mpsc::Receiver<Value>) {
+    let client = reqwest::Client::new();
+    let mut ticker = tokio::time::interval(EXPORT_INTERVAL);
+    let mut failing = false;
+    loop {
+        ticker.tick().await;
+        let mut batch = Vec::new();
+        while batch.len() < MAX_BATCH {
+            match spans.try_recv() {
+                Ok(span) => batch.push(span),
+                Err(mpsc::error::TryRecvError::Empty) => break,
+                Err(mpsc::error::TryRecvError::Disconnected) if batch.is_empty() => return,
+                Err(mpsc::error::TryRecvError::Disconnected) => break,
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        let body = json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [{ "key": "service.name", "value": { "stringValue": service_name } }] },
+                "scopeSpans": [{ "scope": { "name": env!("CARGO_PKG_NAME") }, "spans": batch }],
+            }]
+        });
+        let result = client.post(&url).json(&body).send().await.and_then(|r| r.error_for_status());
+        // Log transitions only, so an unreachable collector doesn't flood the logs
+        match result {
+            Err(e) if !failing => {
+                failing = true;
+                tracing::warn!("OTLP export to {} failing, dropping spans: {}", url, e);
+            }
+            Ok(_) if failing => {
+                failing = false;
+                tracing::info!("OTLP export to {} recovered", url);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn now_ns() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockUpstream};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // Spans posted to a local stand-in for the collector
+    async fn collector() -> (String, Arc<Mutex<Vec<Value>>>) {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let received = spans.clone();
+        let app = axum::Router::new().route(
+            TRACES_PATH,
+            axum::routing::post(move |axum::Json(body): axum::Json<Value>| async move {
+                let batch = body["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().cloned().unwrap_or_default();
+                received.lock().unwrap().extend(batch);
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (endpoint, spans)
+    }
+
+    #[tokio::test]
+    async fn a_request_exports_its_span_tree() {
+        let (endpoint, exported) = collector().await;
+        let layer = OtlpLayer::spawn(&OtlpConfig { endpoint, service_name: "test".to_string() });
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+        let upstream = MockUpstream::start().await;
+        let state = Arc::new(test_support::state(vec![upstream.provider("a")]));
+        let app = axum::Router::new()
+            .route("/v1/chat/completions", axum::routing::post(crate::gateway::handle_chat_completions))
+            .layer(axum::middleware::from_fn(crate::request_id::propagate_request_id))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let body = serde_json::json!({"model": "m", "prompt": "hi"});
+        let response = reqwest::Client::new().post(&url).json(&body).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let wait = async {
+            while exported.lock().unwrap().len() < 4 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(EXPORT_INTERVAL * 3, wait).await.expect("spans not exported");
+        let spans = exported.lock().unwrap().clone();
+        let named = |name: &str| spans.iter().find(|s| s["name"] == name).unwrap_or_else(|| panic!("no {name} span")).clone();
+        let root = named("request");
+        assert!(root.get("parentSpanId").is_none());
+        assert_eq!(root["kind"], KIND_SERVER);
+        for name in ["cache_lookup", "select", "upstream"] {
+            let span = named(name);
+            assert_eq!(span["traceId"], root["traceId"], "{name}");
+            assert_eq!(span["parentSpanId"], root["spanId"], "{name}");
+        }
+        let upstream_span = named("upstream");
+        assert_eq!(upstream_span["kind"], KIND_CLIENT);
+        let attribute = |key: &str| upstream_span["attributes"].as_array().unwrap().iter().find(|a| a["key"] == key).cloned();
+        assert_eq!(attribute("provider").unwrap()["value"]["stringValue"], "a");
+        assert!(attribute("latency_ms").is_some());
+    }
+}
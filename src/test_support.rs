@@ -0,0 +1,256 @@
+use crate::auth::{AuthConfig, ClientAuth};
+use crate::cache::SemanticCache;
+use crate::costs::CostTracker;
+use crate::gateway::{AppState, GatewayOptions};
+use crate::model::{LlmRequest, LlmResponse, ProviderConfig, TokenUsage};
+use crate::router::Router;
+use crate::tokens::TokenEstimator;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{routing::post, Json};
+use futures::StreamExt;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Fixtures shared by the unit tests: a local OpenAI-style upstream, and a gateway state with
+// every optional feature off to route to it.
+
+pub const CONTENT: &str = "mock answer";
+// Usage every upstream answer reports
+pub const PROMPT_TOKENS: u32 = 10;
+pub const COMPLETION_TOKENS: u32 = 10;
+
+#[derive(Default)]
+struct Behavior {
+    calls: AtomicUsize,
+    // Non-zero: answer with this status instead
+    fail_status: AtomicU16,
+    delay_ms: AtomicU64,
+    // Between streamed chunks
+    pace_ms: AtomicU64,
+    // Set: streams drop the connection after their headers, before the first chunk
+    break_streams: AtomicBool,
+    // Non-empty: sent instead of CONTENT
+    content: Mutex<String>,
+    // Non-empty: sent as the answer's `reasoning_content`
+    reasoning: Mutex<String>,
+    // Client addresses calls came from, one per connection
+    peers: Mutex<HashSet<SocketAddr>>,
+    // Headers of the latest call
+    headers: Mutex<HeaderMap>,
+    // When each call arrived
+    arrivals: Mutex<Vec<Instant>>,
+    // API keys answered with 401
+    rejected_keys: Mutex<HashSet<String>>,
+}
+
+pub struct MockUpstream {
+    pub endpoint: String,
+    behavior: Arc<Behavior>,
+}
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        let behavior = Arc::new(Behavior::default());
+        let app = axum::Router::new().route("/v1/chat/completions", post(answer)).with_state(behavior.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await });
+        Self { endpoint, behavior }
+    }
+
+    // A provider serving model `m` from this upstream, at $1 per 1k tokens either way.
+    pub fn provider(&self, id: &str) -> ProviderConfig {
+        ProviderConfig {
+            id: id.to_string(),
+            name: id.to_string(),
+            endpoint: self.endpoint.clone(),
+            api_key: vec!["key".to_string()],
+            cost_per_1k_input: 1.0,
+            cost_per_1k_output: 1.0,
+            model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+            ..ProviderConfig::default()
+        }
+    }
+
+    pub fn calls(&self) -> usize {
+        self.behavior.calls.load(Ordering::SeqCst)
+    }
+
+    // Distinct connections calls arrived on
+    pub fn connections(&self) -> usize {
+        self.behavior.peers.lock().unwrap().len()
+    }
+
+    pub fn arrivals(&self) -> Vec<Instant> {
+        self.behavior.arrivals.lock().unwrap().clone()
+    }
+
+    pub fn last_headers(&self) -> HeaderMap {
+        self.behavior.headers.lock().unwrap().clone()
+    }
+
+    pub fn reject_key(&self, key: &str) {
+        self.behavior.rejected_keys.lock().unwrap().insert(key.to_string());
+    }
+
+    // 0 answers normally again
+    pub fn fail_with(&self, status: u16) {
+        self.behavior.fail_status.store(status, Ordering::SeqCst);
+    }
+
+    // What the answers say instead of CONTENT
+    pub fn answer_with(&self, content: &str) {
+        *self.behavior.content.lock().unwrap() = content.to_string();
+    }
+
+    // Reasoning the answers report alongside their content
+    pub fn think(&self, reasoning: &str) {
+        *self.behavior.reasoning.lock().unwrap() = reasoning.to_string();
+    }
+
+    // Before every answer, failures included
+    pub fn delay(&self, delay: Duration) {
+        self.behavior.delay_ms.store(delay.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    // Before each streamed chunk after the first
+    pub fn pace(&self, pace: Duration) {
+        self.behavior.pace_ms.store(pace.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    // Streams answer 200 with SSE headers, then the connection drops before any chunk
+    pub fn break_streams(&self) {
+        self.behavior.break_streams.store(true, Ordering::SeqCst);
+    }
+}
+
+async fn answer(
+    State(behavior): State<Arc<Behavior>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    behavior.calls.fetch_add(1, Ordering::SeqCst);
+    behavior.arrivals.lock().unwrap().push(Instant::now());
+    behavior.peers.lock().unwrap().insert(peer);
+    let key = headers.get("authorization").and_then(|v| v.to_str().ok()?.strip_prefix("Bearer ")).unwrap_or_default();
+    let rejected = behavior.rejected_keys.lock().unwrap().contains(key);
+    *behavior.headers.lock().unwrap() = headers;
+    if rejected {
+        return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "bad key"}))).into_response();
+    }
+    tokio::time::sleep(Duration::from_millis(behavior.delay_ms.load(Ordering::SeqCst))).await;
+    let fail_status = behavior.fail_status.load(Ordering::SeqCst);
+    if fail_status != 0 {
+        let status = StatusCode::from_u16(fail_status).unwrap();
+        return (status, Json(serde_json::json!({"error": "simulated failure"}))).into_response();
+    }
+    let content = match behavior.content.lock().unwrap().clone() {
+        content if content.is_empty() => CONTENT.to_string(),
+        content => content,
+    };
+    if body.get("stream").and_then(Value::as_bool).unwrap_or(false) {
+        if behavior.break_streams.load(Ordering::SeqCst) {
+            let dropped = futures::stream::once(async { Err::<Event, _>(std::io::Error::other("connection dropped")) });
+            return Sse::new(dropped).into_response();
+        }
+        let events = content
+            .split_inclusive(' ')
+            .map(|word| serde_json::json!({"choices": [{"index": 0, "delta": {"content": word}}]}).to_string())
+            .chain(["[DONE]".to_string()])
+            .collect::<Vec<_>>()
+            .into_iter()
+            .enumerate();
+        let pace = Duration::from_millis(behavior.pace_ms.load(Ordering::SeqCst));
+        let events = futures::stream::iter(events).then(move |(i, data)| async move {
+            if i > 0 {
+                tokio::time::sleep(pace).await;
+            }
+            Ok::<_, Infallible>(Event::default().data(data))
+        });
+        return Sse::new(events).into_response();
+    }
+    let mut message = serde_json::json!({"role": "assistant", "content": content});
+    let reasoning = behavior.reasoning.lock().unwrap().clone();
+    if !reasoning.is_empty() {
+        message["reasoning_content"] = reasoning.into();
+    }
+    // `n` asks for several completions: the first is the content, the others numbered after it
+    let n = body.get("n").and_then(Value::as_u64).unwrap_or(1);
+    let choices: Vec<Value> = (0..n)
+        .map(|i| {
+            let mut message = message.clone();
+            if i > 0 {
+                message["content"] = format!("{content} {i}").into();
+            }
+            serde_json::json!({"index": i, "message": message, "finish_reason": "stop"})
+        })
+        .collect();
+    Json(serde_json::json!({
+        "id": "mock",
+        "object": "chat.completion",
+        "choices": choices,
+        "usage": {
+            "prompt_tokens": PROMPT_TOKENS,
+            "completion_tokens": COMPLETION_TOKENS,
+            "total_tokens": PROMPT_TOKENS + COMPLETION_TOKENS
+        }
+    }))
+    .into_response()
+}
+
+// Optional features are set on the returned state before it is shared.
+pub fn state(providers: Vec<ProviderConfig>) -> AppState {
+    AppState {
+        router: Arc::new(Router::new(providers).unwrap()),
+        cache: Arc::new(SemanticCache::new(1_000, 300)),
+        options: GatewayOptions::default(),
+        costs: Arc::new(CostTracker::new()),
+        estimator: Arc::new(TokenEstimator::new(false)),
+        budget: None,
+        retry_budget: None,
+        backoff: None,
+        negative_cache: None,
+        shadow: None,
+        idempotency: None,
+        moderation: None,
+        queue: None,
+        audit: None,
+        auth: Arc::new(ClientAuth::new(&AuthConfig { disabled: true, ..AuthConfig::default() })),
+    }
+}
+
+// `{"model": "m", "prompt": prompt}` with the given fields merged in
+pub fn request(prompt: &str, extra: Value) -> LlmRequest {
+    let mut req = serde_json::json!({"model": "m", "prompt": prompt});
+    if let (Some(req), Value::Object(extra)) = (req.as_object_mut(), extra) {
+        req.extend(extra);
+    }
+    serde_json::from_value(req).unwrap()
+}
+
+// What provider `provider` might have answered, for seeding the cache
+pub fn response(provider: &str, content: &str) -> LlmResponse {
+    LlmResponse {
+        content: content.to_string(),
+        usage: TokenUsage::default(),
+        provider: provider.to_string(),
+        latency_ms: 1,
+        reasoning: None,
+        cache_ttl: None,
+        choices: Vec::new(),
+    }
+}
+
+// Runs a chat completion through the handler, as the HTTP route would
+pub async fn complete(state: &Arc<AppState>, headers: HeaderMap, req: LlmRequest) -> Response {
+    crate::gateway::handle_chat_completions(State(state.clone()), headers, Ok(Json(req))).await
+}
@@ -0,0 +1,177 @@
+use crate::model::{LlmRequest, TokenUsage};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// Weight of the newest observation in the per-model averages.
+const EWMA_ALPHA: f64 = 0.1;
+// Bounds on the learned correction, so a few odd responses can't run it away.
+const MIN_CORRECTION: f64 = 0.25;
+const MAX_CORRECTION: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct ModelEstimate {
+    pub samples: u64,
+    // EWMA of |estimated - actual| / actual for the estimates actually handed out
+    pub relative_error: f64,
+    // Multiplier applied to the raw `estimate_tokens` count; stays 1.0 unless auto-correction is on
+    pub correction: f64,
+}
+
+impl Default for ModelEstimate {
+    fn default() -> Self {
+        Self { samples: 0, relative_error: 0.0, correction: 1.0 }
+    }
+}
+
+// Reconciles pre-call input token estimates against the `usage.prompt_tokens` providers
+// report, per client-facing model name.
+#[derive(Debug, Default)]
+pub struct TokenEstimator {
+    auto_correct: bool,
+    by_model: Mutex<HashMap<String, ModelEstimate>>,
+}
+
+impl TokenEstimator {
+    // With `auto_correct`, each model's correction tracks the observed actual/raw ratio.
+    pub fn new(auto_correct: bool) -> Self {
+        Self { auto_correct, by_model: Mutex::default() }
+    }
+
+    pub fn estimate(&self, req: &LlmRequest) -> u64 {
+        let raw = raw_estimate(req);
+        let correction = self.model(&req.model).correction;
+        (raw as f64 * correction).round() as u64
+    }
+
+    // Streams carry no usage, so only pass requests whose provider reported `prompt_tokens`.
+    pub fn observe(&self, req: &LlmRequest, actual_tokens: u32) {
+        let raw = raw_estimate(req);
+        if raw == 0 || actual_tokens == 0 {
+            return;
+        }
+        let actual = actual_tokens as f64;
+        let Ok(mut by_model) = self.by_model.lock() else { return };
+        let entry = by_model.entry(req.model.clone()).or_default();
+
+        let estimated = (raw as f64 * entry.correction).round();
+        let error = (estimated - actual).abs() / actual;
+        entry.relative_error = if entry.samples == 0 { error } else { ewma(entry.relative_error, error) };
+        if self.auto_correct {
+            let ratio = actual / raw as f64;
+            entry.correction = ewma(entry.correction, ratio).clamp(MIN_CORRECTION, MAX_CORRECTION);
+        }
+        entry.samples += 1;
+    }
+
+    // Estimates the counts a provider didn't report (streams never do), so cost tracking
+    // still sees the call. `completion` is the generated text.
+    pub fn fill_missing(&self, req: &LlmRequest, usage: &mut TokenUsage, completion: &str) {
+        if usage.prompt_tokens == 0 {
+            usage.prompt_tokens = self.estimate(req).min(u32::MAX as u64) as u32;
+        }
+        if usage.completion_tokens == 0 {
+            usage.completion_tokens = estimate_tokens(completion);
+        }
+        usage.total_tokens = usage.total_tokens.max(usage.prompt_tokens.saturating_add(usage.completion_tokens));
+    }
+
+    pub fn model(&self, model: &str) -> ModelEstimate {
+        match self.by_model.lock() {
+            Ok(by_model) => by_model.get(model).copied().unwrap_or_default(),
+            Err(_) => ModelEstimate::default(),
+        }
+    }
+
+    // Snapshot sorted by model, for stable output.
+    pub fn by_model(&self) -> BTreeMap<String, ModelEstimate> {
+        match self.by_model.lock() {
+            Ok(by_model) => by_model.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            Err(_) => BTreeMap::new(),
+        }
+    }
+}
+
+fn ewma(current: f64, sample: f64) -> f64 {
+    EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * current
+}
+
+// Uncorrected input size of a request (see `estimate_tokens`).
+pub fn raw_estimate(req: &LlmRequest) -> u64 {
+    req.transcript().map(|t| estimate_tokens(&t) as u64).unwrap_or(0)
+}
+
+// Heuristic token count, close to BPE tokenizers (cl100k-style) on typical input without
+// shipping a vocabulary: an ASCII word takes a token per 4 characters (at least one), ASCII
+// punctuation a token each, and every other character (CJK, emoji, accented text) a token
+// each, since BPE rarely merges those. Whitespace is absorbed into the following word.
+pub fn estimate_tokens(text: &str) -> u32 {
+    let mut tokens: u32 = 0;
+    let mut word_len: u32 = 0;
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            word_len += 1;
+            continue;
+        }
+        tokens = tokens.saturating_add(word_len.div_ceil(4));
+        word_len = 0;
+        if !c.is_ascii_whitespace() {
+            tokens = tokens.saturating_add(1);
+        }
+    }
+    tokens.saturating_add(word_len.div_ceil(4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(model: &str) -> LlmRequest {
+        serde_json::from_value(serde_json::json!({"model": model, "prompt": "how many tokens is this prompt"})).unwrap()
+    }
+
+    #[test]
+    fn consistent_underestimates_raise_the_correction() {
+        let estimator = TokenEstimator::new(true);
+        let req = request("m");
+        let raw = raw_estimate(&req);
+        let actual = (raw * 2) as u32;
+
+        let mut last = estimator.model("m");
+        for _ in 0..50 {
+            estimator.observe(&req, actual);
+            let now = estimator.model("m");
+            assert!(now.correction > last.correction);
+            last = now;
+        }
+        assert!((last.correction - 2.0).abs() < 0.02, "{}", last.correction);
+        assert!(last.relative_error < 0.1);
+        assert_eq!(last.samples, 50);
+        assert!(estimator.estimate(&req).abs_diff(actual as u64) <= 1);
+        // Per model
+        assert_eq!(estimator.model("other"), ModelEstimate::default());
+    }
+
+    #[test]
+    fn without_auto_correction_only_the_error_is_tracked() {
+        let estimator = TokenEstimator::new(false);
+        let req = request("m");
+        let actual = (raw_estimate(&req) * 2) as u32;
+        for _ in 0..10 {
+            estimator.observe(&req, actual);
+        }
+        let model = estimator.model("m");
+        assert_eq!(model.correction, 1.0);
+        assert!((model.relative_error - 0.5).abs() < 0.05, "{}", model.relative_error);
+        assert_eq!(estimator.estimate(&req), raw_estimate(&req));
+    }
+
+    #[test]
+    fn token_estimates_follow_words_punctuation_and_wide_characters() {
+        assert_eq!(estimate_tokens(""), 0);
+        // A token per 4 characters of a word, at least one
+        assert_eq!(estimate_tokens("a tokenizer"), 1 + 3);
+        assert_eq!(estimate_tokens("hi, there!"), 1 + 1 + 2 + 1);
+        assert_eq!(estimate_tokens("日本語"), 3);
+    }
+}
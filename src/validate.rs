@@ -0,0 +1,126 @@
+use crate::error::ApiError;
+use crate::gateway::GatewayOptions;
+use crate::model::LlmRequest;
+use crate::router::Router;
+use serde::Serialize;
+
+// OpenAI's accepted sampling temperature range.
+const TEMPERATURE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+// A parsed request that passed the checks below, made from the request plus what it is
+// checked against: the live provider list and the gateway's limits.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidRequest<'a>(pub &'a LlmRequest);
+
+// Checks a parsed request before it reaches the cache or a provider, collecting every
+// problem rather than stopping at the first. The 400 carries the first error's code and
+// message, and all of them under `errors`.
+impl<'a> TryFrom<(&'a LlmRequest, &Router, &GatewayOptions)> for ValidRequest<'a> {
+    type Error = ApiError;
+
+    fn try_from((req, router, options): (&'a LlmRequest, &Router, &GatewayOptions)) -> Result<Self, ApiError> {
+        let errors = field_errors(req, router, options);
+        let Some(first) = errors.first() else { return Ok(Self(req)) };
+        let message = errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; ");
+        Err(ApiError::invalid_request(first.code, message).with_detail("errors", &errors))
+    }
+}
+
+pub fn field_errors(req: &LlmRequest, router: &Router, options: &GatewayOptions) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let mut fail = |field, code, message: String| errors.push(FieldError { field, code, message });
+
+    if req.model.trim().is_empty() {
+        fail("model", "missing_model", "`model` must not be empty".to_string());
+    } else if !router.providers().iter().any(|p| p.supports_model(&req.model)) {
+        fail("model", "unsupported_model", format!("No provider serves model `{}`", req.model));
+    }
+    if req.canonical_text().is_none() {
+        fail("messages", "missing_input", "Request must include `prompt` or `messages`".to_string());
+    }
+    if let Some(temperature) = req.temperature {
+        if !TEMPERATURE_RANGE.contains(&temperature) {
+            fail("temperature", "invalid_temperature", format!("`temperature` must be between 0 and 2, got {}", temperature));
+        }
+    }
+    if let Some(max_tokens) = req.max_tokens {
+        let ceiling = options.max_tokens_ceiling.unwrap_or(u32::MAX);
+        if max_tokens == 0 || max_tokens > ceiling {
+            fail("max_tokens", "invalid_max_tokens", format!("`max_tokens` must be between 1 and {}", ceiling));
+        }
+    }
+    match req.n {
+        Some(0) => fail("n", "invalid_n", "`n` must be positive".to_string()),
+        Some(n) if n > 1 && req.is_streaming() => {
+            fail("n", "invalid_n", "`n` greater than 1 is not supported for streaming requests".to_string())
+        }
+        _ => {}
+    }
+    if req.max_latency_ms == Some(0) {
+        fail("max_latency_ms", "invalid_max_latency_ms", "`max_latency_ms` must be positive".to_string());
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ProviderConfig;
+    use std::collections::HashMap;
+
+    fn router() -> Router {
+        Router::new(vec![ProviderConfig {
+            id: "a".to_string(),
+            name: "a".to_string(),
+            endpoint: "http://127.0.0.1:9/v1/chat/completions".to_string(),
+            model_map: HashMap::from([("m".to_string(), "m".to_string())]),
+            ..ProviderConfig::default()
+        }])
+        .unwrap()
+    }
+
+    fn errors(body: serde_json::Value) -> Vec<(&'static str, &'static str)> {
+        let req: LlmRequest = serde_json::from_value(body).unwrap();
+        field_errors(&req, &router(), &GatewayOptions::default()).iter().map(|e| (e.field, e.code)).collect()
+    }
+
+    #[test]
+    fn valid_requests_pass() {
+        let req = serde_json::json!({"model": "m", "prompt": "hi", "temperature": 2.0, "max_tokens": 1});
+        assert_eq!(errors(req), []);
+    }
+
+    #[test]
+    fn each_invalid_field_is_reported() {
+        let cases = [
+            (serde_json::json!({"model": "", "prompt": "hi"}), ("model", "missing_model")),
+            (serde_json::json!({"model": "other", "prompt": "hi"}), ("model", "unsupported_model")),
+            (serde_json::json!({"model": "m"}), ("messages", "missing_input")),
+            (serde_json::json!({"model": "m", "prompt": "hi", "temperature": 2.5}), ("temperature", "invalid_temperature")),
+            (serde_json::json!({"model": "m", "prompt": "hi", "temperature": -0.1}), ("temperature", "invalid_temperature")),
+            (serde_json::json!({"model": "m", "prompt": "hi", "max_tokens": 0}), ("max_tokens", "invalid_max_tokens")),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(errors(body.clone()), [expected], "{}", body);
+        }
+    }
+
+    #[test]
+    fn every_problem_is_listed_in_the_400() {
+        let req: LlmRequest =
+            serde_json::from_value(serde_json::json!({"model": "other", "prompt": "hi", "temperature": 3.0, "max_tokens": 0})).unwrap();
+        let error = ValidRequest::try_from((&req, &router(), &GatewayOptions::default())).unwrap_err();
+        assert_eq!(error.status, axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, "unsupported_model");
+        let body = error.body();
+        let fields: Vec<&str> = body["error"]["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, ["model", "temperature", "max_tokens"]);
+    }
+}